@@ -0,0 +1,434 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+};
+
+use crate::{
+    command::{arguments, spec},
+    db::now_ms,
+    frame::Frame,
+    runner::RunnerError,
+};
+
+mod user;
+
+pub use user::{hash_password, User};
+
+/**
+ * How many denials ACL LOG keeps, like redis' acllog-max-len.
+ */
+pub const LOG_MAX_LEN: usize = 128;
+
+/**
+ * A denied command, authentication or access, as listed by ACL LOG.
+ * Denials of the same kind by the same user are counted in one entry.
+ */
+#[derive(Clone)]
+pub struct LogEntry {
+    pub id: u64,
+    pub count: u64,
+    // command, key, channel or auth
+    pub reason: &'static str,
+    // Where it was denied: toplevel or lua
+    pub context: &'static str,
+    // The command, key or channel denied
+    pub object: String,
+    pub username: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+struct State {
+    users: BTreeMap<String, User>,
+    // The newest entry first
+    log: VecDeque<LogEntry>,
+    next_log_id: u64,
+}
+
+/**
+ * The users connections authenticate as and what they are allowed to do.
+ * Lives next to the store in db::Shared, starting with the default user that is allowed everything.
+ * The requirepass and aclfile settings of the config give it its password and more users.
+ */
+pub struct Acl {
+    state: Mutex<State>,
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        Acl {
+            state: Mutex::new(State {
+                users: BTreeMap::from([("default".to_string(), User::default_user())]),
+                log: VecDeque::new(),
+                next_log_id: 0,
+            }),
+        }
+    }
+}
+
+impl Acl {
+    /**
+     * Sets the password of the default user, None letting it in without one.
+     * Connections already authenticated stay so.
+     */
+    pub fn set_requirepass(&self, password: Option<String>) {
+        let password = password.filter(|password| !password.is_empty());
+        let mut state = self.state.lock().unwrap();
+        if let Some(user) = state.users.get_mut("default") {
+            user.apply("resetpass").unwrap();
+            match &password {
+                Some(password) => user.apply(&format!(">{}", password)).unwrap(),
+                None => user.apply("nopass").unwrap(),
+            }
+        }
+    }
+
+    pub fn user(&self, name: &str) -> Option<User> {
+        self.state.lock().unwrap().users.get(name).cloned()
+    }
+
+    pub fn users(&self) -> Vec<User> {
+        self.state.lock().unwrap().users.values().cloned().collect()
+    }
+
+    /**
+     * Applies rules to a user, creating it if it doesn't exist.
+     * Nothing changes if one of the rules is invalid.
+     */
+    pub fn set_user(&self, name: &str, rules: &[String]) -> Result<(), RunnerError> {
+        let mut state = self.state.lock().unwrap();
+        let mut user = state
+            .users
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| User::new(name));
+        for rule in rules {
+            user.apply(rule).map_err(|err| {
+                RunnerError::Other(format!(
+                    "ERR Error in ACL SETUSER modifier '{}': {}",
+                    rule, err
+                ))
+            })?;
+        }
+        state.users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    /**
+     * Deletes users, returns how many existed.
+     */
+    pub fn delete_users(&self, names: &[String]) -> Result<usize, RunnerError> {
+        if names.iter().any(|name| name == "default") {
+            return Err(RunnerError::Other(
+                "ERR The 'default' user cannot be removed".to_string(),
+            ));
+        }
+        let mut state = self.state.lock().unwrap();
+        Ok(names
+            .iter()
+            .filter(|name| state.users.remove(name.as_str()).is_some())
+            .count())
+    }
+
+    /**
+     * Whether a password authenticates an enabled user.
+     * Every password of the user is compared, in a time that doesn't depend on how much of it matches.
+     */
+    pub fn authenticate(&self, username: &str, password: &str) -> bool {
+        let user = match self.user(username) {
+            Some(user) if user.enabled => user,
+            _ => return false,
+        };
+        let hash = hash_password(password);
+        let matched = user.passwords.iter().fold(false, |matched, stored| {
+            constant_time_eq(stored, &hash) | matched
+        });
+        user.nopass || matched
+    }
+
+    /**
+     * Checks that a user can run a command with its keys and channels, logging the denial if it can't.
+     * Commands run by scripts are checked in the lua context.
+     */
+    pub fn check(
+        &self,
+        username: &str,
+        frame: &Frame,
+        context: &'static str,
+    ) -> Result<(), RunnerError> {
+        let (command, args) = match arguments(frame) {
+            Some(arguments) => arguments,
+            None => return Ok(()),
+        };
+        // Unknown commands are left to be rejected when run
+        let spec = match spec(&command) {
+            Some(spec) => spec,
+            None => return Ok(()),
+        };
+        let user = match self.user(username) {
+            Some(user) => user,
+            None => {
+                return Err(RunnerError::Other(
+                    "NOAUTH Authentication required.".to_string(),
+                ))
+            }
+        };
+
+        let command = command.to_lowercase();
+        if !user.can_run(&command) {
+            self.log("command", context, &command, username);
+            return Err(RunnerError::Other(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                username, command
+            )));
+        }
+        if let Some(key) = spec
+            .keys(&args)
+            .into_iter()
+            .find(|key| !user.can_access_key(key))
+        {
+            self.log("key", context, &key, username);
+            return Err(RunnerError::Other(
+                "NOPERM No permissions to access a key".to_string(),
+            ));
+        }
+        let (channels, is_pattern) = spec.channels(&args);
+        if let Some(channel) = channels
+            .into_iter()
+            .find(|channel| !user.can_access_channel(channel, is_pattern))
+        {
+            self.log("channel", context, &channel, username);
+            return Err(RunnerError::Other(
+                "NOPERM No permissions to access a channel".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /**
+     * Adds a denial to ACL LOG, counting it in the last entry of the same kind if there is one.
+     */
+    pub fn log(&self, reason: &'static str, context: &'static str, object: &str, username: &str) {
+        let mut state = self.state.lock().unwrap();
+        let now = now_ms();
+        let existing = state.log.iter_mut().find(|entry| {
+            entry.reason == reason
+                && entry.context == context
+                && entry.object == object
+                && entry.username == username
+        });
+        if let Some(entry) = existing {
+            entry.count += 1;
+            entry.updated_at = now;
+            return;
+        }
+
+        let id = state.next_log_id;
+        state.next_log_id += 1;
+        state.log.push_front(LogEntry {
+            id,
+            count: 1,
+            reason,
+            context,
+            object: object.to_string(),
+            username: username.to_string(),
+            created_at: now,
+            updated_at: now,
+        });
+        state.log.truncate(LOG_MAX_LEN);
+    }
+
+    /**
+     * The newest denials first.
+     */
+    pub fn log_entries(&self, count: Option<usize>) -> Vec<LogEntry> {
+        let state = self.state.lock().unwrap();
+        state
+            .log
+            .iter()
+            .take(count.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+
+    pub fn reset_log(&self) {
+        self.state.lock().unwrap().log.clear();
+    }
+
+    /**
+     * Replaces every user with the ones of an ACL file, nothing changes if a line is invalid.
+     * The default user keeps its settings unless the file has it.
+     */
+    pub fn load(&self, file: &str) -> Result<(), RunnerError> {
+        let content = std::fs::read_to_string(file).map_err(|err| {
+            RunnerError::Other(format!(
+                "ERR Error loading ACLs, opening file '{}': {}",
+                file, err
+            ))
+        })?;
+
+        let mut users = BTreeMap::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error =
+                |err: &str| RunnerError::Other(format!("ERR {}:{}: {}", file, number + 1, err));
+            let mut words = line.split_whitespace();
+            let name = match (words.next(), words.next()) {
+                (Some("user"), Some(name)) => name,
+                _ => return Err(error("line should start with user keyword")),
+            };
+            if users.contains_key(name) {
+                return Err(error(&format!("Duplicate user '{}' found", name)));
+            }
+            let mut user = User::new(name);
+            for rule in words {
+                user.apply(rule).map_err(|err| {
+                    error(&format!("Error in applying operation '{}': {}", rule, err))
+                })?;
+            }
+            users.insert(name.to_string(), user);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if !users.contains_key("default") {
+            let default = state
+                .users
+                .get("default")
+                .cloned()
+                .unwrap_or_else(User::default_user);
+            users.insert("default".to_string(), default);
+        }
+        state.users = users;
+        Ok(())
+    }
+
+    /**
+     * Writes every user to an ACL file.
+     */
+    pub fn save(&self, file: &str) -> Result<(), RunnerError> {
+        let content: String = self
+            .users()
+            .iter()
+            .map(|user| format!("{}\n", user.describe()))
+            .collect();
+        std::fs::write(file, content)
+            .map_err(|err| RunnerError::Other(format!("ERR There was an error trying to save the ACLs. Please check the server logs for more information: {}", err)))
+    }
+}
+
+// Compares two digests in a time that only depends on their length
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::Session, db::DB, frame::command, scripting::Caller};
+
+    #[tokio::test]
+    async fn acl_permissions() {
+        let denied = |reply: Option<Result<Frame, RunnerError>>| match reply {
+            Some(Err(RunnerError::Other(err))) => err,
+            reply => panic!("expected NOPERM, got {:?}", reply),
+        };
+
+        let db = DB::new();
+        let rules = [
+            "on",
+            ">pw",
+            "~cache:*",
+            "&news",
+            "+@read",
+            "+set",
+            "+subscribe",
+            "-hget",
+        ];
+        db.db
+            .acl
+            .set_user("cache", &rules.map(String::from))
+            .unwrap();
+        assert!(db
+            .db
+            .acl
+            .set_user("cache", &["+nosuchcommand".to_string()])
+            .is_err());
+
+        let mut session = Session::new(db.clone());
+        assert!(session
+            .run(&command(&["AUTH", "cache", "wrong"]))
+            .unwrap()
+            .is_err());
+        assert!(session
+            .run(&command(&["AUTH", "cache", "pw"]))
+            .unwrap()
+            .is_ok());
+        assert!(session
+            .run(&command(&["SET", "cache:1", "value"]))
+            .is_none());
+        assert!(session.run(&command(&["GET", "cache:1"])).is_none());
+        assert!(session.run(&command(&["SUBSCRIBE", "news"])).is_none());
+
+        let err = denied(session.run(&command(&["GET", "other"])));
+        assert!(err.starts_with("NOPERM No permissions to access a key"));
+        let err = denied(session.run(&command(&["HGET", "cache:1", "field"])));
+        assert!(err.starts_with("NOPERM User cache has no permissions to run the 'hget' command"));
+        let err = denied(session.run(&command(&["HSET", "cache:2", "field", "1"])));
+        assert!(err.starts_with("NOPERM"));
+        let err = denied(session.run(&command(&["SUBSCRIBE", "sports"])));
+        assert!(err.starts_with("NOPERM No permissions to access a channel"));
+
+        let entries = db.db.acl.log_entries(None);
+        let denials: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|entry| (entry.reason, entry.object.as_str()))
+            .collect();
+        assert_eq!(
+            denials,
+            vec![
+                ("channel", "sports"),
+                ("command", "hset"),
+                ("command", "hget"),
+                ("key", "other"),
+                ("auth", "AUTH"),
+            ]
+        );
+
+        // Scripts run their commands as the user that called them
+        let reply = db
+            .db
+            .scripts
+            .eval(
+                &db,
+                &Caller {
+                    user: Some("cache".to_string()),
+                    tracking: None,
+                },
+                "return redis.pcall('GET', 'other')",
+                vec![],
+                vec![],
+            )
+            .unwrap();
+        assert!(matches!(reply, Frame::SimpleError(err) if err.starts_with("NOPERM")));
+        assert_eq!(db.db.acl.log_entries(Some(1))[0].context, "lua");
+
+        let user = db.db.acl.user("cache").unwrap();
+        assert_eq!(
+            user.describe(),
+            format!(
+                "user cache on #{} ~cache:* &news -@all +@read +set +subscribe -hget",
+                hash_password("pw")
+            )
+        );
+        assert!(db.db.acl.delete_users(&["default".to_string()]).is_err());
+        assert_eq!(db.db.acl.delete_users(&["cache".to_string()]).unwrap(), 1);
+        assert!(session.run(&command(&["GET", "cache:1"])).unwrap().is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::{BTreeSet, HashSet};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    command::{commands, spec, Category},
+    glob,
+};
+
+/**
+ * A user connections authenticate as, with what it is allowed to run and access.
+ * Users are changed by applying rules to them, like ACL SETUSER and the lines of an ACL file do.
+ */
+#[derive(Clone)]
+pub struct User {
+    pub name: String,
+    pub enabled: bool,
+    // Any password authenticates the user
+    pub nopass: bool,
+    // SHA256 digests of the passwords, in hex
+    pub passwords: BTreeSet<String>,
+    // The rules on commands in the order they were applied, e.g -@all +get
+    command_rules: Vec<String>,
+    // The commands the rules allow
+    allowed: HashSet<&'static str>,
+    // Glob patterns of the keys the user can access
+    pub keys: Vec<String>,
+    // Glob patterns of the pub/sub channels the user can access
+    pub channels: Vec<String>,
+}
+
+/**
+ * The SHA256 digest of a password in hex, as users store them.
+ */
+pub fn hash_password(password: &str) -> String {
+    format!("{:x}", Sha256::digest(password.as_bytes()))
+}
+
+impl User {
+    /**
+     * A user that is off, without passwords and allowed nothing.
+     */
+    pub fn new(name: &str) -> User {
+        User {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: BTreeSet::new(),
+            command_rules: vec!["-@all".to_string()],
+            allowed: HashSet::new(),
+            keys: vec![],
+            channels: vec![],
+        }
+    }
+
+    /**
+     * The default user, allowed everything without a password.
+     */
+    pub fn default_user() -> User {
+        let mut user = User::new("default");
+        for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+            user.apply(rule).unwrap();
+        }
+        user
+    }
+
+    /**
+     * Applies a rule, e.g on, >password, ~cache:*, &news, +@read or -flushall.
+     * Returns why the rule is invalid.
+     */
+    pub fn apply(&mut self, rule: &str) -> Result<(), String> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.keys = vec!["*".to_string()],
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => self.channels = vec!["*".to_string()],
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => self.set_commands("+@all"),
+            "nocommands" => self.set_commands("-@all"),
+            "reset" => {
+                for rule in ["resetpass", "resetkeys", "resetchannels", "off", "-@all"] {
+                    self.apply(rule)?;
+                }
+            }
+            _ => return self.apply_pattern(rule),
+        }
+        Ok(())
+    }
+
+    // Rules made of a prefix and a value: passwords, patterns and commands
+    fn apply_pattern(&mut self, rule: &str) -> Result<(), String> {
+        let (prefix, value) = rule.split_at(rule.chars().next().map_or(0, char::len_utf8));
+        match prefix {
+            ">" => {
+                self.passwords.insert(hash_password(value));
+                self.nopass = false;
+            }
+            "<" => {
+                if !self.passwords.remove(&hash_password(value)) {
+                    return Err("no such password".to_string());
+                }
+            }
+            "#" => {
+                if !is_hash(value) {
+                    return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters".to_string());
+                }
+                self.passwords.insert(value.to_string());
+                self.nopass = false;
+            }
+            "!" => {
+                if !self.passwords.remove(value) {
+                    return Err("no such password".to_string());
+                }
+            }
+            "~" => add_pattern(&mut self.keys, value),
+            "&" => add_pattern(&mut self.channels, value),
+            "+" | "-" => {
+                if value.eq_ignore_ascii_case("@all") {
+                    self.set_commands(&rule.to_lowercase());
+                    return Ok(());
+                }
+                match value.strip_prefix('@') {
+                    Some(category) if Category::parse(category).is_none() => {
+                        return Err("Unknown command category".to_string())
+                    }
+                    Some(_) => {}
+                    None if spec(value).is_none() => return Err("Unknown command".to_string()),
+                    None => {}
+                }
+                self.command_rules.push(rule.to_lowercase());
+                self.allowed = allowed_commands(&self.command_rules);
+            }
+            _ => return Err("Syntax error".to_string()),
+        }
+        Ok(())
+    }
+
+    // Rules on all commands replace the ones applied so far
+    fn set_commands(&mut self, rule: &str) {
+        self.command_rules = vec![rule.to_string()];
+        self.allowed = allowed_commands(&self.command_rules);
+    }
+
+    pub fn can_run(&self, command: &str) -> bool {
+        spec(command).is_some_and(|spec| self.allowed.contains(spec.name))
+    }
+
+    pub fn can_access_key(&self, key: &str) -> bool {
+        self.keys
+            .iter()
+            .any(|pattern| glob::matches(pattern.as_bytes(), key.as_bytes()))
+    }
+
+    /**
+     * Whether the user can use a channel, or a pattern of channels which has to be one of its own.
+     */
+    pub fn can_access_channel(&self, channel: &str, is_pattern: bool) -> bool {
+        self.channels.iter().any(|allowed| match is_pattern {
+            true => allowed == "*" || allowed == channel,
+            false => glob::matches(allowed.as_bytes(), channel.as_bytes()),
+        })
+    }
+
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    pub fn commands(&self) -> String {
+        self.command_rules.join(" ")
+    }
+
+    /**
+     * The rules that rebuild the user, as listed by ACL LIST and saved to the ACL file.
+     */
+    pub fn rules(&self) -> Vec<String> {
+        let mut rules: Vec<String> = self.flags().iter().map(|flag| flag.to_string()).collect();
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        match self.keys.is_empty() {
+            true => rules.push("resetkeys".to_string()),
+            false => rules.extend(self.keys.iter().map(|pattern| format!("~{}", pattern))),
+        }
+        match self.channels.is_empty() {
+            true => rules.push("resetchannels".to_string()),
+            false => rules.extend(self.channels.iter().map(|pattern| format!("&{}", pattern))),
+        }
+        rules.extend(self.command_rules.iter().cloned());
+        rules
+    }
+
+    /**
+     * The user as a line of ACL LIST or of an ACL file.
+     */
+    pub fn describe(&self) -> String {
+        format!("user {} {}", self.name, self.rules().join(" "))
+    }
+}
+
+// A pattern matching every key or channel replaces the others
+fn add_pattern(patterns: &mut Vec<String>, pattern: &str) {
+    if pattern == "*" {
+        *patterns = vec!["*".to_string()];
+    } else if !patterns.iter().any(|existing| existing == pattern) {
+        patterns.push(pattern.to_string());
+    }
+}
+
+fn is_hash(value: &str) -> bool {
+    value.len() == 64
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+// Applies command rules in order to find the commands they allow
+fn allowed_commands(rules: &[String]) -> HashSet<&'static str> {
+    let mut allowed = HashSet::new();
+    for rule in rules {
+        let (allow, value) = rule.split_at(1);
+        let names: Vec<&'static str> = match value.strip_prefix('@') {
+            Some("all") => commands().iter().map(|spec| spec.name).collect(),
+            Some(category) => match Category::parse(category) {
+                Some(category) => commands()
+                    .iter()
+                    .filter(|spec| spec.has(category))
+                    .map(|spec| spec.name)
+                    .collect(),
+                None => vec![],
+            },
+            None => spec(value).map(|spec| spec.name).into_iter().collect(),
+        };
+        for name in names {
+            match allow {
+                "+" => allowed.insert(name),
+                _ => allowed.remove(name),
+            };
+        }
+    }
+    allowed
+}
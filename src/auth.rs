@@ -0,0 +1,316 @@
+use std::collections::VecDeque;
+
+use crate::{
+    acl::LogEntry,
+    command::{commands, Category, CATEGORIES},
+    db::{now_ms, DB},
+    frame::Frame,
+    runner::{next_string, nil, rest_strings, RunnerError},
+};
+
+/**
+ * The user a connection is authenticated as.
+ * Connections start as the default user, authenticated right away if it doesn't need a password.
+ * Every command they send is checked against the user's permissions before it runs.
+ */
+pub struct Session {
+    db: DB,
+    user: String,
+    authenticated: bool,
+}
+
+impl Session {
+    pub fn new(db: DB) -> Session {
+        let authenticated = db
+            .db
+            .acl
+            .user("default")
+            .is_some_and(|user| user.enabled && user.nopass);
+        Session {
+            db,
+            user: "default".to_string(),
+            authenticated,
+        }
+    }
+
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    /**
+     * Handles AUTH and ACL, rejects every other command until the connection authenticates
+     * and the commands its user isn't allowed to run.
+     * Returns None if the frame should be run.
+     */
+    pub fn run(&mut self, frame: &Frame) -> Option<Result<Frame, RunnerError>> {
+        let mut frames = match frame {
+            Frame::Array(frames) => frames.clone(),
+            Frame::SimpleString(command) => VecDeque::from([Frame::SimpleString(command.clone())]),
+            _ => return None,
+        };
+        let command = match frames.pop_front() {
+            Some(Frame::SimpleString(command)) => command.to_uppercase(),
+            _ => return None,
+        };
+
+        match command.as_str() {
+            "AUTH" => return Some(rest_strings(&mut frames).and_then(|args| self.auth(args))),
+            "QUIT" => return None,
+            _ if !self.authenticated => {
+                return Some(Err(RunnerError::Other(
+                    "NOAUTH Authentication required.".to_string(),
+                )))
+            }
+            _ => {}
+        }
+        if let Err(err) = self.db.db.acl.check(&self.user, frame, "toplevel") {
+            return Some(Err(err));
+        }
+        match command.as_str() {
+            "ACL" => Some(self.run_acl(&mut frames)),
+            _ => None,
+        }
+    }
+
+    // AUTH [username] password
+    fn auth(&mut self, args: Vec<String>) -> Result<Frame, RunnerError> {
+        let (username, password) = match args.as_slice() {
+            [password] => ("default", password),
+            [username, password] => (username.as_str(), password),
+            _ => return Err(RunnerError::Incomplete),
+        };
+        let acl = &self.db.db.acl;
+        let nopass = acl.user("default").is_some_and(|user| user.nopass);
+        if nopass && args.len() == 1 {
+            return Err(RunnerError::Other(
+                "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?".to_string(),
+            ));
+        }
+        if !acl.authenticate(username, password) {
+            acl.log("auth", "toplevel", "AUTH", username);
+            return Err(RunnerError::Other(
+                "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+            ));
+        }
+        self.user = username.to_string();
+        self.authenticated = true;
+        Ok(Frame::SimpleString("OK".to_string()))
+    }
+
+    /**
+     * ACL SETUSER | GETUSER | DELUSER | USERS | LIST | WHOAMI | CAT | LOG | LOAD | SAVE | GENPASS
+     */
+    fn run_acl(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let acl = &self.db.db.acl;
+        let subcommand = next_string(frames)?;
+        match subcommand.to_uppercase().as_str() {
+            "SETUSER" => {
+                let name = next_string(frames)?;
+                acl.set_user(&name, &rest_strings(frames)?)?;
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "GETUSER" => {
+                let user = match acl.user(&next_string(frames)?) {
+                    Some(user) => user,
+                    None => return Ok(nil()),
+                };
+                let patterns = |prefix: &str, patterns: &[String]| {
+                    patterns
+                        .iter()
+                        .map(|pattern| format!("{}{}", prefix, pattern))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                };
+                Ok(Frame::Array(VecDeque::from([
+                    Frame::SimpleString("flags".to_string()),
+                    strings(user.flags()),
+                    Frame::SimpleString("passwords".to_string()),
+                    strings(user.passwords.iter()),
+                    Frame::SimpleString("commands".to_string()),
+                    Frame::SimpleString(user.commands()),
+                    Frame::SimpleString("keys".to_string()),
+                    Frame::SimpleString(patterns("~", &user.keys)),
+                    Frame::SimpleString("channels".to_string()),
+                    Frame::SimpleString(patterns("&", &user.channels)),
+                ])))
+            }
+            "DELUSER" => {
+                let names = rest_strings(frames)?;
+                if names.is_empty() {
+                    return Err(RunnerError::Incomplete);
+                }
+                Ok(Frame::Integer(acl.delete_users(&names)? as i64))
+            }
+            "USERS" => Ok(strings(acl.users().iter().map(|user| &user.name))),
+            "LIST" => Ok(strings(acl.users().iter().map(|user| user.describe()))),
+            "WHOAMI" => Ok(Frame::SimpleString(self.user.clone())),
+            "CAT" => match frames.is_empty() {
+                true => Ok(strings(CATEGORIES.iter().map(|(name, _)| name))),
+                false => {
+                    let name = next_string(frames)?;
+                    let category = Category::parse(&name).ok_or_else(|| {
+                        RunnerError::Other(format!("ERR Unknown category '{}'", name))
+                    })?;
+                    Ok(strings(
+                        commands()
+                            .iter()
+                            .filter(|spec| spec.has(category))
+                            .map(|spec| spec.name.to_lowercase()),
+                    ))
+                }
+            },
+            "LOG" => match frames.is_empty() {
+                true => Ok(log_frame(acl.log_entries(Some(10)))),
+                false => {
+                    let count = next_string(frames)?;
+                    if count.eq_ignore_ascii_case("RESET") {
+                        acl.reset_log();
+                        return Ok(Frame::SimpleString("OK".to_string()));
+                    }
+                    let count = count.parse::<usize>().map_err(|_| {
+                        RunnerError::Other(
+                            "ERR value is out of range, must be positive".to_string(),
+                        )
+                    })?;
+                    Ok(log_frame(acl.log_entries(Some(count))))
+                }
+            },
+            "LOAD" => {
+                acl.load(&self.acl_file()?)?;
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "SAVE" => {
+                acl.save(&self.acl_file()?)?;
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "GENPASS" => {
+                let bits = match frames.is_empty() {
+                    true => 256,
+                    false => next_string(frames)?
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|bits| (1..=4096).contains(bits))
+                        .ok_or_else(|| {
+                            RunnerError::Other(
+                                "ERR ACL GENPASS argument must be the number of bits for the output password, a positive number up to 4096".to_string(),
+                            )
+                        })?,
+                };
+                let password: String = (0..bits.div_ceil(4))
+                    .map(|_| format!("{:x}", rand::random::<u8>() & 0xf))
+                    .collect();
+                Ok(Frame::SimpleString(password))
+            }
+            _ => Err(RunnerError::Other(format!(
+                "ERR unknown subcommand '{}'. Try ACL HELP.",
+                subcommand
+            ))),
+        }
+    }
+
+    // The aclfile setting, ACL LOAD and ACL SAVE error without one
+    fn acl_file(&self) -> Result<String, RunnerError> {
+        let file = self.db.db.config.settings().aclfile;
+        match file.is_empty() {
+            true => Err(RunnerError::Other("ERR This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration.".to_string())),
+            false => Ok(file),
+        }
+    }
+}
+
+fn strings<T: ToString>(values: impl IntoIterator<Item = T>) -> Frame {
+    Frame::Array(
+        values
+            .into_iter()
+            .map(|value| Frame::SimpleString(value.to_string()))
+            .collect(),
+    )
+}
+
+// The reply of ACL LOG, an array of field value pairs per entry
+fn log_frame(entries: Vec<LogEntry>) -> Frame {
+    let now = now_ms();
+    Frame::Array(
+        entries
+            .into_iter()
+            .map(|entry| {
+                Frame::Array(VecDeque::from([
+                    Frame::SimpleString("count".to_string()),
+                    Frame::Integer(entry.count as i64),
+                    Frame::SimpleString("reason".to_string()),
+                    Frame::SimpleString(entry.reason.to_string()),
+                    Frame::SimpleString("context".to_string()),
+                    Frame::SimpleString(entry.context.to_string()),
+                    Frame::SimpleString("object".to_string()),
+                    Frame::SimpleString(entry.object),
+                    Frame::SimpleString("username".to_string()),
+                    Frame::SimpleString(entry.username),
+                    Frame::SimpleString("age-seconds".to_string()),
+                    Frame::SimpleString(format!(
+                        "{:.3}",
+                        now.saturating_sub(entry.created_at) as f64 / 1000.0
+                    )),
+                    Frame::SimpleString("entry-id".to_string()),
+                    Frame::Integer(entry.id as i64),
+                    Frame::SimpleString("timestamp-created".to_string()),
+                    Frame::Integer(entry.created_at as i64),
+                    Frame::SimpleString("timestamp-last-updated".to_string()),
+                    Frame::Integer(entry.updated_at as i64),
+                ]))
+            })
+            .collect(),
+    )
+}
+
+/**
+ * Whether protected mode refuses a client: while the protected-mode setting is on,
+ * only loopback clients are let in as long as the default user has no password.
+ */
+pub fn is_protected(db: &DB, addr: &std::net::SocketAddr) -> bool {
+    let protected_mode = db.db.config.settings().protected_mode;
+    let nopass = db.db.acl.user("default").is_some_and(|user| user.nopass);
+    protected_mode && nopass && !addr.ip().is_loopback()
+}
+
+/**
+ * The error protected mode refuses clients with before closing their connection.
+ */
+pub const DENIED: &str = "DENIED Redis is running in protected mode because protected mode is enabled and no password is set for the default user. In this mode connections are only accepted from the loopback interface. Set requirepass, or protected-mode to no if you are sure you want clients from other hosts to connect without authenticating.";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::command;
+
+    #[tokio::test]
+    async fn auth_requirepass() {
+        let db = DB::new();
+        // Without a password connections are let in right away
+        let mut session = Session::new(db.clone());
+        assert!(session.run(&command(&["GET", "key"])).is_none());
+        assert!(session.run(&command(&["AUTH", "secret"])).unwrap().is_err());
+
+        db.db.acl.set_requirepass(Some("secret".to_string()));
+        let mut session = Session::new(db.clone());
+        match session.run(&command(&["GET", "key"])) {
+            Some(Err(RunnerError::Other(err))) => assert!(err.starts_with("NOAUTH")),
+            _ => panic!("expected NOAUTH"),
+        }
+        match session.run(&command(&["AUTH", "wrong"])) {
+            Some(Err(RunnerError::Other(err))) => assert!(err.starts_with("WRONGPASS")),
+            _ => panic!("expected WRONGPASS"),
+        }
+        assert!(session
+            .run(&command(&["AUTH", "admin", "secret"]))
+            .unwrap()
+            .is_err());
+        assert_eq!(
+            session
+                .run(&command(&["AUTH", "default", "secret"]))
+                .unwrap()
+                .unwrap(),
+            Frame::SimpleString("OK".to_string())
+        );
+        assert!(session.run(&command(&["GET", "key"])).is_none());
+    }
+}
@@ -1,17 +1,36 @@
-use std::{collections::VecDeque, ops::Sub};
-
 use mini_redis::{
+    auth::{is_protected, Session, DENIED},
+    client::{Client, ClientInfo},
+    command::{arguments, spec, Category},
+    config,
     connection::{Connection, ConnectionError},
     db::DB,
     frame::{Frame, FrameError},
+    metrics,
+    monitor::{self, Monitor},
+    pubsub::Subscriber,
     rdb::RDB,
     runner::{Runner, RunnerError},
+    shutdown::{self, ShutdownOptions},
+    stats::{Outcome, Stats},
+    transaction::Transaction,
+};
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::{
+    net::TcpListener,
+    signal::unix::{signal, SignalKind},
+    sync::{mpsc, oneshot},
+    time::Instant,
 };
-use tokio::net::TcpListener;
+
+// A SHUTDOWN sent by a client, answered with why the server didn't exit
+type ShutdownRequest = (ShutdownOptions, oneshot::Sender<RunnerError>);
 
 #[tokio::main]
 async fn main() {
-    dotenv::dotenv().expect("Failed to read .env file");
+    // Settings can also come from a config file and the command line, .env is optional
+    let _ = dotenv::dotenv();
 
     let subscriber = tracing_subscriber::fmt()
         .compact()
@@ -28,8 +47,25 @@ async fn main() {
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
     tracing::info!("MINIREDIS: Starting mini-redis");
-    // Start db
-    let db = DB::new();
+    // Start db with the settings of the env vars
+    let db = DB::with_config(config::Config::from_env());
+    // Load the config file and command line overrides on top of the env vars
+    // E.g server redis.conf --flush-every 10
+    let loaded = config::parse_args(std::env::args().skip(1))
+        .and_then(|args| db.db.config.load(args.file.as_deref(), &args.overrides));
+    if let Err(err) = loaded {
+        tracing::error!("MINIREDIS: {}", err);
+        return;
+    }
+    db.apply_config(&config::names());
+    let settings = db.db.config.settings();
+    // Load the users of the ACL file, if one is set
+    if !settings.aclfile.is_empty() {
+        if let Err(err) = db.db.acl.load(&settings.aclfile) {
+            tracing::error!("MINIREDIS: Failed to load the ACL file: {:?}", err);
+            return;
+        }
+    }
     // Start rdb instance
     let mut rdb = RDB::new(db.clone());
 
@@ -37,61 +73,467 @@ async fn main() {
     rdb.load().await.unwrap();
 
     // Open TCP listener for new connections
-    let addr = std::env::var("ADDR").expect("ADDR env var not provided");
+    let addr = settings.addr;
     let listener = TcpListener::bind(addr.clone()).await.unwrap();
+    let laddr = listener.local_addr().unwrap();
     tracing::info!("MINIREDIS: Listening for connections at {}", addr);
 
+    // Serve the Prometheus metrics, if an address is set
+    if !settings.metrics_addr.is_empty() {
+        let listener = match TcpListener::bind(&settings.metrics_addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!("MINIREDIS: Failed to bind the metrics listener: {}", err);
+                return;
+            }
+        };
+        tracing::info!(
+            "MINIREDIS: Serving metrics at http://{}/metrics",
+            settings.metrics_addr
+        );
+        tokio::spawn(metrics::serve(db.clone(), listener));
+    }
+
+    let (shutdown_sender, mut shutdown_requests) = mpsc::channel::<ShutdownRequest>(1);
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
     loop {
         /*
         Block the main thread until a connection is created.
         Clone the db for that connection to use.
         Create a new tokio handle(green thread) to handle the connection
+        No connection is accepted while shutting down.
          */
-        let (stream, _) = listener.accept().await.unwrap();
-        let start = chrono::Utc::now();
-        let mut db = db.clone();
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => accepted.unwrap(),
+            Some((options, reply)) = shutdown_requests.recv() => {
+                tracing::warn!("MINIREDIS: User requested shutdown...");
+                if let Err(err) = shutdown_server(&db, &rdb, options).await {
+                    let _ = reply.send(err);
+                }
+                continue;
+            }
+            _ = terminate.recv() => {
+                tracing::warn!("MINIREDIS: Received SIGTERM scheduling shutdown...");
+                let _ = shutdown_server(&db, &rdb, ShutdownOptions::default()).await;
+                continue;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                tracing::warn!("MINIREDIS: Received SIGINT scheduling shutdown...");
+                let _ = shutdown_server(&db, &rdb, ShutdownOptions::default()).await;
+                continue;
+            }
+        };
+        let db = db.clone();
+        let shutdown_sender = shutdown_sender.clone();
+        Stats::incr(&db.db.stats.connections_received);
 
-        let handle = tokio::spawn(async move {
+        tokio::spawn(async move {
             let mut connection = Connection::new(stream);
+            // Protected mode only lets loopback clients in while no password is set
+            if is_protected(&db, &addr) {
+                Stats::incr(&db.db.stats.rejected_connections);
+                tracing::info!("MINIREDIS: Refusing {} in protected mode", addr);
+                let _ = connection
+                    .write_all(Frame::SimpleError(DENIED.to_string()))
+                    .await;
+            } else {
+                let _connected = Stats::enter(&db.db.stats.connected_clients);
+                handle_connection(db.clone(), shutdown_sender, &mut connection, addr, laddr).await;
+            }
+
+            // Shutdown the connection
+            connection.shutdown().await;
+        });
+    }
+}
 
-            // Get a full frame from the connection
-            // A frame in this case refers to a complete data unit in this case corresponds to the redis protocol spec
-            // Most frames are of either Array or String
-            // E.g [Frame::SimpleString("SET"),Frame::SimpleString("KEY"),Frame::SimpleString("VALUE")]
-            // E.g Frame::SimpleString("PING")
-
-            let frame = match connection.read_frame().await {
-                Ok(frame) => match frame {
-                    Some(frame) => frame,
-                    None => Frame::Array(VecDeque::new()),
-                },
+/**
+ * Saves the db one last time and exits the process.
+ * The store stays locked until the process exits, so the commands in flight finish first
+ * and no other command runs after the save. NOSAVE exits right away, even while a script is busy.
+ * Returns why the server can't exit if the save failed, unless forced.
+ */
+async fn shutdown_server(db: &DB, rdb: &RDB, options: ShutdownOptions) -> Result<(), RunnerError> {
+    let (db, rdb) = (db.clone(), rdb.clone());
+    // Waiting for the locks and the disk blocks, so it runs away from the tasks of the runtime
+    tokio::task::spawn_blocking(move || save_and_exit(&db, &rdb, options))
+        .await
+        .unwrap()
+}
+
+fn save_and_exit(db: &DB, rdb: &RDB, options: ShutdownOptions) -> Result<(), RunnerError> {
+    // The save lock is taken before the store like the background worker does, so they can't deadlock
+    let saving = options.save.then(|| rdb.lock_saving());
+    let _transaction = options.save.then(|| db.db.lock_transaction());
+    let mut status = 0;
+    if let Some(saving) = &saving {
+        tracing::info!("MINIREDIS: Saving the final RDB snapshot before exiting.");
+        if let Err(err) = rdb.save_locked(saving) {
+            tracing::error!("MINIREDIS: Error trying to save the DB: {}", err);
+            if !options.force {
+                tracing::error!("MINIREDIS: Errors trying to shut down the server. Check the logs for more information.");
+                return Err(RunnerError::Other(
+                    "ERR Errors trying to SHUTDOWN. Check logs.".to_string(),
+                ));
+            }
+            status = 1;
+        }
+    }
+    tracing::warn!("MINIREDIS: Redis is now ready to exit, bye bye...");
+    std::process::exit(status)
+}
+
+/**
+ * Runs the frames a client sends until it disconnects or gets killed by CLIENT KILL.
+ * While the client is subscribed to channels, published messages are written as they arrive.
+ * SHUTDOWN is passed on to the main task, which stops accepting connections before exiting.
+ */
+async fn handle_connection(
+    mut db: DB,
+    shutdown: mpsc::Sender<ShutdownRequest>,
+    connection: &mut Connection,
+    addr: SocketAddr,
+    laddr: SocketAddr,
+) {
+    let mut session = Session::new(db.clone());
+    let mut subscriber = Subscriber::new(db.clone());
+    let mut transaction = Transaction::new(db.clone()).with_client(&addr.to_string());
+    let mut monitor = Monitor::new(db.clone(), subscriber.id());
+    let mut client = Client::new(
+        db.clone(),
+        subscriber.id(),
+        &addr.to_string(),
+        &laddr.to_string(),
+    );
+    let info = client.info().clone();
+    loop {
+        // Get a full frame from the connection
+        // A frame in this case refers to a complete data unit in this case corresponds to the redis protocol spec
+        // Most frames are of either Array or String
+        // E.g [Frame::SimpleString("SET"),Frame::SimpleString("KEY"),Frame::SimpleString("VALUE")]
+        // E.g Frame::SimpleString("PING")
+        let frame = tokio::select! {
+            frame = connection.read_frame() => match frame {
+                Ok(Some(frame)) => frame,
+                // The client closed the connection
+                Ok(None) => return,
                 Err(err) => {
-                    handle_err(err, &mut connection).await;
+                    handle_err(err, connection).await;
+                    return;
+                }
+            },
+            message = subscriber.receive(), if subscriber.is_subscribed() => match message {
+                Some(message) => {
+                    if connection.write_all(message).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                // Too many messages were waiting for the client
+                None => return,
+            },
+            // Invalidations of the keys the connection tracks without REDIRECT
+            Some(invalidation) = client.invalidated() => {
+                if connection.write_all(invalidation).await.is_err() {
                     return;
                 }
+                continue;
+            }
+            line = monitor.receive(), if monitor.is_monitoring() => match line {
+                Some(line) => {
+                    if connection.write_all(line).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                // The client read the commands too slowly
+                None => return,
+            },
+            _ = info.killed() => return,
+        };
+        let start = Instant::now();
+        Stats::incr(&db.db.stats.commands_processed);
+        // Known commands are counted in INFO commandstats
+        let command = arguments(&frame).map(|(command, _)| command);
+        let spec = command.as_deref().and_then(spec);
+        let name = spec.map(|spec| spec.name);
+        info.update(|state| {
+            if let Some(command) = &command {
+                state.cmd = command.to_lowercase();
+            }
+            state.last_interaction = start;
+            state.qbuf = connection.buffered();
+        });
+
+        // Messages published and keys invalidated before the command are written before its reply
+        while let Some(message) = subscriber
+            .try_receive()
+            .or_else(|| client.try_invalidated())
+        {
+            if connection.write_all(message).await.is_err() {
+                return;
+            }
+        }
+
+        // Nothing but AUTH runs until the connection authenticates.
+        // A monitoring connection may only QUIT or RESET.
+        // Inside MULTI the transaction refuses AUTH, ACL, SHUTDOWN and the other commands EXEC couldn't run first.
+        if let Some(reply) = monitor
+            .run_monitoring(&frame)
+            .or_else(|| transaction.refuse(&frame))
+            .or_else(|| session.run(&frame))
+        {
+            record(&db, name, start.elapsed(), outcome(&reply));
+            info.update(|state| state.user = session.user().to_string());
+            if client.should_reply() {
+                match reply {
+                    Ok(frame) => {
+                        if connection.write_all(frame).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(err) => handle_runner_err(err, connection).await,
+                }
+            }
+            continue;
+        }
+
+        // The reply is only written if the server couldn't exit
+        if let Some(options) = shutdown::parse(&frame) {
+            let err = match options {
+                Ok(options) => {
+                    let (reply, failed) = oneshot::channel();
+                    let _ = shutdown.send((options, reply)).await;
+                    match failed.await {
+                        Ok(err) => err,
+                        // The server is exiting
+                        Err(_) => return,
+                    }
+                }
+                Err(err) => err,
             };
+            record(&db, name, start.elapsed(), error_outcome(&err));
+            if client.should_reply() {
+                handle_runner_err(err, connection).await;
+            }
+            continue;
+        }
 
-            // Takes a frame from earlier step and executes it.
-            // Takes the db instance for frames that require db access.
+        // Other connections wait for a running script, unless it runs for too long
+        if !is_script_kill(&frame) && !db.db.scripts.wait_idle().await {
+            let busy = RunnerError::Other(
+                "BUSY Redis is busy running a script. You can only call SCRIPT KILL.".to_string(),
+            );
+            record(&db, name, start.elapsed(), error_outcome(&busy));
+            if client.should_reply() {
+                handle_runner_err(busy, connection).await;
+            }
+            continue;
+        }
+
+        // Commands held back by CLIENT PAUSE wait, except the ones queued after MULTI until EXEC
+        if transaction.queued().is_none() || name == Some("EXEC") {
+            tokio::select! {
+                _ = db.db.clients.wait_unpaused(spec) => {}
+                _ = info.killed() => return,
+            }
+        }
 
-            let mut runner = Runner::new(&mut db);
-            let results = runner.run(frame);
+        // The runner remembers the keys read for client side caching, scripts and EXEC included.
+        // Frames queued after MULTI are tracked once EXEC runs them.
+        let reader = match transaction.queued() {
+            Some(_) if name != Some("EXEC") => None,
+            _ => client.reader(&frame),
+        };
 
-            // Parse the results from the runner
-            // If successful, write the resulting frame back to the client
-            match results {
-                Err(err) => handle_runner_err(err, &mut connection).await,
-                Ok(frame) => connection.write_all(frame).await.unwrap(),
+        // Transaction commands and frames queued after MULTI are handled by the connection's transaction.
+        // CLIENT commands are handled by the connection's client.
+        // MONITOR is handled by the connection's monitor.
+        // Subscription commands and subscriber mode are handled by the connection's subscriber.
+        // Other frames are executed by the runner against the db.
+        // Frames queued after MULTI are shown by MONITOR once EXEC runs them
+        let in_transaction = transaction.queued().is_some();
+        let time = (!db.db.monitors.is_empty()).then(monitor::now);
+        let queued = match subscriber.is_subscribed() {
+            true => None,
+            false => transaction
+                .run(&frame, session.user(), reader)
+                .or_else(|| client.run(&frame))
+                .or_else(|| monitor.run(&frame)),
+        };
+        // The commands the connection runs itself are shown by MONITOR like the ones of the runner
+        let feed = |time: Option<f64>| {
+            let shown = !in_transaction || spec.is_some_and(|spec| spec.has(Category::Transaction));
+            if let Some(time) = time.filter(|_| shown) {
+                db.db.monitors.feed(time, &addr.to_string(), &frame);
             }
-            let stop = chrono::Utc::now();
+        };
+        // Blocked commands only count the time spent running, not waiting
+        let (results, duration) = match queued.map(|reply| reply.map(|frame| vec![frame])) {
+            Some(results) => {
+                feed(time);
+                (results, start.elapsed())
+            }
+            None => match subscriber.run(&frame) {
+                Some(results) => {
+                    feed(time);
+                    (results, start.elapsed())
+                }
+                None if is_quit(&frame) => {
+                    let _ = connection
+                        .write_all(Frame::SimpleString("OK".to_string()))
+                        .await;
+                    return;
+                }
+                None => {
+                    let (results, duration) = tokio::select! {
+                        ran = run_blocking(&mut db, &frame, session.user(), reader, &info) => ran,
+                        _ = info.killed() => return,
+                    };
+                    (results.map(|frame| vec![frame]), duration)
+                }
+            },
+        };
 
-            // Shutdown the connection
-            connection.shutdown().await;
-            let total_time = stop.sub(start).to_std().unwrap().as_nanos();
-            tracing::info!("MINIREDIS: Handled request for {} ns", total_time)
+        // Parse the results from the runner
+        // If successful, write the resulting frames back to the client
+        record(&db, name, duration, outcome(&results));
+        db.db.slowlog.record(&frame, duration, &info);
+        let event = match spec {
+            Some(spec) if spec.has(Category::Fast) => "fast-command",
+            _ => "command",
+        };
+        db.db.latency.record(event, duration);
+        info.update(|state| {
+            (state.sub, state.psub, state.ssub) = subscriber.subscriptions_len();
+            state.multi = transaction.queued();
+            state.omem = subscriber.pending();
+            state.blocked = false;
+            state.monitor = monitor.is_monitoring();
+            state.last_interaction = Instant::now();
         });
+        if !client.should_reply() {
+            continue;
+        }
+        match results {
+            Err(err) => handle_runner_err(err, connection).await,
+            Ok(frames) => {
+                for frame in frames {
+                    if connection.write_all(frame).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
 
-        handle.await.unwrap();
+// Counts a command sent by a client in INFO commandstats
+fn record(db: &DB, name: Option<&'static str>, duration: Duration, outcome: Outcome) {
+    if let Some(name) = name {
+        db.db.stats.record_command(name, duration, outcome);
+    }
+}
+
+fn outcome<T>(result: &Result<T, RunnerError>) -> Outcome {
+    match result {
+        Ok(_) => Outcome::Ok,
+        Err(err) => error_outcome(err),
+    }
+}
+
+// Commands refused before running are rejected, the others failed
+fn error_outcome(err: &RunnerError) -> Outcome {
+    match err {
+        RunnerError::Incomplete => Outcome::Rejected,
+        RunnerError::Other(err)
+            if ["NOAUTH", "NOPERM", "BUSY"]
+                .iter()
+                .any(|prefix| err.starts_with(prefix)) =>
+        {
+            Outcome::Rejected
+        }
+        _ => Outcome::Failed,
+    }
+}
+
+fn is_quit(frame: &Frame) -> bool {
+    let command = match frame {
+        Frame::Array(frames) => frames.front(),
+        frame => Some(frame),
+    };
+    matches!(command, Some(Frame::SimpleString(command)) if command.eq_ignore_ascii_case("QUIT"))
+}
+
+fn is_script_kill(frame: &Frame) -> bool {
+    match frame {
+        Frame::Array(frames) => matches!(
+            (frames.front(), frames.get(1)),
+            (Some(Frame::SimpleString(command)), Some(Frame::SimpleString(subcommand)))
+                if command.eq_ignore_ascii_case("SCRIPT") && subcommand.eq_ignore_ascii_case("KILL")
+        ),
+        _ => false,
+    }
+}
+
+/**
+ * Runs a frame, waiting for keys to be written while the command is blocked (e.g BZPOPMIN).
+ * Replies nil once the command's timeout elapses.
+ * Also returns the time spent running the command, without the time spent waiting.
+ */
+async fn run_blocking(
+    db: &mut DB,
+    frame: &Frame,
+    user: &str,
+    tracking: Option<u64>,
+    client: &ClientInfo,
+) -> (Result<Frame, RunnerError>, Duration) {
+    let shared = db.db.clone();
+    let mut frame = frame.clone();
+    let mut running = Duration::ZERO;
+    let mut deadline = None;
+    // Counted in blocked_clients from the first time the command blocks
+    let mut _blocked = None;
+    loop {
+        // Register interest before running so writes in between aren't missed
+        let notified = shared.key_ready.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        let started = Instant::now();
+        let mut runner = Runner::new(db).with_user(user).with_client(&client.addr);
+        if let Some(id) = tracking {
+            runner = runner.with_tracking(id);
+        }
+        let results = runner.run(frame.clone());
+        running += started.elapsed();
+        match results {
+            Err(RunnerError::Blocked { timeout, retry }) => {
+                _blocked.get_or_insert_with(|| {
+                    client.update(|state| state.blocked = true);
+                    Stats::enter(&shared.stats.blocked_clients)
+                });
+                if let Some(retry) = retry {
+                    frame = retry;
+                }
+                // Timeouts too far away for the clock block like no timeout at all
+                let deadline = *deadline.get_or_insert_with(|| {
+                    timeout.and_then(|timeout| Instant::now().checked_add(timeout))
+                });
+                match deadline {
+                    Some(deadline) => {
+                        tokio::select! {
+                            _ = &mut notified => {}
+                            _ = tokio::time::sleep_until(deadline) => {
+                                return (Ok(Frame::SimpleError("Nill".to_string())), running)
+                            }
+                        }
+                    }
+                    None => notified.await,
+                }
+            }
+            results => return (results, running),
+        }
     }
 }
 
@@ -109,19 +551,12 @@ async fn handle_err(connection_error: ConnectionError, connection: &mut Connecti
 }
 
 async fn handle_runner_err(runner_error: RunnerError, connection: &mut Connection) {
-    match runner_error {
-        RunnerError::Other(err) => connection.write_all(Frame::SimpleError(err)).await.unwrap(),
-        RunnerError::Incomplete => connection
-            .write_all(Frame::SimpleError(
-                "Protocol Error: Incorrect usage of command".to_string(),
-            ))
-            .await
-            .unwrap(),
-        RunnerError::Unsupported => connection
-            .write_all(Frame::SimpleError(
-                "Protocol Error: Unsupported usage of command or values".to_string(),
-            ))
-            .await
-            .unwrap(),
+    // Blocked commands are resolved by run_blocking
+    if let RunnerError::Blocked { .. } = runner_error {
+        unreachable!()
     }
+    connection
+        .write_all(runner_error.into_frame())
+        .await
+        .unwrap()
 }
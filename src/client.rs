@@ -0,0 +1,700 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    sync::Notify,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    command::{Category, CommandSpec},
+    db::DB,
+    frame::Frame,
+    runner::{next_string, nil, rest_strings, RunnerError},
+    tracking::Tracking,
+};
+
+/**
+ * Which commands CLIENT PAUSE holds back.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PauseMode {
+    // Commands that may write, e.g SET, EVAL or PUBLISH
+    Write,
+    // Every command but CLIENT's, so the pause can be lifted
+    All,
+}
+
+struct Pause {
+    until: Instant,
+    mode: PauseMode,
+}
+
+/**
+ * What a connection is doing, as listed by CLIENT LIST.
+ * The connection updates it as it runs commands.
+ */
+#[derive(Clone)]
+pub struct ClientState {
+    pub name: String,
+    pub user: String,
+    // The last command run, lowercase
+    pub cmd: String,
+    pub last_interaction: Instant,
+    // Bytes read from the connection that aren't parsed into a command yet
+    pub qbuf: usize,
+    // Bytes of published messages waiting to be written to the connection
+    pub omem: usize,
+    pub sub: usize,
+    pub psub: usize,
+    pub ssub: usize,
+    // Commands queued since MULTI, None outside of a transaction
+    pub multi: Option<usize>,
+    // Waiting on a blocking command, e.g BZPOPMIN
+    pub blocked: bool,
+    // The connection tracking invalidations are sent to, None while tracking is off
+    pub redirect: Option<u64>,
+    pub no_evict: bool,
+    // Sent MONITOR
+    pub monitor: bool,
+}
+
+/**
+ * A connection as the other connections see it in the registry.
+ */
+pub struct ClientInfo {
+    pub id: u64,
+    pub addr: String,
+    // The address of the server the client connected to
+    pub laddr: String,
+    created_at: Instant,
+    state: Mutex<ClientState>,
+    killed: Notify,
+}
+
+impl ClientInfo {
+    pub fn state(&self) -> ClientState {
+        self.state.lock().unwrap().clone()
+    }
+
+    pub fn update(&self, update: impl FnOnce(&mut ClientState)) {
+        update(&mut self.state.lock().unwrap());
+    }
+
+    /**
+     * Waits until the client gets killed by CLIENT KILL.
+     */
+    pub async fn killed(&self) {
+        self.killed.notified().await
+    }
+
+    /**
+     * The line of the client in CLIENT LIST, e.g
+     * id=1 addr=127.0.0.1:51234 laddr=127.0.0.1:6379 name= age=10 idle=0 flags=N db=0 ... cmd=get user=default
+     */
+    pub fn line(&self) -> String {
+        let state = self.state();
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db=0 sub={} psub={} ssub={} multi={} qbuf={} omem={} cmd={} user={} redir={} resp=2",
+            self.id,
+            self.addr,
+            self.laddr,
+            state.name,
+            self.created_at.elapsed().as_secs(),
+            state.last_interaction.elapsed().as_secs(),
+            flags(&state),
+            state.sub,
+            state.psub,
+            state.ssub,
+            state.multi.map_or(-1, |queued| queued as i64),
+            state.qbuf,
+            state.omem,
+            state.cmd,
+            state.user,
+            state.redirect.map_or(-1, |redirect| redirect as i64),
+        )
+    }
+
+    fn is_pubsub(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.sub + state.psub + state.ssub > 0
+    }
+}
+
+// O monitor, P subscribed, x in MULTI, b blocked, t tracking, e no-evict, N none of them
+fn flags(state: &ClientState) -> String {
+    let flags: String = [
+        (state.monitor, 'O'),
+        (state.sub + state.psub + state.ssub > 0, 'P'),
+        (state.multi.is_some(), 'x'),
+        (state.blocked, 'b'),
+        (state.redirect.is_some(), 't'),
+        (state.no_evict, 'e'),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, flag)| *flag)
+    .collect();
+    match flags.is_empty() {
+        true => "N".to_string(),
+        false => flags,
+    }
+}
+
+/**
+ * The connected clients, shared by every connection in db::Shared.
+ */
+#[derive(Default)]
+pub struct Clients {
+    clients: Mutex<BTreeMap<u64, Arc<ClientInfo>>>,
+    pause: Mutex<Option<Pause>>,
+    // Wakes up the paused clients on CLIENT UNPAUSE
+    unpaused: Notify,
+}
+
+impl Clients {
+    pub fn register(&self, id: u64, addr: &str, laddr: &str) -> Arc<ClientInfo> {
+        let now = Instant::now();
+        let client = Arc::new(ClientInfo {
+            id,
+            addr: addr.to_string(),
+            laddr: laddr.to_string(),
+            created_at: now,
+            state: Mutex::new(ClientState {
+                name: String::new(),
+                user: "default".to_string(),
+                cmd: "NULL".to_string(),
+                last_interaction: now,
+                qbuf: 0,
+                omem: 0,
+                sub: 0,
+                psub: 0,
+                ssub: 0,
+                multi: None,
+                blocked: false,
+                redirect: None,
+                no_evict: false,
+                monitor: false,
+            }),
+            killed: Notify::new(),
+        });
+        self.clients.lock().unwrap().insert(id, client.clone());
+        client
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.clients.lock().unwrap().remove(&id);
+    }
+
+    /**
+     * The connected clients, sorted by id.
+     */
+    pub fn list(&self) -> Vec<Arc<ClientInfo>> {
+        self.clients.lock().unwrap().values().cloned().collect()
+    }
+
+    /**
+     * Disconnects the clients matching filter, returns how many there were.
+     */
+    pub fn kill(&self, filter: impl Fn(&ClientInfo) -> bool) -> usize {
+        let mut clients = self.clients.lock().unwrap();
+        let killed: Vec<u64> = clients
+            .values()
+            .filter(|client| filter(client))
+            .map(|client| client.id)
+            .collect();
+        for id in &killed {
+            if let Some(client) = clients.remove(id) {
+                client.killed.notify_one();
+            }
+        }
+        killed.len()
+    }
+
+    /**
+     * Holds back the commands of mode for duration.
+     * While a pause is in effect, the longest one and the most restrictive mode win.
+     */
+    pub fn pause(&self, duration: Duration, mode: PauseMode) {
+        let until = Instant::now() + duration;
+        let mut pause = self.pause.lock().unwrap();
+        *pause = Some(match pause.take() {
+            Some(current) if current.until > Instant::now() => Pause {
+                until: current.until.max(until),
+                mode: match (current.mode, mode) {
+                    (PauseMode::Write, PauseMode::Write) => PauseMode::Write,
+                    _ => PauseMode::All,
+                },
+            },
+            _ => Pause { until, mode },
+        });
+    }
+
+    pub fn unpause(&self) {
+        self.pause.lock().unwrap().take();
+        self.unpaused.notify_waiters();
+    }
+
+    /**
+     * Waits until a command is no longer held back by CLIENT PAUSE.
+     */
+    pub async fn wait_unpaused(&self, spec: Option<&CommandSpec>) {
+        loop {
+            // Registered before checking so an UNPAUSE in between isn't missed
+            let unpaused = self.unpaused.notified();
+            let until = match &*self.pause.lock().unwrap() {
+                Some(pause) if pause.until > Instant::now() && is_paused(pause.mode, spec) => {
+                    pause.until
+                }
+                _ => return,
+            };
+            tokio::select! {
+                _ = unpaused => {}
+                _ = tokio::time::sleep_until(until) => {}
+            }
+        }
+    }
+}
+
+fn is_paused(mode: PauseMode, spec: Option<&CommandSpec>) -> bool {
+    match (mode, spec) {
+        (PauseMode::All, Some(spec)) => spec.name != "CLIENT",
+        (PauseMode::All, None) => true,
+        // Scripts, published messages and transactions may write too
+        (PauseMode::Write, Some(spec)) => {
+            spec.has(Category::Write)
+                || ["EVAL", "EVALSHA", "FCALL", "PUBLISH", "SPUBLISH", "EXEC"].contains(&spec.name)
+        }
+        (PauseMode::Write, None) => false,
+    }
+}
+
+/**
+ * The CLIENT side of a connection: registers it while it is connected and handles the CLIENT subcommands,
+ * client side caching ones being handled by its tracking.
+ */
+pub struct Client {
+    db: DB,
+    info: Arc<ClientInfo>,
+    tracking: Tracking,
+    // Set by CLIENT REPLY OFF
+    silent: bool,
+    // Replies left to skip after CLIENT REPLY SKIP, counting its own
+    skipped: u8,
+}
+
+impl Client {
+    pub fn new(db: DB, id: u64, addr: &str, laddr: &str) -> Client {
+        let info = db.db.clients.register(id, addr, laddr);
+        Client {
+            tracking: Tracking::new(db.clone(), id),
+            db,
+            info,
+            silent: false,
+            skipped: 0,
+        }
+    }
+
+    pub fn info(&self) -> &Arc<ClientInfo> {
+        &self.info
+    }
+
+    /**
+     * The tracking connection id the runner remembers the keys read by a command for, see Runner::with_tracking.
+     */
+    pub fn reader(&mut self, frame: &Frame) -> Option<u64> {
+        self.tracking.reader(frame)
+    }
+
+    /**
+     * Waits for the next invalidation of the keys the connection tracks without REDIRECT.
+     */
+    pub async fn invalidated(&mut self) -> Option<Frame> {
+        self.tracking.receive().await
+    }
+
+    /**
+     * An invalidation of the keys the connection tracks without REDIRECT that was already sent, if any.
+     */
+    pub fn try_invalidated(&mut self) -> Option<Frame> {
+        self.tracking.try_receive()
+    }
+
+    /**
+     * Whether the reply of the command that just ran should be written, as set by CLIENT REPLY.
+     */
+    pub fn should_reply(&mut self) -> bool {
+        if self.skipped > 0 {
+            self.skipped -= 1;
+            return false;
+        }
+        !self.silent
+    }
+
+    /**
+     * Handles the CLIENT subcommands of the connection.
+     * Returns None if the frame should be run right away.
+     */
+    pub fn run(&mut self, frame: &Frame) -> Option<Result<Frame, RunnerError>> {
+        let mut frames = match frame {
+            Frame::Array(frames) => frames.clone(),
+            _ => return None,
+        };
+        let is_client = matches!(
+            frames.pop_front(),
+            Some(Frame::SimpleString(command)) if command.eq_ignore_ascii_case("CLIENT")
+        );
+        if !is_client {
+            return None;
+        }
+
+        let subcommand = match next_string(&mut frames) {
+            Ok(subcommand) => subcommand,
+            Err(err) => return Some(Err(err)),
+        };
+        let reply = match subcommand.to_uppercase().as_str() {
+            "ID" => Ok(Frame::Integer(self.info.id as i64)),
+            "INFO" => Ok(Frame::BulkString(
+                format!("{}\n", self.info.line()).into_bytes(),
+            )),
+            "LIST" => self.run_list(&mut frames),
+            "KILL" => self.run_kill(&mut frames),
+            "SETNAME" => self.run_setname(&mut frames),
+            "GETNAME" => Ok(match self.info.state().name {
+                name if name.is_empty() => nil(),
+                name => Frame::SimpleString(name),
+            }),
+            "PAUSE" => self.run_pause(&mut frames),
+            "UNPAUSE" => {
+                self.db.db.clients.unpause();
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "NO-EVICT" => match next_string(&mut frames).map(|arg| arg.to_uppercase()) {
+                Ok(arg) if arg == "ON" || arg == "OFF" => {
+                    self.info.update(|state| state.no_evict = arg == "ON");
+                    Ok(Frame::SimpleString("OK".to_string()))
+                }
+                Ok(_) => Err(RunnerError::Other("ERR syntax error".to_string())),
+                Err(err) => Err(err),
+            },
+            "REPLY" => match next_string(&mut frames).map(|arg| arg.to_uppercase()) {
+                Ok(arg) if arg == "ON" => {
+                    (self.silent, self.skipped) = (false, 0);
+                    Ok(Frame::SimpleString("OK".to_string()))
+                }
+                Ok(arg) if arg == "OFF" => {
+                    (self.silent, self.skipped) = (true, 0);
+                    Ok(Frame::SimpleString("OK".to_string()))
+                }
+                Ok(arg) if arg == "SKIP" => {
+                    self.skipped = 2;
+                    Ok(Frame::SimpleString("OK".to_string()))
+                }
+                Ok(_) => Err(RunnerError::Other("ERR syntax error".to_string())),
+                Err(err) => Err(err),
+            },
+            _ => match self.tracking.run(&subcommand, &mut frames) {
+                Some(reply) => reply,
+                None => Err(RunnerError::Other(format!(
+                    "ERR unknown subcommand '{}'. Try CLIENT HELP.",
+                    subcommand.to_lowercase()
+                ))),
+            },
+        };
+        self.info
+            .update(|state| state.redirect = self.tracking.redirect());
+        Some(reply)
+    }
+
+    // CLIENT LIST [TYPE normal|pubsub] [ID id [id ...]], one line per client
+    fn run_list(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let mut args = rest_strings(frames)?.into_iter();
+        let mut pubsub = None;
+        let mut ids = vec![];
+        while let Some(arg) = args.next() {
+            match arg.to_uppercase().as_str() {
+                "TYPE" => pubsub = Some(parse_type(&args.next().ok_or(RunnerError::Incomplete)?)?),
+                "ID" => {
+                    for id in args.by_ref() {
+                        ids.push(parse_id(&id)?);
+                    }
+                    if ids.is_empty() {
+                        return Err(RunnerError::Incomplete);
+                    }
+                }
+                _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            }
+        }
+
+        let lines: Vec<String> = self
+            .db
+            .db
+            .clients
+            .list()
+            .into_iter()
+            .filter(|client| ids.is_empty() || ids.contains(&client.id))
+            .filter(|client| pubsub.is_none_or(|pubsub| pubsub == Some(client.is_pubsub())))
+            .map(|client| client.line())
+            .collect();
+        // One line per client, each ending with a newline like redis
+        let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        Ok(Frame::BulkString(text.into_bytes()))
+    }
+
+    // CLIENT KILL addr:port | [ID id] [ADDR addr:port] [LADDR addr:port] [USER username]
+    // [TYPE normal|pubsub] [SKIPME yes|no] [MAXAGE seconds]
+    fn run_kill(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let args = rest_strings(frames)?;
+        let clients = &self.db.db.clients;
+        if let [addr] = args.as_slice() {
+            return match clients.kill(|client| &client.addr == addr) {
+                0 => Err(RunnerError::Other("ERR No such client".to_string())),
+                _ => Ok(Frame::SimpleString("OK".to_string())),
+            };
+        }
+        if args.is_empty() || args.len() % 2 != 0 {
+            return Err(RunnerError::Other("ERR syntax error".to_string()));
+        }
+
+        let (mut id, mut addr, mut laddr, mut user, mut pubsub, mut maxage) =
+            (None, None, None, None, None, None);
+        let mut skipme = true;
+        for pair in args.chunks(2) {
+            let value = &pair[1];
+            match pair[0].to_uppercase().as_str() {
+                "ID" => id = Some(parse_id(value)?),
+                "ADDR" => addr = Some(value.clone()),
+                "LADDR" => laddr = Some(value.clone()),
+                "USER" => user = Some(value.clone()),
+                "TYPE" => pubsub = Some(parse_type(value)?),
+                "SKIPME" => {
+                    skipme = match value.to_lowercase().as_str() {
+                        "yes" => true,
+                        "no" => false,
+                        _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+                    }
+                }
+                "MAXAGE" => {
+                    maxage = Some(value.parse::<u64>().map_err(|_| {
+                        RunnerError::Other(
+                            "ERR value is not an integer or out of range".to_string(),
+                        )
+                    })?)
+                }
+                _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            }
+        }
+
+        let me = self.info.id;
+        let killed = clients.kill(|client| {
+            !(skipme && client.id == me)
+                && id.is_none_or(|id| client.id == id)
+                && addr.as_ref().is_none_or(|addr| &client.addr == addr)
+                && laddr.as_ref().is_none_or(|laddr| &client.laddr == laddr)
+                && user
+                    .as_ref()
+                    .is_none_or(|user| &client.state().user == user)
+                && pubsub.is_none_or(|pubsub| pubsub == Some(client.is_pubsub()))
+                && maxage.is_none_or(|maxage| client.created_at.elapsed().as_secs() >= maxage)
+        });
+        Ok(Frame::Integer(killed as i64))
+    }
+
+    fn run_setname(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let name = next_string(frames)?;
+        if !frames.is_empty() {
+            return Err(RunnerError::Other("ERR syntax error".to_string()));
+        }
+        if name.chars().any(|char| !('!'..='~').contains(&char)) {
+            return Err(RunnerError::Other(
+                "ERR Client names cannot contain spaces, newlines or special characters."
+                    .to_string(),
+            ));
+        }
+        self.info.update(|state| state.name = name);
+        Ok(Frame::SimpleString("OK".to_string()))
+    }
+
+    // CLIENT PAUSE timeout [WRITE|ALL], the timeout being in milliseconds
+    fn run_pause(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let timeout = next_string(frames)?.parse::<u64>().map_err(|_| {
+            RunnerError::Other("ERR timeout is not an integer or out of range".to_string())
+        })?;
+        let mode = match rest_strings(frames)?.as_slice() {
+            [] => PauseMode::All,
+            [mode] if mode.eq_ignore_ascii_case("ALL") => PauseMode::All,
+            [mode] if mode.eq_ignore_ascii_case("WRITE") => PauseMode::Write,
+            _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+        };
+        self.db
+            .db
+            .clients
+            .pause(Duration::from_millis(timeout), mode);
+        Ok(Frame::SimpleString("OK".to_string()))
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.db.db.clients.unregister(self.info.id);
+    }
+}
+
+fn parse_id(id: &str) -> Result<u64, RunnerError> {
+    id.parse::<u64>()
+        .map_err(|_| RunnerError::Other("ERR Invalid client ID".to_string()))
+}
+
+// Whether TYPE asks for pubsub clients, Some(None) being a type no client has e.g replica
+fn parse_type(kind: &str) -> Result<Option<bool>, RunnerError> {
+    match kind.to_lowercase().as_str() {
+        "normal" => Ok(Some(false)),
+        "pubsub" => Ok(Some(true)),
+        "master" | "replica" | "slave" => Ok(None),
+        _ => Err(RunnerError::Other(format!(
+            "ERR Unknown client type '{}'",
+            kind
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        command::spec,
+        frame::{command, text},
+    };
+
+    fn reply(client: &mut Client, args: &[&str]) -> Frame {
+        client.run(&command(args)).unwrap().unwrap()
+    }
+
+    fn client(db: &DB, id: u64) -> Client {
+        Client::new(
+            db.clone(),
+            id,
+            &format!("127.0.0.1:{}", 5000 + id),
+            "127.0.0.1:6379",
+        )
+    }
+
+    #[tokio::test]
+    async fn client_setname_info() {
+        let db = DB::new();
+        let mut worker = client(&db, 2);
+        assert!(worker.run(&command(&["GET", "key"])).is_none());
+        assert!(worker
+            .run(&command(&["CLIENT", "SETNAME", "bad name"]))
+            .unwrap()
+            .is_err());
+        reply(&mut worker, &["CLIENT", "SETNAME", "worker"]);
+        reply(&mut worker, &["CLIENT", "NO-EVICT", "ON"]);
+        assert_eq!(text(reply(&mut worker, &["CLIENT", "GETNAME"])), "worker");
+
+        let info = reply(&mut worker, &["CLIENT", "INFO"]);
+        assert!(matches!(info, Frame::BulkString(_)));
+        let info = text(info);
+        assert!(info.starts_with(
+            "id=2 addr=127.0.0.1:5002 laddr=127.0.0.1:6379 name=worker age=0 idle=0 flags=e db=0"
+        ));
+        assert!(info.ends_with('\n'));
+    }
+
+    #[tokio::test]
+    async fn client_list() {
+        let db = DB::new();
+        let mut admin = client(&db, 1);
+        let _worker = client(&db, 2);
+        let _other = client(&db, 3);
+
+        let list = reply(&mut admin, &["CLIENT", "LIST"]);
+        assert!(matches!(list, Frame::BulkString(_)));
+        let list = text(list);
+        assert_eq!(list.lines().count(), 3);
+        assert!(list.ends_with('\n'));
+        assert!(list.lines().next().unwrap().starts_with("id=1 "));
+        assert_eq!(
+            text(reply(&mut admin, &["CLIENT", "LIST", "ID", "3", "9"]))
+                .lines()
+                .count(),
+            1
+        );
+        assert_eq!(
+            text(reply(&mut admin, &["CLIENT", "LIST", "TYPE", "pubsub"])),
+            ""
+        );
+    }
+
+    #[tokio::test]
+    async fn client_reply_skip() {
+        let db = DB::new();
+        let mut admin = client(&db, 1);
+        // CLIENT REPLY SKIP silences its own reply and the next one
+        reply(&mut admin, &["CLIENT", "REPLY", "SKIP"]);
+        assert!(!admin.should_reply());
+        assert!(!admin.should_reply());
+        assert!(admin.should_reply());
+    }
+
+    #[tokio::test]
+    async fn client_kill() {
+        let db = DB::new();
+        let mut admin = client(&db, 1);
+        let worker = client(&db, 2);
+        let other = client(&db, 3);
+        other
+            .info()
+            .update(|state| state.user = "reporting".to_string());
+
+        // Killed clients are told to disconnect and leave the registry
+        assert_eq!(
+            reply(&mut admin, &["CLIENT", "KILL", "USER", "reporting"]),
+            Frame::Integer(1)
+        );
+        tokio::time::timeout(Duration::from_millis(100), other.info().killed())
+            .await
+            .unwrap();
+        // SKIPME is on by default
+        assert_eq!(
+            reply(&mut admin, &["CLIENT", "KILL", "ID", "1"]),
+            Frame::Integer(0)
+        );
+        assert!(admin
+            .run(&command(&["CLIENT", "KILL", "127.0.0.1:5003"]))
+            .unwrap()
+            .is_err());
+        reply(&mut admin, &["CLIENT", "KILL", "127.0.0.1:5002"]);
+        assert_eq!(db.db.clients.list().len(), 1);
+        drop(worker);
+        drop(other);
+    }
+
+    #[tokio::test]
+    async fn client_pause_write() {
+        let db = DB::new();
+        let mut admin = client(&db, 1);
+        // CLIENT PAUSE WRITE only holds back the commands that may write
+        reply(&mut admin, &["CLIENT", "PAUSE", "10000", "WRITE"]);
+        let clients = &db.db.clients;
+        tokio::time::timeout(
+            Duration::from_millis(100),
+            clients.wait_unpaused(spec("GET")),
+        )
+        .await
+        .unwrap();
+        assert!(tokio::time::timeout(
+            Duration::from_millis(100),
+            clients.wait_unpaused(spec("SET"))
+        )
+        .await
+        .is_err());
+        let paused = clients.wait_unpaused(spec("EVAL"));
+        reply(&mut admin, &["CLIENT", "UNPAUSE"]);
+        tokio::time::timeout(Duration::from_millis(100), paused)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+use crate::frame::Frame;
+
+/**
+ * The ACL categories a command belongs to, e.g +@read allows every command in @read.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Category {
+    Keyspace,
+    Read,
+    Write,
+    Set,
+    SortedSet,
+    List,
+    Hash,
+    String,
+    Bitmap,
+    HyperLogLog,
+    Geo,
+    Stream,
+    PubSub,
+    Admin,
+    Fast,
+    Slow,
+    Blocking,
+    Dangerous,
+    Connection,
+    Transaction,
+    Scripting,
+}
+
+// Every category by the name it is written with after @
+pub const CATEGORIES: [(&str, Category); 21] = [
+    ("keyspace", Category::Keyspace),
+    ("read", Category::Read),
+    ("write", Category::Write),
+    ("set", Category::Set),
+    ("sortedset", Category::SortedSet),
+    ("list", Category::List),
+    ("hash", Category::Hash),
+    ("string", Category::String),
+    ("bitmap", Category::Bitmap),
+    ("hyperloglog", Category::HyperLogLog),
+    ("geo", Category::Geo),
+    ("stream", Category::Stream),
+    ("pubsub", Category::PubSub),
+    ("admin", Category::Admin),
+    ("fast", Category::Fast),
+    ("slow", Category::Slow),
+    ("blocking", Category::Blocking),
+    ("dangerous", Category::Dangerous),
+    ("connection", Category::Connection),
+    ("transaction", Category::Transaction),
+    ("scripting", Category::Scripting),
+];
+
+impl Category {
+    pub fn parse(name: &str) -> Option<Category> {
+        CATEGORIES
+            .iter()
+            .find(|(category, _)| category.eq_ignore_ascii_case(name))
+            .map(|(_, category)| *category)
+    }
+}
+
+/**
+ * Where the keys of a command are among its arguments, the command name excluded.
+ */
+#[derive(Clone, Copy)]
+enum Keys {
+    // From first to last, negative lasts counting from the end (-1 being the last argument)
+    Range { first: usize, last: isize },
+    // A count of keys at an index followed by the keys, e.g EVAL script numkeys key...
+    Numkeys(usize),
+    // The first half of the arguments after STREAMS, ids being the second half
+    Streams,
+}
+
+/**
+ * Which arguments of a command are pub/sub channels.
+ */
+#[derive(Clone, Copy, PartialEq)]
+enum Channels {
+    None,
+    First,
+    All,
+    // Glob patterns of channels, e.g PSUBSCRIBE
+    Patterns,
+}
+
+/**
+ * What is known about a command: its ACL categories and where its keys and channels are.
+ */
+pub struct CommandSpec {
+    pub name: &'static str,
+    // How many arguments it takes counting its name like redis does, negative for at least that many
+    arity: isize,
+    pub categories: &'static [Category],
+    keys: &'static [Keys],
+    channels: Channels,
+}
+
+const NONE: &[Keys] = &[];
+const FIRST: &[Keys] = &[Keys::Range { first: 0, last: 0 }];
+const SECOND: &[Keys] = &[Keys::Range { first: 1, last: 1 }];
+const FIRST_TWO: &[Keys] = &[Keys::Range { first: 0, last: 1 }];
+const ALL: &[Keys] = &[Keys::Range { first: 0, last: -1 }];
+const ALL_BUT_FIRST: &[Keys] = &[Keys::Range { first: 1, last: -1 }];
+const ALL_BUT_LAST: &[Keys] = &[Keys::Range { first: 0, last: -2 }];
+const NUMKEYS: &[Keys] = &[Keys::Numkeys(0)];
+const STORE_NUMKEYS: &[Keys] = &[Keys::Range { first: 0, last: 0 }, Keys::Numkeys(1)];
+const SCRIPT_NUMKEYS: &[Keys] = &[Keys::Numkeys(1)];
+const STREAMS: &[Keys] = &[Keys::Streams];
+
+const fn command(
+    name: &'static str,
+    arity: isize,
+    categories: &'static [Category],
+    keys: &'static [Keys],
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        categories,
+        keys,
+        channels: Channels::None,
+    }
+}
+
+const fn pubsub(
+    name: &'static str,
+    arity: isize,
+    categories: &'static [Category],
+    channels: Channels,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        categories,
+        keys: NONE,
+        channels,
+    }
+}
+
+static COMMANDS: &[CommandSpec] = {
+    use Category::*;
+    &[
+        command("PING", -1, &[Fast, Connection], NONE),
+        command("QUIT", -1, &[Fast, Connection], NONE),
+        command("AUTH", -2, &[Fast, Connection], NONE),
+        command("CLIENT", -2, &[Slow, Connection], NONE),
+        command("ACL", -2, &[Admin, Slow, Dangerous], NONE),
+        command("CONFIG", -2, &[Admin, Slow, Dangerous], NONE),
+        command("SHUTDOWN", -1, &[Admin, Slow, Dangerous], NONE),
+        command("INFO", -1, &[Slow, Dangerous], NONE),
+        command("SLOWLOG", -2, &[Admin, Slow, Dangerous], NONE),
+        command("LATENCY", -2, &[Admin, Slow, Dangerous], NONE),
+        command("MONITOR", 1, &[Admin, Slow, Dangerous], NONE),
+        command("SET", -3, &[Write, String, Slow], FIRST),
+        command("GET", 2, &[Read, String, Fast], FIRST),
+        command("SETBIT", 4, &[Write, Bitmap, Slow], FIRST),
+        command("GETBIT", 3, &[Read, Bitmap, Fast], FIRST),
+        command("BITCOUNT", -2, &[Read, Bitmap, Slow], FIRST),
+        command("BITPOS", -3, &[Read, Bitmap, Slow], FIRST),
+        command("BITOP", -4, &[Write, Bitmap, Slow], ALL_BUT_FIRST),
+        command("BITFIELD", -2, &[Write, Bitmap, Slow], FIRST),
+        command("BITFIELD_RO", -2, &[Read, Bitmap, Fast], FIRST),
+        command("GEOADD", -5, &[Write, Geo, Slow], FIRST),
+        command("GEODIST", -4, &[Read, Geo, Slow], FIRST),
+        command("GEOPOS", -2, &[Read, Geo, Slow], FIRST),
+        command("GEOHASH", -2, &[Read, Geo, Slow], FIRST),
+        command("GEOSEARCH", -7, &[Read, Geo, Slow], FIRST),
+        command("GEOSEARCHSTORE", -8, &[Write, Geo, Slow], FIRST_TWO),
+        pubsub("PUBLISH", 3, &[PubSub, Fast], Channels::First),
+        pubsub("SPUBLISH", 3, &[PubSub, Fast], Channels::First),
+        pubsub("PUBSUB", -2, &[PubSub, Slow], Channels::None),
+        pubsub("SUBSCRIBE", -2, &[PubSub, Slow], Channels::All),
+        pubsub("SSUBSCRIBE", -2, &[PubSub, Slow], Channels::All),
+        pubsub("PSUBSCRIBE", -2, &[PubSub, Slow], Channels::Patterns),
+        pubsub("UNSUBSCRIBE", -1, &[PubSub, Slow], Channels::None),
+        pubsub("SUNSUBSCRIBE", -1, &[PubSub, Slow], Channels::None),
+        pubsub("PUNSUBSCRIBE", -1, &[PubSub, Slow], Channels::None),
+        command("PFADD", -2, &[Write, HyperLogLog, Fast], FIRST),
+        command("PFCOUNT", -2, &[Read, HyperLogLog, Slow], ALL),
+        command("PFMERGE", -2, &[Write, HyperLogLog, Slow], ALL),
+        command("HSET", -4, &[Write, Hash, Fast], FIRST),
+        command("HMSET", -4, &[Write, Hash, Fast], FIRST),
+        command("HSETNX", 4, &[Write, Hash, Fast], FIRST),
+        command("HGET", 3, &[Read, Hash, Fast], FIRST),
+        command("HMGET", -3, &[Read, Hash, Fast], FIRST),
+        command("HDEL", -3, &[Write, Hash, Fast], FIRST),
+        command("HGETALL", 2, &[Read, Hash, Slow], FIRST),
+        command("HKEYS", 2, &[Read, Hash, Slow], FIRST),
+        command("HVALS", 2, &[Read, Hash, Slow], FIRST),
+        command("HLEN", 2, &[Read, Hash, Fast], FIRST),
+        command("HEXISTS", 3, &[Read, Hash, Fast], FIRST),
+        command("HSTRLEN", 3, &[Read, Hash, Fast], FIRST),
+        command("HINCRBY", 4, &[Write, Hash, Fast], FIRST),
+        command("HINCRBYFLOAT", 4, &[Write, Hash, Fast], FIRST),
+        command("HSCAN", -3, &[Read, Hash, Slow], FIRST),
+        command("HRANDFIELD", -2, &[Read, Hash, Slow], FIRST),
+        command("HEXPIRE", -6, &[Write, Hash, Fast], FIRST),
+        command("HPEXPIRE", -6, &[Write, Hash, Fast], FIRST),
+        command("HEXPIREAT", -6, &[Write, Hash, Fast], FIRST),
+        command("HPEXPIREAT", -6, &[Write, Hash, Fast], FIRST),
+        command("HTTL", -5, &[Read, Hash, Fast], FIRST),
+        command("HPTTL", -5, &[Read, Hash, Fast], FIRST),
+        command("HEXPIRETIME", -5, &[Read, Hash, Fast], FIRST),
+        command("HPEXPIRETIME", -5, &[Read, Hash, Fast], FIRST),
+        command("HPERSIST", -5, &[Write, Hash, Fast], FIRST),
+        command("SADD", -3, &[Write, Set, Fast], FIRST),
+        command("SREM", -3, &[Write, Set, Fast], FIRST),
+        command("SMEMBERS", 2, &[Read, Set, Slow], FIRST),
+        command("SISMEMBER", 3, &[Read, Set, Fast], FIRST),
+        command("SMISMEMBER", -3, &[Read, Set, Fast], FIRST),
+        command("SCARD", 2, &[Read, Set, Fast], FIRST),
+        command("SMOVE", 4, &[Write, Set, Fast], FIRST_TWO),
+        command("SINTER", -2, &[Read, Set, Slow], ALL),
+        command("SUNION", -2, &[Read, Set, Slow], ALL),
+        command("SDIFF", -2, &[Read, Set, Slow], ALL),
+        command("SINTERSTORE", -3, &[Write, Set, Slow], ALL),
+        command("SUNIONSTORE", -3, &[Write, Set, Slow], ALL),
+        command("SDIFFSTORE", -3, &[Write, Set, Slow], ALL),
+        command("SINTERCARD", -3, &[Read, Set, Slow], NUMKEYS),
+        command("SPOP", -2, &[Write, Set, Fast], FIRST),
+        command("SRANDMEMBER", -2, &[Read, Set, Slow], FIRST),
+        command("SSCAN", -3, &[Read, Set, Slow], FIRST),
+        command("ZADD", -4, &[Write, SortedSet, Fast], FIRST),
+        command("ZINCRBY", 4, &[Write, SortedSet, Fast], FIRST),
+        command("ZREM", -3, &[Write, SortedSet, Fast], FIRST),
+        command("ZCARD", 2, &[Read, SortedSet, Fast], FIRST),
+        command("ZSCORE", 3, &[Read, SortedSet, Fast], FIRST),
+        command("ZMSCORE", -3, &[Read, SortedSet, Fast], FIRST),
+        command("ZRANK", -3, &[Read, SortedSet, Fast], FIRST),
+        command("ZREVRANK", -3, &[Read, SortedSet, Fast], FIRST),
+        command("ZCOUNT", 4, &[Read, SortedSet, Fast], FIRST),
+        command("ZRANGE", -4, &[Read, SortedSet, Slow], FIRST),
+        command("ZREVRANGE", -4, &[Read, SortedSet, Slow], FIRST),
+        command("ZRANGEBYSCORE", -4, &[Read, SortedSet, Slow], FIRST),
+        command("ZREVRANGEBYSCORE", -4, &[Read, SortedSet, Slow], FIRST),
+        command("ZRANGEBYLEX", -4, &[Read, SortedSet, Slow], FIRST),
+        command("ZREVRANGEBYLEX", -4, &[Read, SortedSet, Slow], FIRST),
+        command("ZPOPMIN", -2, &[Write, SortedSet, Fast], FIRST),
+        command("ZPOPMAX", -2, &[Write, SortedSet, Fast], FIRST),
+        command(
+            "BZPOPMIN",
+            -3,
+            &[Write, SortedSet, Fast, Blocking],
+            ALL_BUT_LAST,
+        ),
+        command(
+            "BZPOPMAX",
+            -3,
+            &[Write, SortedSet, Fast, Blocking],
+            ALL_BUT_LAST,
+        ),
+        command("ZUNION", -3, &[Read, SortedSet, Slow], NUMKEYS),
+        command("ZINTER", -3, &[Read, SortedSet, Slow], NUMKEYS),
+        command("ZUNIONSTORE", -4, &[Write, SortedSet, Slow], STORE_NUMKEYS),
+        command("ZINTERSTORE", -4, &[Write, SortedSet, Slow], STORE_NUMKEYS),
+        command("ZSCAN", -3, &[Read, SortedSet, Slow], FIRST),
+        command("XADD", -5, &[Write, Stream, Fast], FIRST),
+        command("XTRIM", -4, &[Write, Stream, Slow], FIRST),
+        command("XLEN", 2, &[Read, Stream, Fast], FIRST),
+        command("XDEL", -3, &[Write, Stream, Fast], FIRST),
+        command("XRANGE", -4, &[Read, Stream, Slow], FIRST),
+        command("XREVRANGE", -4, &[Read, Stream, Slow], FIRST),
+        command("XREAD", -4, &[Read, Stream, Slow, Blocking], STREAMS),
+        command("XGROUP", -2, &[Write, Stream, Slow], SECOND),
+        command("XREADGROUP", -7, &[Write, Stream, Slow, Blocking], STREAMS),
+        command("XACK", -4, &[Write, Stream, Fast], FIRST),
+        command("XPENDING", -3, &[Read, Stream, Slow], FIRST),
+        command("XCLAIM", -6, &[Write, Stream, Fast], FIRST),
+        command("XAUTOCLAIM", -6, &[Write, Stream, Fast], FIRST),
+        command("XINFO", -2, &[Read, Stream, Slow], SECOND),
+        command("EVAL", -3, &[Slow, Scripting], SCRIPT_NUMKEYS),
+        command("EVALSHA", -3, &[Slow, Scripting], SCRIPT_NUMKEYS),
+        command("SCRIPT", -2, &[Slow, Scripting], NONE),
+        command("FCALL", -3, &[Slow, Scripting], SCRIPT_NUMKEYS),
+        command("FCALL_RO", -3, &[Slow, Scripting], SCRIPT_NUMKEYS),
+        command("FUNCTION", -2, &[Slow, Scripting], NONE),
+        command("MULTI", 1, &[Fast, Transaction], NONE),
+        command("EXEC", 1, &[Slow, Transaction], NONE),
+        command("DISCARD", 1, &[Fast, Transaction], NONE),
+        command("WATCH", -2, &[Fast, Transaction], ALL),
+        command("UNWATCH", 1, &[Fast, Transaction], NONE),
+    ]
+};
+
+/**
+ * Every command, in the order they are listed by ACL CAT.
+ */
+pub fn commands() -> &'static [CommandSpec] {
+    COMMANDS
+}
+
+/**
+ * The spec of a command by its name in any case, None if it is unknown.
+ */
+pub fn spec(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS
+        .iter()
+        .find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+impl CommandSpec {
+    pub fn has(&self, category: Category) -> bool {
+        self.categories.contains(&category)
+    }
+
+    /**
+     * Whether the command takes that many arguments, its name excluded.
+     */
+    pub fn accepts(&self, args: usize) -> bool {
+        let count = args as isize + 1;
+        match self.arity {
+            arity if arity < 0 => count >= -arity,
+            arity => count == arity,
+        }
+    }
+
+    /**
+     * The keys among the arguments of the command.
+     */
+    pub fn keys(&self, args: &[String]) -> Vec<String> {
+        let mut keys = vec![];
+        for spec in self.keys {
+            let (first, last) = match *spec {
+                Keys::Range { first, last } => {
+                    let last = match last {
+                        last if last < 0 => args.len() as isize + last,
+                        last => last,
+                    };
+                    (first, last + 1)
+                }
+                Keys::Numkeys(index) => {
+                    let numkeys = args
+                        .get(index)
+                        .and_then(|numkeys| numkeys.parse::<usize>().ok())
+                        .unwrap_or(0);
+                    (index + 1, (index + 1 + numkeys) as isize)
+                }
+                Keys::Streams => {
+                    let streams = args
+                        .iter()
+                        .position(|arg| arg.eq_ignore_ascii_case("STREAMS"))
+                        .map_or(args.len(), |position| position + 1);
+                    (streams, (streams + (args.len() - streams) / 2) as isize)
+                }
+            };
+            let last = (last.max(0) as usize).min(args.len());
+            if first < last {
+                keys.extend_from_slice(&args[first..last]);
+            }
+        }
+        keys
+    }
+
+    /**
+     * The channels among the arguments of the command, with whether they are patterns.
+     */
+    pub fn channels(&self, args: &[String]) -> (Vec<String>, bool) {
+        match self.channels {
+            Channels::None => (vec![], false),
+            Channels::First => (args.iter().take(1).cloned().collect(), false),
+            Channels::All => (args.to_vec(), false),
+            Channels::Patterns => (args.to_vec(), true),
+        }
+    }
+}
+
+/**
+ * The name and arguments of a command frame, integers turned into strings.
+ */
+pub fn arguments(frame: &Frame) -> Option<(String, Vec<String>)> {
+    let mut args = match frame {
+        Frame::Array(frames) => frames
+            .iter()
+            .filter_map(|frame| match frame {
+                Frame::SimpleString(arg) => Some(arg.clone()),
+                Frame::Integer(arg) => Some(arg.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<String>>()
+            .into_iter(),
+        Frame::SimpleString(command) => vec![command.clone()].into_iter(),
+        _ => return None,
+    };
+    let command = args.next()?;
+    Some((command, args.collect()))
+}
@@ -0,0 +1,658 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use tokio::sync::Notify;
+
+use crate::{
+    db::{DBError, KeyspaceEvents, DB},
+    glob,
+    runner::RunnerError,
+};
+
+/**
+ * The longest flush interval tokio can sleep for, in seconds.
+ */
+pub const MAX_FLUSH_EVERY: u64 = 68719476734;
+
+/**
+ * The settings of the server.
+ * They are read from the env vars (and .env), then a redis.conf style file, then command line overrides.
+ */
+#[derive(Clone, PartialEq)]
+pub struct Settings {
+    // Address the tcp listener binds to
+    pub addr: String,
+    // Directory the RDB file is written to
+    pub dir: String,
+    // Name of the RDB file
+    pub dbfilename: String,
+    // Seconds between flushes of the db into the RDB file
+    pub flush_every: u64,
+    pub notify_keyspace_events: String,
+    // Password of the default user, empty letting every connection in
+    pub requirepass: String,
+    // Refuse non loopback clients while no password is set
+    pub protected_mode: bool,
+    // File the ACL users are loaded from and saved to, empty if there is none
+    pub aclfile: String,
+    // Address the Prometheus metrics listener binds to, empty if there is none
+    pub metrics_addr: String,
+    // Microseconds a command has to run for to be in the slow log, negative disabling it
+    pub slowlog_log_slower_than: i64,
+    // Entries the slow log keeps
+    pub slowlog_max_len: u64,
+    // Milliseconds an event has to take to be recorded by the latency monitor, 0 disabling it
+    pub latency_monitor_threshold: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            addr: "127.0.0.1:6379".to_string(),
+            dir: ".".to_string(),
+            dbfilename: "rdb.rdb".to_string(),
+            flush_every: 60,
+            notify_keyspace_events: String::new(),
+            requirepass: String::new(),
+            protected_mode: true,
+            aclfile: String::new(),
+            metrics_addr: String::new(),
+            slowlog_log_slower_than: 10000,
+            slowlog_max_len: 128,
+            latency_monitor_threshold: 0,
+        }
+    }
+}
+
+impl Settings {
+    /**
+     * Where the RDB file is, dir joined with dbfilename.
+     */
+    pub fn rdb_path(&self) -> PathBuf {
+        Path::new(&self.dir).join(&self.dbfilename)
+    }
+}
+
+/**
+ * A setting as CONFIG GET and CONFIG SET know it.
+ * Immutable ones can only be set on startup.
+ */
+struct Param {
+    name: &'static str,
+    mutable: bool,
+    get: fn(&Settings) -> String,
+    set: fn(&mut Settings, &str) -> Result<(), String>,
+}
+
+static PARAMS: &[Param] = &[
+    Param {
+        name: "addr",
+        mutable: false,
+        get: |settings| settings.addr.clone(),
+        set: |settings, value| {
+            settings.addr = value.to_string();
+            Ok(())
+        },
+    },
+    Param {
+        name: "dir",
+        mutable: true,
+        get: |settings| settings.dir.clone(),
+        set: |settings, value| match Path::new(value).is_dir() {
+            true => {
+                settings.dir = value.to_string();
+                Ok(())
+            }
+            false => Err("No such file or directory".to_string()),
+        },
+    },
+    Param {
+        name: "dbfilename",
+        mutable: true,
+        get: |settings| settings.dbfilename.clone(),
+        set: |settings, value| match value.is_empty() || value.contains('/') {
+            true => Err("dbfilename can't be a path, just a filename".to_string()),
+            false => {
+                settings.dbfilename = value.to_string();
+                Ok(())
+            }
+        },
+    },
+    Param {
+        name: "flush-every",
+        mutable: true,
+        get: |settings| settings.flush_every.to_string(),
+        set: |settings, value| match value.parse::<u64>() {
+            Ok(seconds) if (1..=MAX_FLUSH_EVERY).contains(&seconds) => {
+                settings.flush_every = seconds;
+                Ok(())
+            }
+            Ok(_) => Err(format!(
+                "argument must be between 1 and {} inclusive",
+                MAX_FLUSH_EVERY
+            )),
+            Err(_) => Err("argument couldn't be parsed into an integer".to_string()),
+        },
+    },
+    Param {
+        name: "notify-keyspace-events",
+        mutable: true,
+        get: |settings| settings.notify_keyspace_events.clone(),
+        set: |settings, value| match KeyspaceEvents::parse(value) {
+            Ok(_) => {
+                settings.notify_keyspace_events = value.to_string();
+                Ok(())
+            }
+            Err(DBError::Other(err)) => Err(err.trim_start_matches("ERR ").to_string()),
+            Err(DBError::WrongType) => unreachable!(),
+        },
+    },
+    Param {
+        name: "requirepass",
+        mutable: true,
+        get: |settings| settings.requirepass.clone(),
+        set: |settings, value| {
+            settings.requirepass = value.to_string();
+            Ok(())
+        },
+    },
+    Param {
+        name: "protected-mode",
+        mutable: true,
+        get: |settings| yes_no(settings.protected_mode),
+        set: |settings, value| {
+            settings.protected_mode = parse_yes_no(value)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "aclfile",
+        mutable: false,
+        get: |settings| settings.aclfile.clone(),
+        set: |settings, value| {
+            settings.aclfile = value.to_string();
+            Ok(())
+        },
+    },
+    Param {
+        name: "metrics-addr",
+        mutable: false,
+        get: |settings| settings.metrics_addr.clone(),
+        set: |settings, value| {
+            settings.metrics_addr = value.to_string();
+            Ok(())
+        },
+    },
+    Param {
+        name: "slowlog-log-slower-than",
+        mutable: true,
+        get: |settings| settings.slowlog_log_slower_than.to_string(),
+        set: |settings, value| match value.parse::<i64>() {
+            Ok(micros) => {
+                settings.slowlog_log_slower_than = micros;
+                Ok(())
+            }
+            Err(_) => Err("argument couldn't be parsed into an integer".to_string()),
+        },
+    },
+    Param {
+        name: "slowlog-max-len",
+        mutable: true,
+        get: |settings| settings.slowlog_max_len.to_string(),
+        set: |settings, value| match value.parse::<u64>() {
+            Ok(len) => {
+                settings.slowlog_max_len = len;
+                Ok(())
+            }
+            Err(_) => Err("argument couldn't be parsed into an integer".to_string()),
+        },
+    },
+    Param {
+        name: "latency-monitor-threshold",
+        mutable: true,
+        get: |settings| settings.latency_monitor_threshold.to_string(),
+        set: |settings, value| match value.parse::<u64>() {
+            Ok(millis) => {
+                settings.latency_monitor_threshold = millis;
+                Ok(())
+            }
+            Err(_) => Err("argument couldn't be parsed into an integer".to_string()),
+        },
+    },
+];
+
+// The env vars of .env.example by the setting they set, RDB_URL setting both dir and dbfilename
+const ENV_VARS: [(&str, &str); 10] = [
+    ("ADDR", "addr"),
+    ("FLUSH_EVERY", "flush-every"),
+    ("NOTIFY_KEYSPACE_EVENTS", "notify-keyspace-events"),
+    ("REQUIREPASS", "requirepass"),
+    ("PROTECTED_MODE", "protected-mode"),
+    ("ACLFILE", "aclfile"),
+    ("METRICS_ADDR", "metrics-addr"),
+    ("SLOWLOG_LOG_SLOWER_THAN", "slowlog-log-slower-than"),
+    ("SLOWLOG_MAX_LEN", "slowlog-max-len"),
+    ("LATENCY_MONITOR_THRESHOLD", "latency-monitor-threshold"),
+];
+
+fn param(name: &str) -> Option<&'static Param> {
+    PARAMS
+        .iter()
+        .find(|param| param.name.eq_ignore_ascii_case(name))
+}
+
+fn yes_no(value: bool) -> String {
+    match value {
+        true => "yes".to_string(),
+        false => "no".to_string(),
+    }
+}
+
+fn parse_yes_no(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+/**
+ * The settings of a running server, shared by every connection in db::Shared.
+ * Changed by CONFIG SET and written back to the config file the server started with by CONFIG REWRITE.
+ */
+pub struct Config {
+    settings: Mutex<Settings>,
+    // The config file the server was started with
+    file: Mutex<Option<String>>,
+    // Wakes up the tasks that depend on the settings whenever they change
+    pub changed: Notify,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            settings: Mutex::new(Settings::default()),
+            file: Mutex::new(None),
+            changed: Notify::new(),
+        }
+    }
+}
+
+impl Config {
+    /**
+     * The settings of the env vars the server starts with, invalid ones are logged and left to their defaults.
+     */
+    pub fn from_env() -> Config {
+        let mut settings = Settings::default();
+        if let Ok(url) = std::env::var("RDB_URL") {
+            let path = Path::new(&url);
+            if let Some(name) = path.file_name() {
+                settings.dbfilename = name.to_string_lossy().to_string();
+            }
+            settings.dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_string_lossy().to_string(),
+                _ => ".".to_string(),
+            };
+        }
+        for (var, name) in ENV_VARS {
+            if let Ok(value) = std::env::var(var) {
+                if let Err(err) = (param(name).unwrap().set)(&mut settings, &value) {
+                    tracing::error!("CONFIG: Invalid {} env var provided: {}", var, err);
+                }
+            }
+        }
+
+        Config {
+            settings: Mutex::new(settings),
+            ..Config::default()
+        }
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    pub fn file(&self) -> Option<String> {
+        self.file.lock().unwrap().clone()
+    }
+
+    /**
+     * Applies the settings of a config file, if any, then the command line overrides.
+     * Nothing changes if one of them is invalid.
+     */
+    pub fn load(&self, file: Option<&str>, overrides: &[(String, String)]) -> Result<(), String> {
+        let mut settings = self.settings();
+        if let Some(file) = file {
+            let content = std::fs::read_to_string(file).map_err(|err| {
+                format!("Fatal error, can't open config file '{}': {}", file, err)
+            })?;
+            for (number, line) in content.lines().enumerate() {
+                let words = split_line(line);
+                let (name, value) = match words.split_first() {
+                    None => continue,
+                    Some((name, value)) => (name, value.join(" ")),
+                };
+                set(&mut settings, name, &value).map_err(|err| {
+                    format!(
+                        "Bad config file '{}' at line {}: '{}': {}",
+                        file,
+                        number + 1,
+                        line.trim(),
+                        err
+                    )
+                })?;
+            }
+        }
+        for (name, value) in overrides {
+            set(&mut settings, name, value).map_err(|err| {
+                format!("Bad command line option '--{} {}': {}", name, value, err)
+            })?;
+        }
+
+        *self.settings.lock().unwrap() = settings;
+        *self.file.lock().unwrap() = file.map(str::to_string);
+        Ok(())
+    }
+
+    /**
+     * The settings whose names match any of the glob patterns, as (name, value) pairs.
+     */
+    pub fn get(&self, patterns: &[String]) -> Vec<(&'static str, String)> {
+        let settings = self.settings();
+        PARAMS
+            .iter()
+            .filter(|param| {
+                patterns.iter().any(|pattern| {
+                    glob::matches(pattern.to_lowercase().as_bytes(), param.name.as_bytes())
+                })
+            })
+            .map(|param| (param.name, (param.get)(&settings)))
+            .collect()
+    }
+
+    /**
+     * Changes settings at runtime, returning the names of the ones that changed.
+     * Either every pair is applied or none is.
+     */
+    pub fn set(&self, pairs: &[(String, String)]) -> Result<Vec<&'static str>, RunnerError> {
+        let mut settings = self.settings();
+        let mut names: Vec<&'static str> = vec![];
+        for (name, value) in pairs {
+            let param = param(name).ok_or_else(|| {
+                RunnerError::Other(format!(
+                    "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                    name
+                ))
+            })?;
+            let failed = |reason: &str| {
+                RunnerError::Other(format!(
+                    "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                    param.name, reason
+                ))
+            };
+            if names.contains(&param.name) {
+                return Err(failed("duplicate parameter"));
+            }
+            if !param.mutable {
+                return Err(failed("can't set immutable config"));
+            }
+            (param.set)(&mut settings, value).map_err(|err| failed(&err))?;
+            names.push(param.name);
+        }
+
+        let mut current = self.settings.lock().unwrap();
+        let old = current.clone();
+        *current = settings;
+        names.retain(|name| {
+            let param = param(name).unwrap();
+            (param.get)(&old) != (param.get)(&current)
+        });
+        drop(current);
+        self.changed.notify_waiters();
+        Ok(names)
+    }
+
+    /**
+     * Writes the current settings to the config file.
+     * Lines of settings are updated in place, comments and other lines kept,
+     * and settings that aren't in the file yet are appended if they differ from their defaults.
+     */
+    pub fn rewrite(&self) -> Result<(), RunnerError> {
+        let file = self.file().ok_or_else(|| {
+            RunnerError::Other("ERR The server is running without a config file".to_string())
+        })?;
+        let error =
+            |err: std::io::Error| RunnerError::Other(format!("ERR Rewriting config file: {}", err));
+        let content = match std::fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(error(err)),
+        };
+
+        let settings = self.settings();
+        let defaults = Settings::default();
+        let mut written: Vec<&'static str> = vec![];
+        let mut lines: Vec<String> = vec![];
+        for line in content.lines() {
+            let param = split_line(line).first().and_then(|name| param(name));
+            match param {
+                // Later lines of a setting already written are dropped
+                Some(param) if written.contains(&param.name) => {}
+                Some(param) => {
+                    lines.push(format!("{} {}", param.name, quote(&(param.get)(&settings))));
+                    written.push(param.name);
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+        for param in PARAMS {
+            let value = (param.get)(&settings);
+            if !written.contains(&param.name) && value != (param.get)(&defaults) {
+                lines.push(format!("{} {}", param.name, quote(&value)));
+            }
+        }
+
+        let content: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        std::fs::write(&file, content).map_err(error)
+    }
+}
+
+fn set(settings: &mut Settings, name: &str, value: &str) -> Result<(), String> {
+    let param =
+        param(name).ok_or_else(|| "Bad directive or wrong number of arguments".to_string())?;
+    (param.set)(settings, value)
+}
+
+// Splits a config file line into words, double quotes grouping words and comments skipped
+fn split_line(line: &str) -> Vec<String> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return vec![];
+    }
+    let mut words = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        if c == '"' {
+            chars.next();
+            for c in chars.by_ref() {
+                if c == '"' {
+                    break;
+                }
+                word.push(c);
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+        }
+        words.push(word);
+    }
+    words
+}
+
+// Quotes values that are empty or have spaces so they are read back as one word
+fn quote(value: &str) -> String {
+    match value.is_empty() || value.contains(char::is_whitespace) {
+        true => format!("\"{}\"", value),
+        false => value.to_string(),
+    }
+}
+
+/**
+ * The command line of the server.
+ */
+pub struct Args {
+    pub file: Option<String>,
+    // Settings as (name, value) applied over the ones of the file
+    pub overrides: Vec<(String, String)>,
+}
+
+/**
+ * Reads the command line of the server: an optional config file followed by --name value overrides,
+ * a value running until the next --name.
+ */
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut args = args.into_iter().peekable();
+    let file = match args.peek() {
+        Some(arg) if !arg.starts_with("--") => args.next(),
+        _ => None,
+    };
+
+    let mut overrides: Vec<(String, Vec<String>)> = vec![];
+    for arg in args {
+        match (arg.strip_prefix("--"), overrides.last_mut()) {
+            (Some(name), _) => overrides.push((name.to_string(), vec![])),
+            (None, Some((_, values))) => values.push(arg),
+            (None, None) => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+    }
+    Ok(Args {
+        file,
+        overrides: overrides
+            .into_iter()
+            .map(|(name, values)| (name, values.join(" ")))
+            .collect(),
+    })
+}
+
+impl DB {
+    /**
+     * Applies settings to the parts of the server they configure, e.g requirepass to the default user.
+     * Settings read whenever they are used, like dir, need nothing.
+     */
+    pub fn apply_config(&self, names: &[&str]) {
+        let settings = self.db.config.settings();
+        for name in names {
+            match *name {
+                "requirepass" => self
+                    .db
+                    .acl
+                    .set_requirepass(Some(settings.requirepass.clone())),
+                "notify-keyspace-events" => {
+                    let events =
+                        KeyspaceEvents::parse(&settings.notify_keyspace_events).unwrap_or_default();
+                    self.set_keyspace_events(events);
+                }
+                "slowlog-log-slower-than" => self
+                    .db
+                    .slowlog
+                    .set_threshold(settings.slowlog_log_slower_than),
+                "slowlog-max-len" => self.db.slowlog.set_max_len(settings.slowlog_max_len),
+                "latency-monitor-threshold" => self
+                    .db
+                    .latency
+                    .set_threshold(settings.latency_monitor_threshold),
+                _ => {}
+            }
+        }
+    }
+}
+
+/**
+ * The names of every setting.
+ */
+pub fn names() -> Vec<&'static str> {
+    PARAMS.iter().map(|param| param.name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn config_file() {
+        let file = std::env::temp_dir().join(format!("mini-redis-{}.conf", std::process::id()));
+        let file = file.to_str().unwrap().to_string();
+        std::fs::write(
+            &file,
+            "# Test config\nflush-every 30\nrequirepass \"top secret\"\n",
+        )
+        .unwrap();
+
+        let db = DB::new();
+        let config = &db.db.config;
+        assert!(config.load(Some(&file), &[]).is_ok());
+        let overrides = [("dbfilename".to_string(), "test.rdb".to_string())];
+        assert!(config.load(Some(&file), &overrides).is_ok());
+        assert!(config
+            .load(Some(&file), &[("maxmemory".to_string(), "1gb".to_string())])
+            .is_err());
+        assert_eq!(config.settings().flush_every, 30);
+        assert_eq!(
+            config.settings().rdb_path(),
+            std::path::Path::new("./test.rdb")
+        );
+        assert_eq!(
+            config.get(&["*pass".to_string(), "FLUSH-*".to_string()]),
+            vec![
+                ("flush-every", "30".to_string()),
+                ("requirepass", "top secret".to_string())
+            ]
+        );
+
+        let pairs = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<(String, String)>>()
+        };
+        assert!(config.set(&pairs(&[("addr", "0.0.0.0:1")])).is_err());
+        // Nothing changes if one of the settings is invalid
+        assert!(config
+            .set(&pairs(&[
+                ("flush-every", "10"),
+                ("protected-mode", "maybe")
+            ]))
+            .is_err());
+        assert_eq!(config.settings().flush_every, 30);
+        let changed = config
+            .set(&pairs(&[
+                ("flush-every", "10"),
+                ("requirepass", "pw"),
+                ("dir", "."),
+            ]))
+            .unwrap();
+        assert_eq!(changed, vec!["flush-every", "requirepass"]);
+        db.apply_config(&changed);
+        assert!(db.db.acl.authenticate("default", "pw"));
+        assert!(!db.db.acl.authenticate("default", "top secret"));
+
+        config.rewrite().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&file).unwrap(),
+            "# Test config\nflush-every 10\nrequirepass pw\ndbfilename test.rdb\n"
+        );
+        std::fs::remove_file(&file).unwrap();
+    }
+}
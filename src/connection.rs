@@ -41,7 +41,6 @@ impl Connection {
      * Returns a frame if one is found else None if no frame is in the stream.
      *
      */
-
     pub async fn read_frame(&mut self) -> Result<Option<Frame>, ConnectionError> {
         loop {
             // Try to read a parse a frame to completion.
@@ -68,6 +67,13 @@ impl Connection {
         }
     }
 
+    /**
+     * The size of the data read from the stream that isn't parsed into a frame yet.
+     */
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
     /**
      * Use a cursor to read a frame from the stream.
      * A frame can be parsed from multiple buffers from the same stream, one after the other
@@ -96,28 +102,7 @@ impl Connection {
      *
      */
     pub async fn write_all(&mut self, frame: Frame) -> std::io::Result<()> {
-        match frame {
-            Frame::SimpleString(_) => {
-                self.write(frame).await?;
-            }
-            Frame::SimpleError(_) => {
-                self.write(frame).await?;
-            }
-            Frame::Integer(_) => {
-                self.write(frame).await?;
-            }
-
-            Frame::Array(frames) => {
-                self.stream.write_all('*'.to_string().as_bytes()).await?;
-                self.stream
-                    .write_all(format!("{}\r\n", frames.len()).as_bytes())
-                    .await?;
-
-                for frame in frames {
-                    self.write(frame.to_owned()).await?;
-                }
-            }
-        }
+        self.write(frame).await?;
 
         self.stream.flush().await?;
 
@@ -143,6 +128,13 @@ impl Connection {
 
                 self.stream.write_all(data.as_bytes()).await?;
             }
+            Frame::BulkString(input) => {
+                let mut data = format!("${}\r\n", input.len()).into_bytes();
+                data.extend_from_slice(&input);
+                data.extend_from_slice(b"\r\n");
+
+                self.stream.write_all(&data).await?;
+            }
             Frame::SimpleError(input) => {
                 let mut data = String::new();
                 data.push('-');
@@ -151,8 +143,16 @@ impl Connection {
 
                 self.stream.write_all(data.as_bytes()).await?;
             }
-            // TODO: Handle these arms
-            Frame::Array(_) => {}
+            Frame::Array(frames) => {
+                self.stream
+                    .write_all(format!("*{}\r\n", frames.len()).as_bytes())
+                    .await?;
+
+                // Arrays can be nested e.g HSCAN replies with [cursor, [fields...]]
+                for frame in frames {
+                    Box::pin(self.write(frame)).await?;
+                }
+            }
         }
 
         Ok(())
@@ -1,31 +1,152 @@
 use std::{
-    collections::{BTreeSet, HashMap},
-    sync::{Arc, Mutex},
-    time::Duration,
+    collections::{hash_map::DefaultHasher, BTreeSet, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
 };
 
+use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
 use tokio::sync::Notify;
 
+use crate::{
+    acl::Acl, client::Clients, config::Config, latency::LatencyMonitor, monitor::Monitors,
+    pubsub::Hub, scripting::Scripts, slowlog::SlowLog, stats::Stats,
+};
+
+mod bitmap;
+mod geo;
+mod hash;
+mod hyperloglog;
+mod notify;
+mod set;
+mod skiplist;
+mod stream;
+mod tracking;
+mod zset;
+
+pub use bitmap::{BitOperation, BitfieldOp, BitfieldType, Overflow};
+pub use geo::{GeoMatch, GeoOrigin, GeoSearch, GeoShape};
+pub use notify::{EventClass, KeyspaceEvents};
+pub use set::{Set, SetOperation};
+pub use stream::{
+    ClaimOptions, Consumer, ConsumerGroup, PendingEntry, Stream, StreamEntry, StreamId, StreamTrim,
+    TrimStrategy, XAddId,
+};
+pub use zset::{Aggregate, LexBound, ScoreBound, SortedSet, ZAddFlags, ZAddReply, ZRange};
+
+use tracking::TrackingTable;
+
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/**
+ * The kinds of values a key can hold.
+ */
 #[derive(Clone)]
-pub struct Value {
+pub enum Data {
+    String(Vec<u8>),
+    Hash(HashMap<String, HashField>),
+    Set(Set),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+#[derive(Clone)]
+pub struct HashField {
     pub value: Vec<u8>,
+    // Unix time in milliseconds after which the field is removed from its hash
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Clone)]
+pub struct Value {
+    pub value: Data,
     pub expires_at: Option<u32>,
 }
+
 /**
-* Key-Value database that stores the data.
-* It is protected via an arc that safe to pass across threads.
-* Implements a mutex to protect the data from multithread access.
- Mutex is implemented on the db as ttls will be accessed from only one thread thus no race conditions are possible
-*/
+ * Conditions for updating an expiry, as given by the NX|XX|GT|LT options.
+ * A missing expiry counts as an infinite ttl.
+ */
+#[derive(Clone, Copy)]
+pub enum ExpireCondition {
+    // Only when there is no expiry yet
+    Nx,
+    // Only when there is an expiry already
+    Xx,
+    // Only when the new expiry is later than the current one
+    Gt,
+    // Only when the new expiry is earlier than the current one
+    Lt,
+}
+
+impl ExpireCondition {
+    pub fn allows(&self, current: Option<u64>, new: u64) -> bool {
+        match (self, current) {
+            (ExpireCondition::Nx, current) => current.is_none(),
+            (ExpireCondition::Xx, current) => current.is_some(),
+            (ExpireCondition::Gt, Some(current)) => new > current,
+            (ExpireCondition::Gt, None) => false,
+            (ExpireCondition::Lt, Some(current)) => new < current,
+            (ExpireCondition::Lt, None) => true,
+        }
+    }
+}
 
+#[derive(Debug, PartialEq)]
+pub enum DBError {
+    // The key holds a value of a different type than the command expects
+    WrongType,
+    Other(String),
+}
+
+/**
+ * Key-Value database that stores the data.
+ * It is protected via an arc that safe to pass across threads.
+ * Implements a mutex to protect the data from multithread access.
+ * Mutex is implemented on the db as ttls will be accessed from only one thread thus no race conditions are possible
+ */
 pub struct Store {
     pub entries: HashMap<String, Value>,
     pub ttls: BTreeSet<(u32, String)>,
+    // Hash fields with a ttl as (expires_at in milliseconds, key, field)
+    pub field_ttls: BTreeSet<(u64, String, String)>,
+    // Modification versions of the keys watched by transactions
+    pub watched: HashMap<String, WatchedKey>,
+    pub keyspace_events: KeyspaceEvents,
+    // Keyspace events as (event, key) waiting for the store to be unlocked to be published
+    events: Vec<(&'static str, String)>,
+    // Keys cached by clients with CLIENT TRACKING on
+    tracking: TrackingTable,
+    // Writes since the last flush into the RDB file
+    pub dirty: u64,
+}
+
+/**
+ * A key watched by at least one connection.
+ * Its version is bumped whenever the key is written, deleted or expires.
+ */
+pub struct WatchedKey {
+    pub version: u64,
+    pub watchers: usize,
 }
 
 pub struct Shared {
     pub state: Mutex<Store>,
+    // Held for the whole EXEC of a transaction or run of a script so no other connection gets to the store in between.
+    // It is reentrant since the queued commands lock the store again.
+    transaction: ReentrantMutex<()>,
     pub bg_task: Notify,
+    // Wakes up clients blocked on keys (e.g BZPOPMIN) whenever values are added
+    pub key_ready: Notify,
+    pub pubsub: Hub,
+    pub scripts: Scripts,
+    pub acl: Acl,
+    pub config: Config,
+    pub stats: Stats,
+    pub slowlog: SlowLog,
+    pub latency: LatencyMonitor,
+    pub clients: Clients,
+    pub monitors: Monitors,
 }
 
 #[derive(Clone)]
@@ -35,13 +156,37 @@ pub struct DB {
 
 impl DB {
     pub fn new() -> DB {
+        DB::with_config(Config::default())
+    }
+
+    /**
+     * A db running with the given settings, e.g the ones of the env vars the server starts with.
+     */
+    pub fn with_config(config: Config) -> DB {
         tracing::info!("DB: Starting database service");
         let shared = Shared {
             bg_task: Notify::new(),
+            key_ready: Notify::new(),
+            pubsub: Hub::default(),
+            scripts: Scripts::default(),
+            acl: Acl::default(),
+            config,
+            stats: Stats::default(),
+            slowlog: SlowLog::default(),
+            latency: LatencyMonitor::default(),
+            clients: Clients::default(),
+            monitors: Monitors::default(),
             state: Mutex::new(Store {
                 entries: HashMap::new(),
                 ttls: BTreeSet::new(),
+                field_ttls: BTreeSet::new(),
+                watched: HashMap::new(),
+                keyspace_events: KeyspaceEvents::default(),
+                events: Vec::new(),
+                dirty: 0,
+                tracking: TrackingTable::default(),
             }),
+            transaction: ReentrantMutex::new(()),
         };
 
         let shared = Arc::new(shared);
@@ -52,7 +197,24 @@ impl DB {
     }
 
     pub fn set(&mut self, key: String, value: Vec<u8>, ttl: Option<u32>) {
-        let mut store = self.db.state.lock().unwrap();
+        let created = self.insert(key.clone(), Data::String(value), ttl);
+
+        let mut store = self.db.lock();
+        if created {
+            store.notify(EventClass::New, "new", &key);
+        }
+        store.notify(EventClass::String, "set", &key);
+        if ttl.is_some() {
+            store.notify(EventClass::Generic, "expire", &key);
+        }
+    }
+
+    /**
+     * Stores a value of any type under key, replacing whatever was there.
+     * Returns whether the key is new.
+     */
+    pub fn insert(&mut self, key: String, value: Data, ttl: Option<u32>) -> bool {
+        let mut store = self.db.lock();
 
         let expires_at = if let Some(ttl) = ttl {
             let expires_at = chrono::Utc::now().timestamp() as u32 + ttl;
@@ -63,30 +225,241 @@ impl DB {
         } else {
             None
         };
-        store.entries.insert(key, Value { value, expires_at });
+        store.touch(&key);
+        let old = store
+            .entries
+            .insert(key.clone(), Value { value, expires_at });
+        let created = old.is_none();
+        store.forget_ttl(&key, old);
+        if store.index_field_ttls(&key) {
+            self.db.bg_task.notify_one();
+        }
+        created
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DBError> {
+        let store = self.db.lock();
+
+        match store.entries.get(key) {
+            Some(Value {
+                value: Data::String(value),
+                ..
+            }) => Ok(Some(value.clone())),
+            Some(_) => Err(DBError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn delete(&mut self, key: &str) -> Option<Value> {
+        let mut store = self.db.lock();
+
+        let value = store.remove(key);
+        if value.is_some() {
+            store.notify(EventClass::Generic, "del", key);
+        }
+        value
+    }
+
+    /**
+     * Counts the keys looked up by a read command as keyspace hits or misses, misses are also keymiss events.
+     */
+    pub fn count_lookups(&self, keys: &[String]) {
+        let mut store = self.db.lock();
+        for key in keys {
+            match store.entries.contains_key(key) {
+                true => Stats::incr(&self.db.stats.keyspace_hits),
+                false => {
+                    Stats::incr(&self.db.stats.keyspace_misses);
+                    store.notify(EventClass::KeyMiss, "keymiss", key);
+                }
+            }
+        }
+    }
+
+    pub fn keyspace_events(&self) -> KeyspaceEvents {
+        self.db.lock().keyspace_events
+    }
+
+    /**
+     * Changes which keyspace events are published, like CONFIG SET notify-keyspace-events.
+     */
+    pub fn set_keyspace_events(&self, events: KeyspaceEvents) {
+        self.db.lock().keyspace_events = events;
+    }
+}
+
+/**
+ * The store locked for a single command.
+ */
+pub struct StoreGuard<'a> {
+    store: MutexGuard<'a, Store>,
+    pubsub: &'a Hub,
+    _transaction: ReentrantMutexGuard<'a, ()>,
+}
+
+impl Drop for StoreGuard<'_> {
+    // Publishes the keyspace events and invalidations of the command
+    fn drop(&mut self) {
+        let keyspace_events = self.store.keyspace_events;
+        for (event, key) in std::mem::take(&mut self.store.events) {
+            keyspace_events.publish(self.pubsub, event, &key);
+        }
+        for (id, keys) in self.store.tracking.take_pending() {
+            self.pubsub.invalidate(id, &keys);
+        }
+    }
+}
+
+impl std::ops::Deref for StoreGuard<'_> {
+    type Target = Store;
+
+    fn deref(&self) -> &Store {
+        &self.store
+    }
+}
+
+impl std::ops::DerefMut for StoreGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Store {
+        &mut self.store
+    }
+}
+
+impl Shared {
+    /**
+     * Locks the store, waiting for any transaction of another connection to finish.
+     */
+    pub fn lock(&self) -> StoreGuard<'_> {
+        let transaction = self.transaction.lock();
+        StoreGuard {
+            store: self.state.lock().unwrap(),
+            pubsub: &self.pubsub,
+            _transaction: transaction,
+        }
+    }
+
+    /**
+     * Keeps other connections away from the store until the guard is dropped, e.g during EXEC.
+     * The current thread can still lock the store.
+     */
+    pub fn lock_transaction(&self) -> ReentrantMutexGuard<'_, ()> {
+        self.transaction.lock()
+    }
+}
+
+impl DB {
+    /**
+     * Starts watching keys, returns their current versions.
+     */
+    pub fn watch(&self, keys: &[String]) -> Vec<(String, u64)> {
+        let mut store = self.db.lock();
+        keys.iter()
+            .map(|key| {
+                let watched = store.watched.entry(key.clone()).or_insert(WatchedKey {
+                    version: 0,
+                    watchers: 0,
+                });
+                watched.watchers += 1;
+                (key.clone(), watched.version)
+            })
+            .collect()
+    }
+
+    pub fn unwatch(&self, watched: &[(String, u64)]) {
+        let mut store = self.db.lock();
+        for (key, _) in watched {
+            if let Some(entry) = store.watched.get_mut(key) {
+                entry.watchers -= 1;
+                if entry.watchers == 0 {
+                    store.watched.remove(key);
+                }
+            }
+        }
     }
 
-    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
-        let store = self.db.state.lock().unwrap();
+    /**
+     * Whether any of the watched keys was modified since its version was taken.
+     */
+    pub fn watched_changed(&self, watched: &[(String, u64)]) -> bool {
+        let store = self.db.lock();
+        watched.iter().any(|(key, version)| {
+            store
+                .watched
+                .get(key)
+                .is_none_or(|watched| watched.version != *version)
+        })
+    }
+}
 
-        let value = store.entries.get(key);
-        if let Some(value) = value {
-            return Some(value.value.clone());
+impl Store {
+    // Bumps the version of a key about to be modified if it is watched, and invalidates it for tracking clients
+    fn touch(&mut self, key: &str) {
+        self.dirty += 1;
+        if let Some(watched) = self.watched.get_mut(key) {
+            watched.version += 1;
         }
-        None
+        self.invalidate(key);
     }
 
-    pub fn delete(&mut self, key: &str) -> Option<Vec<u8>> {
-        let mut store = self.db.state.lock().unwrap();
+    // Removes a key together with its ttl entry, a missing key is left untouched
+    fn remove(&mut self, key: &str) -> Option<Value> {
+        let value = self.entries.remove(key)?;
+        self.touch(key);
+        if let Some(expires_at) = value.expires_at {
+            self.ttls.remove(&(expires_at, key.to_string()));
+        }
+        Some(value)
+    }
 
-        let value = store.entries.remove(key);
-        if let Some(value) = value {
-            return Some(value.value);
+    // Drops the ttl entry of a value that has been replaced so the background worker won't delete its successor
+    fn forget_ttl(&mut self, key: &str, old: Option<Value>) {
+        if let Some(Value {
+            expires_at: Some(expires_at),
+            ..
+        }) = old
+        {
+            let current = self.entries.get(key).and_then(|value| value.expires_at);
+            if current != Some(expires_at) {
+                self.ttls.remove(&(expires_at, key.to_string()));
+            }
         }
-        None
     }
 }
 
+pub fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+/**
+ * Cursor based iteration used by the *SCAN commands.
+ * Members are visited in the order of their hash, and the cursor is the hash of the next member to visit.
+ * This keeps a cursor valid while the collection changes between calls:
+ * members present for the whole iteration are always returned, some might be returned more than once.
+ * A cursor of 0 starts an iteration and is returned when it is complete.
+ */
+pub fn scan<'a, T>(
+    members: impl Iterator<Item = (&'a str, T)>,
+    cursor: u64,
+    count: usize,
+) -> (u64, Vec<T>) {
+    let mut members: Vec<(u64, T)> = members
+        .map(|(member, item)| (scan_hash(member), item))
+        .filter(|(hash, _)| *hash >= cursor)
+        .collect();
+    members.sort_by_key(|(hash, _)| *hash);
+
+    let next = members.get(count).map(|(hash, _)| *hash).unwrap_or(0);
+    members.truncate(count);
+
+    (next, members.into_iter().map(|(_, item)| item).collect())
+}
+
+fn scan_hash(member: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    member.hash(&mut hasher);
+    // 0 is reserved for the start and end of an iteration
+    hasher.finish().max(1)
+}
+
 impl Default for DB {
     fn default() -> Self {
         Self::new()
@@ -94,22 +467,40 @@ impl Default for DB {
 }
 
 impl Shared {
-    fn delete_entries(&self) -> Option<u32> {
-        let mut store = self.state.lock().unwrap();
+    // Deletes expired keys and hash fields.
+    // Returns when the next one expires as a unix time in milliseconds.
+    fn delete_entries(&self) -> Option<u64> {
+        let mut store = self.lock();
         let now = chrono::Utc::now().timestamp() as u32;
 
         let store = &mut *store;
 
+        let mut next = None;
         while let Some(ttl) = store.ttls.iter().next().cloned() {
             if ttl.0 > now {
-                return Some(ttl.0);
+                next = Some(ttl.0 as u64 * 1000);
+                break;
             }
 
             store.ttls.remove(&ttl);
-            store.entries.remove(&ttl.1);
+            store.touch(&ttl.1);
+            if store.entries.remove(&ttl.1).is_some() {
+                Stats::incr(&self.stats.expired_keys);
+                store.notify(EventClass::Expired, "expired", &ttl.1);
+            }
+        }
+
+        let now = now_ms();
+        while let Some(ttl) = store.field_ttls.iter().next().cloned() {
+            if ttl.0 > now {
+                next = Some(next.map_or(ttl.0, |next: u64| next.min(ttl.0)));
+                break;
+            }
+
+            store.expire_field(&ttl);
         }
 
-        None
+        next
     }
 }
 
@@ -118,10 +509,14 @@ async fn delete_entries(shared: Arc<Shared>) {
     loop {
         // Task should run after some ttl or if it get's notified.
 
-        match shared.delete_entries() {
+        shared.scripts.finished().await;
+        let started = Instant::now();
+        let next = shared.delete_entries();
+        shared.latency.record("expire-cycle", started.elapsed());
+        match next {
             Some(ttl) => {
                 tokio::select! {
-                    _ = tokio::time::sleep(Duration::from_secs((ttl-chrono::Utc::now().timestamp() as u32 ) as u64)) => {}
+                    _ = tokio::time::sleep(Duration::from_millis(ttl.saturating_sub(now_ms()))) => {}
                     _ = shared.bg_task.notified() => {}
                 }
             }
@@ -132,8 +527,11 @@ async fn delete_entries(shared: Arc<Shared>) {
 
 #[cfg(test)]
 mod tests {
-
     use super::*;
+    use crate::{
+        frame::{command, Frame},
+        runner::{Runner, RunnerError},
+    };
 
     #[tokio::test]
     async fn set_get() {
@@ -141,7 +539,7 @@ mod tests {
         let value = "value".as_bytes().to_vec();
         db.set("key".to_string(), value.clone(), None);
 
-        let result_value = db.get("key").unwrap();
+        let result_value = db.get("key").unwrap().unwrap();
 
         assert_eq!(value, result_value);
     }
@@ -151,7 +549,7 @@ mod tests {
     async fn get_nonexistent() {
         let db = DB::new();
 
-        db.get("key").unwrap();
+        db.get("key").unwrap().unwrap();
     }
 
     #[tokio::test]
@@ -163,7 +561,7 @@ mod tests {
 
         db.delete("key");
 
-        db.get("key").unwrap();
+        db.get("key").unwrap().unwrap();
     }
 
     #[tokio::test]
@@ -174,6 +572,448 @@ mod tests {
         let value = "value".as_bytes().to_vec();
         db.set("key".to_string(), value.clone(), Some(2));
         tokio::time::sleep(Duration::from_secs(3)).await;
-        db.get("key").unwrap();
+        db.get("key").unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn hash_set_get_del() {
+        let mut db = DB::new();
+        let added = db
+            .hset(
+                "session",
+                vec![
+                    ("user".to_string(), b"kariuki".to_vec()),
+                    ("visits".to_string(), b"1".to_vec()),
+                ],
+            )
+            .unwrap();
+        assert_eq!(added, 2);
+
+        assert_eq!(db.hincrby("session", "visits", 4).unwrap(), 5);
+        assert_eq!(db.hget("session", "user").unwrap().unwrap(), b"kariuki");
+        assert_eq!(db.get("session"), Err(DBError::WrongType));
+
+        db.hdel("session", &["user".to_string(), "visits".to_string()])
+            .unwrap();
+        assert!(db.db.state.lock().unwrap().entries.is_empty());
+
+        // Failed and no-op writes neither leave an empty hash behind nor touch the key
+        let dirty = db.db.state.lock().unwrap().dirty;
+        assert!(db.hincrbyfloat("session", "visits", f64::INFINITY).is_err());
+        assert_eq!(db.hdel("session", &["user".to_string()]).unwrap(), 0);
+        db.hset("cart", vec![("item".to_string(), b"book".to_vec())])
+            .unwrap();
+        assert!(!db
+            .hsetnx("cart", "item".to_string(), b"pen".to_vec())
+            .unwrap());
+        assert!(db.hincrby("cart", "item", 1).is_err());
+        let store = db.db.state.lock().unwrap();
+        assert!(!store.entries.contains_key("session"));
+        assert_eq!(store.dirty, dirty + 1);
+    }
+
+    #[tokio::test]
+    async fn hash_scan() {
+        let mut db = DB::new();
+        let fields = (0..25)
+            .map(|field| (field.to_string(), vec![]))
+            .collect::<Vec<_>>();
+        db.hset("hash", fields).unwrap();
+
+        let mut seen = std::collections::HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, fields) = db.hscan("hash", cursor, None, 10).unwrap();
+            seen.extend(fields.into_iter().map(|(field, _)| field));
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(seen.len(), 25);
+    }
+
+    #[tokio::test]
+    async fn hash_field_expiry() {
+        let mut db = DB::new();
+        db.hset(
+            "user",
+            vec![
+                ("name".to_string(), b"kariuki".to_vec()),
+                ("token".to_string(), b"secret".to_vec()),
+            ],
+        )
+        .unwrap();
+
+        let replies = db
+            .hexpire(
+                "user",
+                now_ms() + 500,
+                None,
+                &["token".to_string(), "missing".to_string()],
+            )
+            .unwrap();
+        assert_eq!(replies, vec![1, -2]);
+        tokio::time::sleep(Duration::from_millis(700)).await;
+
+        assert_eq!(db.hget("user", "token").unwrap(), None);
+        assert_eq!(db.hlen("user").unwrap(), 1);
+        assert!(db.db.state.lock().unwrap().field_ttls.is_empty());
+    }
+
+    #[tokio::test]
+    async fn hash_field_expiry_out_of_range() {
+        let mut db = DB::new();
+        db.hset("user", vec![("name".to_string(), b"kariuki".to_vec())])
+            .unwrap();
+
+        // Times past the clock are refused instead of wrapping around to an expiry in the past
+        let mut runner = Runner::new(&mut db);
+        for args in [
+            ["HEXPIRE", "user", "9223372036854775807"],
+            ["HPEXPIREAT", "user", "281474976710656"],
+        ] {
+            let hexpire = command(&[&args[..], &["FIELDS", "1", "name"]].concat());
+            assert!(matches!(
+                runner.run(hexpire),
+                Err(RunnerError::Other(err)) if err.starts_with("ERR invalid expire time in")
+            ));
+        }
+        assert_eq!(db.hget("user", "name").unwrap(), Some(b"kariuki".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn set_encoding() {
+        let mut db = DB::new();
+        db.sadd("cohort", vec!["1".to_string(), "2".to_string()])
+            .unwrap();
+        assert!(matches!(
+            db.db.state.lock().unwrap().entries["cohort"].value,
+            Data::Set(Set::IntSet(_))
+        ));
+
+        db.sadd("cohort", vec!["beta".to_string()]).unwrap();
+        assert!(matches!(
+            db.db.state.lock().unwrap().entries["cohort"].value,
+            Data::Set(Set::HashSet(_))
+        ));
+
+        db.sadd("other", vec!["2".to_string(), "beta".to_string()])
+            .unwrap();
+        let mut inter = db
+            .set_operation(
+                &["cohort".to_string(), "other".to_string()],
+                SetOperation::Inter,
+            )
+            .unwrap();
+        inter.sort();
+        assert_eq!(inter, vec!["2".to_string(), "beta".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn sorted_set_rank_range() {
+        let mut db = DB::new();
+        let members = (0..100)
+            .map(|score| (score as f64, format!("player{}", score)))
+            .collect();
+        let reply = db
+            .zadd("leaderboard", ZAddFlags::default(), members)
+            .unwrap();
+        assert_eq!(reply.added, 100);
+
+        db.zrem("leaderboard", &["player10".to_string()]).unwrap();
+        assert_eq!(
+            db.zrank("leaderboard", "player50", false).unwrap(),
+            Some((49, 50.0))
+        );
+        assert_eq!(
+            db.zrank("leaderboard", "player50", true).unwrap(),
+            Some((49, 50.0))
+        );
+
+        let top = db
+            .zrange("leaderboard", &ZRange::Rank(0, 2), true, 0, None)
+            .unwrap();
+        assert_eq!(
+            top,
+            vec![
+                ("player99".to_string(), 99.0),
+                ("player98".to_string(), 98.0),
+                ("player97".to_string(), 97.0)
+            ]
+        );
+
+        let range = ZRange::Score(
+            ScoreBound {
+                value: 5.0,
+                exclusive: true,
+            },
+            ScoreBound {
+                value: 12.0,
+                exclusive: false,
+            },
+        );
+        let members = db.zrange("leaderboard", &range, false, 1, Some(3)).unwrap();
+        assert_eq!(
+            members
+                .into_iter()
+                .map(|(member, _)| member)
+                .collect::<Vec<_>>(),
+            vec!["player7", "player8", "player9"]
+        );
+
+        let flags = ZAddFlags {
+            gt: true,
+            ch: true,
+            ..Default::default()
+        };
+        let reply = db
+            .zadd("leaderboard", flags, vec![(1.0, "player99".to_string())])
+            .unwrap();
+        assert_eq!(reply.updated, 0);
+    }
+
+    #[tokio::test]
+    async fn blocking_pop_timeout_out_of_range() {
+        let mut db = DB::new();
+        // Finite timeouts too long for a Duration are refused before blocking
+        let reply = Runner::new(&mut db).run(command(&["BZPOPMIN", "empty", "1e300"]));
+        assert!(
+            matches!(reply, Err(RunnerError::Other(err)) if err == "ERR timeout is out of range")
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_consumer_group() {
+        let mut db = DB::new();
+        for seq in 1..=3 {
+            db.xadd(
+                "events",
+                XAddId::Explicit(StreamId { ms: 1, seq }),
+                vec![("n".to_string(), seq.to_string().into_bytes())],
+                false,
+                None,
+            )
+            .unwrap();
+        }
+        let err = db
+            .xadd("events", XAddId::AutoSeq(0), vec![], false, None)
+            .unwrap_err();
+        assert!(matches!(err, DBError::Other(_)));
+
+        db.xgroup_create("events", "workers", Some(StreamId::MIN), false)
+            .unwrap();
+        let streams = vec![("events".to_string(), None)];
+        let read = db
+            .xreadgroup("workers", "alice", &streams, Some(2), false)
+            .unwrap();
+        assert_eq!(read[0].1.len(), 2);
+
+        let id = StreamId { ms: 1, seq: 1 };
+        assert_eq!(db.xack("events", "workers", &[id]).unwrap(), 1);
+        let summary = db.xpending_summary("events", "workers").unwrap();
+        assert_eq!(summary.count, 1);
+        assert_eq!(summary.consumers, vec![("alice".to_string(), 1)]);
+
+        let claimed = db
+            .xclaim(
+                "events",
+                "workers",
+                "bob",
+                0,
+                &[StreamId { ms: 1, seq: 2 }],
+                ClaimOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(claimed.len(), 1);
+        let summary = db.xpending_summary("events", "workers").unwrap();
+        assert_eq!(summary.consumers, vec![("bob".to_string(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn bitmap_operations() {
+        let mut db = DB::new();
+        db.set("key".to_string(), b"foobar".to_vec(), None);
+        assert_eq!(db.bitcount("key", None, false).unwrap(), 26);
+        assert_eq!(db.bitcount("key", Some((5, 30)), true).unwrap(), 17);
+
+        assert_eq!(db.setbit("active", 9, true).unwrap(), 0);
+        assert_eq!(db.getbit("active", 9).unwrap(), 1);
+        assert_eq!(db.bitpos("active", true, 0, None, false).unwrap(), 9);
+        assert_eq!(db.get("active").unwrap().unwrap(), vec![0, 0b0100_0000]);
+
+        let i8 = BitfieldType {
+            signed: true,
+            bits: 8,
+        };
+        let replies = db
+            .bitfield(
+                "counter",
+                &[
+                    BitfieldOp::Set(i8, 0, 120),
+                    BitfieldOp::IncrBy(i8, 0, 10),
+                    BitfieldOp::Overflow(Overflow::Sat),
+                    BitfieldOp::IncrBy(i8, 0, -200),
+                    BitfieldOp::Overflow(Overflow::Fail),
+                    BitfieldOp::IncrBy(i8, 0, -1),
+                ],
+            )
+            .unwrap();
+        assert_eq!(replies, vec![Some(0), Some(-126), Some(-128), None]);
+
+        let u8 = BitfieldType {
+            signed: false,
+            bits: 8,
+        };
+        assert!(db
+            .bitfield("huge", &[BitfieldOp::Get(u8, u64::MAX)])
+            .is_err());
+        assert!(db
+            .bitfield("huge", &[BitfieldOp::Set(u8, u64::MAX - 4, 1)])
+            .is_err());
+        assert!(db.get("huge").unwrap().is_none());
+
+        // Writes that FAIL prevented don't leave an empty string behind
+        let replies = db
+            .bitfield(
+                "failed",
+                &[
+                    BitfieldOp::Overflow(Overflow::Fail),
+                    BitfieldOp::Set(u8, 0, 999),
+                ],
+            )
+            .unwrap();
+        assert_eq!(replies, vec![None]);
+        assert!(db.get("failed").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn hyperloglog_count() {
+        let mut db = DB::new();
+        assert!(db.pfadd("empty", &[]).unwrap());
+        let empty = db.get("empty").unwrap().unwrap();
+        assert_eq!(&empty[..5], b"HYLL\x01");
+        assert_eq!(&empty[16..], &[0x7f, 0xff]);
+
+        // The bytes redis' hyperloglog.c writes for PFADD h a b c: a stale cache, then registers
+        // 8436 and 15780 set to 1 and 12711 set to 2 between runs of zeros
+        assert!(db
+            .pfadd("h", &["a".to_string(), "b".to_string(), "c".to_string()])
+            .unwrap());
+        let mut abc = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80".to_vec();
+        abc.extend([
+            0x60, 0xf3, 0x80, 0x50, 0xb1, 0x84, 0x4b, 0xfb, 0x80, 0x42, 0x5a,
+        ]);
+        assert_eq!(db.get("h").unwrap().unwrap(), abc);
+        assert_eq!(db.pfcount(&["h".to_string()]).unwrap(), 3);
+        assert_eq!(
+            &db.get("h").unwrap().unwrap()[8..16],
+            &[3, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        let elements: Vec<String> = (0..20000).map(|i| format!("element:{}", i)).collect();
+        assert!(db.pfadd("first", &elements[..10000]).unwrap());
+        assert!(!db.pfadd("first", &elements[..100]).unwrap());
+        assert!(db.pfadd("second", &elements[10000..]).unwrap());
+        // Too many registers are set for the sparse encoding
+        assert_eq!(db.get("first").unwrap().unwrap()[4], 0);
+
+        // The first registers and the estimate hyperloglog.c computes for the same dense value
+        let first = db.get("first").unwrap().unwrap();
+        assert_eq!(
+            &first[16..28],
+            &[0x02, 0x00, 0x00, 0x80, 0x30, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(db.pfcount(&["first".to_string()]).unwrap(), 10017);
+        db.pfmerge("all", &["first".to_string(), "second".to_string()])
+            .unwrap();
+        let count = db.pfcount(&["all".to_string()]).unwrap();
+        assert!((count as f64 - 20000.0).abs() < 400.0);
+        assert_eq!(
+            db.pfcount(&["first".to_string(), "second".to_string()])
+                .unwrap(),
+            count
+        );
+    }
+
+    #[tokio::test]
+    async fn hyperloglog_get_set_round_trip() {
+        let mut db = DB::new();
+        let mut runner = Runner::new(&mut db);
+        runner.run(command(&["PFADD", "h", "a", "b", "c"])).unwrap();
+        // The registers aren't utf-8, a bulk string keeps them as they are
+        let bytes = match runner.run(command(&["GET", "h"])).unwrap() {
+            Frame::BulkString(bytes) => bytes,
+            frame => panic!("expected a bulk string, got {:?}", frame),
+        };
+        assert!(String::from_utf8(bytes.clone()).is_err());
+
+        let mut set = command(&["SET", "copy"]);
+        if let Frame::Array(frames) = &mut set {
+            frames.push_back(Frame::BulkString(bytes.clone()));
+        }
+        runner.run(set).unwrap();
+        assert_eq!(
+            runner.run(command(&["GET", "copy"])).unwrap(),
+            Frame::BulkString(bytes)
+        );
+        assert_eq!(
+            runner.run(command(&["PFCOUNT", "copy"])).unwrap(),
+            Frame::Integer(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn geo_search() {
+        let mut db = DB::new();
+        let places = vec![
+            (13.361389, 38.115556, "Palermo".to_string()),
+            (15.087269, 37.502669, "Catania".to_string()),
+            (12.758489, 38.788135, "edge1".to_string()),
+            (17.241510, 38.788135, "edge2".to_string()),
+        ];
+        assert_eq!(
+            db.geoadd("Sicily", ZAddFlags::default(), places)
+                .unwrap()
+                .added,
+            4
+        );
+
+        let distance = db.geodist("Sicily", "Palermo", "Catania").unwrap().unwrap();
+        assert_eq!(format!("{:.4}", distance), "166274.1516");
+        assert_eq!(
+            db.geohash("Sicily", &["Palermo".to_string()]).unwrap(),
+            vec![Some("sqc8b49rny0".to_string())]
+        );
+
+        let mut search = GeoSearch {
+            origin: GeoOrigin::Coordinates(15.0, 37.0),
+            shape: GeoShape::Radius(200_000.0),
+            count: None,
+            any: false,
+            sort: Some(false),
+        };
+        let members = |matches: Vec<GeoMatch>| -> Vec<String> {
+            matches.into_iter().map(|found| found.member).collect()
+        };
+        assert_eq!(
+            members(db.geosearch("Sicily", &search).unwrap()),
+            vec!["Catania", "Palermo"]
+        );
+        search.shape = GeoShape::Box {
+            width: 400_000.0,
+            height: 400_000.0,
+        };
+        search.sort = Some(true);
+        assert_eq!(
+            members(db.geosearch("Sicily", &search).unwrap()),
+            vec!["edge1", "edge2", "Palermo", "Catania"]
+        );
+        search.count = Some(1);
+        assert_eq!(
+            db.geosearch_store("nearest", "Sicily", &search, None)
+                .unwrap(),
+            1
+        );
     }
 }
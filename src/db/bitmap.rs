@@ -0,0 +1,425 @@
+use super::{DBError, Data, EventClass, Store, Value, DB};
+
+// Like redis, strings can't grow past 512MB through bit operations
+const MAX_BITS: u64 = 512 * 1024 * 1024 * 8;
+
+/**
+ * The operators of BITOP.
+ */
+#[derive(Clone, Copy)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+/**
+ * An integer type of BITFIELD such as i5 or u16.
+ * Signed types go up to 64 bits, unsigned ones up to 63.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BitfieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+/**
+ * What BITFIELD does when SET or INCRBY go past the range of a type.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+    // Wraps around like two's complement arithmetic
+    Wrap,
+    // Saturates at the minimum or maximum value
+    Sat,
+    // Leaves the value unchanged and returns nil
+    Fail,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitfieldOp {
+    Get(BitfieldType, u64),
+    Set(BitfieldType, u64, i64),
+    IncrBy(BitfieldType, u64, i64),
+    Overflow(Overflow),
+}
+
+impl BitfieldType {
+    fn min(&self) -> i128 {
+        if self.signed {
+            -(1i128 << (self.bits - 1))
+        } else {
+            0
+        }
+    }
+
+    fn max(&self) -> i128 {
+        if self.signed {
+            (1i128 << (self.bits - 1)) - 1
+        } else {
+            (1i128 << self.bits) - 1
+        }
+    }
+
+    // Reads the raw bits as a value of this type
+    fn decode(&self, raw: u64) -> i64 {
+        if self.signed && self.bits < 64 && raw >> (self.bits - 1) & 1 == 1 {
+            (raw as i128 - (1i128 << self.bits)) as i64
+        } else {
+            raw as i64
+        }
+    }
+
+    // Fits a value into the type, None if it overflows with FAIL
+    fn fit(&self, value: i128, overflow: Overflow) -> Option<i64> {
+        if value >= self.min() && value <= self.max() {
+            return Some(value as i64);
+        }
+        match overflow {
+            Overflow::Wrap => {
+                let span = 1i128 << self.bits;
+                Some(((value - self.min()).rem_euclid(span) + self.min()) as i64)
+            }
+            Overflow::Sat => Some(value.clamp(self.min(), self.max()) as i64),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+// Reads bits at offset, bit 0 being the most significant bit of the first byte
+fn get_bits(bytes: &[u8], offset: u64, bits: u32) -> u64 {
+    let mut value = 0;
+    for i in 0..bits as u64 {
+        value = (value << 1) | get_bit(bytes, offset + i) as u64;
+    }
+    value
+}
+
+fn set_bits(bytes: &mut Vec<u8>, offset: u64, bits: u32, value: u64) {
+    for i in 0..bits as u64 {
+        let bit = (value >> (bits as u64 - 1 - i)) & 1 == 1;
+        set_bit(bytes, offset + i, bit);
+    }
+}
+
+fn get_bit(bytes: &[u8], offset: u64) -> u8 {
+    let byte = bytes.get((offset / 8) as usize).copied().unwrap_or(0);
+    (byte >> (7 - offset % 8)) & 1
+}
+
+// Sets a bit, growing the string with zero bytes if needed
+fn set_bit(bytes: &mut Vec<u8>, offset: u64, bit: bool) {
+    let index = (offset / 8) as usize;
+    if index >= bytes.len() {
+        bytes.resize(index + 1, 0);
+    }
+    let mask = 1 << (7 - offset % 8);
+    if bit {
+        bytes[index] |= mask;
+    } else {
+        bytes[index] &= !mask;
+    }
+}
+
+// Turns a start and end that may count from the end into an inclusive range within len
+fn normalize_range(start: i64, end: i64, len: i64) -> Option<(u64, u64)> {
+    let start = if start < 0 { start + len } else { start }.max(0);
+    let end = if end < 0 { end + len } else { end }.min(len - 1);
+    if start > end || len == 0 {
+        return None;
+    }
+    Some((start as u64, end as u64))
+}
+
+fn out_of_range() -> DBError {
+    DBError::Other("ERR bit offset is not an integer or out of range".to_string())
+}
+
+impl Store {
+    fn string(&self, key: &str) -> Result<Option<&Vec<u8>>, DBError> {
+        match self.entries.get(key) {
+            Some(Value {
+                value: Data::String(value),
+                ..
+            }) => Ok(Some(value)),
+            Some(_) => Err(DBError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    // Runs a bit write on the string at key like update_hash, an empty string standing in for a missing key.
+    // The key is only touched if the write modified the string, and not kept if it was created and left empty.
+    fn update_string<T>(
+        &mut self,
+        key: &str,
+        write: impl FnOnce(&mut Vec<u8>) -> (T, bool),
+    ) -> Result<T, DBError> {
+        let created = !self.entries.contains_key(key);
+        let value = self
+            .entries
+            .entry(key.to_string())
+            .or_insert_with(|| Value {
+                value: Data::String(vec![]),
+                expires_at: None,
+            });
+        let Data::String(bytes) = &mut value.value else {
+            return Err(DBError::WrongType);
+        };
+        let (result, changed) = write(bytes);
+        if created && bytes.is_empty() {
+            self.entries.remove(key);
+        } else if created {
+            self.notify(EventClass::New, "new", key);
+        }
+        if changed {
+            self.touch(key);
+        }
+        Ok(result)
+    }
+}
+
+impl DB {
+    /**
+     * Sets or clears the bit at offset, returns its previous value.
+     */
+    pub fn setbit(&mut self, key: &str, offset: u64, bit: bool) -> Result<u8, DBError> {
+        if offset >= MAX_BITS {
+            return Err(out_of_range());
+        }
+        let mut store = self.db.lock();
+        let previous = store.update_string(key, |bytes| {
+            let previous = get_bit(bytes, offset);
+            set_bit(bytes, offset, bit);
+            (previous, true)
+        })?;
+        store.notify(EventClass::String, "setbit", key);
+
+        Ok(previous)
+    }
+
+    pub fn getbit(&self, key: &str, offset: u64) -> Result<u8, DBError> {
+        let store = self.db.lock();
+
+        Ok(store
+            .string(key)?
+            .map(|bytes| get_bit(bytes, offset))
+            .unwrap_or(0))
+    }
+
+    /**
+     * Counts the set bits, optionally within a range of bytes or bits when bit_unit is set.
+     */
+    pub fn bitcount(
+        &self,
+        key: &str,
+        range: Option<(i64, i64)>,
+        bit_unit: bool,
+    ) -> Result<usize, DBError> {
+        let store = self.db.lock();
+        let bytes = match store.string(key)? {
+            Some(bytes) => bytes,
+            None => return Ok(0),
+        };
+
+        let (start, end) = match range {
+            None => return Ok(bytes.iter().map(|byte| byte.count_ones() as usize).sum()),
+            Some(range) => range,
+        };
+        let len = bytes.len() as i64 * if bit_unit { 8 } else { 1 };
+        let (start, end) = match normalize_range(start, end, len) {
+            Some(range) => range,
+            None => return Ok(0),
+        };
+        if bit_unit {
+            Ok((start..=end)
+                .filter(|offset| get_bit(bytes, *offset) == 1)
+                .count())
+        } else {
+            Ok(bytes[start as usize..=end as usize]
+                .iter()
+                .map(|byte| byte.count_ones() as usize)
+                .sum())
+        }
+    }
+
+    /**
+     * The position of the first bit set to bit within a range of bytes or bits, -1 if there is none.
+     * When looking for a clear bit without an explicit end, the string counts as padded with zeros.
+     */
+    pub fn bitpos(
+        &self,
+        key: &str,
+        bit: bool,
+        start: i64,
+        end: Option<i64>,
+        bit_unit: bool,
+    ) -> Result<i64, DBError> {
+        let store = self.db.lock();
+        let bytes = match store.string(key)? {
+            Some(bytes) => bytes,
+            None => return Ok(if bit { -1 } else { 0 }),
+        };
+
+        let len = bytes.len() as i64 * if bit_unit { 8 } else { 1 };
+        let (start, end_offset) = match normalize_range(start, end.unwrap_or(-1), len) {
+            Some(range) => range,
+            None => return Ok(-1),
+        };
+        let (first_bit, last_bit) = if bit_unit {
+            (start, end_offset)
+        } else {
+            (start * 8, end_offset * 8 + 7)
+        };
+
+        let wanted = bit as u8;
+        match (first_bit..=last_bit).find(|offset| get_bit(bytes, *offset) == wanted) {
+            Some(offset) => Ok(offset as i64),
+            None if !bit && end.is_none() => Ok(last_bit as i64 + 1),
+            None => Ok(-1),
+        }
+    }
+
+    /**
+     * Stores the bitwise operation of the strings at keys in destination, missing keys count as zeros.
+     * Returns the length of the result, an empty result deletes destination.
+     */
+    pub fn bitop(
+        &mut self,
+        operation: BitOperation,
+        destination: &str,
+        keys: &[String],
+    ) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        let mut sources = Vec::with_capacity(keys.len());
+        for key in keys {
+            sources.push(
+                store
+                    .string(key)?
+                    .map(|bytes| bytes.as_slice())
+                    .unwrap_or(&[]),
+            );
+        }
+
+        let len = sources.iter().map(|bytes| bytes.len()).max().unwrap_or(0);
+        let byte = |bytes: &[u8], index: usize| bytes.get(index).copied().unwrap_or(0);
+        let result: Vec<u8> = (0..len)
+            .map(|index| match operation {
+                BitOperation::Not => !byte(sources[0], index),
+                BitOperation::And => sources
+                    .iter()
+                    .fold(0xff, |result, bytes| result & byte(bytes, index)),
+                BitOperation::Or => sources
+                    .iter()
+                    .fold(0, |result, bytes| result | byte(bytes, index)),
+                BitOperation::Xor => sources
+                    .iter()
+                    .fold(0, |result, bytes| result ^ byte(bytes, index)),
+            })
+            .collect();
+
+        let removed = store.remove(destination).is_some();
+        if !result.is_empty() {
+            if !removed {
+                store.notify(EventClass::New, "new", destination);
+            }
+            store.notify(EventClass::String, "set", destination);
+        } else if removed {
+            store.notify(EventClass::Generic, "del", destination);
+        }
+        if !result.is_empty() {
+            store.entries.insert(
+                destination.to_string(),
+                Value {
+                    value: Data::String(result),
+                    expires_at: None,
+                },
+            );
+        }
+        Ok(len)
+    }
+
+    /**
+     * Runs the BITFIELD operations in order.
+     * GET returns the value, SET the previous value and INCRBY the new value, None when FAIL prevented an update.
+     * Reads alone never create the key.
+     */
+    pub fn bitfield(&mut self, key: &str, ops: &[BitfieldOp]) -> Result<Vec<Option<i64>>, DBError> {
+        for op in ops {
+            if let BitfieldOp::Get(ty, offset)
+            | BitfieldOp::Set(ty, offset, _)
+            | BitfieldOp::IncrBy(ty, offset, _) = op
+            {
+                // Offsets may be as large as u64::MAX, so the end of the field can't be added blindly
+                if offset
+                    .checked_add(ty.bits as u64)
+                    .filter(|end| *end <= MAX_BITS)
+                    .is_none()
+                {
+                    return Err(out_of_range());
+                }
+            }
+        }
+
+        let mut store = self.db.lock();
+        let writes = ops
+            .iter()
+            .any(|op| matches!(op, BitfieldOp::Set(..) | BitfieldOp::IncrBy(..)));
+        if !writes {
+            let bytes = store
+                .string(key)?
+                .map(|bytes| bytes.as_slice())
+                .unwrap_or(&[]);
+            return Ok(ops
+                .iter()
+                .filter_map(|op| match op {
+                    BitfieldOp::Get(ty, offset) => {
+                        Some(Some(ty.decode(get_bits(bytes, *offset, ty.bits))))
+                    }
+                    _ => None,
+                })
+                .collect());
+        }
+
+        let (replies, changed) = store.update_string(key, |bytes| {
+            let mut overflow = Overflow::Wrap;
+            let mut replies = vec![];
+            let mut changed = false;
+            for op in ops {
+                match *op {
+                    BitfieldOp::Overflow(mode) => overflow = mode,
+                    BitfieldOp::Get(ty, offset) => {
+                        replies.push(Some(ty.decode(get_bits(bytes, offset, ty.bits))))
+                    }
+                    BitfieldOp::Set(ty, offset, value) => {
+                        let previous = ty.decode(get_bits(bytes, offset, ty.bits));
+                        match ty.fit(value as i128, overflow) {
+                            Some(value) => {
+                                set_bits(bytes, offset, ty.bits, value as u64);
+                                replies.push(Some(previous));
+                                changed = true;
+                            }
+                            None => replies.push(None),
+                        }
+                    }
+                    BitfieldOp::IncrBy(ty, offset, increment) => {
+                        let current = ty.decode(get_bits(bytes, offset, ty.bits));
+                        match ty.fit(current as i128 + increment as i128, overflow) {
+                            Some(value) => {
+                                set_bits(bytes, offset, ty.bits, value as u64);
+                                replies.push(Some(value));
+                                changed = true;
+                            }
+                            None => replies.push(None),
+                        }
+                    }
+                }
+            }
+            ((replies, changed), changed)
+        })?;
+        if changed {
+            store.notify(EventClass::String, "setbit", key);
+        }
+        Ok(replies)
+    }
+}
@@ -0,0 +1,463 @@
+use super::{
+    DBError, Data, EventClass, ScoreBound, SortedSet, Store, Value, ZAddFlags, ZAddReply, DB,
+};
+
+/*
+ * Geospatial indexes are sorted sets scored by 52 bit geohashes, the same way redis stores them.
+ * Longitude and latitude are each quantized to 26 bits and interleaved, longitude taking the odd bits,
+ * so members close to each other usually have close scores and an area maps to a range of scores.
+ */
+
+// The EPSG:3857 latitude limits, the same as redis
+const LATITUDE_MIN: f64 = -85.05112878;
+const LATITUDE_MAX: f64 = 85.05112878;
+const LONGITUDE_MIN: f64 = -180.0;
+const LONGITUDE_MAX: f64 = 180.0;
+const STEP_MAX: u32 = 26;
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+// Half the circumference of the earth in the mercator projection
+const MERCATOR_MAX: f64 = 20037726.37;
+const GEOHASH_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/**
+ * Where a search is centered.
+ */
+#[derive(Clone, Debug)]
+pub enum GeoOrigin {
+    Member(String),
+    Coordinates(f64, f64),
+}
+
+/**
+ * The area of a search in meters.
+ */
+#[derive(Clone, Copy, Debug)]
+pub enum GeoShape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+/**
+ * The arguments of GEOSEARCH.
+ * With any set the search stops after finding count members instead of returning the closest ones.
+ */
+#[derive(Clone, Debug)]
+pub struct GeoSearch {
+    pub origin: GeoOrigin,
+    pub shape: GeoShape,
+    pub count: Option<usize>,
+    pub any: bool,
+    // Sorts by distance, descending when true
+    pub sort: Option<bool>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeoMatch {
+    pub member: String,
+    // In meters
+    pub distance: f64,
+    pub hash: u64,
+    pub longitude: f64,
+    pub latitude: f64,
+}
+
+// Spreads the 32 bits of x to the even bits of a u64
+fn spread(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | (x << 16)) & 0x0000_FFFF_0000_FFFF;
+    x = (x | (x << 8)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+// Gathers the even bits of a u64
+fn squash(x: u64) -> u32 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x >> 4)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x >> 8)) & 0x0000_FFFF_0000_FFFF;
+    ((x | (x >> 16)) & 0x0000_0000_FFFF_FFFF) as u32
+}
+
+// The cells of a coordinate at step, each axis being split in 2^step cells
+fn cell(longitude: f64, latitude: f64, step: u32, latitude_range: (f64, f64)) -> (u32, u32) {
+    let (latitude_min, latitude_max) = latitude_range;
+    let x = (longitude - LONGITUDE_MIN) / (LONGITUDE_MAX - LONGITUDE_MIN) * (1u64 << step) as f64;
+    let y = (latitude - latitude_min) / (latitude_max - latitude_min) * (1u64 << step) as f64;
+    (x as u32, y as u32)
+}
+
+fn interleave(x: u32, y: u32) -> u64 {
+    spread(y) | (spread(x) << 1)
+}
+
+/**
+ * The 52 bit geohash redis uses as the score of a coordinate.
+ */
+fn encode(longitude: f64, latitude: f64) -> u64 {
+    let (x, y) = cell(longitude, latitude, STEP_MAX, (LATITUDE_MIN, LATITUDE_MAX));
+    interleave(x, y)
+}
+
+/**
+ * The center of the area a geohash covers.
+ */
+fn decode(hash: u64) -> (f64, f64) {
+    let x = squash(hash >> 1) as f64;
+    let y = squash(hash) as f64;
+    let cells = (1u64 << STEP_MAX) as f64;
+    let longitude_scale = LONGITUDE_MAX - LONGITUDE_MIN;
+    let latitude_scale = LATITUDE_MAX - LATITUDE_MIN;
+
+    let longitude_min = LONGITUDE_MIN + x / cells * longitude_scale;
+    let longitude_max = LONGITUDE_MIN + (x + 1.0) / cells * longitude_scale;
+    let latitude_min = LATITUDE_MIN + y / cells * latitude_scale;
+    let latitude_max = LATITUDE_MIN + (y + 1.0) / cells * latitude_scale;
+    (
+        ((longitude_min + longitude_max) / 2.0).clamp(LONGITUDE_MIN, LONGITUDE_MAX),
+        ((latitude_min + latitude_max) / 2.0).clamp(LATITUDE_MIN, LATITUDE_MAX),
+    )
+}
+
+/**
+ * The standard 11 character geohash of a coordinate, as returned by GEOHASH.
+ * Unlike scores it uses the full -90 to 90 latitude range.
+ */
+fn geohash_string(longitude: f64, latitude: f64) -> String {
+    let (x, y) = cell(longitude, latitude, STEP_MAX, (-90.0, 90.0));
+    let hash = interleave(x, y);
+    (0..11)
+        .map(|i| {
+            // 52 bits only fill 10 characters and a bit
+            let index = if i == 10 {
+                0
+            } else {
+                (hash >> (52 - (i + 1) * 5)) & 0x1f
+            };
+            GEOHASH_ALPHABET[index as usize] as char
+        })
+        .collect()
+}
+
+/**
+ * The haversine distance in meters between two coordinates.
+ */
+fn distance(longitude1: f64, latitude1: f64, longitude2: f64, latitude2: f64) -> f64 {
+    let latitude1 = latitude1.to_radians();
+    let latitude2 = latitude2.to_radians();
+    let u = ((latitude2 - latitude1) / 2.0).sin();
+    let v = ((longitude2.to_radians() - longitude1.to_radians()) / 2.0).sin();
+    let a = u * u + latitude1.cos() * latitude2.cos() * v * v;
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+
+impl GeoShape {
+    // The radius of a circle covering the whole shape
+    fn radius(&self) -> f64 {
+        match *self {
+            GeoShape::Radius(radius) => radius,
+            GeoShape::Box { width, height } => (width / 2.0).hypot(height / 2.0),
+        }
+    }
+
+    // The distance of a point from the center if it lies within the shape
+    fn contains(&self, center: (f64, f64), point: (f64, f64)) -> Option<f64> {
+        let from_center = distance(center.0, center.1, point.0, point.1);
+        match *self {
+            GeoShape::Radius(radius) => (from_center <= radius).then_some(from_center),
+            GeoShape::Box { width, height } => {
+                let latitude_distance =
+                    EARTH_RADIUS_IN_METERS * (point.1.to_radians() - center.1.to_radians()).abs();
+                if latitude_distance > height / 2.0 {
+                    return None;
+                }
+                let longitude_distance = distance(point.0, point.1, center.0, point.1);
+                if longitude_distance > width / 2.0 {
+                    return None;
+                }
+                Some(from_center)
+            }
+        }
+    }
+}
+
+// The precision redis starts from for a search radius
+fn estimate_step(radius: f64, latitude: f64) -> u32 {
+    if radius == 0.0 {
+        return STEP_MAX;
+    }
+    let mut radius = radius;
+    let mut step: i32 = 1;
+    while radius < MERCATOR_MAX {
+        radius *= 2.0;
+        step += 1;
+    }
+    // Cells a bit larger than the radius so neighbors are enough
+    step -= 2;
+    // Cells shrink towards the poles
+    if !(-66.0..=66.0).contains(&latitude) {
+        step -= 1;
+        if !(-80.0..=80.0).contains(&latitude) {
+            step -= 1;
+        }
+    }
+    step.clamp(1, STEP_MAX as i32) as u32
+}
+
+// Whether the cell around center and its neighbors cover a circle of radius at step
+fn covers(center: (f64, f64), radius: f64, step: u32) -> bool {
+    if step == 0 {
+        return true;
+    }
+    let angle = radius / EARTH_RADIUS_IN_METERS;
+    let cells = (1u64 << step) as f64;
+    let cell_height = ((LATITUDE_MAX - LATITUDE_MIN) / cells).to_radians();
+    let cell_width = ((LONGITUDE_MAX - LONGITUDE_MIN) / cells).to_radians();
+    if angle > cell_height {
+        return false;
+    }
+    // How far the circle reaches in longitude
+    let reach = angle.sin() / center.1.to_radians().cos();
+    reach < 1.0 && reach.asin() <= cell_width
+}
+
+// The score ranges of the cell holding center and its neighbors
+fn search_ranges(center: (f64, f64), radius: f64) -> Vec<(u64, u64)> {
+    let mut step = estimate_step(radius, center.1);
+    while !covers(center, radius, step) {
+        step -= 1;
+    }
+
+    let cells = 1i64 << step;
+    let (x, y) = cell(center.0, center.1, step, (LATITUDE_MIN, LATITUDE_MAX));
+    let shift = 2 * (STEP_MAX - step);
+    let mut ranges = vec![];
+    for (dx, dy) in [
+        (0, 0),
+        (0, 1),
+        (0, -1),
+        (1, 0),
+        (-1, 0),
+        (1, 1),
+        (-1, 1),
+        (1, -1),
+        (-1, -1),
+    ] {
+        // Longitude wraps around, there is nothing past the latitude limits
+        let y = y as i64 + dy;
+        if y < 0 || y >= cells {
+            continue;
+        }
+        let x = (x as i64 + dx).rem_euclid(cells);
+        let hash = interleave(x as u32, y as u32) << shift;
+        let range = (hash, hash + (1 << shift));
+        if !ranges.contains(&range) {
+            ranges.push(range);
+        }
+    }
+    ranges
+}
+
+fn search(zset: &SortedSet, center: (f64, f64), search: &GeoSearch) -> Vec<GeoMatch> {
+    // Without ANY the closest members are returned
+    let limit = match search.any {
+        true => search.count,
+        false => None,
+    };
+
+    let mut matches = vec![];
+    'ranges: for (min, max) in search_ranges(center, search.shape.radius()) {
+        let members = zset.range_by_score(
+            ScoreBound {
+                value: min as f64,
+                exclusive: false,
+            },
+            ScoreBound {
+                value: max as f64,
+                exclusive: true,
+            },
+            false,
+            0,
+            None,
+        );
+        for (member, score) in members {
+            let hash = score as u64;
+            let (longitude, latitude) = decode(hash);
+            if let Some(distance) = search.shape.contains(center, (longitude, latitude)) {
+                matches.push(GeoMatch {
+                    member,
+                    distance,
+                    hash,
+                    longitude,
+                    latitude,
+                });
+                if limit.is_some_and(|limit| matches.len() >= limit) {
+                    break 'ranges;
+                }
+            }
+        }
+    }
+
+    let sort = match (search.sort, search.count) {
+        (None, Some(_)) if !search.any => Some(false),
+        (sort, _) => sort,
+    };
+    if let Some(descending) = sort {
+        matches.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        if descending {
+            matches.reverse();
+        }
+    }
+    if let Some(count) = search.count {
+        matches.truncate(count);
+    }
+    matches
+}
+
+impl Store {
+    fn geosearch(&self, key: &str, geo_search: &GeoSearch) -> Result<Vec<GeoMatch>, DBError> {
+        let zset = match self.zset(key)? {
+            Some(zset) => zset,
+            None => return Ok(vec![]),
+        };
+        let center = match &geo_search.origin {
+            GeoOrigin::Coordinates(longitude, latitude) => (*longitude, *latitude),
+            GeoOrigin::Member(member) => match zset.score(member) {
+                Some(score) => decode(score as u64),
+                None => {
+                    return Err(DBError::Other(
+                        "ERR could not decode requested zset member".to_string(),
+                    ))
+                }
+            },
+        };
+        Ok(search(zset, center, geo_search))
+    }
+
+    fn geo_coordinates(&self, key: &str, member: &str) -> Result<Option<(f64, f64)>, DBError> {
+        Ok(self
+            .zset(key)?
+            .and_then(|zset| zset.score(member))
+            .map(|score| decode(score as u64)))
+    }
+}
+
+impl DB {
+    /**
+     * Adds members at longitude, latitude coordinates with the ZADD flags.
+     */
+    pub fn geoadd(
+        &mut self,
+        key: &str,
+        flags: ZAddFlags,
+        members: Vec<(f64, f64, String)>,
+    ) -> Result<ZAddReply, DBError> {
+        let mut scored = Vec::with_capacity(members.len());
+        for (longitude, latitude, member) in members {
+            if !(LONGITUDE_MIN..=LONGITUDE_MAX).contains(&longitude)
+                || !(LATITUDE_MIN..=LATITUDE_MAX).contains(&latitude)
+            {
+                return Err(DBError::Other(format!(
+                    "ERR invalid longitude,latitude pair {:.6},{:.6}",
+                    longitude, latitude
+                )));
+            }
+            scored.push((encode(longitude, latitude) as f64, member));
+        }
+
+        self.zadd(key, flags, scored)
+    }
+
+    pub fn geopos(
+        &self,
+        key: &str,
+        members: &[String],
+    ) -> Result<Vec<Option<(f64, f64)>>, DBError> {
+        let store = self.db.lock();
+        members
+            .iter()
+            .map(|member| store.geo_coordinates(key, member))
+            .collect()
+    }
+
+    /**
+     * The distance in meters between two members, None if either is missing.
+     */
+    pub fn geodist(&self, key: &str, member1: &str, member2: &str) -> Result<Option<f64>, DBError> {
+        let store = self.db.lock();
+        let first = store.geo_coordinates(key, member1)?;
+        let second = store.geo_coordinates(key, member2)?;
+
+        Ok(first
+            .zip(second)
+            .map(|(first, second)| distance(first.0, first.1, second.0, second.1)))
+    }
+
+    pub fn geohash(&self, key: &str, members: &[String]) -> Result<Vec<Option<String>>, DBError> {
+        let store = self.db.lock();
+        members
+            .iter()
+            .map(|member| {
+                Ok(store
+                    .geo_coordinates(key, member)?
+                    .map(|(longitude, latitude)| geohash_string(longitude, latitude)))
+            })
+            .collect()
+    }
+
+    /**
+     * Members of the sorted set at key within a radius or box.
+     */
+    pub fn geosearch(&self, key: &str, search: &GeoSearch) -> Result<Vec<GeoMatch>, DBError> {
+        let store = self.db.lock();
+        store.geosearch(key, search)
+    }
+
+    /**
+     * Stores the result of a search in destination scored by geohash,
+     * or by distance divided by unit (meters per unit) when store_dist is set.
+     * Returns the number of members stored, an empty result deletes destination.
+     */
+    pub fn geosearch_store(
+        &mut self,
+        destination: &str,
+        key: &str,
+        search: &GeoSearch,
+        store_dist: Option<f64>,
+    ) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        let matches = store.geosearch(key, search)?;
+
+        let mut zset = SortedSet::new();
+        for found in matches {
+            let score = match store_dist {
+                Some(unit) => found.distance / unit,
+                None => found.hash as f64,
+            };
+            zset.insert(found.member, score);
+        }
+        let len = zset.len();
+        let removed = store.remove(destination).is_some();
+        if len > 0 {
+            if !removed {
+                store.notify(EventClass::New, "new", destination);
+            }
+            store.notify(EventClass::SortedSet, "geosearchstore", destination);
+        } else if removed {
+            store.notify(EventClass::Generic, "del", destination);
+        }
+        if len > 0 {
+            store.entries.insert(
+                destination.to_string(),
+                Value {
+                    value: Data::SortedSet(zset),
+                    expires_at: None,
+                },
+            );
+            self.db.key_ready.notify_waiters();
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+
+use rand::seq::{IteratorRandom, SliceRandom};
+
+use super::{
+    now_ms, scan, DBError, Data, EventClass, ExpireCondition, HashField, Store, Value, DB,
+};
+
+type Hash = HashMap<String, HashField>;
+type Field = (String, Vec<u8>);
+
+impl Store {
+    fn hash(&self, key: &str) -> Result<Option<&Hash>, DBError> {
+        match self.entries.get(key) {
+            Some(Value {
+                value: Data::Hash(hash),
+                ..
+            }) => Ok(Some(hash)),
+            Some(_) => Err(DBError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /**
+     * Runs a write on the hash at key, an empty hash standing in for a missing key.
+     * The write returns its result and whether it modified the hash, the key is only touched if it did.
+     * A hash created for a write that left it empty (e.g a failed HINCRBYFLOAT) is dropped again.
+     */
+    fn update_hash<T>(
+        &mut self,
+        key: &str,
+        write: impl FnOnce(&mut Hash) -> Result<(T, bool), DBError>,
+    ) -> Result<T, DBError> {
+        let created = !self.entries.contains_key(key);
+        let value = self
+            .entries
+            .entry(key.to_string())
+            .or_insert_with(|| Value {
+                value: Data::Hash(HashMap::new()),
+                expires_at: None,
+            });
+        let Data::Hash(hash) = &mut value.value else {
+            return Err(DBError::WrongType);
+        };
+        let result = write(hash);
+        if created && hash.is_empty() {
+            self.entries.remove(key);
+        } else if created {
+            self.notify(EventClass::New, "new", key);
+        }
+        if let Ok((_, true)) = result {
+            self.touch(key);
+        }
+        result.map(|(result, _)| result)
+    }
+
+    // Redis does not keep empty aggregates around
+    fn remove_empty_hash(&mut self, key: &str) {
+        if let Ok(Some(hash)) = self.hash(key) {
+            if hash.is_empty() {
+                self.remove(key);
+                self.notify(EventClass::Generic, "del", key);
+            }
+        }
+    }
+
+    /**
+     * Registers the field ttls of a hash that was stored as a whole e.g when loading the RDB file.
+     * Returns whether there were any.
+     */
+    pub(super) fn index_field_ttls(&mut self, key: &str) -> bool {
+        let ttls: Vec<(u64, String, String)> = match self.hash(key) {
+            Ok(Some(hash)) => hash
+                .iter()
+                .filter_map(|(field, value)| {
+                    value
+                        .expires_at
+                        .map(|expires_at| (expires_at, key.to_string(), field.clone()))
+                })
+                .collect(),
+            _ => return false,
+        };
+
+        let indexed = !ttls.is_empty();
+        self.field_ttls.extend(ttls);
+        indexed
+    }
+
+    /**
+     * Removes a hash field whose ttl has come due.
+     * The ttl index is not cleaned up when fields are overwritten or deleted,
+     * so the field is only removed if it still carries that exact expiry.
+     */
+    pub(super) fn expire_field(&mut self, ttl: &(u64, String, String)) {
+        self.field_ttls.remove(ttl);
+
+        let (expires_at, key, field) = ttl;
+        let expired = self.update_hash(key, |hash| {
+            let due = hash.get(field).and_then(|value| value.expires_at) == Some(*expires_at);
+            if due {
+                hash.remove(field);
+            }
+            Ok((due, due))
+        });
+        if let Ok(true) = expired {
+            self.notify(EventClass::Hash, "hexpired", key);
+        }
+        self.remove_empty_hash(key);
+    }
+
+    // Lazily expires the fields of a hash that the background worker has not gotten to yet
+    fn expire_fields(&mut self, key: &str) {
+        let now = now_ms();
+        let due: Vec<(u64, String, String)> = self
+            .field_ttls
+            .iter()
+            .take_while(|(expires_at, _, _)| *expires_at <= now)
+            .filter(|(_, ttl_key, _)| ttl_key == key)
+            .cloned()
+            .collect();
+
+        for ttl in due.iter() {
+            self.expire_field(ttl);
+        }
+    }
+}
+
+impl DB {
+    /**
+     * Sets fields in the hash stored at key, clearing any ttls they had.
+     * Returns the number of fields that were added.
+     */
+    pub fn hset(&mut self, key: &str, fields: Vec<Field>) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+        let added = store.update_hash(key, |hash| {
+            let mut added = 0;
+            for (field, value) in fields {
+                let value = HashField {
+                    value,
+                    expires_at: None,
+                };
+                if hash.insert(field, value).is_none() {
+                    added += 1;
+                }
+            }
+            Ok((added, true))
+        })?;
+        store.notify(EventClass::Hash, "hset", key);
+        Ok(added)
+    }
+
+    /**
+     * Sets a field only if it does not exist yet.
+     */
+    pub fn hsetnx(&mut self, key: &str, field: String, value: Vec<u8>) -> Result<bool, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+        let added = store.update_hash(key, |hash| {
+            if hash.contains_key(&field) {
+                return Ok((false, false));
+            }
+            let value = HashField {
+                value,
+                expires_at: None,
+            };
+            hash.insert(field, value);
+            Ok((true, true))
+        })?;
+        if added {
+            store.notify(EventClass::Hash, "hset", key);
+        }
+        Ok(added)
+    }
+
+    pub fn hget(&self, key: &str, field: &str) -> Result<Option<Vec<u8>>, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+
+        Ok(store
+            .hash(key)?
+            .and_then(|hash| hash.get(field))
+            .map(|field| field.value.clone()))
+    }
+
+    pub fn hmget(&self, key: &str, fields: &[String]) -> Result<Vec<Option<Vec<u8>>>, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+        let hash = store.hash(key)?;
+
+        Ok(fields
+            .iter()
+            .map(|field| {
+                hash.and_then(|hash| hash.get(field))
+                    .map(|field| field.value.clone())
+            })
+            .collect())
+    }
+
+    /**
+     * Removes fields from the hash, deleting the key once the hash is empty.
+     * Returns the number of fields that were removed.
+     */
+    pub fn hdel(&mut self, key: &str, fields: &[String]) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+        let removed = store.update_hash(key, |hash| {
+            let removed = fields
+                .iter()
+                .filter(|field| hash.remove(*field).is_some())
+                .count();
+            Ok((removed, removed > 0))
+        })?;
+        if removed > 0 {
+            store.notify(EventClass::Hash, "hdel", key);
+        }
+        store.remove_empty_hash(key);
+
+        Ok(removed)
+    }
+
+    pub fn hgetall(&self, key: &str) -> Result<Vec<Field>, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+
+        Ok(store
+            .hash(key)?
+            .map(|hash| {
+                hash.iter()
+                    .map(|(field, value)| (field.clone(), value.value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    pub fn hlen(&self, key: &str) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+
+        Ok(store.hash(key)?.map(|hash| hash.len()).unwrap_or(0))
+    }
+
+    pub fn hexists(&self, key: &str, field: &str) -> Result<bool, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+
+        Ok(store
+            .hash(key)?
+            .map(|hash| hash.contains_key(field))
+            .unwrap_or(false))
+    }
+
+    pub fn hstrlen(&self, key: &str, field: &str) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+
+        Ok(store
+            .hash(key)?
+            .and_then(|hash| hash.get(field))
+            .map(|field| field.value.len())
+            .unwrap_or(0))
+    }
+
+    /**
+     * Increments the integer stored in a field, a missing field counts as 0.
+     * The field keeps its ttl.
+     */
+    pub fn hincrby(&mut self, key: &str, field: &str, increment: i64) -> Result<i64, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+        let value = store.update_hash(key, |hash| {
+            let current = match hash.get(field) {
+                Some(field) => String::from_utf8_lossy(&field.value)
+                    .parse::<i64>()
+                    .map_err(|_| DBError::Other("ERR hash value is not an integer".to_string()))?,
+                None => 0,
+            };
+            let value = current.checked_add(increment).ok_or_else(|| {
+                DBError::Other("ERR increment or decrement would overflow".to_string())
+            })?;
+            set_keeping_ttl(hash, field, value.to_string().into_bytes());
+            Ok((value, true))
+        })?;
+        store.notify(EventClass::Hash, "hincrby", key);
+        Ok(value)
+    }
+
+    /**
+     * Increments the float stored in a field, a missing field counts as 0.
+     * The field keeps its ttl.
+     */
+    pub fn hincrbyfloat(&mut self, key: &str, field: &str, increment: f64) -> Result<f64, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+        let value = store.update_hash(key, |hash| {
+            let current = match hash.get(field) {
+                Some(field) => String::from_utf8_lossy(&field.value)
+                    .parse::<f64>()
+                    .map_err(|_| DBError::Other("ERR hash value is not a float".to_string()))?,
+                None => 0.0,
+            };
+            let value = current + increment;
+            if !value.is_finite() {
+                return Err(DBError::Other(
+                    "ERR increment would produce NaN or Infinity".to_string(),
+                ));
+            }
+            set_keeping_ttl(hash, field, value.to_string().into_bytes());
+            Ok((value, true))
+        })?;
+        store.notify(EventClass::Hash, "hincrbyfloat", key);
+        Ok(value)
+    }
+
+    /**
+     * Iterates the fields of a hash, see db::scan.
+     * Fields not matching the pattern are filtered out after a batch is picked, like redis does.
+     */
+    pub fn hscan(
+        &self,
+        key: &str,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(u64, Vec<Field>), DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+        let hash = match store.hash(key)? {
+            Some(hash) => hash,
+            None => return Ok((0, vec![])),
+        };
+
+        let (cursor, fields) = scan(
+            hash.iter()
+                .map(|(field, value)| (field.as_str(), (field, value))),
+            cursor,
+            count,
+        );
+        let fields = fields
+            .into_iter()
+            .filter(|(field, _)| match pattern {
+                Some(pattern) => crate::glob::matches(pattern.as_bytes(), field.as_bytes()),
+                None => true,
+            })
+            .map(|(field, value)| (field.clone(), value.value.clone()))
+            .collect();
+
+        Ok((cursor, fields))
+    }
+
+    /**
+     * Picks random fields from a hash.
+     * A positive count returns distinct fields, a negative one may return the same field multiple times.
+     */
+    pub fn hrandfield(&self, key: &str, count: i64) -> Result<Vec<Field>, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+        let hash = match store.hash(key)? {
+            Some(hash) => hash,
+            None => return Ok(vec![]),
+        };
+        let mut rng = rand::thread_rng();
+
+        let fields: Vec<(&String, &HashField)> = if count >= 0 {
+            hash.iter().choose_multiple(&mut rng, count as usize)
+        } else {
+            let all: Vec<(&String, &HashField)> = hash.iter().collect();
+            (0..count.unsigned_abs())
+                .filter_map(|_| all.choose(&mut rng).copied())
+                .collect()
+        };
+
+        Ok(fields
+            .into_iter()
+            .map(|(field, value)| (field.clone(), value.value.clone()))
+            .collect())
+    }
+
+    /**
+     * Sets the expiry of hash fields to a unix time in milliseconds.
+     * Replies per field like HEXPIRE:
+     * -2 if the field does not exist, 0 if the condition was not met,
+     * 1 if the expiry was set and 2 if the field was deleted because the time has already passed.
+     */
+    pub fn hexpire(
+        &mut self,
+        key: &str,
+        expires_at: u64,
+        condition: Option<ExpireCondition>,
+        fields: &[String],
+    ) -> Result<Vec<i64>, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+        let now = now_ms();
+
+        let mut ttls = vec![];
+        let replies = store.update_hash(key, |hash| {
+            let mut replies = vec![-2; fields.len()];
+            for (reply, field) in replies.iter_mut().zip(fields) {
+                let value = match hash.get_mut(field) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                if let Some(condition) = condition {
+                    if !condition.allows(value.expires_at, expires_at) {
+                        *reply = 0;
+                        continue;
+                    }
+                }
+
+                if expires_at <= now {
+                    hash.remove(field);
+                    *reply = 2;
+                } else {
+                    value.expires_at = Some(expires_at);
+                    ttls.push((expires_at, key.to_string(), field.clone()));
+                    *reply = 1;
+                }
+            }
+            let changed = replies.iter().any(|reply| *reply > 0);
+            Ok((replies, changed))
+        })?;
+
+        if replies.contains(&1) {
+            store.notify(EventClass::Hash, "hexpire", key);
+        }
+        if replies.contains(&2) {
+            store.notify(EventClass::Hash, "hdel", key);
+        }
+        store.remove_empty_hash(key);
+        if !ttls.is_empty() {
+            store.field_ttls.extend(ttls);
+            self.db.bg_task.notify_one();
+        }
+
+        Ok(replies)
+    }
+
+    /**
+     * Removes the expiry of hash fields.
+     * Replies per field: -2 if the field does not exist, -1 if it has no expiry and 1 if it was removed.
+     */
+    pub fn hpersist(&mut self, key: &str, fields: &[String]) -> Result<Vec<i64>, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+
+        let replies = store.update_hash(key, |hash| {
+            let mut replies = vec![-2; fields.len()];
+            for (reply, field) in replies.iter_mut().zip(fields) {
+                if let Some(value) = hash.get_mut(field) {
+                    *reply = match value.expires_at.take() {
+                        Some(_) => 1,
+                        None => -1,
+                    };
+                }
+            }
+            let changed = replies.contains(&1);
+            Ok((replies, changed))
+        })?;
+        if replies.contains(&1) {
+            store.notify(EventClass::Hash, "hpersist", key);
+        }
+
+        Ok(replies)
+    }
+
+    /**
+     * Gets the expiry of hash fields as a unix time in milliseconds.
+     * Like HPEXPIRETIME, -2 is returned for missing fields and -1 for fields without an expiry.
+     */
+    pub fn hexpiretime(&self, key: &str, fields: &[String]) -> Result<Vec<i64>, DBError> {
+        let mut store = self.db.lock();
+        store.expire_fields(key);
+        let hash = store.hash(key)?;
+
+        Ok(fields
+            .iter()
+            .map(|field| match hash.and_then(|hash| hash.get(field)) {
+                None => -2,
+                Some(field) => field.expires_at.map_or(-1, |expires_at| expires_at as i64),
+            })
+            .collect())
+    }
+}
+
+fn set_keeping_ttl(hash: &mut Hash, field: &str, value: Vec<u8>) {
+    match hash.get_mut(field) {
+        Some(field) => field.value = value,
+        None => {
+            hash.insert(
+                field.to_string(),
+                HashField {
+                    value,
+                    expires_at: None,
+                },
+            );
+        }
+    }
+}
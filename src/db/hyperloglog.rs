@@ -0,0 +1,391 @@
+use super::{DBError, Data, EventClass, Store, Value, DB};
+
+/*
+ * HyperLogLog cardinality estimation stored in strings with the same bytes redis' hyperloglog.c
+ * writes, so PFCOUNT and PFMERGE read values copied from redis with GET and SET, and the other way
+ * around. There is no DUMP/RESTORE and the RDB file is mini-redis' own, so they only move that way.
+ *
+ * Layout: "HYLL", the encoding byte (0 dense, 1 sparse), 3 unused bytes, then the cached cardinality
+ * as 8 little endian bytes whose most significant bit marks the cache as stale.
+ *
+ * Dense: 16384 registers of 6 bits packed from the least significant bit of each byte.
+ * Sparse: a run length encoding of the registers made of three opcodes:
+ *  - ZERO 00xxxxxx: xxxxxx+1 registers set to 0
+ *  - XZERO 01xxxxxx yyyyyyyy: xxxxxxyyyyyyyy+1 registers set to 0
+ *  - VAL 1vvvvvxx: xx+1 registers set to vvvvv+1
+ */
+
+const MAGIC: &[u8] = b"HYLL";
+const HEADER_SIZE: usize = 16;
+// Bits of the hash used to pick a register
+const P: u32 = 14;
+// Bits of the hash used to count leading zeros
+const Q: u32 = 50;
+const REGISTERS: usize = 1 << P;
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+const DENSE_SIZE: usize = HEADER_SIZE + REGISTERS * REGISTER_BITS / 8;
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+// Like redis' hll-sparse-max-bytes, larger sparse values (header included) are converted to dense
+const SPARSE_MAX_BYTES: usize = 3000;
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+const SPARSE_XZERO_MAX_LEN: usize = 16384;
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+const SEED: u64 = 0xadc8_3b19;
+
+fn invalid() -> DBError {
+    DBError::Other("WRONGTYPE Key is not a valid HyperLogLog string value.".to_string())
+}
+
+/**
+ * MurmurHash2, 64-bit version by Austin Appleby, as used by redis.
+ */
+fn murmurhash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+    let chunks = key.chunks_exact(8);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+// The register an element maps to and the length of its run of zeros plus one
+fn pattern(element: &[u8]) -> (usize, u8) {
+    let hash = murmurhash64a(element, SEED);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // Makes sure the loop terminates
+    let hash = (hash >> P) | (1 << Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+fn header(encoding: u8) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend([encoding, 0, 0, 0]);
+    // A stale cache
+    bytes.extend([0, 0, 0, 0, 0, 0, 0, 0x80]);
+    bytes
+}
+
+/**
+ * Reads the registers of a dense or sparse value.
+ */
+fn decode(bytes: &[u8]) -> Result<Vec<u8>, DBError> {
+    if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+        return Err(invalid());
+    }
+    let mut registers = vec![0; REGISTERS];
+    match bytes[4] {
+        DENSE => {
+            if bytes.len() != DENSE_SIZE {
+                return Err(invalid());
+            }
+            let dense = &bytes[HEADER_SIZE..];
+            for (index, register) in registers.iter_mut().enumerate() {
+                let byte = index * REGISTER_BITS / 8;
+                let shift = index * REGISTER_BITS % 8;
+                let b0 = dense[byte] as u16;
+                let b1 = dense.get(byte + 1).copied().unwrap_or(0) as u16;
+                *register = (((b0 >> shift) | (b1 << (8 - shift))) & REGISTER_MAX as u16) as u8;
+            }
+        }
+        SPARSE => {
+            let mut index = 0;
+            let mut bytes = bytes[HEADER_SIZE..].iter();
+            while let Some(opcode) = bytes.next() {
+                let (len, value) = match opcode >> 6 {
+                    0b00 => ((opcode & 0x3f) as usize + 1, 0),
+                    0b01 => {
+                        let low = *bytes.next().ok_or_else(invalid)? as usize;
+                        ((((opcode & 0x3f) as usize) << 8 | low) + 1, 0)
+                    }
+                    _ => ((opcode & 0x3) as usize + 1, ((opcode >> 2) & 0x1f) + 1),
+                };
+                if index + len > REGISTERS {
+                    return Err(invalid());
+                }
+                registers[index..index + len].fill(value);
+                index += len;
+            }
+            if index != REGISTERS {
+                return Err(invalid());
+            }
+        }
+        _ => return Err(invalid()),
+    }
+    Ok(registers)
+}
+
+fn encode_dense(registers: &[u8]) -> Vec<u8> {
+    let mut bytes = header(DENSE);
+    bytes.resize(DENSE_SIZE, 0);
+    let dense = &mut bytes[HEADER_SIZE..];
+    for (index, register) in registers.iter().enumerate() {
+        let byte = index * REGISTER_BITS / 8;
+        let shift = index * REGISTER_BITS % 8;
+        let value = (*register as u16) << shift;
+        dense[byte] |= value as u8;
+        if let Some(next) = dense.get_mut(byte + 1) {
+            *next |= (value >> 8) as u8;
+        }
+    }
+    bytes
+}
+
+// None if a register is too big for the sparse encoding or the result would be too large
+fn encode_sparse(registers: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = header(SPARSE);
+    let mut index = 0;
+    while index < registers.len() {
+        let value = registers[index];
+        if value > SPARSE_VAL_MAX_VALUE {
+            return None;
+        }
+        let run = registers[index..]
+            .iter()
+            .take_while(|register| **register == value)
+            .count();
+
+        let mut left = run;
+        while left > 0 {
+            let len = if value != 0 {
+                let len = left.min(SPARSE_VAL_MAX_LEN);
+                bytes.push(0x80 | (value - 1) << 2 | (len - 1) as u8);
+                len
+            } else if left > SPARSE_ZERO_MAX_LEN {
+                let len = left.min(SPARSE_XZERO_MAX_LEN) - 1;
+                bytes.push(0x40 | (len >> 8) as u8);
+                bytes.push(len as u8);
+                len + 1
+            } else {
+                bytes.push((left - 1) as u8);
+                left
+            };
+            left -= len;
+        }
+        index += run;
+
+        if bytes.len() > SPARSE_MAX_BYTES {
+            return None;
+        }
+    }
+    Some(bytes)
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+/**
+ * Estimates the cardinality with the improved estimator of Otmar Ertl, like redis does.
+ */
+fn estimate(registers: &[u8]) -> u64 {
+    let mut histogram = [0u32; 64];
+    for register in registers {
+        histogram[*register as usize] += 1;
+    }
+
+    let m = REGISTERS as f64;
+    let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+    for count in histogram[1..=Q as usize].iter().rev() {
+        z += *count as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn cached_cardinality(bytes: &[u8]) -> Option<u64> {
+    if bytes[15] & 0x80 != 0 {
+        return None;
+    }
+    Some(u64::from_le_bytes(bytes[8..16].try_into().unwrap()))
+}
+
+fn cache_cardinality(bytes: &mut [u8], cardinality: u64) {
+    bytes[8..16].copy_from_slice(&cardinality.to_le_bytes());
+}
+
+// Encodes registers, staying sparse when possible unless dense is required
+fn encode(registers: &[u8], dense: bool) -> Vec<u8> {
+    if dense {
+        return encode_dense(registers);
+    }
+    encode_sparse(registers).unwrap_or_else(|| encode_dense(registers))
+}
+
+fn is_dense(bytes: &[u8]) -> bool {
+    bytes[4] == DENSE
+}
+
+impl Store {
+    // Gets the registers of the HyperLogLog at key along with whether it is dense
+    fn hyperloglog(&self, key: &str) -> Result<Option<(Vec<u8>, bool)>, DBError> {
+        match self.entries.get(key) {
+            Some(Value {
+                value: Data::String(bytes),
+                ..
+            }) => Ok(Some((decode(bytes)?, is_dense(bytes)))),
+            Some(_) => Err(DBError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    // Stores an encoded HyperLogLog, keeping the ttl of the key it replaces
+    fn store_hyperloglog(&mut self, key: &str, bytes: Vec<u8>) {
+        self.touch(key);
+        match self.entries.get_mut(key) {
+            Some(value) => value.value = Data::String(bytes),
+            None => {
+                self.notify(EventClass::New, "new", key);
+                self.entries.insert(
+                    key.to_string(),
+                    Value {
+                        value: Data::String(bytes),
+                        expires_at: None,
+                    },
+                );
+            }
+        }
+    }
+}
+
+impl DB {
+    /**
+     * Adds elements to the HyperLogLog at key.
+     * Returns true if a register changed or the key was created.
+     */
+    pub fn pfadd(&mut self, key: &str, elements: &[String]) -> Result<bool, DBError> {
+        let mut store = self.db.lock();
+        let (mut registers, dense, created) = match store.hyperloglog(key)? {
+            Some((registers, dense)) => (registers, dense, false),
+            None => (vec![0; REGISTERS], false, true),
+        };
+
+        let mut changed = created;
+        for element in elements {
+            let (index, count) = pattern(element.as_bytes());
+            if count > registers[index] {
+                registers[index] = count;
+                changed = true;
+            }
+        }
+        if changed {
+            store.store_hyperloglog(key, encode(&registers, dense));
+            store.notify(EventClass::String, "pfadd", key);
+        }
+
+        Ok(changed)
+    }
+
+    /**
+     * Estimates the number of unique elements added to the HyperLogLogs at keys.
+     * The estimate of a single key is cached in its header.
+     */
+    pub fn pfcount(&mut self, keys: &[String]) -> Result<u64, DBError> {
+        let mut store = self.db.lock();
+
+        if let [key] = keys {
+            let bytes = match store.entries.get_mut(key.as_str()) {
+                Some(Value {
+                    value: Data::String(bytes),
+                    ..
+                }) => bytes,
+                Some(_) => return Err(DBError::WrongType),
+                None => return Ok(0),
+            };
+            let registers = decode(bytes)?;
+            if let Some(cardinality) = cached_cardinality(bytes) {
+                return Ok(cardinality);
+            }
+            let cardinality = estimate(&registers);
+            cache_cardinality(bytes, cardinality);
+            return Ok(cardinality);
+        }
+
+        let mut merged = vec![0; REGISTERS];
+        for key in keys {
+            if let Some((registers, _)) = store.hyperloglog(key)? {
+                for (merged, register) in merged.iter_mut().zip(registers) {
+                    *merged = (*merged).max(register);
+                }
+            }
+        }
+        Ok(estimate(&merged))
+    }
+
+    /**
+     * Merges the HyperLogLogs at sources into destination, creating it if needed.
+     * The result stays sparse only if every input was sparse.
+     */
+    pub fn pfmerge(&mut self, destination: &str, sources: &[String]) -> Result<(), DBError> {
+        let mut store = self.db.lock();
+
+        let mut merged = vec![0; REGISTERS];
+        let mut dense = false;
+        for key in std::iter::once(destination).chain(sources.iter().map(|key| key.as_str())) {
+            if let Some((registers, is_dense)) = store.hyperloglog(key)? {
+                dense |= is_dense;
+                for (merged, register) in merged.iter_mut().zip(registers) {
+                    *merged = (*merged).max(register);
+                }
+            }
+        }
+        store.store_hyperloglog(destination, encode(&merged, dense));
+        store.notify(EventClass::String, "pfadd", destination);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt;
+
+use crate::pubsub::Hub;
+
+use super::{DBError, Store};
+
+/**
+ * The classes of keyspace events, each one enabled by a character of notify-keyspace-events.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventClass {
+    // g: commands that aren't type specific, e.g del and expire
+    Generic,
+    // $: string commands
+    String,
+    // l: list commands
+    List,
+    // s: set commands
+    Set,
+    // h: hash commands
+    Hash,
+    // z: sorted set commands
+    SortedSet,
+    // x: keys expiring
+    Expired,
+    // t: stream commands
+    Stream,
+    // m: keys missing when accessed
+    KeyMiss,
+    // n: new keys
+    New,
+}
+
+const KEYSPACE: u16 = 1 << 0;
+const KEYEVENT: u16 = 1 << 1;
+
+impl EventClass {
+    fn flag(self) -> u16 {
+        match self {
+            EventClass::Generic => 1 << 2,
+            EventClass::String => 1 << 3,
+            EventClass::List => 1 << 4,
+            EventClass::Set => 1 << 5,
+            EventClass::Hash => 1 << 6,
+            EventClass::SortedSet => 1 << 7,
+            EventClass::Expired => 1 << 8,
+            EventClass::Stream => 1 << 9,
+            EventClass::KeyMiss => 1 << 10,
+            EventClass::New => 1 << 11,
+        }
+    }
+}
+
+// The classes A stands for, in the order redis prints them.
+// Nothing is ever evicted since there is no maxmemory, so the e class of redis is refused.
+const ALL: [(char, EventClass); 8] = [
+    ('g', EventClass::Generic),
+    ('$', EventClass::String),
+    ('l', EventClass::List),
+    ('s', EventClass::Set),
+    ('h', EventClass::Hash),
+    ('z', EventClass::SortedSet),
+    ('x', EventClass::Expired),
+    ('t', EventClass::Stream),
+];
+
+/**
+ * Which keyspace events are published, as set by notify-keyspace-events e.g "KEA" or "Ex".
+ * K publishes to __keyspace@0__:<key> channels and E to __keyevent@0__:<event> channels,
+ * nothing is published unless one of them is set along with some event classes.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KeyspaceEvents(u16);
+
+impl KeyspaceEvents {
+    pub fn parse(flags: &str) -> Result<KeyspaceEvents, DBError> {
+        let mut events = 0;
+        for flag in flags.chars() {
+            events |= match flag {
+                'A' => ALL.iter().fold(0, |all, (_, class)| all | class.flag()),
+                'K' => KEYSPACE,
+                'E' => KEYEVENT,
+                'm' => EventClass::KeyMiss.flag(),
+                'n' => EventClass::New.flag(),
+                flag => match ALL.iter().find(|(name, _)| *name == flag) {
+                    Some((_, class)) => class.flag(),
+                    None => {
+                        return Err(DBError::Other(
+                            "ERR Invalid event class character. Use 'Ag$lshzxKEtmn'.".to_string(),
+                        ))
+                    }
+                },
+            };
+        }
+        Ok(KeyspaceEvents(events))
+    }
+
+    pub fn notifies(&self, class: EventClass) -> bool {
+        self.0 & (KEYSPACE | KEYEVENT) != 0 && self.0 & class.flag() != 0
+    }
+
+    /**
+     * Publishes an event on a key to the channels enabled by K and E.
+     */
+    pub(super) fn publish(&self, hub: &Hub, event: &str, key: &str) {
+        if self.0 & KEYSPACE != 0 {
+            hub.publish(&format!("__keyspace@0__:{}", key), event);
+        }
+        if self.0 & KEYEVENT != 0 {
+            hub.publish(&format!("__keyevent@0__:{}", event), key);
+        }
+    }
+}
+
+impl fmt::Display for KeyspaceEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let all = ALL.iter().all(|(_, class)| self.0 & class.flag() != 0);
+        if all {
+            write!(f, "A")?;
+        }
+        for (name, class) in ALL.iter() {
+            if !all && self.0 & class.flag() != 0 {
+                write!(f, "{}", name)?;
+            }
+        }
+        for (name, flag) in [
+            ('K', KEYSPACE),
+            ('E', KEYEVENT),
+            ('m', EventClass::KeyMiss.flag()),
+            ('n', EventClass::New.flag()),
+        ] {
+            if self.0 & flag != 0 {
+                write!(f, "{}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Store {
+    /**
+     * Queues a keyspace event, it is published once the store is unlocked.
+     */
+    pub(super) fn notify(&mut self, class: EventClass, event: &'static str, key: &str) {
+        if self.keyspace_events.notifies(class) {
+            self.events.push((event, key.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::DB,
+        frame::{command, Frame},
+        pubsub::Subscriber,
+        runner::Runner,
+    };
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn keyspace_notifications() {
+        let mut db = DB::new();
+        // Nothing is published until notify-keyspace-events is set
+        assert!(!db.keyspace_events().notifies(EventClass::String));
+        assert!(KeyspaceEvents::parse("Kq").is_err());
+        assert!(KeyspaceEvents::parse("Ke").is_err());
+        let events = KeyspaceEvents::parse("KEA").unwrap();
+        assert_eq!(events.to_string(), "AKE");
+        db.set_keyspace_events(events);
+
+        let mut subscriber = Subscriber::new(db.clone());
+        subscriber
+            .run(&command(&["PSUBSCRIBE", "__key*__:*"]))
+            .unwrap()
+            .unwrap();
+        let next = |subscriber: &mut Subscriber| {
+            let message = match subscriber.try_receive() {
+                Some(Frame::Array(message)) => message,
+                other => panic!("expected a message, got {:?}", other),
+            };
+            match (&message[2], &message[3]) {
+                (Frame::SimpleString(channel), Frame::SimpleString(payload)) => {
+                    (channel.clone(), payload.clone())
+                }
+                _ => panic!("unexpected message {:?}", message),
+            }
+        };
+
+        db.set("key".to_string(), b"value".to_vec(), Some(1));
+        assert_eq!(
+            next(&mut subscriber),
+            ("__keyspace@0__:key".to_string(), "set".to_string())
+        );
+        assert_eq!(
+            next(&mut subscriber),
+            ("__keyevent@0__:set".to_string(), "key".to_string())
+        );
+        assert_eq!(
+            next(&mut subscriber),
+            ("__keyspace@0__:key".to_string(), "expire".to_string())
+        );
+        assert_eq!(
+            next(&mut subscriber),
+            ("__keyevent@0__:expire".to_string(), "key".to_string())
+        );
+
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert_eq!(
+            next(&mut subscriber),
+            ("__keyspace@0__:key".to_string(), "expired".to_string())
+        );
+        assert_eq!(
+            next(&mut subscriber),
+            ("__keyevent@0__:expired".to_string(), "key".to_string())
+        );
+
+        db.hset("hash", vec![("field".to_string(), b"1".to_vec())])
+            .unwrap();
+        assert_eq!(
+            next(&mut subscriber),
+            ("__keyspace@0__:hash".to_string(), "hset".to_string())
+        );
+        next(&mut subscriber);
+        db.delete("hash");
+        assert_eq!(
+            next(&mut subscriber),
+            ("__keyspace@0__:hash".to_string(), "del".to_string())
+        );
+        next(&mut subscriber);
+        assert_eq!(subscriber.try_receive(), None);
+
+        // m and n aren't part of A: reads of missing keys and keys created by writes
+        let events = KeyspaceEvents::parse("Emn").unwrap();
+        assert_eq!(events.to_string(), "Emn");
+        db.set_keyspace_events(events);
+        Runner::new(&mut db)
+            .run(command(&["GET", "missing"]))
+            .unwrap();
+        assert_eq!(
+            next(&mut subscriber),
+            ("__keyevent@0__:keymiss".to_string(), "missing".to_string())
+        );
+        db.hset("hash", vec![("field".to_string(), b"1".to_vec())])
+            .unwrap();
+        assert_eq!(
+            next(&mut subscriber),
+            ("__keyevent@0__:new".to_string(), "hash".to_string())
+        );
+        db.hset("hash", vec![("field".to_string(), b"2".to_vec())])
+            .unwrap();
+        assert!(db.hincrbyfloat("other", "field", f64::INFINITY).is_err());
+        assert_eq!(subscriber.try_receive(), None);
+    }
+}
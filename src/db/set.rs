@@ -0,0 +1,487 @@
+use std::collections::HashSet;
+
+use rand::seq::{IteratorRandom, SliceRandom};
+
+use super::{scan, DBError, Data, EventClass, Store, Value, DB};
+
+// Like redis' set-max-intset-entries
+const MAX_INTSET_ENTRIES: usize = 512;
+
+/**
+ * A set of unique strings.
+ * Sets made up of integers only are stored as a sorted vector of integers which is far more compact,
+ * they are converted to a hash set once a non integer member is added or they grow too big.
+ */
+#[derive(Clone, Debug)]
+pub enum Set {
+    IntSet(Vec<i64>),
+    HashSet(HashSet<String>),
+}
+
+impl Set {
+    pub fn new() -> Set {
+        Set::IntSet(vec![])
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Set::IntSet(ints) => ints.len(),
+            Set::HashSet(set) => set.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, member: &str) -> bool {
+        match self {
+            Set::IntSet(ints) => match as_integer(member) {
+                Some(int) => ints.binary_search(&int).is_ok(),
+                None => false,
+            },
+            Set::HashSet(set) => set.contains(member),
+        }
+    }
+
+    /**
+     * Adds a member, returns false if it was already present.
+     */
+    pub fn insert(&mut self, member: String) -> bool {
+        if let Set::IntSet(ints) = self {
+            match as_integer(&member) {
+                Some(int) => match ints.binary_search(&int) {
+                    Ok(_) => return false,
+                    Err(position) if ints.len() < MAX_INTSET_ENTRIES => {
+                        ints.insert(position, int);
+                        return true;
+                    }
+                    Err(_) => self.convert(),
+                },
+                None => self.convert(),
+            }
+        }
+
+        match self {
+            Set::HashSet(set) => set.insert(member),
+            Set::IntSet(_) => unreachable!("the intset has been converted"),
+        }
+    }
+
+    /**
+     * Removes a member, returns false if it was not present.
+     */
+    pub fn remove(&mut self, member: &str) -> bool {
+        match self {
+            Set::IntSet(ints) => match as_integer(member).map(|int| ints.binary_search(&int)) {
+                Some(Ok(position)) => {
+                    ints.remove(position);
+                    true
+                }
+                _ => false,
+            },
+            Set::HashSet(set) => set.remove(member),
+        }
+    }
+
+    pub fn members(&self) -> Vec<String> {
+        match self {
+            Set::IntSet(ints) => ints.iter().map(|int| int.to_string()).collect(),
+            Set::HashSet(set) => set.iter().cloned().collect(),
+        }
+    }
+
+    // Switches an intset to the hash set encoding
+    fn convert(&mut self) {
+        if let Set::IntSet(ints) = self {
+            *self = Set::HashSet(ints.iter().map(|int| int.to_string()).collect());
+        }
+    }
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromIterator<String> for Set {
+    fn from_iter<T: IntoIterator<Item = String>>(iter: T) -> Self {
+        let mut set = Set::new();
+        for member in iter {
+            set.insert(member);
+        }
+        set
+    }
+}
+
+// Only members that read back exactly the same can be stored as integers e.g "10" but not "010"
+fn as_integer(member: &str) -> Option<i64> {
+    member
+        .parse::<i64>()
+        .ok()
+        .filter(|int| int.to_string() == member)
+}
+
+impl Store {
+    fn set(&self, key: &str) -> Result<Option<&Set>, DBError> {
+        match self.entries.get(key) {
+            Some(Value {
+                value: Data::Set(set),
+                ..
+            }) => Ok(Some(set)),
+            Some(_) => Err(DBError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn set_mut(&mut self, key: &str) -> Result<Option<&mut Set>, DBError> {
+        self.touch(key);
+        match self.entries.get_mut(key) {
+            Some(Value {
+                value: Data::Set(set),
+                ..
+            }) => Ok(Some(set)),
+            Some(_) => Err(DBError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    // Gets the set at key, creating an empty one if the key does not exist
+    fn set_or_create(&mut self, key: &str) -> Result<&mut Set, DBError> {
+        self.touch(key);
+        if !self.entries.contains_key(key) {
+            self.notify(EventClass::New, "new", key);
+        }
+        let value = self
+            .entries
+            .entry(key.to_string())
+            .or_insert_with(|| Value {
+                value: Data::Set(Set::new()),
+                expires_at: None,
+            });
+        match &mut value.value {
+            Data::Set(set) => Ok(set),
+            _ => Err(DBError::WrongType),
+        }
+    }
+
+    fn remove_empty_set(&mut self, key: &str) {
+        if let Ok(Some(set)) = self.set(key) {
+            if set.is_empty() {
+                self.remove(key);
+                self.notify(EventClass::Generic, "del", key);
+            }
+        }
+    }
+
+    // Gets the sets at keys, missing keys count as empty sets
+    fn sets(&self, keys: &[String]) -> Result<Vec<Option<&Set>>, DBError> {
+        keys.iter().map(|key| self.set(key)).collect()
+    }
+
+    // Replaces destination with the result of a *STORE command, an empty result deletes it
+    fn store_set(&mut self, destination: &str, set: Set, event: &'static str) -> usize {
+        let len = set.len();
+        let removed = self.remove(destination).is_some();
+        if len > 0 {
+            if !removed {
+                self.notify(EventClass::New, "new", destination);
+            }
+            self.notify(EventClass::Set, event, destination);
+        } else if removed {
+            self.notify(EventClass::Generic, "del", destination);
+        }
+        if len > 0 {
+            self.entries.insert(
+                destination.to_string(),
+                Value {
+                    value: Data::Set(set),
+                    expires_at: None,
+                },
+            );
+        }
+        len
+    }
+}
+
+/**
+ * The set algebra behind SINTER, SUNION and SDIFF and their STORE variants.
+ */
+#[derive(Clone, Copy)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+fn combine(sets: &[Option<&Set>], operation: SetOperation) -> Set {
+    let mut result = Set::new();
+    match operation {
+        SetOperation::Inter => {
+            // Any missing key makes the intersection empty
+            if sets.iter().any(|set| set.is_none()) {
+                return result;
+            }
+            let mut sets: Vec<&Set> = sets.iter().flatten().copied().collect();
+            // Walk the smallest set checking the others
+            sets.sort_by_key(|set| set.len());
+            if let Some((smallest, others)) = sets.split_first() {
+                for member in smallest.members() {
+                    if others.iter().all(|set| set.contains(&member)) {
+                        result.insert(member);
+                    }
+                }
+            }
+        }
+        SetOperation::Union => {
+            for set in sets.iter().flatten() {
+                for member in set.members() {
+                    result.insert(member);
+                }
+            }
+        }
+        SetOperation::Diff => {
+            if let Some((Some(first), others)) = sets.split_first() {
+                for member in first.members() {
+                    if !others.iter().flatten().any(|set| set.contains(&member)) {
+                        result.insert(member);
+                    }
+                }
+            }
+        }
+    }
+    result
+}
+
+impl DB {
+    /**
+     * Adds members to the set stored at key.
+     * Returns the number of members that were added.
+     */
+    pub fn sadd(&mut self, key: &str, members: Vec<String>) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        let set = store.set_or_create(key)?;
+
+        let added = members
+            .into_iter()
+            .filter(|member| set.insert(member.clone()))
+            .count();
+        if added > 0 {
+            store.notify(EventClass::Set, "sadd", key);
+        }
+        Ok(added)
+    }
+
+    /**
+     * Removes members from the set, deleting the key once the set is empty.
+     * Returns the number of members that were removed.
+     */
+    pub fn srem(&mut self, key: &str, members: &[String]) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        let removed = match store.set_mut(key)? {
+            Some(set) => members.iter().filter(|member| set.remove(member)).count(),
+            None => 0,
+        };
+        if removed > 0 {
+            store.notify(EventClass::Set, "srem", key);
+        }
+        store.remove_empty_set(key);
+
+        Ok(removed)
+    }
+
+    pub fn smembers(&self, key: &str) -> Result<Vec<String>, DBError> {
+        let store = self.db.lock();
+
+        Ok(store.set(key)?.map(|set| set.members()).unwrap_or_default())
+    }
+
+    pub fn scard(&self, key: &str) -> Result<usize, DBError> {
+        let store = self.db.lock();
+
+        Ok(store.set(key)?.map(|set| set.len()).unwrap_or(0))
+    }
+
+    /**
+     * Checks the membership of several members at once, as SMISMEMBER does.
+     */
+    pub fn smismember(&self, key: &str, members: &[String]) -> Result<Vec<bool>, DBError> {
+        let store = self.db.lock();
+        let set = store.set(key)?;
+
+        Ok(members
+            .iter()
+            .map(|member| set.map(|set| set.contains(member)).unwrap_or(false))
+            .collect())
+    }
+
+    /**
+     * Moves a member from one set to another.
+     * Returns false if the member was not in the source set.
+     */
+    pub fn smove(
+        &mut self,
+        source: &str,
+        destination: &str,
+        member: String,
+    ) -> Result<bool, DBError> {
+        let mut store = self.db.lock();
+        // Both keys have to be sets before anything is changed
+        store.set(destination)?;
+        let removed = match store.set_mut(source)? {
+            Some(set) => set.remove(&member),
+            None => false,
+        };
+        if !removed {
+            return Ok(false);
+        }
+        store.notify(EventClass::Set, "srem", source);
+        store.remove_empty_set(source);
+        store.set_or_create(destination)?.insert(member);
+        store.notify(EventClass::Set, "sadd", destination);
+
+        Ok(true)
+    }
+
+    pub fn set_operation(
+        &self,
+        keys: &[String],
+        operation: SetOperation,
+    ) -> Result<Vec<String>, DBError> {
+        let store = self.db.lock();
+        let sets = store.sets(keys)?;
+
+        Ok(combine(&sets, operation).members())
+    }
+
+    /**
+     * Stores the result of a set operation in destination.
+     * Returns the size of the resulting set.
+     */
+    pub fn set_operation_store(
+        &mut self,
+        destination: &str,
+        keys: &[String],
+        operation: SetOperation,
+    ) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        let sets = store.sets(keys)?;
+        let event = match operation {
+            SetOperation::Inter => "sinterstore",
+            SetOperation::Union => "sunionstore",
+            SetOperation::Diff => "sdiffstore",
+        };
+        let result = combine(&sets, operation);
+
+        Ok(store.store_set(destination, result, event))
+    }
+
+    /**
+     * Counts the members of the intersection, stopping early once limit is reached (0 means no limit).
+     */
+    pub fn sintercard(&self, keys: &[String], limit: usize) -> Result<usize, DBError> {
+        let store = self.db.lock();
+        let sets = store.sets(keys)?;
+        if sets.iter().any(|set| set.is_none()) {
+            return Ok(0);
+        }
+        let mut sets: Vec<&Set> = sets.into_iter().flatten().collect();
+        sets.sort_by_key(|set| set.len());
+
+        let mut count = 0;
+        if let Some((smallest, others)) = sets.split_first() {
+            for member in smallest.members() {
+                if others.iter().all(|set| set.contains(&member)) {
+                    count += 1;
+                    if count == limit {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /**
+     * Removes and returns up to count random members.
+     */
+    pub fn spop(&mut self, key: &str, count: usize) -> Result<Vec<String>, DBError> {
+        let mut store = self.db.lock();
+        let popped = match store.set_mut(key)? {
+            Some(set) => {
+                let members = set
+                    .members()
+                    .into_iter()
+                    .choose_multiple(&mut rand::thread_rng(), count);
+                for member in members.iter() {
+                    set.remove(member);
+                }
+                members
+            }
+            None => vec![],
+        };
+        if !popped.is_empty() {
+            store.notify(EventClass::Set, "spop", key);
+        }
+        store.remove_empty_set(key);
+
+        Ok(popped)
+    }
+
+    /**
+     * Picks random members from a set.
+     * A positive count returns distinct members, a negative one may return the same member multiple times.
+     */
+    pub fn srandmember(&self, key: &str, count: i64) -> Result<Vec<String>, DBError> {
+        let store = self.db.lock();
+        let members = match store.set(key)? {
+            Some(set) => set.members(),
+            None => return Ok(vec![]),
+        };
+        let mut rng = rand::thread_rng();
+
+        if count >= 0 {
+            Ok(members
+                .into_iter()
+                .choose_multiple(&mut rng, count as usize))
+        } else {
+            Ok((0..count.unsigned_abs())
+                .filter_map(|_| members.choose(&mut rng).cloned())
+                .collect())
+        }
+    }
+
+    /**
+     * Iterates the members of a set, see db::scan.
+     */
+    pub fn sscan(
+        &self,
+        key: &str,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(u64, Vec<String>), DBError> {
+        let store = self.db.lock();
+        let members = match store.set(key)? {
+            Some(set) => set.members(),
+            None => return Ok((0, vec![])),
+        };
+
+        let (cursor, members) = scan(
+            members.iter().map(|member| (member.as_str(), member)),
+            cursor,
+            count,
+        );
+        let members = members
+            .into_iter()
+            .filter(|member| match pattern {
+                Some(pattern) => crate::glob::matches(pattern.as_bytes(), member.as_bytes()),
+                None => true,
+            })
+            .cloned()
+            .collect();
+
+        Ok((cursor, members))
+    }
+}
@@ -0,0 +1,365 @@
+use rand::Rng;
+
+const MAX_LEVEL: usize = 32;
+// Probability of a node getting promoted to the next level
+const P: f64 = 0.25;
+// The head node is always stored at index 0
+const HEAD: usize = 0;
+
+#[derive(Clone, Debug)]
+struct Level {
+    forward: Option<usize>,
+    // Number of nodes skipped when following forward, used to compute ranks
+    span: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    member: String,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Level>,
+}
+
+/**
+ * Skip list of (score, member) pairs ordered by score then member, modelled after redis' zskiplist.
+ * Every level keeps the span of its links, which allows rank lookups in O(log n).
+ * Nodes live in a vector and link to each other by index, freed slots are reused.
+ */
+#[derive(Clone, Debug)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    length: usize,
+    level: usize,
+}
+
+// Whether the node sorts before (score, member)
+fn before(node: &Node, score: f64, member: &str) -> bool {
+    node.score < score || (node.score == score && node.member.as_str() < member)
+}
+
+impl SkipList {
+    pub fn new() -> SkipList {
+        let head = Node {
+            member: String::new(),
+            score: 0.0,
+            backward: None,
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0,
+                };
+                MAX_LEVEL
+            ],
+        };
+        SkipList {
+            nodes: vec![head],
+            free: vec![],
+            tail: None,
+            length: 0,
+            level: 1,
+        }
+    }
+
+    fn random_level() -> usize {
+        let mut rng = rand::thread_rng();
+        let mut level = 1;
+        while level < MAX_LEVEL && rng.gen::<f64>() < P {
+            level += 1;
+        }
+        level
+    }
+
+    fn forward(&self, node: usize, level: usize) -> Option<usize> {
+        self.nodes[node].levels[level].forward
+    }
+
+    /**
+     * Inserts a pair, the caller makes sure the member is not in the list yet.
+     */
+    pub fn insert(&mut self, score: f64, member: String) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.forward(x, i) {
+                if !before(&self.nodes[next], score, &member) {
+                    break;
+                }
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = Self::random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.length;
+            }
+            self.level = level;
+        }
+
+        let node = Node {
+            member,
+            score,
+            backward: None,
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0,
+                };
+                level
+            ],
+        };
+        let x = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let prev = update[i];
+            self.nodes[x].levels[i].forward = self.nodes[prev].levels[i].forward;
+            self.nodes[prev].levels[i].forward = Some(x);
+
+            self.nodes[x].levels[i].span = self.nodes[prev].levels[i].span - (rank[0] - rank[i]);
+            self.nodes[prev].levels[i].span = (rank[0] - rank[i]) + 1;
+        }
+        // The untouched levels now skip one more node
+        for (i, prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*prev].levels[i].span += 1;
+        }
+
+        self.nodes[x].backward = if update[0] == HEAD {
+            None
+        } else {
+            Some(update[0])
+        };
+        match self.forward(x, 0) {
+            Some(next) => self.nodes[next].backward = Some(x),
+            None => self.tail = Some(x),
+        }
+        self.length += 1;
+    }
+
+    /**
+     * Removes a pair, returns false if it was not found.
+     */
+    pub fn delete(&mut self, score: f64, member: &str) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if !before(&self.nodes[next], score, member) {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        match self.forward(x, 0) {
+            Some(x) if self.nodes[x].score == score && self.nodes[x].member == member => {
+                self.delete_node(x, &update);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn delete_node(&mut self, x: usize, update: &[usize; MAX_LEVEL]) {
+        for (i, prev) in update.iter().enumerate().take(self.level) {
+            if self.nodes[*prev].levels[i].forward == Some(x) {
+                self.nodes[*prev].levels[i].span += self.nodes[x].levels[i].span;
+                self.nodes[*prev].levels[i].span -= 1;
+                self.nodes[*prev].levels[i].forward = self.nodes[x].levels[i].forward;
+            } else {
+                self.nodes[*prev].levels[i].span -= 1;
+            }
+        }
+
+        match self.forward(x, 0) {
+            Some(next) => self.nodes[next].backward = self.nodes[x].backward,
+            None => self.tail = self.nodes[x].backward,
+        }
+
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].forward.is_none() {
+            self.level -= 1;
+        }
+        self.length -= 1;
+
+        self.nodes[x].member = String::new();
+        self.nodes[x].levels.clear();
+        self.free.push(x);
+    }
+
+    /**
+     * The 0 based rank of a pair in the list.
+     */
+    pub fn rank(&self, score: f64, member: &str) -> Option<usize> {
+        let mut rank = 0;
+
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                let node = &self.nodes[next];
+                if !(before(node, score, member) || (node.score == score && node.member == member))
+                {
+                    break;
+                }
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if x != HEAD && self.nodes[x].member == member {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /**
+     * Finds the node at a 0 based rank.
+     */
+    fn node_by_rank(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let mut traversed = 0;
+
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if traversed + self.nodes[x].levels[i].span > target {
+                    break;
+                }
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    // The first node for which in_range holds, given that in_range is false up to some point and true after
+    fn first_where(&self, in_range: impl Fn(&Node) -> bool) -> Option<usize> {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if in_range(&self.nodes[next]) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        self.forward(x, 0)
+    }
+
+    // The last node for which in_range holds, given that in_range is true up to some point and false after
+    fn last_where(&self, in_range: impl Fn(&Node) -> bool) -> Option<usize> {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if !in_range(&self.nodes[next]) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        if x == HEAD {
+            None
+        } else {
+            Some(x)
+        }
+    }
+
+    /**
+     * Walks the list from the node at a rank, forwards or backwards.
+     */
+    pub fn iter_from_rank(&self, rank: usize, reverse: bool) -> Iter<'_> {
+        Iter {
+            list: self,
+            node: self.node_by_rank(rank),
+            reverse,
+        }
+    }
+
+    /**
+     * Walks forwards from the first pair with a score above min, see first_where.
+     */
+    pub fn iter_from(&self, above_min: impl Fn(f64, &str) -> bool) -> Iter<'_> {
+        Iter {
+            list: self,
+            node: self.first_where(|node| above_min(node.score, &node.member)),
+            reverse: false,
+        }
+    }
+
+    /**
+     * Walks backwards from the last pair with a score below max, see last_where.
+     */
+    pub fn iter_back_from(&self, below_max: impl Fn(f64, &str) -> bool) -> Iter<'_> {
+        Iter {
+            list: self,
+            node: self.last_where(|node| below_max(node.score, &node.member)),
+            reverse: true,
+        }
+    }
+
+    pub fn first(&self) -> Option<(&str, f64)> {
+        self.forward(HEAD, 0)
+            .map(|x| (self.nodes[x].member.as_str(), self.nodes[x].score))
+    }
+
+    pub fn last(&self) -> Option<(&str, f64)> {
+        self.tail
+            .map(|x| (self.nodes[x].member.as_str(), self.nodes[x].score))
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            list: self,
+            node: self.forward(HEAD, 0),
+            reverse: false,
+        }
+    }
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    node: Option<usize>,
+    reverse: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let x = self.node?;
+        let node = &self.list.nodes[x];
+        self.node = if self.reverse {
+            node.backward
+        } else {
+            node.levels[0].forward
+        };
+        Some((node.member.as_str(), node.score))
+    }
+}
@@ -0,0 +1,946 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    ops::Bound,
+};
+
+use super::{now_ms, DBError, Data, EventClass, Store, Value, DB};
+
+pub type Field = (String, Vec<u8>);
+pub type StreamEntry = (StreamId, Vec<Field>);
+// Entries read by a consumer group, None for pending entries that were deleted from the stream
+pub type GroupEntry = (StreamId, Option<Vec<Field>>);
+
+/**
+ * The id of a stream entry: the unix time in milliseconds it was added at and a sequence number
+ * for entries added within the same millisecond.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /**
+     * Parses "ms-seq", a missing sequence number is replaced by default_seq.
+     */
+    pub fn parse(input: &str, default_seq: u64) -> Option<StreamId> {
+        match input.split_once('-') {
+            Some((ms, seq)) => Some(StreamId {
+                ms: ms.parse().ok()?,
+                seq: seq.parse().ok()?,
+            }),
+            None => Some(StreamId {
+                ms: input.parse().ok()?,
+                seq: default_seq,
+            }),
+        }
+    }
+
+    // The smallest id greater than this one
+    pub fn next(&self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/**
+ * The id given to XADD.
+ */
+#[derive(Clone, Copy)]
+pub enum XAddId {
+    // *
+    Auto,
+    // ms-*
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+/**
+ * The MAXLEN|MINID trimming strategy of XADD and XTRIM.
+ * Approximate trimming (~) is done exactly, limit caps the number of evicted entries.
+ */
+#[derive(Clone, Copy)]
+pub struct StreamTrim {
+    pub strategy: TrimStrategy,
+    pub limit: Option<usize>,
+}
+
+#[derive(Clone, Copy)]
+pub enum TrimStrategy {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+/**
+ * An append only log of entries with field value pairs, optionally read through consumer groups.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Stream {
+    pub entries: BTreeMap<StreamId, Vec<Field>>,
+    // The id of the latest entry ever added, entries can't be added below it
+    pub last_id: StreamId,
+    // Number of entries added over the lifetime of the stream
+    pub entries_added: u64,
+    pub groups: BTreeMap<String, ConsumerGroup>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConsumerGroup {
+    pub last_delivered: StreamId,
+    // Entries delivered to a consumer but not acknowledged yet
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<String, Consumer>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PendingEntry {
+    pub consumer: String,
+    // Unix time in milliseconds of the last delivery
+    pub delivered_at: u64,
+    pub deliveries: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Consumer {
+    // Unix time in milliseconds the consumer last read or claimed entries
+    pub seen_at: u64,
+}
+
+/**
+ * The options of XCLAIM.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct ClaimOptions {
+    // Sets the delivery time of claimed entries, as an idle time or a unix time in milliseconds
+    pub idle: Option<u64>,
+    pub time: Option<u64>,
+    pub retry_count: Option<u64>,
+    // Claims ids that are not pending yet as long as they exist in the stream
+    pub force: bool,
+    // Doesn't count as a delivery
+    pub just_id: bool,
+    pub last_id: Option<StreamId>,
+}
+
+pub struct PendingSummary {
+    pub count: usize,
+    // The smallest and greatest pending ids
+    pub bounds: Option<(StreamId, StreamId)>,
+    pub consumers: Vec<(String, usize)>,
+}
+
+pub struct PendingDetail {
+    pub id: StreamId,
+    pub consumer: String,
+    pub idle: u64,
+    pub deliveries: u64,
+}
+
+pub struct StreamInfo {
+    pub length: usize,
+    pub last_id: StreamId,
+    pub entries_added: u64,
+    pub groups: usize,
+    pub first: Option<StreamEntry>,
+    pub last: Option<StreamEntry>,
+}
+
+pub struct GroupInfo {
+    pub name: String,
+    pub consumers: usize,
+    pub pending: usize,
+    pub last_delivered: StreamId,
+}
+
+pub struct ConsumerInfo {
+    pub name: String,
+    pub pending: usize,
+    pub idle: u64,
+}
+
+impl Stream {
+    pub fn new() -> Stream {
+        Stream::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /**
+     * Appends an entry and returns its id.
+     */
+    fn add(&mut self, id: XAddId, fields: Vec<Field>) -> Result<StreamId, DBError> {
+        let id = match id {
+            XAddId::Auto => {
+                let ms = now_ms();
+                if ms > self.last_id.ms {
+                    StreamId { ms, seq: 0 }
+                } else {
+                    self.last_id.next().ok_or_else(id_too_small)?
+                }
+            }
+            XAddId::AutoSeq(ms) if ms == self.last_id.ms => {
+                self.last_id.next().ok_or_else(id_too_small)?
+            }
+            XAddId::AutoSeq(ms) => StreamId {
+                ms,
+                seq: if ms == 0 { 1 } else { 0 },
+            },
+            XAddId::Explicit(id) => id,
+        };
+        if id == StreamId::MIN {
+            return Err(DBError::Other(
+                "ERR The ID specified in XADD must be greater than 0-0".to_string(),
+            ));
+        }
+        if id <= self.last_id {
+            return Err(id_too_small());
+        }
+
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+        Ok(id)
+    }
+
+    /**
+     * Evicts the oldest entries, returns how many were removed.
+     */
+    fn trim(&mut self, trim: StreamTrim) -> usize {
+        let limit = trim.limit.filter(|limit| *limit > 0).unwrap_or(usize::MAX);
+        let mut removed = 0;
+        while removed < limit {
+            let oldest = match self.entries.keys().next() {
+                Some(oldest) => *oldest,
+                None => break,
+            };
+            let evict = match trim.strategy {
+                TrimStrategy::MaxLen(max) => self.len() > max,
+                TrimStrategy::MinId(min) => oldest < min,
+            };
+            if !evict {
+                break;
+            }
+            self.entries.remove(&oldest);
+            removed += 1;
+        }
+        removed
+    }
+
+    fn range(
+        &self,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        reverse: bool,
+        count: Option<usize>,
+    ) -> Vec<StreamEntry> {
+        if !valid_range(start, end) {
+            return vec![];
+        }
+        let entries = self.entries.range((start, end));
+        let count = count.unwrap_or(usize::MAX);
+        let clone = |(id, fields): (&StreamId, &Vec<Field>)| (*id, fields.clone());
+        if reverse {
+            entries.rev().take(count).map(clone).collect()
+        } else {
+            entries.take(count).map(clone).collect()
+        }
+    }
+}
+
+fn id_too_small() -> DBError {
+    DBError::Other(
+        "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+            .to_string(),
+    )
+}
+
+// BTreeMap::range panics when start is greater than end
+fn valid_range(start: Bound<StreamId>, end: Bound<StreamId>) -> bool {
+    match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) => start <= end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end)) => start < end,
+        _ => true,
+    }
+}
+
+fn no_group(key: &str, group: &str) -> DBError {
+    DBError::Other(format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        key, group
+    ))
+}
+
+impl ConsumerGroup {
+    fn new(last_delivered: StreamId) -> ConsumerGroup {
+        ConsumerGroup {
+            last_delivered,
+            ..Default::default()
+        }
+    }
+
+    // Marks a consumer as seen, creating it if needed
+    fn touch(&mut self, consumer: &str) {
+        self.consumers
+            .insert(consumer.to_string(), Consumer { seen_at: now_ms() });
+    }
+
+    // Gives a pending entry to a consumer, counting a new delivery
+    fn deliver(&mut self, id: StreamId, consumer: &str, now: u64) {
+        let pending = self.pending.entry(id).or_insert(PendingEntry {
+            consumer: consumer.to_string(),
+            delivered_at: now,
+            deliveries: 0,
+        });
+        pending.consumer = consumer.to_string();
+        pending.delivered_at = now;
+        pending.deliveries += 1;
+    }
+}
+
+impl Store {
+    fn stream(&self, key: &str) -> Result<Option<&Stream>, DBError> {
+        match self.entries.get(key) {
+            Some(Value {
+                value: Data::Stream(stream),
+                ..
+            }) => Ok(Some(stream)),
+            Some(_) => Err(DBError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn stream_mut(&mut self, key: &str) -> Result<Option<&mut Stream>, DBError> {
+        self.touch(key);
+        match self.entries.get_mut(key) {
+            Some(Value {
+                value: Data::Stream(stream),
+                ..
+            }) => Ok(Some(stream)),
+            Some(_) => Err(DBError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    // Gets the stream at key, creating an empty one if the key does not exist
+    fn stream_or_create(&mut self, key: &str) -> Result<&mut Stream, DBError> {
+        self.touch(key);
+        if !self.entries.contains_key(key) {
+            self.notify(EventClass::New, "new", key);
+        }
+        let value = self
+            .entries
+            .entry(key.to_string())
+            .or_insert_with(|| Value {
+                value: Data::Stream(Stream::new()),
+                expires_at: None,
+            });
+        match &mut value.value {
+            Data::Stream(stream) => Ok(stream),
+            _ => Err(DBError::WrongType),
+        }
+    }
+
+    // Gets a stream together with one of its consumer groups
+    fn stream_group(
+        &mut self,
+        key: &str,
+        group: &str,
+    ) -> Result<(&BTreeMap<StreamId, Vec<Field>>, &mut ConsumerGroup), DBError> {
+        let stream = self.stream_mut(key)?.ok_or_else(|| no_group(key, group))?;
+        let group = stream
+            .groups
+            .get_mut(group)
+            .ok_or_else(|| no_group(key, group))?;
+        Ok((&stream.entries, group))
+    }
+}
+
+impl DB {
+    /**
+     * Appends an entry to the stream at key, creating it unless nomkstream is set.
+     * Returns the id of the new entry, None if the stream does not exist and nomkstream is set.
+     */
+    pub fn xadd(
+        &mut self,
+        key: &str,
+        id: XAddId,
+        fields: Vec<Field>,
+        nomkstream: bool,
+        trim: Option<StreamTrim>,
+    ) -> Result<Option<StreamId>, DBError> {
+        let mut store = self.db.lock();
+        if nomkstream && store.stream(key)?.is_none() {
+            return Ok(None);
+        }
+        // Check the type before creating the stream so a failed add doesn't leave an empty one behind
+        let created = store.stream(key)?.is_none();
+        let stream = store.stream_or_create(key)?;
+        let id = match stream.add(id, fields) {
+            Ok(id) => id,
+            Err(err) => {
+                if created {
+                    store.remove(key);
+                }
+                return Err(err);
+            }
+        };
+        let trimmed = trim.map(|trim| stream.trim(trim)).unwrap_or(0);
+        store.notify(EventClass::Stream, "xadd", key);
+        if trimmed > 0 {
+            store.notify(EventClass::Stream, "xtrim", key);
+        }
+        self.db.key_ready.notify_waiters();
+
+        Ok(Some(id))
+    }
+
+    pub fn xtrim(&mut self, key: &str, trim: StreamTrim) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        let trimmed = store
+            .stream_mut(key)?
+            .map(|stream| stream.trim(trim))
+            .unwrap_or(0);
+        if trimmed > 0 {
+            store.notify(EventClass::Stream, "xtrim", key);
+        }
+
+        Ok(trimmed)
+    }
+
+    pub fn xlen(&self, key: &str) -> Result<usize, DBError> {
+        let store = self.db.lock();
+
+        Ok(store.stream(key)?.map(|stream| stream.len()).unwrap_or(0))
+    }
+
+    /**
+     * Removes entries from a stream, returns how many existed.
+     */
+    pub fn xdel(&mut self, key: &str, ids: &[StreamId]) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+
+        let deleted = match store.stream_mut(key)? {
+            Some(stream) => ids
+                .iter()
+                .filter(|id| stream.entries.remove(id).is_some())
+                .count(),
+            None => 0,
+        };
+        if deleted > 0 {
+            store.notify(EventClass::Stream, "xdel", key);
+        }
+
+        Ok(deleted)
+    }
+
+    /**
+     * The entries between start and end, from the end when reversed.
+     */
+    pub fn xrange(
+        &self,
+        key: &str,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        reverse: bool,
+        count: Option<usize>,
+    ) -> Result<Vec<StreamEntry>, DBError> {
+        let store = self.db.lock();
+
+        Ok(store
+            .stream(key)?
+            .map(|stream| stream.range(start, end, reverse, count))
+            .unwrap_or_default())
+    }
+
+    /**
+     * The id of the latest entry added to a stream, used to resolve the $ id.
+     */
+    pub fn xlast_id(&self, key: &str) -> Result<StreamId, DBError> {
+        let store = self.db.lock();
+
+        Ok(store
+            .stream(key)?
+            .map(|stream| stream.last_id)
+            .unwrap_or_default())
+    }
+
+    /**
+     * Reads the entries added after the given id of each stream.
+     * Streams without new entries are left out.
+     */
+    pub fn xread(
+        &self,
+        streams: &[(String, StreamId)],
+        count: Option<usize>,
+    ) -> Result<Vec<(String, Vec<StreamEntry>)>, DBError> {
+        let store = self.db.lock();
+
+        let mut result = vec![];
+        for (key, id) in streams {
+            if let Some(stream) = store.stream(key)? {
+                let entries = stream.range(Bound::Excluded(*id), Bound::Unbounded, false, count);
+                if !entries.is_empty() {
+                    result.push((key.clone(), entries));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /**
+     * Creates a consumer group that delivers entries after id, None meaning the end of the stream ($).
+     */
+    pub fn xgroup_create(
+        &mut self,
+        key: &str,
+        group: &str,
+        id: Option<StreamId>,
+        mkstream: bool,
+    ) -> Result<(), DBError> {
+        let mut store = self.db.lock();
+        let stream = match store.stream(key)? {
+            Some(_) => store.stream_mut(key)?.unwrap(),
+            None if mkstream => store.stream_or_create(key)?,
+            None => return Err(DBError::Other(
+                "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically."
+                    .to_string(),
+            )),
+        };
+        if stream.groups.contains_key(group) {
+            return Err(DBError::Other(
+                "BUSYGROUP Consumer Group name already exists".to_string(),
+            ));
+        }
+        let last_delivered = id.unwrap_or(stream.last_id);
+        stream
+            .groups
+            .insert(group.to_string(), ConsumerGroup::new(last_delivered));
+        store.notify(EventClass::Stream, "xgroup-create", key);
+
+        Ok(())
+    }
+
+    pub fn xgroup_setid(
+        &mut self,
+        key: &str,
+        group: &str,
+        id: Option<StreamId>,
+    ) -> Result<(), DBError> {
+        let mut store = self.db.lock();
+        let stream = store.stream_mut(key)?.ok_or_else(|| no_group(key, group))?;
+        let last_id = stream.last_id;
+        let group = stream
+            .groups
+            .get_mut(group)
+            .ok_or_else(|| no_group(key, group))?;
+        group.last_delivered = id.unwrap_or(last_id);
+        store.notify(EventClass::Stream, "xgroup-setid", key);
+
+        Ok(())
+    }
+
+    pub fn xgroup_destroy(&mut self, key: &str, group: &str) -> Result<bool, DBError> {
+        let mut store = self.db.lock();
+
+        let destroyed = match store.stream_mut(key)? {
+            Some(stream) => stream.groups.remove(group).is_some(),
+            None => false,
+        };
+        if destroyed {
+            store.notify(EventClass::Stream, "xgroup-destroy", key);
+        }
+
+        Ok(destroyed)
+    }
+
+    pub fn xgroup_createconsumer(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+    ) -> Result<bool, DBError> {
+        let mut store = self.db.lock();
+        let (_, group) = store.stream_group(key, group)?;
+        if group.consumers.contains_key(consumer) {
+            return Ok(false);
+        }
+        group.touch(consumer);
+        store.notify(EventClass::Stream, "xgroup-createconsumer", key);
+
+        Ok(true)
+    }
+
+    /**
+     * Deletes a consumer along with its pending entries, returns how many were pending.
+     */
+    pub fn xgroup_delconsumer(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+    ) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        let (_, group) = store.stream_group(key, group)?;
+        if group.consumers.remove(consumer).is_none() {
+            return Ok(0);
+        }
+        let pending = group.pending.len();
+        group
+            .pending
+            .retain(|_, pending| pending.consumer != consumer);
+        let deleted = pending - group.pending.len();
+        store.notify(EventClass::Stream, "xgroup-delconsumer", key);
+
+        Ok(deleted)
+    }
+
+    /**
+     * Reads through a consumer group.
+     * An id of None (>) delivers entries never delivered to the group and adds them to the pending list unless noack is set.
+     * Other ids return the history of the consumer's pending entries after that id.
+     * Streams without new entries are left out, histories are always included.
+     */
+    pub fn xreadgroup(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        streams: &[(String, Option<StreamId>)],
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Vec<(String, Vec<GroupEntry>)>, DBError> {
+        let mut store = self.db.lock();
+        // Every stream and group has to exist before anything is read
+        for (key, _) in streams {
+            store.stream_group(key, group)?;
+        }
+
+        let now = now_ms();
+        let mut result = vec![];
+        for (key, id) in streams {
+            let (entries, group) = store.stream_group(key, group)?;
+            group.touch(consumer);
+
+            match id {
+                None => {
+                    let new: Vec<StreamEntry> = entries
+                        .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+                        .take(count.unwrap_or(usize::MAX))
+                        .map(|(id, fields)| (*id, fields.clone()))
+                        .collect();
+                    if let Some((last, _)) = new.last() {
+                        group.last_delivered = *last;
+                    }
+                    if !noack {
+                        for (id, _) in new.iter() {
+                            group.deliver(*id, consumer, now);
+                        }
+                    }
+                    if !new.is_empty() {
+                        result.push((
+                            key.clone(),
+                            new.into_iter()
+                                .map(|(id, fields)| (id, Some(fields)))
+                                .collect(),
+                        ));
+                    }
+                }
+                Some(id) => {
+                    let history = group
+                        .pending
+                        .range((Bound::Excluded(*id), Bound::Unbounded))
+                        .filter(|(_, pending)| pending.consumer == consumer)
+                        .take(count.unwrap_or(usize::MAX))
+                        .map(|(id, _)| (*id, entries.get(id).cloned()))
+                        .collect();
+                    result.push((key.clone(), history));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /**
+     * Acknowledges pending entries, returns how many were pending.
+     */
+    pub fn xack(&mut self, key: &str, group: &str, ids: &[StreamId]) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        let group = match store.stream_mut(key)? {
+            Some(stream) => match stream.groups.get_mut(group) {
+                Some(group) => group,
+                None => return Ok(0),
+            },
+            None => return Ok(0),
+        };
+
+        Ok(ids
+            .iter()
+            .filter(|id| group.pending.remove(id).is_some())
+            .count())
+    }
+
+    /**
+     * The summary form of XPENDING.
+     */
+    pub fn xpending_summary(&self, key: &str, group: &str) -> Result<PendingSummary, DBError> {
+        let store = self.db.lock();
+        let group = store
+            .stream(key)?
+            .and_then(|stream| stream.groups.get(group))
+            .ok_or_else(|| no_group(key, group))?;
+
+        let mut consumers: BTreeMap<&str, usize> = BTreeMap::new();
+        for pending in group.pending.values() {
+            *consumers.entry(&pending.consumer).or_default() += 1;
+        }
+        let bounds = group
+            .pending
+            .keys()
+            .next()
+            .zip(group.pending.keys().next_back());
+
+        Ok(PendingSummary {
+            count: group.pending.len(),
+            bounds: bounds.map(|(first, last)| (*first, *last)),
+            consumers: consumers
+                .into_iter()
+                .map(|(consumer, count)| (consumer.to_string(), count))
+                .collect(),
+        })
+    }
+
+    /**
+     * The extended form of XPENDING, listing pending entries between start and end.
+     */
+    #[allow(clippy::too_many_arguments)]
+    pub fn xpending(
+        &self,
+        key: &str,
+        group: &str,
+        min_idle: u64,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: usize,
+        consumer: Option<&str>,
+    ) -> Result<Vec<PendingDetail>, DBError> {
+        let store = self.db.lock();
+        let group = store
+            .stream(key)?
+            .and_then(|stream| stream.groups.get(group))
+            .ok_or_else(|| no_group(key, group))?;
+        if !valid_range(start, end) {
+            return Ok(vec![]);
+        }
+
+        let now = now_ms();
+        Ok(group
+            .pending
+            .range((start, end))
+            .filter(|(_, pending)| consumer.is_none_or(|consumer| pending.consumer == consumer))
+            .map(|(id, pending)| PendingDetail {
+                id: *id,
+                consumer: pending.consumer.clone(),
+                idle: now.saturating_sub(pending.delivered_at),
+                deliveries: pending.deliveries,
+            })
+            .filter(|detail| detail.idle >= min_idle)
+            .take(count)
+            .collect())
+    }
+
+    /**
+     * Transfers pending entries idle for at least min_idle milliseconds to consumer.
+     * Entries deleted from the stream are dropped from the pending list instead.
+     */
+    pub fn xclaim(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        ids: &[StreamId],
+        options: ClaimOptions,
+    ) -> Result<Vec<StreamEntry>, DBError> {
+        let mut store = self.db.lock();
+        let (entries, group) = store.stream_group(key, group)?;
+        let now = now_ms();
+        let delivered_at = match (options.idle, options.time) {
+            (Some(idle), _) => now.saturating_sub(idle),
+            (None, Some(time)) => time,
+            (None, None) => now,
+        };
+
+        if let Some(last_id) = options.last_id {
+            group.last_delivered = group.last_delivered.max(last_id);
+        }
+        group.touch(consumer);
+
+        let mut claimed = vec![];
+        for id in ids {
+            let fields = match entries.get(id) {
+                Some(fields) => fields,
+                None => {
+                    group.pending.remove(id);
+                    continue;
+                }
+            };
+            let pending = match group.pending.get_mut(id) {
+                Some(pending) => pending,
+                None if options.force => group.pending.entry(*id).or_insert(PendingEntry {
+                    consumer: consumer.to_string(),
+                    delivered_at: 0,
+                    deliveries: 0,
+                }),
+                None => continue,
+            };
+            if now.saturating_sub(pending.delivered_at) < min_idle {
+                continue;
+            }
+
+            pending.consumer = consumer.to_string();
+            pending.delivered_at = delivered_at;
+            if let Some(retry_count) = options.retry_count {
+                pending.deliveries = retry_count;
+            } else if !options.just_id {
+                pending.deliveries += 1;
+            }
+            claimed.push((*id, fields.clone()));
+        }
+        Ok(claimed)
+    }
+
+    /**
+     * Claims up to count pending entries idle for at least min_idle milliseconds, scanning from start.
+     * Returns the id to continue scanning from (0-0 once done), the claimed entries and the ids of
+     * pending entries that were deleted from the stream.
+     */
+    #[allow(clippy::too_many_arguments)]
+    pub fn xautoclaim(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        start: StreamId,
+        count: usize,
+        just_id: bool,
+    ) -> Result<(StreamId, Vec<StreamEntry>, Vec<StreamId>), DBError> {
+        let mut store = self.db.lock();
+        let (entries, group) = store.stream_group(key, group)?;
+        let now = now_ms();
+        group.touch(consumer);
+
+        let mut claimed = vec![];
+        let mut deleted = vec![];
+        let mut next = StreamId::MIN;
+        // Like redis, look at no more than 10 times count entries per call
+        let mut attempts = count.saturating_mul(10);
+        let ids: Vec<StreamId> = group.pending.range(start..).map(|(id, _)| *id).collect();
+        for id in ids {
+            if claimed.len() == count || attempts == 0 {
+                next = id;
+                break;
+            }
+            attempts -= 1;
+
+            let fields = match entries.get(&id) {
+                Some(fields) => fields,
+                None => {
+                    group.pending.remove(&id);
+                    deleted.push(id);
+                    continue;
+                }
+            };
+            let pending = group.pending.get_mut(&id).unwrap();
+            if now.saturating_sub(pending.delivered_at) < min_idle {
+                continue;
+            }
+            pending.consumer = consumer.to_string();
+            pending.delivered_at = now;
+            if !just_id {
+                pending.deliveries += 1;
+            }
+            claimed.push((id, fields.clone()));
+        }
+        Ok((next, claimed, deleted))
+    }
+
+    pub fn xinfo_stream(&self, key: &str) -> Result<Option<StreamInfo>, DBError> {
+        let store = self.db.lock();
+        let clone = |(id, fields): (&StreamId, &Vec<Field>)| (*id, fields.clone());
+
+        Ok(store.stream(key)?.map(|stream| StreamInfo {
+            length: stream.len(),
+            last_id: stream.last_id,
+            entries_added: stream.entries_added,
+            groups: stream.groups.len(),
+            first: stream.entries.iter().next().map(clone),
+            last: stream.entries.iter().next_back().map(clone),
+        }))
+    }
+
+    pub fn xinfo_groups(&self, key: &str) -> Result<Option<Vec<GroupInfo>>, DBError> {
+        let store = self.db.lock();
+
+        Ok(store.stream(key)?.map(|stream| {
+            stream
+                .groups
+                .iter()
+                .map(|(name, group)| GroupInfo {
+                    name: name.clone(),
+                    consumers: group.consumers.len(),
+                    pending: group.pending.len(),
+                    last_delivered: group.last_delivered,
+                })
+                .collect()
+        }))
+    }
+
+    pub fn xinfo_consumers(&self, key: &str, group: &str) -> Result<Vec<ConsumerInfo>, DBError> {
+        let store = self.db.lock();
+        let group = store
+            .stream(key)?
+            .and_then(|stream| stream.groups.get(group))
+            .ok_or_else(|| no_group(key, group))?;
+
+        let now = now_ms();
+        let mut pending: HashMap<&str, usize> = HashMap::new();
+        for entry in group.pending.values() {
+            *pending.entry(&entry.consumer).or_default() += 1;
+        }
+        Ok(group
+            .consumers
+            .iter()
+            .map(|(name, consumer)| ConsumerInfo {
+                name: name.clone(),
+                pending: pending.get(name.as_str()).copied().unwrap_or(0),
+                idle: now.saturating_sub(consumer.seen_at),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{Store, DB};
+
+/**
+ * Which connections may have cached which keys, for client side caching.
+ * Connections are known by their id, their invalidations are sent to the connection they redirect to.
+ */
+#[derive(Default)]
+pub struct TrackingTable {
+    // The connection each tracking connection redirects its invalidations to
+    redirects: HashMap<u64, u64>,
+    // Connections that read each key, a key is forgotten once it's invalidated
+    keys: HashMap<String, HashSet<u64>>,
+    // BCAST connections by the prefixes they track, an empty prefix matches every key
+    prefixes: HashMap<String, HashSet<u64>>,
+    // Invalidated keys waiting for the store to be unlocked, by the connection they are sent to
+    pending: HashMap<u64, Vec<String>>,
+}
+
+impl TrackingTable {
+    // Queues an invalidation of key for the connections tracking it
+    fn invalidate(&mut self, key: &str) {
+        let mut ids = self.keys.remove(key).unwrap_or_default();
+        for (prefix, prefix_ids) in &self.prefixes {
+            if key.starts_with(prefix.as_str()) {
+                ids.extend(prefix_ids);
+            }
+        }
+
+        let mut targets: HashSet<u64> = HashSet::new();
+        for id in ids {
+            // Connections that turned tracking off since reading the key are skipped
+            if let Some(redirect) = self.redirects.get(&id) {
+                targets.insert(*redirect);
+            }
+        }
+        for target in targets {
+            self.pending
+                .entry(target)
+                .or_default()
+                .push(key.to_string());
+        }
+    }
+
+    pub(super) fn take_pending(&mut self) -> HashMap<u64, Vec<String>> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+impl Store {
+    // Invalidates the cached copies of a key about to be modified
+    pub(super) fn invalidate(&mut self, key: &str) {
+        if !self.tracking.redirects.is_empty() {
+            self.tracking.invalidate(key);
+        }
+    }
+}
+
+impl DB {
+    /**
+     * Turns tracking on for a connection, its invalidations are sent to redirect.
+     * With BCAST prefixes the connection is sent every key matching them, none meaning all keys,
+     * otherwise only the keys it read through track_keys.
+     */
+    pub fn track(&self, id: u64, redirect: u64, bcast: Option<&[String]>) {
+        let mut store = self.db.lock();
+        store.tracking.redirects.insert(id, redirect);
+        if let Some(prefixes) = bcast {
+            let all = [String::new()];
+            let prefixes = match prefixes.is_empty() {
+                true => &all[..],
+                false => prefixes,
+            };
+            for prefix in prefixes {
+                store
+                    .tracking
+                    .prefixes
+                    .entry(prefix.clone())
+                    .or_default()
+                    .insert(id);
+            }
+        }
+    }
+
+    /**
+     * Turns tracking off for a connection.
+     * The keys it read are dropped lazily, when they get invalidated.
+     */
+    pub fn untrack(&self, id: u64) {
+        let mut store = self.db.lock();
+        store.tracking.redirects.remove(&id);
+        store.tracking.prefixes.retain(|_, ids| {
+            ids.remove(&id);
+            !ids.is_empty()
+        });
+    }
+
+    /**
+     * Remembers keys read by a tracking connection so it gets invalidated when they are modified.
+     */
+    pub fn track_keys(&self, id: u64, keys: &[String]) {
+        let mut store = self.db.lock();
+        for key in keys {
+            store
+                .tracking
+                .keys
+                .entry(key.clone())
+                .or_default()
+                .insert(id);
+        }
+    }
+}
@@ -0,0 +1,757 @@
+use std::collections::HashMap;
+
+use super::{scan, skiplist::SkipList, DBError, Data, EventClass, Store, Value, DB};
+
+type Member = (String, f64);
+
+/**
+ * A sorted set: unique members ordered by their score.
+ * A map gives O(1) score lookups by member while a skip list keeps the ordering for ranges and ranks.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    list: SkipList,
+}
+
+/**
+ * A min or max score of a range, exclusive when written as "(score".
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct ScoreBound {
+    pub value: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    fn above_min(&self, score: f64) -> bool {
+        if self.exclusive {
+            score > self.value
+        } else {
+            score >= self.value
+        }
+    }
+
+    fn below_max(&self, score: f64) -> bool {
+        if self.exclusive {
+            score < self.value
+        } else {
+            score <= self.value
+        }
+    }
+}
+
+/**
+ * A min or max member of a lexicographical range: "-", "+", "[member" or "(member".
+ */
+#[derive(Clone, Debug)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(String),
+    Exclusive(String),
+}
+
+impl LexBound {
+    fn above_min(&self, member: &str) -> bool {
+        match self {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(min) => member >= min.as_str(),
+            LexBound::Exclusive(min) => member > min.as_str(),
+        }
+    }
+
+    fn below_max(&self, member: &str) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(max) => member <= max.as_str(),
+            LexBound::Exclusive(max) => member < max.as_str(),
+        }
+    }
+}
+
+/**
+ * What ZRANGE selects members by.
+ * Bounds are always given as (min, max) even for reversed ranges.
+ */
+#[derive(Clone, Debug)]
+pub enum ZRange {
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+/**
+ * The NX|XX|GT|LT|CH|INCR options of ZADD.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct ZAddFlags {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    pub ch: bool,
+    pub incr: bool,
+}
+
+pub struct ZAddReply {
+    pub added: usize,
+    pub updated: usize,
+    // The new score in INCR mode, None if the update was prevented by the flags
+    pub score: Option<f64>,
+}
+
+/**
+ * How ZUNIONSTORE and ZINTERSTORE combine the scores of a member present in several sets.
+ */
+#[derive(Clone, Copy)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(&self, a: f64, b: f64) -> f64 {
+        match self {
+            Aggregate::Sum => {
+                let sum = a + b;
+                // inf + -inf, redis settles on 0
+                if sum.is_nan() {
+                    0.0
+                } else {
+                    sum
+                }
+            }
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> SortedSet {
+        SortedSet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /**
+     * Adds a member or updates its score, returns true if it was added.
+     */
+    pub fn insert(&mut self, member: String, score: f64) -> bool {
+        match self.scores.insert(member.clone(), score) {
+            Some(old) => {
+                if old != score {
+                    self.list.delete(old, &member);
+                    self.list.insert(score, member);
+                }
+                false
+            }
+            None => {
+                self.list.insert(score, member);
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, member: &str) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.list.delete(score, member),
+            None => false,
+        }
+    }
+
+    /**
+     * The 0 based rank of a member, counted from the highest score when reversed.
+     */
+    pub fn rank(&self, member: &str, reverse: bool) -> Option<usize> {
+        let score = self.score(member)?;
+        let rank = self.list.rank(score, member)?;
+        if reverse {
+            Some(self.len() - 1 - rank)
+        } else {
+            Some(rank)
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.list.iter()
+    }
+
+    /**
+     * Members between two ranks, negative ranks count from the end like in ZRANGE.
+     */
+    pub fn range_by_rank(&self, start: i64, stop: i64, reverse: bool) -> Vec<Member> {
+        let len = self.len() as i64;
+        let start = if start < 0 { start + len } else { start }.max(0);
+        let stop = if stop < 0 { stop + len } else { stop }.min(len - 1);
+        if start > stop || start >= len {
+            return vec![];
+        }
+
+        let first = if reverse { len - 1 - start } else { start };
+        self.list
+            .iter_from_rank(first as usize, reverse)
+            .take((stop - start + 1) as usize)
+            .map(|(member, score)| (member.to_string(), score))
+            .collect()
+    }
+
+    pub fn range_by_score(
+        &self,
+        min: ScoreBound,
+        max: ScoreBound,
+        reverse: bool,
+        offset: usize,
+        count: Option<usize>,
+    ) -> Vec<Member> {
+        let members: Box<dyn Iterator<Item = (&str, f64)>> = if reverse {
+            Box::new(
+                self.list
+                    .iter_back_from(|score, _| max.below_max(score))
+                    .take_while(|(_, score)| min.above_min(*score)),
+            )
+        } else {
+            Box::new(
+                self.list
+                    .iter_from(|score, _| min.above_min(score))
+                    .take_while(|(_, score)| max.below_max(*score)),
+            )
+        };
+
+        members
+            .skip(offset)
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(member, score)| (member.to_string(), score))
+            .collect()
+    }
+
+    /**
+     * Members between two lexicographical bounds.
+     * Like in redis this only makes sense when all members have the same score.
+     */
+    pub fn range_by_lex(
+        &self,
+        min: &LexBound,
+        max: &LexBound,
+        reverse: bool,
+        offset: usize,
+        count: Option<usize>,
+    ) -> Vec<Member> {
+        let members: Box<dyn Iterator<Item = (&str, f64)>> = if reverse {
+            Box::new(
+                self.list
+                    .iter_back_from(|_, member| max.below_max(member))
+                    .take_while(|(member, _)| min.above_min(member)),
+            )
+        } else {
+            Box::new(
+                self.list
+                    .iter_from(|_, member| min.above_min(member))
+                    .take_while(|(member, _)| max.below_max(member)),
+            )
+        };
+
+        members
+            .skip(offset)
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(member, score)| (member.to_string(), score))
+            .collect()
+    }
+
+    /**
+     * Counts the members within a score range using their ranks.
+     */
+    pub fn count(&self, min: ScoreBound, max: ScoreBound) -> usize {
+        let first = self.list.iter_from(|score, _| min.above_min(score)).next();
+        let last = self
+            .list
+            .iter_back_from(|score, _| max.below_max(score))
+            .next();
+
+        match (first, last) {
+            (Some((first, first_score)), Some((last, last_score))) => {
+                let first = self.list.rank(first_score, first);
+                let last = self.list.rank(last_score, last);
+                match (first, last) {
+                    (Some(first), Some(last)) if first <= last => last - first + 1,
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    /**
+     * Removes up to count members with the lowest (or highest) scores.
+     */
+    pub fn pop(&mut self, count: usize, max: bool) -> Vec<Member> {
+        let mut popped = Vec::with_capacity(count.min(self.len()));
+        for _ in 0..count {
+            let next = if max {
+                self.list.last()
+            } else {
+                self.list.first()
+            };
+            let (member, score) = match next {
+                Some((member, score)) => (member.to_string(), score),
+                None => break,
+            };
+            self.remove(&member);
+            popped.push((member, score));
+        }
+        popped
+    }
+}
+
+impl Store {
+    pub(super) fn zset(&self, key: &str) -> Result<Option<&SortedSet>, DBError> {
+        match self.entries.get(key) {
+            Some(Value {
+                value: Data::SortedSet(zset),
+                ..
+            }) => Ok(Some(zset)),
+            Some(_) => Err(DBError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn zset_mut(&mut self, key: &str) -> Result<Option<&mut SortedSet>, DBError> {
+        self.touch(key);
+        match self.entries.get_mut(key) {
+            Some(Value {
+                value: Data::SortedSet(zset),
+                ..
+            }) => Ok(Some(zset)),
+            Some(_) => Err(DBError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    // Gets the sorted set at key, creating an empty one if the key does not exist
+    fn zset_or_create(&mut self, key: &str) -> Result<&mut SortedSet, DBError> {
+        self.touch(key);
+        if !self.entries.contains_key(key) {
+            self.notify(EventClass::New, "new", key);
+        }
+        let value = self
+            .entries
+            .entry(key.to_string())
+            .or_insert_with(|| Value {
+                value: Data::SortedSet(SortedSet::new()),
+                expires_at: None,
+            });
+        match &mut value.value {
+            Data::SortedSet(zset) => Ok(zset),
+            _ => Err(DBError::WrongType),
+        }
+    }
+
+    fn remove_empty_zset(&mut self, key: &str) {
+        if let Ok(Some(zset)) = self.zset(key) {
+            if zset.is_empty() {
+                self.remove(key);
+                self.notify(EventClass::Generic, "del", key);
+            }
+        }
+    }
+
+    // The members of a ZUNIONSTORE/ZINTERSTORE source, plain sets count as having a score of 1
+    fn zset_source(&self, key: &str) -> Result<Option<HashMap<String, f64>>, DBError> {
+        match self.entries.get(key) {
+            Some(Value {
+                value: Data::SortedSet(zset),
+                ..
+            }) => Ok(Some(zset.scores.clone())),
+            Some(Value {
+                value: Data::Set(set),
+                ..
+            }) => Ok(Some(
+                set.members()
+                    .into_iter()
+                    .map(|member| (member, 1.0))
+                    .collect(),
+            )),
+            Some(_) => Err(DBError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /**
+     * Adds or updates members following the ZADD flags.
+     */
+    fn zadd(
+        &mut self,
+        key: &str,
+        flags: ZAddFlags,
+        members: Vec<(f64, String)>,
+    ) -> Result<ZAddReply, DBError> {
+        // Don't create an empty key if nothing can be added
+        if flags.xx && self.zset(key)?.is_none() {
+            return Ok(ZAddReply {
+                added: 0,
+                updated: 0,
+                score: None,
+            });
+        }
+        let zset = self.zset_or_create(key)?;
+
+        let mut reply = ZAddReply {
+            added: 0,
+            updated: 0,
+            score: None,
+        };
+        for (score, member) in members {
+            match zset.score(&member) {
+                Some(current) => {
+                    if flags.nx {
+                        continue;
+                    }
+                    let score = if flags.incr { current + score } else { score };
+                    if score.is_nan() {
+                        return Err(DBError::Other(
+                            "ERR resulting score is not a number (NaN)".to_string(),
+                        ));
+                    }
+                    if (flags.gt && score <= current) || (flags.lt && score >= current) {
+                        continue;
+                    }
+                    if score != current {
+                        zset.insert(member, score);
+                        reply.updated += 1;
+                    }
+                    reply.score = Some(score);
+                }
+                None => {
+                    if flags.xx {
+                        continue;
+                    }
+                    zset.insert(member, score);
+                    reply.added += 1;
+                    reply.score = Some(score);
+                }
+            }
+        }
+        if reply.added > 0 || reply.updated > 0 {
+            let event = if flags.incr { "zincr" } else { "zadd" };
+            self.notify(EventClass::SortedSet, event, key);
+        }
+        self.remove_empty_zset(key);
+
+        Ok(reply)
+    }
+}
+
+impl DB {
+    pub fn zadd(
+        &mut self,
+        key: &str,
+        flags: ZAddFlags,
+        members: Vec<(f64, String)>,
+    ) -> Result<ZAddReply, DBError> {
+        let mut store = self.db.lock();
+        let reply = store.zadd(key, flags, members)?;
+        if reply.added > 0 {
+            self.db.key_ready.notify_waiters();
+        }
+
+        Ok(reply)
+    }
+
+    pub fn zrem(&mut self, key: &str, members: &[String]) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        let removed = match store.zset_mut(key)? {
+            Some(zset) => members.iter().filter(|member| zset.remove(member)).count(),
+            None => 0,
+        };
+        if removed > 0 {
+            store.notify(EventClass::SortedSet, "zrem", key);
+        }
+        store.remove_empty_zset(key);
+
+        Ok(removed)
+    }
+
+    pub fn zcard(&self, key: &str) -> Result<usize, DBError> {
+        let store = self.db.lock();
+
+        Ok(store.zset(key)?.map(|zset| zset.len()).unwrap_or(0))
+    }
+
+    pub fn zmscore(&self, key: &str, members: &[String]) -> Result<Vec<Option<f64>>, DBError> {
+        let store = self.db.lock();
+        let zset = store.zset(key)?;
+
+        Ok(members
+            .iter()
+            .map(|member| zset.and_then(|zset| zset.score(member)))
+            .collect())
+    }
+
+    /**
+     * The rank of a member together with its score.
+     */
+    pub fn zrank(
+        &self,
+        key: &str,
+        member: &str,
+        reverse: bool,
+    ) -> Result<Option<(usize, f64)>, DBError> {
+        let store = self.db.lock();
+
+        Ok(store
+            .zset(key)?
+            .and_then(|zset| zset.rank(member, reverse).zip(zset.score(member))))
+    }
+
+    pub fn zcount(&self, key: &str, min: ScoreBound, max: ScoreBound) -> Result<usize, DBError> {
+        let store = self.db.lock();
+
+        Ok(store
+            .zset(key)?
+            .map(|zset| zset.count(min, max))
+            .unwrap_or(0))
+    }
+
+    /**
+     * The members selected by ZRANGE.
+     * offset and count implement the LIMIT option, they are ignored for rank ranges.
+     */
+    pub fn zrange(
+        &self,
+        key: &str,
+        range: &ZRange,
+        reverse: bool,
+        offset: usize,
+        count: Option<usize>,
+    ) -> Result<Vec<Member>, DBError> {
+        let store = self.db.lock();
+        let zset = match store.zset(key)? {
+            Some(zset) => zset,
+            None => return Ok(vec![]),
+        };
+
+        Ok(match range {
+            ZRange::Rank(start, stop) => zset.range_by_rank(*start, *stop, reverse),
+            ZRange::Score(min, max) => zset.range_by_score(*min, *max, reverse, offset, count),
+            ZRange::Lex(min, max) => zset.range_by_lex(min, max, reverse, offset, count),
+        })
+    }
+
+    pub fn zpop(&mut self, key: &str, count: usize, max: bool) -> Result<Vec<Member>, DBError> {
+        let mut store = self.db.lock();
+        let popped = match store.zset_mut(key)? {
+            Some(zset) => zset.pop(count, max),
+            None => vec![],
+        };
+        if !popped.is_empty() {
+            store.notify(EventClass::SortedSet, pop_event(max), key);
+        }
+        store.remove_empty_zset(key);
+
+        Ok(popped)
+    }
+
+    /**
+     * Pops from the first non empty sorted set among keys, as BZPOPMIN and BZPOPMAX do.
+     */
+    pub fn zpop_first(
+        &mut self,
+        keys: &[String],
+        max: bool,
+    ) -> Result<Option<(String, Member)>, DBError> {
+        let mut store = self.db.lock();
+        for key in keys {
+            let popped = match store.zset_mut(key)? {
+                Some(zset) => zset.pop(1, max),
+                None => continue,
+            };
+            if !popped.is_empty() {
+                store.notify(EventClass::SortedSet, pop_event(max), key);
+            }
+            store.remove_empty_zset(key);
+            if let Some(member) = popped.into_iter().next() {
+                return Ok(Some((key.clone(), member)));
+            }
+        }
+        Ok(None)
+    }
+
+    /**
+     * Computes the union or intersection of sorted sets (and sets) with weights applied to their scores.
+     */
+    pub fn zcombine(
+        &self,
+        keys: &[String],
+        weights: &[f64],
+        aggregate: Aggregate,
+        inter: bool,
+    ) -> Result<Vec<Member>, DBError> {
+        let store = self.db.lock();
+        let result = combine(&store, keys, weights, aggregate, inter)?;
+
+        let mut zset = SortedSet::new();
+        for (member, score) in result {
+            zset.insert(member, score);
+        }
+        Ok(zset
+            .iter()
+            .map(|(member, score)| (member.to_string(), score))
+            .collect())
+    }
+
+    /**
+     * Stores the union or intersection in destination, see zcombine.
+     * Returns the size of the resulting sorted set.
+     */
+    pub fn zcombine_store(
+        &mut self,
+        destination: &str,
+        keys: &[String],
+        weights: &[f64],
+        aggregate: Aggregate,
+        inter: bool,
+    ) -> Result<usize, DBError> {
+        let mut store = self.db.lock();
+        let result = combine(&store, keys, weights, aggregate, inter)?;
+
+        let mut zset = SortedSet::new();
+        for (member, score) in result {
+            zset.insert(member, score);
+        }
+        let len = zset.len();
+        let removed = store.remove(destination).is_some();
+        if len > 0 {
+            if !removed {
+                store.notify(EventClass::New, "new", destination);
+            }
+            let event = if inter { "zinterstore" } else { "zunionstore" };
+            store.notify(EventClass::SortedSet, event, destination);
+        } else if removed {
+            store.notify(EventClass::Generic, "del", destination);
+        }
+        if len > 0 {
+            store.entries.insert(
+                destination.to_string(),
+                Value {
+                    value: Data::SortedSet(zset),
+                    expires_at: None,
+                },
+            );
+            self.db.key_ready.notify_waiters();
+        }
+        Ok(len)
+    }
+
+    /**
+     * Iterates the members of a sorted set, see db::scan.
+     */
+    pub fn zscan(
+        &self,
+        key: &str,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(u64, Vec<Member>), DBError> {
+        let store = self.db.lock();
+        let zset = match store.zset(key)? {
+            Some(zset) => zset,
+            None => return Ok((0, vec![])),
+        };
+
+        let (cursor, members) = scan(
+            zset.scores
+                .iter()
+                .map(|(member, score)| (member.as_str(), (member, *score))),
+            cursor,
+            count,
+        );
+        let members = members
+            .into_iter()
+            .filter(|(member, _)| match pattern {
+                Some(pattern) => crate::glob::matches(pattern.as_bytes(), member.as_bytes()),
+                None => true,
+            })
+            .map(|(member, score)| (member.clone(), score))
+            .collect();
+
+        Ok((cursor, members))
+    }
+}
+
+fn combine(
+    store: &Store,
+    keys: &[String],
+    weights: &[f64],
+    aggregate: Aggregate,
+    inter: bool,
+) -> Result<HashMap<String, f64>, DBError> {
+    let mut sources = Vec::with_capacity(keys.len());
+    for key in keys {
+        sources.push(store.zset_source(key)?);
+    }
+
+    let weighted = |score: f64, index: usize| {
+        let score = score * weights.get(index).copied().unwrap_or(1.0);
+        // 0 * inf
+        if score.is_nan() {
+            0.0
+        } else {
+            score
+        }
+    };
+
+    let mut result: HashMap<String, f64> = HashMap::new();
+    if inter {
+        if sources.iter().any(|source| source.is_none()) {
+            return Ok(result);
+        }
+        let sources: Vec<HashMap<String, f64>> = sources.into_iter().flatten().collect();
+        if let Some((first, others)) = sources.split_first() {
+            'members: for (member, score) in first {
+                let mut total = weighted(*score, 0);
+                for (index, other) in others.iter().enumerate() {
+                    match other.get(member) {
+                        Some(score) => total = aggregate.apply(total, weighted(*score, index + 1)),
+                        None => continue 'members,
+                    }
+                }
+                result.insert(member.clone(), total);
+            }
+        }
+    } else {
+        for (index, source) in sources.into_iter().enumerate() {
+            for (member, score) in source.into_iter().flatten() {
+                let score = weighted(score, index);
+                match result.get_mut(&member) {
+                    Some(total) => *total = aggregate.apply(*total, score),
+                    None => {
+                        result.insert(member, score);
+                    }
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn pop_event(max: bool) -> &'static str {
+    if max {
+        "zpopmax"
+    } else {
+        "zpopmin"
+    }
+}
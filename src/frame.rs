@@ -13,7 +13,9 @@ Example ->
 pub enum Frame {
     SimpleString(String),
     SimpleError(String),
-    Integer(usize),
+    // Binary safe and may span lines, e.g the text of INFO or the value of a key
+    BulkString(Vec<u8>),
+    Integer(i64),
     Array(VecDeque<Frame>),
 }
 
@@ -49,6 +51,11 @@ impl Frame {
 
                 Ok(())
             }
+            b'$' => {
+                get_bulk_string(cursor)?;
+
+                Ok(())
+            }
             b'*' => check_array(cursor),
             _ => Err(FrameError::Other(String::from(
                 "Protocol Error: Invalid input ",
@@ -56,6 +63,20 @@ impl Frame {
         }
     }
 
+    // The number of bytes the frame takes once written to a connection
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Frame::SimpleString(input) | Frame::SimpleError(input) => input.len() + 3,
+            Frame::Integer(input) => input.to_string().len() + 3,
+            Frame::BulkString(input) => input.len().to_string().len() + input.len() + 5,
+            Frame::Array(frames) => {
+                frames.len().to_string().len()
+                    + 3
+                    + frames.iter().map(Frame::encoded_len).sum::<usize>()
+            }
+        }
+    }
+
     // Deserializes a frame from a buffer
 
     pub fn parse(cursor: &mut Cursor<&[u8]>) -> Result<Frame, FrameError> {
@@ -82,6 +103,10 @@ impl Frame {
 
                 Ok(Frame::Integer(integer))
             }
+            b'$' => {
+                let bytes = get_bulk_string(cursor)?;
+                Ok(Frame::BulkString(bytes.to_vec()))
+            }
 
             b'*' => get_array(cursor),
 
@@ -106,7 +131,7 @@ fn check_array(cursor: &mut Cursor<&[u8]>) -> Result<(), FrameError> {
 
     let num_of_items = get_integer(cursor)?;
 
-    for _ in 0..num_of_items {
+    for _ in 0..num_of_items.max(0) {
         Frame::check(cursor)?;
     }
 
@@ -121,7 +146,7 @@ fn get_array(cursor: &mut Cursor<&[u8]>) -> Result<Frame, FrameError> {
 
     let mut frames = VecDeque::new();
 
-    for _ in 0..num_of_items {
+    for _ in 0..num_of_items.max(0) {
         let frame = Frame::parse(cursor)?;
         frames.push_back(frame);
     }
@@ -129,7 +154,7 @@ fn get_array(cursor: &mut Cursor<&[u8]>) -> Result<Frame, FrameError> {
     Ok(Frame::Array(frames))
 }
 
-fn get_integer(cursor: &mut Cursor<&[u8]>) -> Result<usize, FrameError> {
+fn get_integer(cursor: &mut Cursor<&[u8]>) -> Result<i64, FrameError> {
     let start = cursor.position() as usize;
     let end = cursor.get_ref().len() - 1;
 
@@ -137,7 +162,7 @@ fn get_integer(cursor: &mut Cursor<&[u8]>) -> Result<usize, FrameError> {
         if cursor.get_ref()[position] == b'\r' && cursor.get_ref()[position + 1] == b'\n' {
             cursor.set_position((position + 2) as u64);
             let bytes = &cursor.get_ref()[start..position];
-            return atoi::atoi::<usize>(bytes)
+            return atoi::atoi::<i64>(bytes)
                 .ok_or_else(|| FrameError::Other("Protocol Error: Invalid input".to_string()));
         }
     }
@@ -156,3 +181,74 @@ fn get_simple_string<'a>(cursor: &'a mut Cursor<&[u8]>) -> Result<&'a [u8], Fram
     }
     Err(FrameError::Incomplete)
 }
+
+// The bytes of a bulk string: its length then the bytes followed by \r\n
+fn get_bulk_string<'a>(cursor: &'a mut Cursor<&[u8]>) -> Result<&'a [u8], FrameError> {
+    let len = usize::try_from(get_integer(cursor)?)
+        .map_err(|_| FrameError::Other("Protocol Error: Invalid bulk length".to_string()))?;
+    let start = cursor.position() as usize;
+    let end = start
+        .checked_add(len)
+        .filter(|end| *end <= usize::MAX - 2)
+        .ok_or_else(|| FrameError::Other("Protocol Error: Invalid bulk length".to_string()))?;
+    if cursor.get_ref().len() < end + 2 {
+        return Err(FrameError::Incomplete);
+    }
+    if &cursor.get_ref()[end..end + 2] != b"\r\n" {
+        return Err(FrameError::Other(
+            "Protocol Error: Invalid bulk string".to_string(),
+        ));
+    }
+    cursor.set_position((end + 2) as u64);
+    Ok(&cursor.get_ref()[start..end])
+}
+
+/**
+ * The frame of a command as clients send it, e.g command(&["SET", "key", "value"]).
+ */
+#[cfg(test)]
+pub(crate) fn command(args: &[&str]) -> Frame {
+    Frame::Array(
+        args.iter()
+            .map(|arg| Frame::SimpleString(arg.to_string()))
+            .collect(),
+    )
+}
+
+/**
+ * The text of a simple or bulk string reply, panicking on any other frame.
+ */
+#[cfg(test)]
+pub(crate) fn text(frame: Frame) -> String {
+    match frame {
+        Frame::SimpleString(text) => text,
+        Frame::BulkString(bytes) => String::from_utf8(bytes).unwrap(),
+        frame => panic!("expected a text, got {:?}", frame),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bulk_string() {
+        let input: &[u8] = b"$7\r\nab\r\ncd\xff\r\n";
+        let mut cursor = Cursor::new(input);
+        Frame::check(&mut cursor).unwrap();
+        assert_eq!(cursor.position() as usize, input.len());
+
+        let frame = Frame::parse(&mut Cursor::new(input)).unwrap();
+        assert_eq!(frame, Frame::BulkString(b"ab\r\ncd\xff".to_vec()));
+        assert_eq!(frame.encoded_len(), input.len());
+
+        assert!(matches!(
+            Frame::check(&mut Cursor::new(&input[..10])),
+            Err(FrameError::Incomplete)
+        ));
+        assert!(matches!(
+            Frame::check(&mut Cursor::new(&b"$2\r\nabc\r\n"[..])),
+            Err(FrameError::Other(_))
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+/**
+ * Glob-style pattern matching as used by redis for MATCH options, KEYS, PSUBSCRIBE etc.
+ * Supported syntax:
+ * - `?` matches any single byte
+ * - `*` matches any number of bytes, including none
+ * - `[abc]`, `[^abc]`, `[a-z]` match a byte from (or not from) the set
+ * - `\x` matches x literally
+ */
+pub fn matches(pattern: &[u8], input: &[u8]) -> bool {
+    let mut p = 0;
+    let mut i = 0;
+    // Where to resume from if the current attempt after a '*' fails
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while i < input.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    backtrack = Some((p, i));
+                    p += 1;
+                    continue;
+                }
+                b'?' => {
+                    p += 1;
+                    i += 1;
+                    continue;
+                }
+                b'[' => {
+                    let (matched, next) = match_class(pattern, p, input[i]);
+                    if matched {
+                        p = next;
+                        i += 1;
+                        continue;
+                    }
+                }
+                b'\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == input[i] {
+                        p += 2;
+                        i += 1;
+                        continue;
+                    }
+                }
+                byte => {
+                    if byte == input[i] {
+                        p += 1;
+                        i += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // Mismatch: let the last '*' swallow one more byte
+        match backtrack {
+            Some((star, matched)) => {
+                p = star + 1;
+                i = matched + 1;
+                backtrack = Some((star, matched + 1));
+            }
+            None => return false,
+        }
+    }
+
+    // Only trailing stars may remain
+    pattern[p..].iter().all(|byte| *byte == b'*')
+}
+
+// Matches a byte against the character class starting at pattern[start] == '['.
+// Returns whether it matched and the position right after the class.
+fn match_class(pattern: &[u8], start: usize, byte: u8) -> (bool, usize) {
+    let mut p = start + 1;
+    let negate = p < pattern.len() && pattern[p] == b'^';
+    if negate {
+        p += 1;
+    }
+
+    let mut matched = false;
+    loop {
+        if p >= pattern.len() {
+            // Unterminated class, redis treats the end of the pattern as the end of the class
+            break;
+        }
+        match pattern[p] {
+            b']' => {
+                p += 1;
+                break;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                if pattern[p + 1] == byte {
+                    matched = true;
+                }
+                p += 2;
+            }
+            low if p + 2 < pattern.len() && pattern[p + 1] == b'-' && pattern[p + 2] != b']' => {
+                let high = pattern[p + 2];
+                let (low, high) = if low > high { (high, low) } else { (low, high) };
+                if byte >= low && byte <= high {
+                    matched = true;
+                }
+                p += 3;
+            }
+            other => {
+                if other == byte {
+                    matched = true;
+                }
+                p += 1;
+            }
+        }
+    }
+
+    (matched != negate, p)
+}
@@ -0,0 +1,267 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::db::now_ms;
+
+// Samples kept per event
+const HISTORY_LEN: usize = 160;
+
+/**
+ * A latency spike, as listed by LATENCY HISTORY.
+ * Spikes of an event in the same second are merged into the highest one.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatencySample {
+    // Unix time in seconds
+    pub time: u64,
+    // Milliseconds
+    pub latency: u64,
+}
+
+/**
+ * The spikes of an event since the last LATENCY RESET.
+ */
+#[derive(Clone, Default)]
+pub struct LatencyEvent {
+    // Oldest first
+    pub samples: VecDeque<LatencySample>,
+    // The highest latency ever seen, even if its sample was dropped
+    pub max: u64,
+}
+
+/**
+ * Records the events that took at least latency-monitor-threshold milliseconds, e.g
+ * command: a command ran for too long
+ * fast-command: a command of @fast ran for too long, usually because something else blocked the server
+ * snapshot: the store was locked while the db was serialized for the RDB file
+ * snapshot-fsync: the RDB file was written and synced to disk
+ * expire-cycle: the background worker deleted expired keys
+ */
+#[derive(Default)]
+pub struct LatencyMonitor {
+    // Milliseconds, 0 disabling the monitor
+    threshold: AtomicU64,
+    events: Mutex<HashMap<&'static str, LatencyEvent>>,
+}
+
+impl LatencyMonitor {
+    pub fn set_threshold(&self, millis: u64) {
+        self.threshold.store(millis, Ordering::Relaxed);
+    }
+
+    pub fn threshold(&self) -> u64 {
+        self.threshold.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Adds a sample of an event if it took at least the threshold.
+     */
+    pub fn record(&self, event: &'static str, duration: Duration) {
+        let threshold = self.threshold();
+        let latency = duration.as_millis() as u64;
+        if threshold == 0 || latency < threshold {
+            return;
+        }
+
+        let time = now_ms() / 1000;
+        let mut events = self.events.lock().unwrap();
+        let event = events.entry(event).or_default();
+        event.max = event.max.max(latency);
+        match event.samples.back_mut() {
+            Some(last) if last.time == time => last.latency = last.latency.max(latency),
+            _ => {
+                event.samples.push_back(LatencySample { time, latency });
+                if event.samples.len() > HISTORY_LEN {
+                    event.samples.pop_front();
+                }
+            }
+        }
+    }
+
+    /**
+     * The events with samples, sorted by name.
+     */
+    pub fn events(&self) -> Vec<(&'static str, LatencyEvent)> {
+        let mut events: Vec<(&'static str, LatencyEvent)> = self
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, event)| (*name, event.clone()))
+            .collect();
+        events.sort_by_key(|(name, _)| *name);
+        events
+    }
+
+    pub fn history(&self, event: &str) -> Vec<LatencySample> {
+        self.events
+            .lock()
+            .unwrap()
+            .get(event)
+            .map(|event| event.samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /**
+     * Drops the samples of the given events, or of every event if none is given.
+     * Returns how many events were dropped.
+     */
+    pub fn reset(&self, names: &[String]) -> usize {
+        let mut events = self.events.lock().unwrap();
+        if names.is_empty() {
+            let count = events.len();
+            events.clear();
+            return count;
+        }
+        names
+            .iter()
+            .filter(|name| events.remove(name.to_lowercase().as_str()).is_some())
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::DB,
+        frame::{command, text, Frame},
+        runner::Runner,
+        stats::Outcome,
+    };
+
+    // A db monitoring spikes of 100ms, with spikes of the command and expire-cycle events
+    fn monitored() -> DB {
+        let db = DB::new();
+        db.db
+            .config
+            .set(&[("latency-monitor-threshold".to_string(), "100".to_string())])
+            .unwrap();
+        db.apply_config(&["latency-monitor-threshold"]);
+        let latency = &db.db.latency;
+        latency.record("command", Duration::from_millis(50));
+        latency.record("command", Duration::from_millis(150));
+        latency.record("command", Duration::from_millis(300));
+        latency.record("expire-cycle", Duration::from_millis(120));
+        db
+    }
+
+    #[tokio::test]
+    async fn latency_disabled() {
+        let mut db = DB::new();
+        db.db.latency.record("command", Duration::from_millis(500));
+        assert!(db.db.latency.events().is_empty());
+        assert!(text(
+            Runner::new(&mut db)
+                .run(command(&["LATENCY", "DOCTOR"]))
+                .unwrap()
+        )
+        .starts_with("Latency monitoring is disabled"));
+    }
+
+    #[tokio::test]
+    async fn latency_latest_and_history() {
+        let mut db = monitored();
+        let mut runner = Runner::new(&mut db);
+        match runner.run(command(&["LATENCY", "LATEST"])).unwrap() {
+            Frame::Array(events) => {
+                assert_eq!(events.len(), 2);
+                match &events[0] {
+                    Frame::Array(event) => {
+                        assert_eq!(event[0], Frame::SimpleString("command".to_string()));
+                        // Spikes in the same second are merged into the highest
+                        assert_eq!(event[2], Frame::Integer(300));
+                        assert_eq!(event[3], Frame::Integer(300));
+                    }
+                    frame => panic!("expected an event, got {:?}", frame),
+                }
+            }
+            frame => panic!("expected the events, got {:?}", frame),
+        }
+        match runner
+            .run(command(&["LATENCY", "HISTORY", "COMMAND"]))
+            .unwrap()
+        {
+            Frame::Array(samples) => assert_eq!(samples.len(), 1),
+            frame => panic!("expected the samples, got {:?}", frame),
+        }
+        assert!(runner.run(command(&["LATENCY", "HISTORY"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn latency_histogram() {
+        let mut db = DB::new();
+        let stats = &db.db.stats;
+        stats.record_command("GET", Duration::from_micros(80), Outcome::Ok);
+        stats.record_command("GET", Duration::from_millis(2), Outcome::Ok);
+        stats.record_command("GET", Duration::from_secs(2), Outcome::Ok);
+        assert_eq!(
+            Runner::new(&mut db)
+                .run(command(&["LATENCY", "HISTOGRAM", "get", "set"]))
+                .unwrap(),
+            Frame::Array(
+                [
+                    Frame::SimpleString("get".to_string()),
+                    Frame::Array(
+                        [
+                            Frame::SimpleString("calls".to_string()),
+                            Frame::Integer(3),
+                            Frame::SimpleString("histogram_usec".to_string()),
+                            Frame::Array(
+                                [
+                                    Frame::Integer(100),
+                                    Frame::Integer(1),
+                                    Frame::Integer(2500),
+                                    Frame::Integer(2),
+                                    Frame::SimpleString("+inf".to_string()),
+                                    Frame::Integer(3),
+                                ]
+                                .into()
+                            ),
+                        ]
+                        .into()
+                    ),
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn latency_doctor() {
+        let mut db = monitored();
+        let report = Runner::new(&mut db)
+            .run(command(&["LATENCY", "DOCTOR"]))
+            .unwrap();
+        // The report spans lines, which only a bulk string can hold
+        assert!(matches!(report, Frame::BulkString(_)));
+        let report = text(report);
+        assert!(report.contains("\n1. command: 1 latency spikes (average 300ms"));
+        assert!(report.contains("\n2. expire-cycle: 1 latency spikes"));
+        assert!(report.contains("\n- expire-cycle: "));
+    }
+
+    #[tokio::test]
+    async fn latency_reset() {
+        let mut db = monitored();
+        let mut runner = Runner::new(&mut db);
+        assert_eq!(
+            runner
+                .run(command(&["LATENCY", "RESET", "expire-cycle", "fork"]))
+                .unwrap(),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            runner.run(command(&["LATENCY", "RESET"])).unwrap(),
+            Frame::Integer(1)
+        );
+        assert!(text(runner.run(command(&["LATENCY", "DOCTOR"])).unwrap())
+            .starts_with("No latency spike of 100 milliseconds"));
+    }
+}
@@ -1,5 +1,21 @@
+pub mod acl;
+pub mod auth;
+pub mod client;
+pub mod command;
+pub mod config;
 pub mod connection;
 pub mod db;
 pub mod frame;
+pub mod glob;
+pub mod latency;
+pub mod metrics;
+pub mod monitor;
+pub mod pubsub;
 pub mod rdb;
 pub mod runner;
+pub mod scripting;
+pub mod shutdown;
+pub mod slowlog;
+pub mod stats;
+pub mod tracking;
+pub mod transaction;
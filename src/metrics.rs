@@ -0,0 +1,368 @@
+use std::fmt::Write;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    db::DB,
+    stats::{Stats, LATENCY_BUCKETS},
+};
+
+// Requests with a longer head are refused
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/**
+ * Serves the metrics of the db in the Prometheus text format at GET /metrics,
+ * until the process exits.
+ */
+pub async fn serve(db: DB, listener: TcpListener) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                tracing::error!("METRICS: Failed to accept a connection: {}", err);
+                continue;
+            }
+        };
+        let db = db.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_request(&db, stream).await {
+                tracing::debug!("METRICS: Failed to answer a scrape: {}", err);
+            }
+        });
+    }
+}
+
+// Answers the one request of a connection, then closes it
+async fn handle_request(db: &DB, mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request = vec![];
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_SIZE {
+            return respond(&mut stream, "431 Request Header Fields Too Large", "").await;
+        }
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&request);
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    // Query strings are ignored
+    let path = request_line
+        .next()
+        .unwrap_or_default()
+        .split('?')
+        .next()
+        .unwrap_or_default();
+    match (method, path) {
+        ("GET", "/metrics") => respond(&mut stream, "200 OK", &render(db)).await,
+        (_, "/metrics") => respond(&mut stream, "405 Method Not Allowed", "").await,
+        _ => respond(&mut stream, "404 Not Found", "").await,
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/**
+ * The metrics of the db in the Prometheus text format.
+ * Counters restart from 0 after CONFIG RESETSTAT, which Prometheus handles as a counter reset.
+ * Ops/sec per command is the rate of mini_redis_commands_total.
+ */
+pub fn render(db: &DB) -> String {
+    let stats = &db.db.stats;
+    let mut metrics = Metrics::default();
+
+    metrics.family(
+        "mini_redis_uptime_seconds",
+        "gauge",
+        "Seconds since the server started.",
+    );
+    metrics.sample(
+        "mini_redis_uptime_seconds",
+        "",
+        stats.started_at.elapsed().as_secs(),
+    );
+    for (name, counter, help) in [
+        (
+            "mini_redis_connected_clients",
+            &stats.connected_clients,
+            "Clients connected to the server.",
+        ),
+        (
+            "mini_redis_blocked_clients",
+            &stats.blocked_clients,
+            "Clients waiting on a blocking command.",
+        ),
+    ] {
+        metrics.family(name, "gauge", help);
+        metrics.sample(name, "", Stats::get(counter));
+    }
+    for (name, counter, help) in [
+        (
+            "mini_redis_connections_received_total",
+            &stats.connections_received,
+            "Connections accepted by the server.",
+        ),
+        (
+            "mini_redis_rejected_connections_total",
+            &stats.rejected_connections,
+            "Connections refused by protected mode.",
+        ),
+        (
+            "mini_redis_commands_processed_total",
+            &stats.commands_processed,
+            "Commands sent by the clients.",
+        ),
+        (
+            "mini_redis_expired_keys_total",
+            &stats.expired_keys,
+            "Keys deleted once their TTL elapsed.",
+        ),
+        (
+            "mini_redis_evicted_keys_total",
+            &stats.evicted_keys,
+            "Keys evicted for maxmemory.",
+        ),
+        (
+            "mini_redis_keyspace_hits_total",
+            &stats.keyspace_hits,
+            "Keys looked up by read commands that existed.",
+        ),
+        (
+            "mini_redis_keyspace_misses_total",
+            &stats.keyspace_misses,
+            "Keys looked up by read commands that didn't exist.",
+        ),
+    ] {
+        metrics.family(name, "counter", help);
+        metrics.sample(name, "", Stats::get(counter));
+    }
+
+    let (keys, expires, dirty) = {
+        let store = db.db.lock();
+        (store.entries.len(), store.ttls.len(), store.dirty)
+    };
+    metrics.family("mini_redis_keys", "gauge", "Keys in the db.");
+    metrics.sample("mini_redis_keys", "", keys);
+    metrics.family(
+        "mini_redis_expiring_keys",
+        "gauge",
+        "Keys in the db with a TTL.",
+    );
+    metrics.sample("mini_redis_expiring_keys", "", expires);
+
+    let commands = stats.commands();
+    for (name, help) in [
+        ("mini_redis_commands_total", "Calls of a command."),
+        (
+            "mini_redis_commands_failed_total",
+            "Calls of a command that replied an error.",
+        ),
+        (
+            "mini_redis_commands_rejected_total",
+            "Calls of a command refused before running.",
+        ),
+    ] {
+        metrics.family(name, "counter", help);
+        for (command, calls) in &commands {
+            let value = match name {
+                "mini_redis_commands_total" => calls.calls,
+                "mini_redis_commands_failed_total" => calls.failed_calls,
+                _ => calls.rejected_calls,
+            };
+            metrics.sample(name, &command_label(command), value);
+        }
+    }
+    metrics.family(
+        "mini_redis_command_duration_seconds",
+        "histogram",
+        "Time spent running a command.",
+    );
+    for (command, calls) in &commands {
+        let label = command_label(command);
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(calls.latencies) {
+            cumulative += count;
+            metrics.sample(
+                "mini_redis_command_duration_seconds_bucket",
+                &format!("{},le=\"{}\"", label, bound),
+                cumulative,
+            );
+        }
+        metrics.sample(
+            "mini_redis_command_duration_seconds_bucket",
+            &format!("{},le=\"+Inf\"", label),
+            calls.calls,
+        );
+        metrics.sample(
+            "mini_redis_command_duration_seconds_sum",
+            &label,
+            calls.usec as f64 / 1_000_000.0,
+        );
+        metrics.sample(
+            "mini_redis_command_duration_seconds_count",
+            &label,
+            calls.calls,
+        );
+    }
+
+    let persistence = stats.persistence();
+    metrics.family(
+        "mini_redis_rdb_changes_since_last_save",
+        "gauge",
+        "Writes to the db since the last flush into the RDB file.",
+    );
+    metrics.sample("mini_redis_rdb_changes_since_last_save", "", dirty);
+    metrics.family(
+        "mini_redis_rdb_saves_total",
+        "counter",
+        "Successful flushes into the RDB file.",
+    );
+    metrics.sample("mini_redis_rdb_saves_total", "", persistence.saves);
+    metrics.family(
+        "mini_redis_rdb_save_failures_total",
+        "counter",
+        "Failed flushes into the RDB file.",
+    );
+    metrics.sample(
+        "mini_redis_rdb_save_failures_total",
+        "",
+        persistence.failed_saves,
+    );
+    metrics.family(
+        "mini_redis_rdb_last_save_timestamp_seconds",
+        "gauge",
+        "Unix time of the last successful flush into the RDB file.",
+    );
+    metrics.sample(
+        "mini_redis_rdb_last_save_timestamp_seconds",
+        "",
+        persistence.last_save_time,
+    );
+    metrics.family(
+        "mini_redis_rdb_save_duration_seconds",
+        "summary",
+        "Time spent flushing into the RDB file.",
+    );
+    metrics.sample(
+        "mini_redis_rdb_save_duration_seconds_sum",
+        "",
+        persistence.save_duration.as_secs_f64(),
+    );
+    metrics.sample(
+        "mini_redis_rdb_save_duration_seconds_count",
+        "",
+        persistence.saves + persistence.failed_saves,
+    );
+
+    metrics.text
+}
+
+fn command_label(command: &str) -> String {
+    format!("command=\"{}\"", command.to_lowercase())
+}
+
+// The text exposition format, a HELP and TYPE header per family followed by its samples
+#[derive(Default)]
+struct Metrics {
+    text: String,
+}
+
+impl Metrics {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &str, value: impl std::fmt::Display) {
+        let _ = match labels {
+            "" => writeln!(self.text, "{} {}", name, value),
+            labels => writeln!(self.text, "{}{{{}}} {}", name, labels, value),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::Outcome;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn metrics_scrape() {
+        let mut db = DB::new();
+        db.set("a".to_string(), b"1".to_vec(), None);
+        db.set("b".to_string(), b"2".to_vec(), Some(100));
+        let stats = &db.db.stats;
+        stats.record_command("GET", Duration::from_micros(50), Outcome::Ok);
+        stats.record_command("GET", Duration::from_millis(3), Outcome::Failed);
+        stats.record_command("GET", Duration::ZERO, Outcome::Rejected);
+        stats.record_save(true, Duration::from_millis(500));
+        stats.record_save(false, Duration::from_millis(250));
+        let _connected = Stats::enter(&stats.connected_clients);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(db.clone(), listener));
+        let scrape = |request: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = scrape("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        for line in [
+            "# TYPE mini_redis_connected_clients gauge",
+            "mini_redis_connected_clients 1",
+            "mini_redis_keys 2",
+            "mini_redis_expiring_keys 1",
+            "mini_redis_expired_keys_total 0",
+            "mini_redis_evicted_keys_total 0",
+            "mini_redis_commands_total{command=\"get\"} 2",
+            "mini_redis_commands_failed_total{command=\"get\"} 1",
+            "mini_redis_commands_rejected_total{command=\"get\"} 1",
+            "# TYPE mini_redis_command_duration_seconds histogram",
+            "mini_redis_command_duration_seconds_bucket{command=\"get\",le=\"0.0001\"} 1",
+            "mini_redis_command_duration_seconds_bucket{command=\"get\",le=\"0.0025\"} 1",
+            "mini_redis_command_duration_seconds_bucket{command=\"get\",le=\"0.005\"} 2",
+            "mini_redis_command_duration_seconds_bucket{command=\"get\",le=\"+Inf\"} 2",
+            "mini_redis_command_duration_seconds_count{command=\"get\"} 2",
+            "mini_redis_rdb_saves_total 1",
+            "mini_redis_rdb_save_failures_total 1",
+            "mini_redis_rdb_save_duration_seconds_sum 0.75",
+            "mini_redis_rdb_save_duration_seconds_count 2",
+        ] {
+            assert!(
+                body.lines().any(|metric| metric == line),
+                "missing {}",
+                line
+            );
+        }
+
+        assert!(scrape("GET / HTTP/1.1\r\n\r\n")
+            .await
+            .starts_with("HTTP/1.1 404 Not Found"));
+        assert!(scrape("POST /metrics HTTP/1.1\r\n\r\n")
+            .await
+            .starts_with("HTTP/1.1 405 Method Not Allowed"));
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::mpsc;
+
+use crate::{
+    command::{arguments, spec, Category},
+    db::DB,
+    frame::Frame,
+    runner::RunnerError,
+};
+
+// Lines queued for a monitor before it is disconnected for reading too slowly
+const MONITOR_BACKLOG: usize = 10_000;
+
+/**
+ * Sends the commands run by every client to the connections that sent MONITOR.
+ * Lives next to the store in db::Shared.
+ * Monitors that don't keep up are disconnected instead of slowing down the clients.
+ */
+#[derive(Default)]
+pub struct Monitors {
+    senders: Mutex<HashMap<u64, mpsc::Sender<Frame>>>,
+    // Checked before formatting anything, most of the time nobody is monitoring
+    count: AtomicUsize,
+}
+
+impl Monitors {
+    pub fn is_empty(&self) -> bool {
+        self.count.load(Ordering::Relaxed) == 0
+    }
+
+    fn add(&self, id: u64) -> mpsc::Receiver<Frame> {
+        let (sender, receiver) = mpsc::channel(MONITOR_BACKLOG);
+        let mut senders = self.senders.lock().unwrap();
+        senders.insert(id, sender);
+        self.count.store(senders.len(), Ordering::Relaxed);
+        receiver
+    }
+
+    fn remove(&self, id: u64) {
+        let mut senders = self.senders.lock().unwrap();
+        senders.remove(&id);
+        self.count.store(senders.len(), Ordering::Relaxed);
+    }
+
+    /**
+     * Sends a command that ran at time (seconds since the unix epoch) to the monitors, e.g
+     * 1339518083.107412 [0 127.0.0.1:60866] "SET" "key" "value"
+     * Unknown and admin commands aren't sent, the latter because of what their arguments may hold.
+     */
+    pub fn feed(&self, time: f64, client: &str, frame: &Frame) {
+        if self.is_empty() {
+            return;
+        }
+        let Some((command, args)) = arguments(frame) else {
+            return;
+        };
+        match spec(&command) {
+            Some(spec) if !spec.has(Category::Admin) => {}
+            _ => return,
+        }
+
+        let mut line = format!("{:.6} [0 {}]", time, client);
+        for arg in std::iter::once(&command).chain(&args) {
+            line.push(' ');
+            line.push_str(&quote(arg));
+        }
+        let line = Frame::SimpleString(line);
+        let mut senders = self.senders.lock().unwrap();
+        senders.retain(|id, sender| match sender.try_send(line.clone()) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::info!("MONITOR: Disconnecting monitor {} reading too slowly", id);
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        });
+        self.count.store(senders.len(), Ordering::Relaxed);
+    }
+}
+
+/**
+ * Seconds since the unix epoch with microseconds, the time MONITOR lines start with.
+ */
+pub fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs_f64())
+        .unwrap_or_default()
+}
+
+// An argument between double quotes, escaped like redis does so a line never holds \r\n
+fn quote(arg: &str) -> String {
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    for char in arg.chars() {
+        match char {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\u{7}' => quoted.push_str("\\a"),
+            '\u{8}' => quoted.push_str("\\b"),
+            char if char.is_control() => quoted.push_str(&format!("\\x{:02x}", char as u32)),
+            char => quoted.push(char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/**
+ * The MONITOR side of a connection.
+ * Once it sent MONITOR, the commands run by every client are written to it as they run.
+ */
+pub struct Monitor {
+    id: u64,
+    db: DB,
+    // Only set while monitoring, the monitors drop the sending side to disconnect a slow monitor
+    receiver: Option<mpsc::Receiver<Frame>>,
+}
+
+impl Monitor {
+    pub fn new(db: DB, id: u64) -> Monitor {
+        Monitor {
+            id,
+            db,
+            receiver: None,
+        }
+    }
+
+    pub fn is_monitoring(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    /**
+     * Handles MONITOR.
+     * Returns None if the frame should be run right away.
+     */
+    pub fn run(&mut self, frame: &Frame) -> Option<Result<Frame, RunnerError>> {
+        match arguments(frame) {
+            Some((command, args)) if command.eq_ignore_ascii_case("MONITOR") => {
+                if !args.is_empty() {
+                    return Some(Err(RunnerError::Other(
+                        "ERR wrong number of arguments for 'monitor' command".to_string(),
+                    )));
+                }
+                if self.receiver.is_none() {
+                    self.receiver = Some(self.db.db.monitors.add(self.id));
+                }
+                Some(Ok(Frame::SimpleString("OK".to_string())))
+            }
+            _ => None,
+        }
+    }
+
+    /**
+     * Handles the commands of a monitoring connection, which may only QUIT or RESET.
+     * RESET stops monitoring, the rest of the connection is left as it is.
+     * Returns None if the connection isn't monitoring or the frame is QUIT.
+     */
+    pub fn run_monitoring(&mut self, frame: &Frame) -> Option<Result<Frame, RunnerError>> {
+        self.receiver.as_ref()?;
+        let command = arguments(frame).map(|(command, _)| command.to_uppercase());
+        match command.as_deref() {
+            Some("QUIT") => None,
+            Some("RESET") => {
+                self.receiver = None;
+                self.db.db.monitors.remove(self.id);
+                Some(Ok(Frame::SimpleString("RESET".to_string())))
+            }
+            command => Some(Err(RunnerError::Other(format!(
+                "ERR Can't execute '{}': only QUIT and RESET are allowed while monitoring",
+                command.unwrap_or_default().to_lowercase()
+            )))),
+        }
+    }
+
+    /**
+     * Waits for the next command run by a client.
+     * Returns None if the monitor got disconnected for reading too slowly.
+     */
+    pub async fn receive(&mut self) -> Option<Frame> {
+        match &mut self.receiver {
+            Some(receiver) => receiver.recv().await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        if self.receiver.is_some() {
+            self.db.db.monitors.remove(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frame::command, runner::Runner};
+
+    #[tokio::test]
+    async fn monitor_feed() {
+        let mut db = DB::new();
+        let mut monitor = Monitor::new(db.clone(), 1);
+        assert!(monitor.run(&command(&["GET", "key"])).is_none());
+        assert!(monitor
+            .run(&command(&["MONITOR", "extra"]))
+            .unwrap()
+            .is_err());
+        assert_eq!(
+            monitor.run(&command(&["monitor"])).unwrap().unwrap(),
+            Frame::SimpleString("OK".to_string())
+        );
+        assert!(monitor.is_monitoring());
+
+        let mut runner = Runner::new(&mut db).with_client("127.0.0.1:5001");
+        runner.run(command(&["SET", "key", "a \"b\"\r\n"])).unwrap();
+        // Admin commands and unknown commands aren't shown
+        runner
+            .run(command(&["CONFIG", "GET", "requirepass"]))
+            .unwrap();
+        let _ = runner.run(command(&["NOPE"]));
+        Runner::new(&mut db).run(command(&["GET", "key"])).unwrap();
+
+        let line = |frame: Option<Frame>| match frame {
+            Some(Frame::SimpleString(line)) => line,
+            frame => panic!("expected a line, got {:?}", frame),
+        };
+        let set = line(monitor.receive().await);
+        let (time, rest) = set.split_once(' ').unwrap();
+        assert!(time.parse::<f64>().unwrap() > 0.0);
+        assert_eq!(time.split_once('.').unwrap().1.len(), 6);
+        assert_eq!(rest, r#"[0 127.0.0.1:5001] "SET" "key" "a \"b\"\r\n""#);
+        assert!(line(monitor.receive().await).ends_with(r#"[0 unknown] "GET" "key""#));
+
+        // A monitor reading too slowly is dropped instead of holding back the clients
+        for _ in 0..20_000 {
+            Runner::new(&mut db).run(command(&["PING"])).unwrap();
+        }
+        assert!(db.db.monitors.is_empty());
+        while monitor.receive().await.is_some() {}
+    }
+
+    #[tokio::test]
+    async fn monitoring_connection() {
+        let db = DB::new();
+        let mut monitor = Monitor::new(db.clone(), 1);
+        assert!(monitor.run_monitoring(&command(&["GET", "key"])).is_none());
+        monitor.run(&command(&["MONITOR"])).unwrap().unwrap();
+
+        // Only QUIT and RESET are left to a monitoring connection
+        for frame in [command(&["GET", "key"]), command(&["MONITOR"])] {
+            assert!(matches!(
+                monitor.run_monitoring(&frame),
+                Some(Err(RunnerError::Other(err))) if err.ends_with("only QUIT and RESET are allowed while monitoring")
+            ));
+        }
+        assert!(monitor.run_monitoring(&command(&["QUIT"])).is_none());
+        assert_eq!(
+            monitor
+                .run_monitoring(&command(&["reset"]))
+                .unwrap()
+                .unwrap(),
+            Frame::SimpleString("RESET".to_string())
+        );
+        assert!(!monitor.is_monitoring());
+        assert!(db.db.monitors.is_empty());
+        assert!(monitor.run_monitoring(&command(&["GET", "key"])).is_none());
+    }
+
+    #[tokio::test]
+    async fn monitor_feed_connection_commands() {
+        let db = DB::new();
+        let mut monitor = Monitor::new(db.clone(), 1);
+        monitor.run(&command(&["MONITOR"])).unwrap().unwrap();
+        // The connection feeds the commands it runs itself, e.g MULTI or SUBSCRIBE
+        let monitors = &db.db.monitors;
+        monitors.feed(now(), "127.0.0.1:5001", &command(&["MULTI"]));
+        monitors.feed(now(), "127.0.0.1:5001", &command(&["SUBSCRIBE", "news"]));
+        monitors.feed(
+            now(),
+            "127.0.0.1:5001",
+            &command(&["CLIENT", "SETNAME", "a"]),
+        );
+        for expected in [
+            r#""MULTI""#,
+            r#""SUBSCRIBE" "news""#,
+            r#""CLIENT" "SETNAME" "a""#,
+        ] {
+            match monitor.receive().await {
+                Some(Frame::SimpleString(line)) => assert!(line.ends_with(expected)),
+                frame => panic!("expected a line, got {:?}", frame),
+            }
+        }
+    }
+}
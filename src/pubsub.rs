@@ -0,0 +1,640 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::mpsc;
+
+use crate::{
+    db::DB,
+    frame::Frame,
+    glob,
+    runner::{nil, rest_strings, RunnerError},
+};
+
+/**
+ * The channel client side caching invalidations are sent on, to the connection a tracking client redirects to.
+ */
+pub const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+/**
+ * How many bytes of messages may wait for a slow subscriber before it gets disconnected.
+ * Going past the hard limit disconnects right away, staying past the soft limit for soft_seconds does too.
+ * Like redis' client-output-buffer-limit for the pubsub class.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputBufferLimit {
+    pub hard: usize,
+    pub soft: usize,
+    pub soft_seconds: u64,
+}
+
+impl Default for OutputBufferLimit {
+    fn default() -> Self {
+        OutputBufferLimit {
+            hard: 32 * 1024 * 1024,
+            soft: 8 * 1024 * 1024,
+            soft_seconds: 60,
+        }
+    }
+}
+
+/**
+ * What a connection subscribes to: a channel, a glob pattern of channels or a shard channel.
+ * Shard channels are separate from the global ones, like in a redis cluster where they are bound to a slot.
+ */
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Channel,
+    Pattern,
+    ShardChannel,
+}
+
+impl Kind {
+    fn subscribe_reply(&self) -> &'static str {
+        match self {
+            Kind::Channel => "subscribe",
+            Kind::Pattern => "psubscribe",
+            Kind::ShardChannel => "ssubscribe",
+        }
+    }
+
+    fn unsubscribe_reply(&self) -> &'static str {
+        match self {
+            Kind::Channel => "unsubscribe",
+            Kind::Pattern => "punsubscribe",
+            Kind::ShardChannel => "sunsubscribe",
+        }
+    }
+}
+
+// Where messages for a subscribed connection are queued
+struct Mailbox {
+    sender: mpsc::UnboundedSender<Frame>,
+    // Bytes queued but not yet taken by the connection
+    pending: Arc<AtomicUsize>,
+    // When the queue went past the soft limit
+    soft_limit_since: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Subscriptions {
+    mailboxes: HashMap<u64, Mailbox>,
+    channels: HashMap<String, HashSet<u64>>,
+    patterns: HashMap<String, HashSet<u64>>,
+    shard_channels: HashMap<String, HashSet<u64>>,
+    // Tracking connections sent their own invalidations, having no REDIRECT.
+    // A key is only invalidated once until it's read again, so they can't pile up like messages.
+    trackers: HashMap<u64, mpsc::UnboundedSender<Frame>>,
+    limit: OutputBufferLimit,
+}
+
+/**
+ * Routes published messages to the connections subscribed to a channel or to a pattern matching it.
+ * Lives next to the store in db::Shared.
+ */
+#[derive(Default)]
+pub struct Hub {
+    state: Mutex<Subscriptions>,
+    next_id: AtomicU64,
+}
+
+impl Subscriptions {
+    fn targets(&mut self, kind: Kind) -> &mut HashMap<String, HashSet<u64>> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    // Drops a mailbox and every subscription of its connection
+    fn remove(&mut self, id: u64) {
+        self.mailboxes.remove(&id);
+        for targets in [
+            &mut self.channels,
+            &mut self.patterns,
+            &mut self.shard_channels,
+        ] {
+            targets.retain(|_, ids| {
+                ids.remove(&id);
+                !ids.is_empty()
+            });
+        }
+    }
+
+    // Queues a message, disconnecting the subscriber if it went past the output buffer limit
+    fn deliver(&mut self, id: u64, frame: Frame) {
+        let limit = self.limit;
+        let mailbox = match self.mailboxes.get_mut(&id) {
+            Some(mailbox) => mailbox,
+            None => return,
+        };
+
+        let size = frame.encoded_len();
+        let pending = mailbox.pending.load(Ordering::Relaxed) + size;
+        let soft_overflowed = if limit.soft > 0 && pending > limit.soft {
+            let since = *mailbox.soft_limit_since.get_or_insert_with(Instant::now);
+            since.elapsed() > Duration::from_secs(limit.soft_seconds)
+        } else {
+            mailbox.soft_limit_since = None;
+            false
+        };
+        let overflowed = (limit.hard > 0 && pending > limit.hard) || soft_overflowed;
+
+        if overflowed || mailbox.sender.send(frame).is_err() {
+            tracing::info!(
+                "PUBSUB: Disconnecting subscriber {} past its output buffer limit",
+                id
+            );
+            self.remove(id);
+            return;
+        }
+        mailbox.pending.fetch_add(size, Ordering::Relaxed);
+    }
+}
+
+impl Hub {
+    fn subscribe(&self, id: u64, name: &str, kind: Kind) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .targets(kind)
+            .entry(name.to_string())
+            .or_default()
+            .insert(id);
+    }
+
+    fn unsubscribe(&self, id: u64, name: &str, kind: Kind) {
+        let mut state = self.state.lock().unwrap();
+        let targets = state.targets(kind);
+        if let Some(ids) = targets.get_mut(name) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                targets.remove(name);
+            }
+        }
+    }
+
+    fn register(&self, id: u64, sender: mpsc::UnboundedSender<Frame>, pending: Arc<AtomicUsize>) {
+        let mut state = self.state.lock().unwrap();
+        state.mailboxes.insert(
+            id,
+            Mailbox {
+                sender,
+                pending,
+                soft_limit_since: None,
+            },
+        );
+    }
+
+    fn remove(&self, id: u64) {
+        self.state.lock().unwrap().remove(id);
+    }
+
+    /**
+     * Sends a message to the subscribers of channel and of the patterns matching it.
+     * Returns the number of subscriptions that received it.
+     */
+    pub fn publish(&self, channel: &str, message: &str) -> usize {
+        let mut state = self.state.lock().unwrap();
+
+        let mut deliveries = vec![];
+        if let Some(ids) = state.channels.get(channel) {
+            for id in ids {
+                deliveries.push((*id, message_frame(&["message", channel, message])));
+            }
+        }
+        for (pattern, ids) in &state.patterns {
+            if glob::matches(pattern.as_bytes(), channel.as_bytes()) {
+                for id in ids {
+                    deliveries.push((*id, message_frame(&["pmessage", pattern, channel, message])));
+                }
+            }
+        }
+
+        let receivers = deliveries.len();
+        for (id, frame) in deliveries {
+            state.deliver(id, frame);
+        }
+        receivers
+    }
+
+    /**
+     * Sends a message to the subscribers of a shard channel, patterns don't apply to them.
+     * Returns the number of subscribers that received it.
+     */
+    pub fn spublish(&self, channel: &str, message: &str) -> usize {
+        let mut state = self.state.lock().unwrap();
+
+        let ids: Vec<u64> = match state.shard_channels.get(channel) {
+            Some(ids) => ids.iter().copied().collect(),
+            None => return 0,
+        };
+        for id in &ids {
+            state.deliver(*id, message_frame(&["smessage", channel, message]));
+        }
+        ids.len()
+    }
+
+    /**
+     * Sends keys invalidated by client side caching to a connection subscribed to __redis__:invalidate,
+     * or to a tracking connection without REDIRECT.
+     * Returns false if the connection is neither, e.g because it went away.
+     */
+    pub fn invalidate(&self, id: u64, keys: &[String]) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let subscribed = state
+            .channels
+            .get(INVALIDATE_CHANNEL)
+            .is_some_and(|ids| ids.contains(&id));
+        if !subscribed && !state.trackers.contains_key(&id) {
+            return false;
+        }
+
+        let keys = keys
+            .iter()
+            .map(|key| Frame::SimpleString(key.clone()))
+            .collect();
+        let frame = Frame::Array(VecDeque::from([
+            Frame::SimpleString("message".to_string()),
+            Frame::SimpleString(INVALIDATE_CHANNEL.to_string()),
+            Frame::Array(keys),
+        ]));
+        match subscribed {
+            true => state.deliver(id, frame),
+            false => {
+                let _ = state.trackers[&id].send(frame);
+            }
+        }
+        true
+    }
+
+    /**
+     * Sends the invalidations of a tracking connection without REDIRECT to the returned receiver.
+     */
+    pub fn track(&self, id: u64) -> mpsc::UnboundedReceiver<Frame> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.state.lock().unwrap().trackers.insert(id, sender);
+        receiver
+    }
+
+    pub fn untrack(&self, id: u64) {
+        self.state.lock().unwrap().trackers.remove(&id);
+    }
+
+    /**
+     * Whether a connection is in subscriber mode, e.g to check a tracking redirect.
+     */
+    pub fn is_subscriber(&self, id: u64) -> bool {
+        self.state.lock().unwrap().mailboxes.contains_key(&id)
+    }
+
+    // The channels of a kind with at least one subscriber, optionally matching a glob pattern
+    fn active(&self, kind: Kind, pattern: Option<&str>) -> Vec<String> {
+        let mut state = self.state.lock().unwrap();
+        let mut channels: Vec<String> = state
+            .targets(kind)
+            .keys()
+            .filter(|channel| {
+                pattern.is_none_or(|pattern| glob::matches(pattern.as_bytes(), channel.as_bytes()))
+            })
+            .cloned()
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    fn subscribers(&self, kind: Kind, channels: &[String]) -> Vec<usize> {
+        let mut state = self.state.lock().unwrap();
+        let targets = state.targets(kind);
+        channels
+            .iter()
+            .map(|channel| targets.get(channel).map_or(0, |ids| ids.len()))
+            .collect()
+    }
+
+    /**
+     * The channels with at least one subscriber, optionally matching a glob pattern.
+     */
+    pub fn channels(&self, pattern: Option<&str>) -> Vec<String> {
+        self.active(Kind::Channel, pattern)
+    }
+
+    /**
+     * The number of subscribers of each channel, patterns excluded.
+     */
+    pub fn numsub(&self, channels: &[String]) -> Vec<usize> {
+        self.subscribers(Kind::Channel, channels)
+    }
+
+    pub fn shard_channels(&self, pattern: Option<&str>) -> Vec<String> {
+        self.active(Kind::ShardChannel, pattern)
+    }
+
+    pub fn shard_numsub(&self, channels: &[String]) -> Vec<usize> {
+        self.subscribers(Kind::ShardChannel, channels)
+    }
+
+    /**
+     * The number of unique patterns subscribed to.
+     */
+    pub fn numpat(&self) -> usize {
+        self.state.lock().unwrap().patterns.len()
+    }
+
+    pub fn output_buffer_limit(&self) -> OutputBufferLimit {
+        self.state.lock().unwrap().limit
+    }
+
+    pub fn set_output_buffer_limit(&self, limit: OutputBufferLimit) {
+        self.state.lock().unwrap().limit = limit;
+    }
+}
+
+fn message_frame(parts: &[&str]) -> Frame {
+    Frame::Array(
+        parts
+            .iter()
+            .map(|part| Frame::SimpleString(part.to_string()))
+            .collect(),
+    )
+}
+
+/**
+ * The pub/sub side of a connection.
+ * Once subscribed to anything the connection is in subscriber mode:
+ * it only accepts the subscription commands and PING, and messages are pushed to it as they get published.
+ */
+pub struct Subscriber {
+    id: u64,
+    db: DB,
+    channels: BTreeSet<String>,
+    patterns: BTreeSet<String>,
+    shard_channels: BTreeSet<String>,
+    // Only set while subscribed, the hub drops the sending side to disconnect a slow subscriber
+    receiver: Option<mpsc::UnboundedReceiver<Frame>>,
+    pending: Arc<AtomicUsize>,
+}
+
+impl Subscriber {
+    pub fn new(db: DB) -> Subscriber {
+        // Ids start at 1 like redis client ids
+        let id = db.db.pubsub.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        Subscriber {
+            id,
+            db,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+            shard_channels: BTreeSet::new(),
+            receiver: None,
+            pending: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /**
+     * The id of the connection, as replied by CLIENT ID.
+     */
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn is_subscribed(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    /**
+     * How many channels, patterns and shard channels the connection is subscribed to.
+     */
+    pub fn subscriptions_len(&self) -> (usize, usize, usize) {
+        (
+            self.channels.len(),
+            self.patterns.len(),
+            self.shard_channels.len(),
+        )
+    }
+
+    /**
+     * The size of the published messages waiting to be written to the connection.
+     */
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    fn subscriptions(&mut self, kind: Kind) -> &mut BTreeSet<String> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    // The count replied to subscription commands, shard channels are counted on their own
+    fn count(&self, kind: Kind) -> i64 {
+        match kind {
+            Kind::ShardChannel => self.shard_channels.len() as i64,
+            _ => (self.channels.len() + self.patterns.len()) as i64,
+        }
+    }
+
+    /**
+     * Waits for the next published message.
+     * Returns None if the subscriber got disconnected for going past its output buffer limit.
+     */
+    pub async fn receive(&mut self) -> Option<Frame> {
+        let frame = match &mut self.receiver {
+            Some(receiver) => receiver.recv().await?,
+            None => std::future::pending().await,
+        };
+        self.pending
+            .fetch_sub(frame.encoded_len(), Ordering::Relaxed);
+        Some(frame)
+    }
+
+    /**
+     * A message that was already published, if any.
+     */
+    pub fn try_receive(&mut self) -> Option<Frame> {
+        let frame = self.receiver.as_mut()?.try_recv().ok()?;
+        self.pending
+            .fetch_sub(frame.encoded_len(), Ordering::Relaxed);
+        Some(frame)
+    }
+
+    /**
+     * Runs the commands that change or depend on the subscriptions of the connection.
+     * Returns None for any other command, which the runner handles outside subscriber mode.
+     * A command can have several replies, e.g one per channel for SUBSCRIBE.
+     */
+    pub fn run(&mut self, frame: &Frame) -> Option<Result<Vec<Frame>, RunnerError>> {
+        let mut frames = match frame {
+            Frame::Array(frames) => frames.clone(),
+            Frame::SimpleString(command) => VecDeque::from([Frame::SimpleString(command.clone())]),
+            _ => return None,
+        };
+        let command = match frames.pop_front() {
+            Some(Frame::SimpleString(command)) => command.to_uppercase(),
+            _ => return None,
+        };
+        let names = match rest_strings(&mut frames) {
+            Ok(names) => names,
+            Err(err) => return Some(Err(err)),
+        };
+
+        let replies = match command.as_str() {
+            "SUBSCRIBE" => self.subscribe(names, Kind::Channel),
+            "PSUBSCRIBE" => self.subscribe(names, Kind::Pattern),
+            "SSUBSCRIBE" => self.subscribe(names, Kind::ShardChannel),
+            "UNSUBSCRIBE" => Ok(self.unsubscribe(names, Kind::Channel)),
+            "PUNSUBSCRIBE" => Ok(self.unsubscribe(names, Kind::Pattern)),
+            "SUNSUBSCRIBE" => Ok(self.unsubscribe(names, Kind::ShardChannel)),
+            "PING" if self.is_subscribed() => match names.as_slice() {
+                [] => Ok(vec![message_frame(&["pong", ""])]),
+                [message] => Ok(vec![message_frame(&["pong", message])]),
+                _ => Err(RunnerError::Incomplete),
+            },
+            "QUIT" => return None,
+            _ if self.is_subscribed() => Err(RunnerError::Other(format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT are allowed in this context",
+                command.to_lowercase()
+            ))),
+            _ => return None,
+        };
+        Some(replies)
+    }
+
+    fn subscribe(&mut self, names: Vec<String>, kind: Kind) -> Result<Vec<Frame>, RunnerError> {
+        if names.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+        if self.receiver.is_none() {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            self.pending.store(0, Ordering::Relaxed);
+            self.db
+                .db
+                .pubsub
+                .register(self.id, sender, self.pending.clone());
+            self.receiver = Some(receiver);
+        }
+
+        let mut replies = vec![];
+        for name in names {
+            if self.subscriptions(kind).insert(name.clone()) {
+                self.db.db.pubsub.subscribe(self.id, &name, kind);
+            }
+            replies.push(Frame::Array(VecDeque::from([
+                Frame::SimpleString(kind.subscribe_reply().to_string()),
+                Frame::SimpleString(name),
+                Frame::Integer(self.count(kind)),
+            ])));
+        }
+        Ok(replies)
+    }
+
+    // Unsubscribes from everything of a kind when names is empty
+    fn unsubscribe(&mut self, names: Vec<String>, kind: Kind) -> Vec<Frame> {
+        let names = match names.is_empty() {
+            true => self.subscriptions(kind).iter().cloned().collect(),
+            false => names,
+        };
+        if names.is_empty() {
+            return vec![Frame::Array(VecDeque::from([
+                Frame::SimpleString(kind.unsubscribe_reply().to_string()),
+                nil(),
+                Frame::Integer(self.count(kind)),
+            ]))];
+        }
+
+        let mut replies = vec![];
+        for name in names {
+            if self.subscriptions(kind).remove(&name) {
+                self.db.db.pubsub.unsubscribe(self.id, &name, kind);
+            }
+            replies.push(Frame::Array(VecDeque::from([
+                Frame::SimpleString(kind.unsubscribe_reply().to_string()),
+                Frame::SimpleString(name),
+                Frame::Integer(self.count(kind)),
+            ])));
+        }
+        // Leaves subscriber mode
+        let subscribed = self.count(Kind::Channel) + self.count(Kind::ShardChannel);
+        if subscribed == 0 && self.receiver.is_some() {
+            self.db.db.pubsub.remove(self.id);
+            self.receiver = None;
+        }
+        replies
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        if self.receiver.is_some() {
+            self.db.db.pubsub.remove(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::command;
+
+    #[tokio::test]
+    async fn pubsub_delivery() {
+        let db = DB::new();
+        let mut subscriber = Subscriber::new(db.clone());
+        let replies = subscriber
+            .run(&command(&["SUBSCRIBE", "news"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(replies.len(), 1);
+        assert!(subscriber.is_subscribed());
+        // Only subscription commands are allowed in subscriber mode
+        assert!(matches!(
+            subscriber.run(&command(&["GET", "key"])),
+            Some(Err(_))
+        ));
+
+        assert_eq!(db.db.pubsub.publish("news", "hello"), 1);
+        assert_eq!(db.db.pubsub.publish("weather", "sunny"), 0);
+        assert_eq!(
+            subscriber.receive().await.unwrap(),
+            Frame::Array(VecDeque::from([
+                Frame::SimpleString("message".to_string()),
+                Frame::SimpleString("news".to_string()),
+                Frame::SimpleString("hello".to_string()),
+            ]))
+        );
+        assert_eq!(db.db.pubsub.numsub(&["news".to_string()]), vec![1]);
+
+        // Shard channels are separate from global channels of the same name
+        subscriber
+            .run(&command(&["SSUBSCRIBE", "news"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(db.db.pubsub.spublish("news", "local"), 1);
+        assert_eq!(db.db.pubsub.shard_numsub(&["news".to_string()]), vec![1]);
+        assert_eq!(
+            subscriber.receive().await.unwrap(),
+            Frame::Array(VecDeque::from([
+                Frame::SimpleString("smessage".to_string()),
+                Frame::SimpleString("news".to_string()),
+                Frame::SimpleString("local".to_string()),
+            ]))
+        );
+
+        // A subscriber that doesn't keep up gets disconnected
+        db.db.pubsub.set_output_buffer_limit(OutputBufferLimit {
+            hard: 64,
+            soft: 0,
+            soft_seconds: 0,
+        });
+        db.db.pubsub.publish("news", &"x".repeat(100));
+        assert_eq!(subscriber.receive().await, None);
+        assert_eq!(db.db.pubsub.channels(None), Vec::<String>::new());
+        assert_eq!(db.db.pubsub.shard_channels(None), Vec::<String>::new());
+    }
+}
@@ -1,41 +1,93 @@
 // specification: https://rdb.fnordig.de/file_format.html
 
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{self, File};
-use std::io::{BufWriter, Cursor, Read, Write};
+use std::io::{Cursor, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use crate::db::DB;
+use crate::db::{
+    now_ms, Consumer, ConsumerGroup, Data, HashField, PendingEntry, SortedSet, Stream, StreamId, DB,
+};
 
 #[derive(Clone)]
 pub struct RDB {
     db: DB,
+    // Held while the file is written so the background worker and SHUTDOWN never save at once
+    saving: Arc<Mutex<()>>,
 }
 
 impl RDB {
     pub fn new(db: DB) -> RDB {
         tracing::info!("RDB: Starting RDB storage service");
-        let rdb = RDB { db };
+        let rdb = RDB {
+            db,
+            saving: Arc::new(Mutex::new(())),
+        };
         tokio::spawn(handle_saving(rdb.clone()));
         rdb
     }
-    fn save(&self) {
+
+    /**
+     * Flushes the db into the RDB file.
+     * The file is written under a temporary name, synced to disk, then renamed over the previous one
+     * so a failed save never leaves a truncated file behind.
+     */
+    pub fn save(&self) -> std::io::Result<()> {
+        let saving = self.lock_saving();
+        self.save_locked(&saving)
+    }
+
+    /**
+     * Keeps the background worker from saving until the guard is dropped.
+     * It is always taken before the store, e.g by SHUTDOWN which then keeps the store locked while it saves.
+     */
+    pub fn lock_saving(&self) -> MutexGuard<'_, ()> {
+        self.saving.lock().unwrap()
+    }
+
+    /**
+     * Flushes the db into the RDB file while the caller holds the guard of lock_saving.
+     */
+    pub fn save_locked(&self, _saving: &MutexGuard<'_, ()>) -> std::io::Result<()> {
         tracing::info!("RDB: Flushing DB into RDB Storage");
-        let file = std::env::var("RDB_URL");
-        let file = match file {
-            Ok(file) => file,
-            Err(_) => {
-                tracing::warn!("RDB: Could not find RDB_URL env var, defaulting to rdb.rdb");
-                "rdb.rdb".to_string()
-            }
-        };
+        let file = self.db.db.config.settings().rdb_path();
+        let started = std::time::Instant::now();
+        let (buffer, dirty) = self.dump();
+        let latency = &self.db.db.latency;
+        // Clients wait for the store while the db is serialized
+        latency.record("snapshot", started.elapsed());
+        let writing = std::time::Instant::now();
+
+        let temp = file.with_file_name(format!("temp-{}.rdb", std::process::id()));
+        let written = File::create(&temp).and_then(|mut writer| {
+            writer.write_all(&buffer)?;
+            writer.sync_all()
+        });
+        let saved = written
+            .and_then(|_| fs::rename(&temp, &file))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&temp);
+            })
+            // Sync the directory too so the rename survives a crash
+            .and_then(|_| match file.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+                _ => Ok(()),
+            });
+
+        latency.record("snapshot-fsync", writing.elapsed());
+        let stats = &self.db.db.stats;
+        stats.record_save(saved.is_ok(), started.elapsed());
+        if saved.is_ok() {
+            let mut store = self.db.db.lock();
+            store.dirty = store.dirty.saturating_sub(dirty);
+        }
+        saved
+    }
 
-        let store = self.db.db.state.lock().unwrap();
-        let writer = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(file)
-            .unwrap();
-        let mut writer = BufWriter::new(writer);
+    // The db in the RDB format, with the number of writes it includes
+    fn dump(&self) -> (Vec<u8>, u64) {
+        let store = self.db.db.lock();
+        let mut writer = vec![];
         // Magic string
         writer.write_all("REDIS".as_bytes()).unwrap();
         // RDB Version as 4 bytes
@@ -59,36 +111,27 @@ impl RDB {
         for (key, value) in store.entries.iter() {
             match value.expires_at {
                 Some(ttl) => {
-                    self.write_key_value_ttl_sec(
-                        &mut writer,
-                        key,
-                        &String::from_utf8_lossy(&value.value).clone(),
-                        ttl,
-                    );
+                    self.write_key_value_ttl_sec(&mut writer, key, &value.value, ttl);
                 }
-                None => self.write_key_value_no_ttl(
-                    &mut writer,
-                    key,
-                    &String::from_utf8_lossy(&value.value).clone(),
-                ),
+                None => self.write_key_value_no_ttl(&mut writer, key, &value.value),
             }
         }
 
+        // Function libraries, each one is its code
+        for library in self.db.db.scripts.libraries(None) {
+            writer.write_all(&[0xF5]).unwrap();
+            self.write_string_encoded(&mut writer, &library.code);
+        }
+
         // End of file
         writer.write_all(&[0xFF]).unwrap();
 
         // 8-byte checksum
         // TODO
+        (writer, store.dirty)
     }
     pub async fn load(&mut self) -> Result<(), String> {
-        let file = std::env::var("RDB_URL");
-        let file = match file {
-            Ok(file) => file,
-            Err(_) => {
-                tracing::warn!("RDB: Could not find RDB_URL env var, skipping RDB data reloading");
-                return Ok(());
-            }
-        };
+        let file = self.db.db.config.settings().rdb_path();
 
         let reader = fs::OpenOptions::new().read(true).open(file);
         let mut reader = match reader {
@@ -153,24 +196,35 @@ impl RDB {
                     cursor.read_exact(&mut ttl_buffer).unwrap();
                     let ttl = u32::from_le_bytes(ttl_buffer);
 
-                    let _value_type = self.read_string_encoded(&mut cursor).unwrap();
+                    let value_type = self.read_string_encoded(&mut cursor).unwrap();
 
                     let key = self.read_string_encoded(&mut cursor).unwrap();
 
-                    let value = self.read_string_encoded(&mut cursor).unwrap();
+                    let value = self.read_value(&value_type, &mut cursor)?;
+
+                    let value = match value {
+                        Some(value) => value,
+                        None => continue,
+                    };
 
                     if ttl < chrono::Utc::now().timestamp() as u32 {
                         // Drop that key value pair as per rdb protocol
                         continue;
                     }
 
-                    self.db.set(
+                    self.db.insert(
                         key,
-                        value.as_bytes().to_vec(),
+                        value,
                         Some(ttl - chrono::Utc::now().timestamp() as u32),
                     );
                 }
 
+                0xF5 => {
+                    let code = self.read_string_encoded(&mut cursor).unwrap();
+                    if let Err(err) = self.db.db.scripts.load_library(&code, true) {
+                        tracing::error!("RDB: Failed to restore a function library: {err:?}");
+                    }
+                }
                 0xFF => {
                     tracing::info!("RDB: END of RDB: Restored data successfully");
 
@@ -181,18 +235,176 @@ impl RDB {
                     // Key-Value without expiry
                     let value_type = self.read_string_encoded(&mut cursor).unwrap();
 
-                    if value_type != "0" {
-                        tracing::error!("RDB: Unsupported Value Encoding found");
-                        return Err("Unsupported Value Encoding found".to_string());
-                    }
-
                     let key = self.read_string_encoded(&mut cursor).unwrap();
 
-                    let value = self.read_string_encoded(&mut cursor).unwrap();
-                    self.db.set(key, value.as_bytes().to_vec(), None);
+                    if let Some(value) = self.read_value(&value_type, &mut cursor)? {
+                        self.db.insert(key, value, None);
+                    }
+                }
+            }
+        }
+    }
+
+    /**
+     * Reads the value part of a key value pair.
+     * The value type follows the rdb spec: 0 for strings, 2 for sets, 4 for hashes and 24 for hashes with field ttls.
+     * Returns None if nothing is left to restore e.g all fields of a hash expired.
+     */
+    fn read_value(
+        &self,
+        value_type: &str,
+        cursor: &mut Cursor<&Vec<u8>>,
+    ) -> Result<Option<Data>, String> {
+        match value_type {
+            "0" => {
+                let value = self.read_bytes_encoded(cursor).unwrap();
+                Ok(Some(Data::String(value)))
+            }
+            "2" => {
+                let length = self.read_integer_encoded(cursor).unwrap();
+                let mut members = Vec::with_capacity(length);
+                for _ in 0..length {
+                    members.push(self.read_string_encoded(cursor).unwrap());
+                }
+                Ok(Some(Data::Set(members.into_iter().collect())))
+            }
+            "4" => {
+                let length = self.read_integer_encoded(cursor).unwrap();
+                let mut hash = HashMap::with_capacity(length);
+                for _ in 0..length {
+                    let field = self.read_string_encoded(cursor).unwrap();
+                    let value = self.read_bytes_encoded(cursor).unwrap();
+                    hash.insert(
+                        field,
+                        HashField {
+                            value,
+                            expires_at: None,
+                        },
+                    );
                 }
+                Ok(Some(Data::Hash(hash)))
+            }
+            "5" => {
+                let length = self.read_integer_encoded(cursor).unwrap();
+                let mut zset = SortedSet::new();
+                for _ in 0..length {
+                    let member = self.read_string_encoded(cursor).unwrap();
+                    let mut score_buffer: [u8; 8] = [0; 8];
+                    cursor.read_exact(&mut score_buffer).unwrap();
+                    zset.insert(member, f64::from_le_bytes(score_buffer));
+                }
+                Ok(Some(Data::SortedSet(zset)))
+            }
+            "15" => Ok(Some(Data::Stream(self.read_stream(cursor).unwrap()))),
+            "24" => {
+                let length = self.read_integer_encoded(cursor).unwrap();
+                let now = now_ms();
+                let mut hash = HashMap::with_capacity(length);
+                for _ in 0..length {
+                    let mut ttl_buffer: [u8; 8] = [0; 8];
+                    cursor.read_exact(&mut ttl_buffer).unwrap();
+                    let expires_at = u64::from_le_bytes(ttl_buffer);
+
+                    let field = self.read_string_encoded(cursor).unwrap();
+                    let value = self.read_bytes_encoded(cursor).unwrap();
+
+                    match expires_at {
+                        0 => {
+                            hash.insert(
+                                field,
+                                HashField {
+                                    value,
+                                    expires_at: None,
+                                },
+                            );
+                        }
+                        // Drop expired fields like expired keys
+                        expires_at if expires_at <= now => {}
+                        expires_at => {
+                            hash.insert(
+                                field,
+                                HashField {
+                                    value,
+                                    expires_at: Some(expires_at),
+                                },
+                            );
+                        }
+                    }
+                }
+                if hash.is_empty() {
+                    return Ok(None);
+                }
+                Ok(Some(Data::Hash(hash)))
+            }
+            _ => {
+                tracing::error!("RDB: Unsupported Value Encoding found");
+                Err("Unsupported Value Encoding found".to_string())
+            }
+        }
+    }
+
+    fn read_stream(&self, cursor: &mut Cursor<&Vec<u8>>) -> std::io::Result<Stream> {
+        let mut stream = Stream::new();
+
+        let length = self.read_integer_encoded(cursor)?;
+        for _ in 0..length {
+            let id = self.read_stream_id(cursor)?;
+            let fields_length = self.read_integer_encoded(cursor)?;
+            let mut fields = Vec::with_capacity(fields_length);
+            for _ in 0..fields_length {
+                let field = self.read_string_encoded(cursor)?;
+                let value = self.read_bytes_encoded(cursor)?;
+                fields.push((field, value));
             }
+            stream.entries.insert(id, fields);
         }
+        stream.last_id = self.read_stream_id(cursor)?;
+        stream.entries_added = self.read_u64(cursor)?;
+
+        let groups = self.read_integer_encoded(cursor)?;
+        for _ in 0..groups {
+            let name = self.read_string_encoded(cursor)?;
+            let mut group = ConsumerGroup {
+                last_delivered: self.read_stream_id(cursor)?,
+                pending: BTreeMap::new(),
+                consumers: BTreeMap::new(),
+            };
+            let pending = self.read_integer_encoded(cursor)?;
+            for _ in 0..pending {
+                let id = self.read_stream_id(cursor)?;
+                let entry = PendingEntry {
+                    consumer: self.read_string_encoded(cursor)?,
+                    delivered_at: self.read_u64(cursor)?,
+                    deliveries: self.read_u64(cursor)?,
+                };
+                group.pending.insert(id, entry);
+            }
+            let consumers = self.read_integer_encoded(cursor)?;
+            for _ in 0..consumers {
+                let name = self.read_string_encoded(cursor)?;
+                let consumer = Consumer {
+                    seen_at: self.read_u64(cursor)?,
+                };
+                group.consumers.insert(name, consumer);
+            }
+            stream.groups.insert(name, group);
+        }
+
+        Ok(stream)
+    }
+
+    fn read_stream_id(&self, cursor: &mut Cursor<&Vec<u8>>) -> std::io::Result<StreamId> {
+        Ok(StreamId {
+            ms: self.read_u64(cursor)?,
+            seq: self.read_u64(cursor)?,
+        })
+    }
+
+    fn read_u64(&self, cursor: &mut Cursor<&Vec<u8>>) -> std::io::Result<u64> {
+        let mut buffer: [u8; 8] = [0; 8];
+        cursor.read_exact(&mut buffer)?;
+
+        Ok(u64::from_le_bytes(buffer))
     }
 
     fn get_next_byte(&self, cursor: &mut Cursor<&Vec<u8>>) -> std::io::Result<u8> {
@@ -210,13 +422,19 @@ impl RDB {
     }
 
     fn read_string_encoded(&self, cursor: &mut Cursor<&Vec<u8>>) -> std::io::Result<String> {
+        let buffer = self.read_bytes_encoded(cursor)?;
+
+        Ok(String::from_utf8_lossy(&buffer).to_string())
+    }
+
+    fn read_bytes_encoded(&self, cursor: &mut Cursor<&Vec<u8>>) -> std::io::Result<Vec<u8>> {
         let length = self.read_integer_encoded(cursor)?;
 
         let mut buffer = vec![0; length];
 
         cursor.read_exact(&mut buffer)?;
 
-        return Ok(String::from_utf8_lossy(&buffer).to_string());
+        Ok(buffer)
     }
 
     fn read_integer_encoded(&self, cursor: &mut Cursor<&Vec<u8>>) -> std::io::Result<usize> {
@@ -234,37 +452,140 @@ impl RDB {
             let integer = ((byte as usize) << 8) | byte2 as usize;
 
             return Ok(integer);
+        } else if significant_bits == 0b10 {
+            // The next 4 bytes hold the length
+            let mut buffer: [u8; 4] = [0; 4];
+            cursor.read_exact(&mut buffer)?;
+
+            return Ok(u32::from_be_bytes(buffer) as usize);
         }
 
         todo!()
     }
 
-    fn write_string_encoded(&self, writer: &mut BufWriter<File>, input: &str) {
+    fn write_string_encoded(&self, writer: &mut Vec<u8>, input: &str) {
+        self.write_bytes_encoded(writer, input.as_bytes())
+    }
+
+    fn write_bytes_encoded(&self, writer: &mut Vec<u8>, input: &[u8]) {
         self.write_integer_encoded(writer, input.len());
-        writer.write_all(input.as_bytes()).unwrap()
+        writer.write_all(input).unwrap()
     }
 
-    fn write_key_value_ttl_sec(
-        &self,
-        writer: &mut BufWriter<File>,
-        key: &str,
-        value: &str,
-        ttl: u32,
-    ) {
+    fn write_key_value_ttl_sec(&self, writer: &mut Vec<u8>, key: &str, value: &Data, ttl: u32) {
         writer.write_all(&[0xFD]).unwrap();
 
         writer.write_all(&(ttl).to_le_bytes()).unwrap(); // Replace with ttl
-                                                         // Limit the type of value to only strings
-        self.write_string_encoded(writer, "0");
-        self.write_string_encoded(writer, key);
-        self.write_string_encoded(writer, value)
+        self.write_value(writer, key, value)
     }
-    fn write_key_value_no_ttl(&self, writer: &mut BufWriter<File>, key: &str, value: &str) {
-        self.write_string_encoded(writer, "0");
-        self.write_string_encoded(writer, key);
-        self.write_string_encoded(writer, value)
+    fn write_key_value_no_ttl(&self, writer: &mut Vec<u8>, key: &str, value: &Data) {
+        self.write_value(writer, key, value)
     }
-    fn write_integer_encoded(&self, writer: &mut BufWriter<File>, input: usize) {
+    fn write_value(&self, writer: &mut Vec<u8>, key: &str, value: &Data) {
+        match value {
+            Data::String(value) => {
+                self.write_string_encoded(writer, "0");
+                self.write_string_encoded(writer, key);
+                self.write_bytes_encoded(writer, value)
+            }
+            Data::Set(set) => {
+                // Set encoding: the number of members followed by the members
+                self.write_string_encoded(writer, "2");
+                self.write_string_encoded(writer, key);
+                self.write_integer_encoded(writer, set.len());
+                for member in set.members() {
+                    self.write_string_encoded(writer, &member);
+                }
+            }
+            Data::SortedSet(zset) => {
+                // Sorted set encoding: the number of members followed by
+                // each member and its score as a little endian binary double
+                self.write_string_encoded(writer, "5");
+                self.write_string_encoded(writer, key);
+                self.write_integer_encoded(writer, zset.len());
+                for (member, score) in zset.iter() {
+                    self.write_string_encoded(writer, member);
+                    writer.write_all(&score.to_le_bytes()).unwrap();
+                }
+            }
+            Data::Stream(stream) => {
+                self.write_string_encoded(writer, "15");
+                self.write_string_encoded(writer, key);
+                self.write_stream(writer, stream);
+            }
+            Data::Hash(hash) if hash.values().all(|value| value.expires_at.is_none()) => {
+                // Hash encoding: the number of fields followed by field value pairs
+                self.write_string_encoded(writer, "4");
+                self.write_string_encoded(writer, key);
+                self.write_integer_encoded(writer, hash.len());
+                for (field, value) in hash {
+                    self.write_string_encoded(writer, field);
+                    self.write_bytes_encoded(writer, &value.value);
+                }
+            }
+            Data::Hash(hash) => {
+                // Hash with field ttls: each field value pair is preceded by
+                // its expiry as a unix time in milliseconds, 0 if it has none
+                self.write_string_encoded(writer, "24");
+                self.write_string_encoded(writer, key);
+                self.write_integer_encoded(writer, hash.len());
+                for (field, value) in hash {
+                    writer
+                        .write_all(&value.expires_at.unwrap_or(0).to_le_bytes())
+                        .unwrap();
+                    self.write_string_encoded(writer, field);
+                    self.write_bytes_encoded(writer, &value.value);
+                }
+            }
+        }
+    }
+    /**
+     * Stream encoding: the entries as id, number of fields and field value pairs,
+     * then the last id, the number of entries ever added and the consumer groups with their
+     * pending entries and consumers. Ids are two little endian u64, as are times and counters.
+     */
+    fn write_stream(&self, writer: &mut Vec<u8>, stream: &Stream) {
+        self.write_integer_encoded(writer, stream.len());
+        for (id, fields) in stream.entries.iter() {
+            self.write_stream_id(writer, id);
+            self.write_integer_encoded(writer, fields.len());
+            for (field, value) in fields {
+                self.write_string_encoded(writer, field);
+                self.write_bytes_encoded(writer, value);
+            }
+        }
+        self.write_stream_id(writer, &stream.last_id);
+        writer
+            .write_all(&stream.entries_added.to_le_bytes())
+            .unwrap();
+
+        self.write_integer_encoded(writer, stream.groups.len());
+        for (name, group) in stream.groups.iter() {
+            self.write_string_encoded(writer, name);
+            self.write_stream_id(writer, &group.last_delivered);
+            self.write_integer_encoded(writer, group.pending.len());
+            for (id, pending) in group.pending.iter() {
+                self.write_stream_id(writer, id);
+                self.write_string_encoded(writer, &pending.consumer);
+                writer
+                    .write_all(&pending.delivered_at.to_le_bytes())
+                    .unwrap();
+                writer.write_all(&pending.deliveries.to_le_bytes()).unwrap();
+            }
+            self.write_integer_encoded(writer, group.consumers.len());
+            for (name, consumer) in group.consumers.iter() {
+                self.write_string_encoded(writer, name);
+                writer.write_all(&consumer.seen_at.to_le_bytes()).unwrap();
+            }
+        }
+    }
+
+    fn write_stream_id(&self, writer: &mut Vec<u8>, id: &StreamId) {
+        writer.write_all(&id.ms.to_le_bytes()).unwrap();
+        writer.write_all(&id.seq.to_le_bytes()).unwrap();
+    }
+
+    fn write_integer_encoded(&self, writer: &mut Vec<u8>, input: usize) {
         if input < 64 {
             let length = input as u8;
 
@@ -281,7 +602,9 @@ impl RDB {
             writer.write_all(&[byte_one, byte_two]).unwrap();
         } else if input <= 4294967295 {
             // 2^32-1
-            todo!()
+            // 10
+            writer.write_all(&[0b1000_0000]).unwrap();
+            writer.write_all(&(input as u32).to_be_bytes()).unwrap();
         } else {
             // Special formatting
             // 11
@@ -294,36 +617,20 @@ impl RDB {
 async fn handle_saving(rdb: RDB) {
     tracing::info!("RDB: Starting RDB storage background worker");
 
-    let flush_every = std::env::var("FLUSH_EVERY");
-
-    let flush_every = match flush_every {
-        Ok(time) => {
-            match time.parse::<u64>() {
-                Ok(time) => {
-                    if time > 68719476734 {
-                        // Tokio sleep max limit
-                        tracing::error!("Max number of seconds supported is 68719476734, defaulting to 68719476734");
-                        68719476734
-                    } else {
-                        time
-                    }
-                }
-                Err(_) => {
-                    tracing::error!(
-                        "Invalid FLUSH_EVERY env var provided, defaulting to 60 seconds"
-                    );
-                    60
-                }
-            }
+    let config = &rdb.db.db.config;
+    let mut last_save = tokio::time::Instant::now();
+    loop {
+        // The interval is read again whenever CONFIG SET changes the settings
+        let flush_every = config.settings().flush_every;
+        let deadline = last_save + tokio::time::Duration::from_secs(flush_every);
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => {}
+            _ = config.changed.notified() => continue,
         }
-        Err(_) => {
-            tracing::warn!("Failed to read FLUSH_EVERY env var, defaulting to 60 seconds");
-            60
+        rdb.db.db.scripts.finished().await;
+        if let Err(err) = rdb.save() {
+            tracing::error!("RDB: Failed to flush the db into the RDB file: {}", err);
         }
-    };
-
-    loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(flush_every as u64)).await;
-        rdb.save()
+        last_save = tokio::time::Instant::now();
     }
 }
@@ -1,6 +1,27 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, time::Duration};
 
-use crate::{db::DB, frame::Frame};
+use crate::{
+    command::{spec, Category},
+    db::{DBError, SetOperation, DB, WRONGTYPE},
+    frame::Frame,
+    monitor,
+};
+
+mod bitmap;
+mod config;
+mod geo;
+mod hash;
+mod hyperloglog;
+mod info;
+mod latency;
+mod pubsub;
+mod scripting;
+mod set;
+mod slowlog;
+mod stream;
+mod zset;
+
+use zset::RangeBy;
 
 /**
  * Handles execution of a frame.
@@ -8,6 +29,12 @@ use crate::{db::DB, frame::Frame};
  */
 pub struct Runner<'a> {
     db: &'a mut DB,
+    // The ACL user scripts run their commands as, None running them unchecked
+    user: Option<String>,
+    // Where the commands come from as shown by MONITOR, e.g a client address or lua
+    client: Option<String>,
+    // The tracking connection remembering the keys read, for client side caching
+    tracking: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -15,18 +42,115 @@ pub enum RunnerError {
     Incomplete,
     Other(String),
     Unsupported,
+    // The command has to wait for a key to be written, then be run again.
+    // A timeout of None means it waits forever, retry replaces the frame that is run again.
+    Blocked {
+        timeout: Option<Duration>,
+        retry: Option<Frame>,
+    },
+}
+
+impl From<DBError> for RunnerError {
+    fn from(err: DBError) -> Self {
+        match err {
+            DBError::WrongType => RunnerError::Other(WRONGTYPE.to_string()),
+            DBError::Other(err) => RunnerError::Other(err),
+        }
+    }
+}
+
+impl RunnerError {
+    /**
+     * The error reply written back to the client.
+     */
+    pub fn into_frame(self) -> Frame {
+        match self {
+            RunnerError::Other(err) => Frame::SimpleError(err),
+            RunnerError::Incomplete => {
+                Frame::SimpleError("Protocol Error: Incorrect usage of command".to_string())
+            }
+            RunnerError::Unsupported => Frame::SimpleError(
+                "Protocol Error: Unsupported usage of command or values".to_string(),
+            ),
+            // Commands don't block inside a transaction, they reply nil right away
+            RunnerError::Blocked { .. } => nil(),
+        }
+    }
 }
 
 impl<'a> Runner<'a> {
-    pub fn new(db: &mut DB) -> Runner {
-        Runner { db }
+    pub fn new(db: &mut DB) -> Runner<'_> {
+        Runner {
+            db,
+            user: None,
+            client: None,
+            tracking: None,
+        }
+    }
+
+    /**
+     * Runs commands on behalf of a user, whose ACL applies to the commands its scripts call.
+     */
+    pub fn with_user(mut self, user: &str) -> Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    /**
+     * Runs commands sent by a client, which MONITOR shows them as coming from.
+     */
+    pub fn with_client(mut self, client: &str) -> Self {
+        self.client = Some(client.to_string());
+        self
+    }
+
+    /**
+     * Remembers the keys read by the commands it runs, those called by scripts included,
+     * so the tracking connection id gets invalidated when they are modified.
+     */
+    pub fn with_tracking(mut self, id: u64) -> Self {
+        self.tracking = Some(id);
+        self
+    }
+
+    /**
+     * Runs the frames queued by a transaction, no other connection touches the store in between.
+     * Replies nil without running anything if one of the watched keys was modified since WATCH.
+     */
+    pub fn exec(&mut self, frames: Vec<Frame>, watched: &[(String, u64)]) -> Frame {
+        let shared = self.db.db.clone();
+        let _transaction = shared.lock_transaction();
+        if self.db.watched_changed(watched) {
+            return nil();
+        }
+        Frame::Array(
+            frames
+                .into_iter()
+                .map(|frame| self.run(frame).unwrap_or_else(RunnerError::into_frame))
+                .collect(),
+        )
     }
     pub fn run(&mut self, frame: Frame) -> Result<Frame, RunnerError> {
-        match frame {
+        // The frame is consumed by running it, so it is kept for MONITOR beforehand
+        let monitored = match self.db.db.monitors.is_empty() {
+            true => None,
+            false => Some((monitor::now(), frame.clone())),
+        };
+        let result = match frame {
             Frame::SimpleString(input) => self.run_string(input.to_owned()),
             Frame::Array(mut input) => self.run_array(&mut input),
             _ => Err(RunnerError::Unsupported),
+        };
+        // Blocked commands are shown once they run again and go on
+        match monitored {
+            Some(_) if matches!(result, Err(RunnerError::Blocked { .. })) => {}
+            Some((time, frame)) => {
+                let client = self.client.as_deref().unwrap_or("unknown");
+                self.db.db.monitors.feed(time, client, &frame);
+            }
+            None => {}
         }
+        result
     }
 
     fn run_array(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
@@ -41,14 +165,28 @@ impl<'a> Runner<'a> {
         input: String,
         frames: &mut VecDeque<Frame>,
     ) -> Result<Frame, RunnerError> {
-        match input.to_uppercase().as_str() {
-            "SET" => self.run_set(frames),
-            "GET" => self.run_get(frames),
-            _ => Err(RunnerError::Unsupported),
+        let handler = command(&input).ok_or(RunnerError::Unsupported)?;
+        if let Some(spec) = spec(&input).filter(|spec| spec.has(Category::Read)) {
+            let args: Vec<String> = frames
+                .iter()
+                .filter_map(|frame| match frame {
+                    Frame::SimpleString(arg) => Some(arg.clone()),
+                    Frame::Integer(arg) => Some(arg.to_string()),
+                    _ => None,
+                })
+                .collect();
+            let keys = spec.keys(&args);
+            self.db.count_lookups(&keys);
+            // Before running so a write in between isn't missed
+            if let Some(id) = self.tracking.filter(|_| !spec.has(Category::Write)) {
+                if !keys.is_empty() {
+                    self.db.track_keys(id, &keys);
+                }
+            }
         }
+        handler(self, frames)
     }
     fn run_set(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
-      
         let key = frames.pop_front().ok_or(RunnerError::Incomplete)?;
 
         let key = match key {
@@ -58,8 +196,10 @@ impl<'a> Runner<'a> {
 
         let value = frames.pop_front().ok_or(RunnerError::Incomplete)?;
 
+        // A bulk string value is kept byte for byte, e.g the registers of a HyperLogLog
         let value = match value {
-            Frame::SimpleString(input) => input,
+            Frame::SimpleString(input) => input.into_bytes(),
+            Frame::BulkString(input) => input,
             _ => return Err(RunnerError::Unsupported),
         };
 
@@ -98,25 +238,16 @@ impl<'a> Runner<'a> {
             None => None,
         };
 
-        self.db.set(key, value.as_bytes().to_owned(), ttl);
+        self.db.set(key, value, ttl);
         Ok(Frame::SimpleString("OK".to_string()))
     }
 
     fn run_get(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
-       
+        let key = next_string(frames)?;
 
-        let key = frames.pop_front().ok_or(RunnerError::Incomplete)?;
-
-        let key = match key {
-            Frame::SimpleString(input) => input,
-            _ => return Err(RunnerError::Unsupported),
-        };
-
-        let value = self.db.get(&key);
+        let value = self.db.get(&key)?;
         match value {
-            Some(value) => Ok(Frame::SimpleString(
-                String::from_utf8_lossy(&value).into_owned(),
-            )),
+            Some(value) => Ok(bulk(&value)),
             None => Ok(Frame::SimpleError("Nill".to_string())),
         }
     }
@@ -128,3 +259,233 @@ impl<'a> Runner<'a> {
         }
     }
 }
+
+type Handler = fn(&mut Runner<'_>, &mut VecDeque<Frame>) -> Result<Frame, RunnerError>;
+
+// The handler of a command, None if the command is unknown
+fn command(name: &str) -> Option<Handler> {
+    let handler: Handler = match name.to_uppercase().as_str() {
+        "PING" => |runner, frames| match frames.is_empty() {
+            true => runner.run_string("PING".to_string()),
+            false => Err(RunnerError::Unsupported),
+        },
+        "SET" => |runner, frames| runner.run_set(frames),
+        "GET" => |runner, frames| runner.run_get(frames),
+        "SETBIT" => |runner, frames| runner.run_setbit(frames),
+        "GETBIT" => |runner, frames| runner.run_getbit(frames),
+        "BITCOUNT" => |runner, frames| runner.run_bitcount(frames),
+        "BITPOS" => |runner, frames| runner.run_bitpos(frames),
+        "BITOP" => |runner, frames| runner.run_bitop(frames),
+        "BITFIELD" => |runner, frames| runner.run_bitfield(frames, false),
+        "BITFIELD_RO" => |runner, frames| runner.run_bitfield(frames, true),
+        "GEOADD" => |runner, frames| runner.run_geoadd(frames),
+        "GEODIST" => |runner, frames| runner.run_geodist(frames),
+        "GEOPOS" => |runner, frames| runner.run_geopos(frames),
+        "GEOHASH" => |runner, frames| runner.run_geohash(frames),
+        "GEOSEARCH" => |runner, frames| runner.run_geosearch(frames),
+        "GEOSEARCHSTORE" => |runner, frames| runner.run_geosearchstore(frames),
+        "PUBLISH" => |runner, frames| runner.run_publish(frames),
+        "SPUBLISH" => |runner, frames| runner.run_spublish(frames),
+        "PUBSUB" => |runner, frames| runner.run_pubsub(frames),
+        "PFADD" => |runner, frames| runner.run_pfadd(frames),
+        "PFCOUNT" => |runner, frames| runner.run_pfcount(frames),
+        "PFMERGE" => |runner, frames| runner.run_pfmerge(frames),
+        "HSET" | "HMSET" => |runner, frames| runner.run_hset(frames),
+        "HSETNX" => |runner, frames| runner.run_hsetnx(frames),
+        "HGET" => |runner, frames| runner.run_hget(frames),
+        "HMGET" => |runner, frames| runner.run_hmget(frames),
+        "HDEL" => |runner, frames| runner.run_hdel(frames),
+        "HGETALL" => |runner, frames| runner.run_hgetall(frames),
+        "HKEYS" => |runner, frames| runner.run_hkeys(frames),
+        "HVALS" => |runner, frames| runner.run_hvals(frames),
+        "HLEN" => |runner, frames| runner.run_hlen(frames),
+        "HEXISTS" => |runner, frames| runner.run_hexists(frames),
+        "HSTRLEN" => |runner, frames| runner.run_hstrlen(frames),
+        "HINCRBY" => |runner, frames| runner.run_hincrby(frames),
+        "HINCRBYFLOAT" => |runner, frames| runner.run_hincrbyfloat(frames),
+        "HSCAN" => |runner, frames| runner.run_hscan(frames),
+        "HRANDFIELD" => |runner, frames| runner.run_hrandfield(frames),
+        "HEXPIRE" => |runner, frames| runner.run_hexpire(frames, false, false),
+        "HPEXPIRE" => |runner, frames| runner.run_hexpire(frames, true, false),
+        "HEXPIREAT" => |runner, frames| runner.run_hexpire(frames, false, true),
+        "HPEXPIREAT" => |runner, frames| runner.run_hexpire(frames, true, true),
+        "HTTL" => |runner, frames| runner.run_httl(frames, false, false),
+        "HPTTL" => |runner, frames| runner.run_httl(frames, true, false),
+        "HEXPIRETIME" => |runner, frames| runner.run_httl(frames, false, true),
+        "HPEXPIRETIME" => |runner, frames| runner.run_httl(frames, true, true),
+        "HPERSIST" => |runner, frames| runner.run_hpersist(frames),
+        "SADD" => |runner, frames| runner.run_sadd(frames),
+        "SREM" => |runner, frames| runner.run_srem(frames),
+        "SMEMBERS" => |runner, frames| runner.run_smembers(frames),
+        "SISMEMBER" => |runner, frames| runner.run_sismember(frames),
+        "SMISMEMBER" => |runner, frames| runner.run_smismember(frames),
+        "SCARD" => |runner, frames| runner.run_scard(frames),
+        "SMOVE" => |runner, frames| runner.run_smove(frames),
+        "SINTER" => |runner, frames| runner.run_set_operation(frames, SetOperation::Inter),
+        "SUNION" => |runner, frames| runner.run_set_operation(frames, SetOperation::Union),
+        "SDIFF" => |runner, frames| runner.run_set_operation(frames, SetOperation::Diff),
+        "SINTERSTORE" => {
+            |runner, frames| runner.run_set_operation_store(frames, SetOperation::Inter)
+        }
+        "SUNIONSTORE" => {
+            |runner, frames| runner.run_set_operation_store(frames, SetOperation::Union)
+        }
+        "SDIFFSTORE" => |runner, frames| runner.run_set_operation_store(frames, SetOperation::Diff),
+        "SINTERCARD" => |runner, frames| runner.run_sintercard(frames),
+        "SPOP" => |runner, frames| runner.run_spop(frames),
+        "SRANDMEMBER" => |runner, frames| runner.run_srandmember(frames),
+        "SSCAN" => |runner, frames| runner.run_sscan(frames),
+        "ZADD" => |runner, frames| runner.run_zadd(frames),
+        "ZINCRBY" => |runner, frames| runner.run_zincrby(frames),
+        "ZREM" => |runner, frames| runner.run_zrem(frames),
+        "ZCARD" => |runner, frames| runner.run_zcard(frames),
+        "ZSCORE" => |runner, frames| runner.run_zscore(frames),
+        "ZMSCORE" => |runner, frames| runner.run_zmscore(frames),
+        "ZRANK" => |runner, frames| runner.run_zrank(frames, false),
+        "ZREVRANK" => |runner, frames| runner.run_zrank(frames, true),
+        "ZCOUNT" => |runner, frames| runner.run_zcount(frames),
+        "ZRANGE" => |runner, frames| runner.run_zrange(frames, RangeBy::Rank, false, false),
+        "ZREVRANGE" => |runner, frames| runner.run_zrange(frames, RangeBy::Rank, true, true),
+        "ZRANGEBYSCORE" => |runner, frames| runner.run_zrange(frames, RangeBy::Score, false, true),
+        "ZREVRANGEBYSCORE" => {
+            |runner, frames| runner.run_zrange(frames, RangeBy::Score, true, true)
+        }
+        "ZRANGEBYLEX" => |runner, frames| runner.run_zrange(frames, RangeBy::Lex, false, true),
+        "ZREVRANGEBYLEX" => |runner, frames| runner.run_zrange(frames, RangeBy::Lex, true, true),
+        "ZPOPMIN" => |runner, frames| runner.run_zpop(frames, false),
+        "ZPOPMAX" => |runner, frames| runner.run_zpop(frames, true),
+        "BZPOPMIN" => |runner, frames| runner.run_bzpop(frames, false),
+        "BZPOPMAX" => |runner, frames| runner.run_bzpop(frames, true),
+        "ZUNION" => |runner, frames| runner.run_zcombine(frames, false),
+        "ZINTER" => |runner, frames| runner.run_zcombine(frames, true),
+        "ZUNIONSTORE" => |runner, frames| runner.run_zcombine_store(frames, false),
+        "ZINTERSTORE" => |runner, frames| runner.run_zcombine_store(frames, true),
+        "ZSCAN" => |runner, frames| runner.run_zscan(frames),
+        "XADD" => |runner, frames| runner.run_xadd(frames),
+        "XTRIM" => |runner, frames| runner.run_xtrim(frames),
+        "XLEN" => |runner, frames| runner.run_xlen(frames),
+        "XDEL" => |runner, frames| runner.run_xdel(frames),
+        "XRANGE" => |runner, frames| runner.run_xrange(frames, false),
+        "XREVRANGE" => |runner, frames| runner.run_xrange(frames, true),
+        "XREAD" => |runner, frames| runner.run_xread(frames),
+        "XGROUP" => |runner, frames| runner.run_xgroup(frames),
+        "XREADGROUP" => |runner, frames| runner.run_xreadgroup(frames),
+        "XACK" => |runner, frames| runner.run_xack(frames),
+        "XPENDING" => |runner, frames| runner.run_xpending(frames),
+        "XCLAIM" => |runner, frames| runner.run_xclaim(frames),
+        "XAUTOCLAIM" => |runner, frames| runner.run_xautoclaim(frames),
+        "XINFO" => |runner, frames| runner.run_xinfo(frames),
+        "EVAL" => |runner, frames| runner.run_eval(frames),
+        "EVALSHA" => |runner, frames| runner.run_evalsha(frames),
+        "SCRIPT" => |runner, frames| runner.run_script(frames),
+        "FCALL" => |runner, frames| runner.run_fcall(frames, false),
+        "FCALL_RO" => |runner, frames| runner.run_fcall(frames, true),
+        "FUNCTION" => |runner, frames| runner.run_function(frames),
+        "CONFIG" => |runner, frames| runner.run_config(frames),
+        "INFO" => |runner, frames| runner.run_info(frames),
+        "SLOWLOG" => |runner, frames| runner.run_slowlog(frames),
+        "LATENCY" => |runner, frames| runner.run_latency(frames),
+        _ => return None,
+    };
+    Some(handler)
+}
+
+/**
+ * Whether the runner knows a command, e.g to reject unknown commands queued in a transaction.
+ */
+pub fn is_command(name: &str) -> bool {
+    command(name).is_some()
+}
+
+// Pops the next argument of a command as a string
+pub(crate) fn next_string(frames: &mut VecDeque<Frame>) -> Result<String, RunnerError> {
+    match frames.pop_front().ok_or(RunnerError::Incomplete)? {
+        Frame::SimpleString(input) => Ok(input),
+        Frame::Integer(input) => Ok(input.to_string()),
+        _ => Err(RunnerError::Unsupported),
+    }
+}
+
+// Pops the next argument of a command as an integer.
+// Clients may send numbers either as integers or strings.
+fn next_integer(frames: &mut VecDeque<Frame>) -> Result<i64, RunnerError> {
+    match frames.pop_front().ok_or(RunnerError::Incomplete)? {
+        Frame::Integer(input) => Ok(input),
+        Frame::SimpleString(input) => input.parse::<i64>().map_err(|_| {
+            RunnerError::Other("ERR value is not an integer or out of range".to_string())
+        }),
+        _ => Err(RunnerError::Unsupported),
+    }
+}
+
+fn next_float(frames: &mut VecDeque<Frame>) -> Result<f64, RunnerError> {
+    match frames.pop_front().ok_or(RunnerError::Incomplete)? {
+        Frame::Integer(input) => Ok(input as f64),
+        Frame::SimpleString(input) => match input.parse::<f64>() {
+            Ok(input) if !input.is_nan() => Ok(input),
+            _ => Err(RunnerError::Other(
+                "ERR value is not a valid float".to_string(),
+            )),
+        },
+        _ => Err(RunnerError::Unsupported),
+    }
+}
+
+// Pops the remaining arguments of a command as strings
+pub(crate) fn rest_strings(frames: &mut VecDeque<Frame>) -> Result<Vec<String>, RunnerError> {
+    let mut strings = Vec::with_capacity(frames.len());
+    while !frames.is_empty() {
+        strings.push(next_string(frames)?);
+    }
+    Ok(strings)
+}
+
+/**
+ * The arguments shared by the *SCAN commands: cursor [MATCH pattern] [COUNT count] [NOVALUES]
+ */
+struct ScanArguments {
+    cursor: u64,
+    pattern: Option<String>,
+    count: usize,
+    novalues: bool,
+}
+
+impl ScanArguments {
+    fn parse(frames: &mut VecDeque<Frame>) -> Result<ScanArguments, RunnerError> {
+        let cursor = next_string(frames)?
+            .parse::<u64>()
+            .map_err(|_| RunnerError::Other("ERR invalid cursor".to_string()))?;
+
+        let mut scan = ScanArguments {
+            cursor,
+            pattern: None,
+            count: 10,
+            novalues: false,
+        };
+        while !frames.is_empty() {
+            match next_string(frames)?.to_uppercase().as_str() {
+                "MATCH" => scan.pattern = Some(next_string(frames)?),
+                "COUNT" => {
+                    let count = next_integer(frames)?;
+                    if count < 1 {
+                        return Err(RunnerError::Other("ERR syntax error".to_string()));
+                    }
+                    scan.count = count as usize;
+                }
+                "NOVALUES" => scan.novalues = true,
+                _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            }
+        }
+        Ok(scan)
+    }
+}
+
+// Values are replied as bulk strings so bytes that aren't utf-8 (e.g the registers of a HyperLogLog)
+// read back the same
+fn bulk(value: &[u8]) -> Frame {
+    Frame::BulkString(value.to_vec())
+}
+
+pub(crate) fn nil() -> Frame {
+    Frame::SimpleError("Nill".to_string())
+}
@@ -0,0 +1,234 @@
+use std::collections::VecDeque;
+
+use crate::{
+    db::{BitOperation, BitfieldOp, BitfieldType, Overflow},
+    frame::Frame,
+};
+
+use super::{next_integer, next_string, nil, rest_strings, Runner, RunnerError};
+
+impl<'a> Runner<'a> {
+    /**
+     * SETBIT key offset 0|1
+     */
+    pub(super) fn run_setbit(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let offset = bit_offset(frames)?;
+        let bit = match next_integer(frames) {
+            Ok(bit @ (0 | 1)) => bit == 1,
+            _ => {
+                return Err(RunnerError::Other(
+                    "ERR bit is not an integer or out of range".to_string(),
+                ))
+            }
+        };
+
+        Ok(Frame::Integer(self.db.setbit(&key, offset, bit)? as i64))
+    }
+
+    pub(super) fn run_getbit(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let offset = bit_offset(frames)?;
+
+        Ok(Frame::Integer(self.db.getbit(&key, offset)? as i64))
+    }
+
+    /**
+     * BITCOUNT key [start end [BYTE|BIT]]
+     */
+    pub(super) fn run_bitcount(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let range = match frames.is_empty() {
+            true => None,
+            false => Some((next_integer(frames)?, next_integer(frames)?)),
+        };
+        let bit_unit = bit_unit(frames)?;
+
+        Ok(Frame::Integer(
+            self.db.bitcount(&key, range, bit_unit)? as i64
+        ))
+    }
+
+    /**
+     * BITPOS key 0|1 [start [end [BYTE|BIT]]]
+     */
+    pub(super) fn run_bitpos(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let bit = match next_integer(frames) {
+            Ok(bit @ (0 | 1)) => bit == 1,
+            _ => {
+                return Err(RunnerError::Other(
+                    "ERR The bit argument must be 1 or 0.".to_string(),
+                ))
+            }
+        };
+        let start = match frames.is_empty() {
+            true => 0,
+            false => next_integer(frames)?,
+        };
+        let end = match frames.is_empty() {
+            true => None,
+            false => Some(next_integer(frames)?),
+        };
+        let bit_unit = bit_unit(frames)?;
+
+        Ok(Frame::Integer(
+            self.db.bitpos(&key, bit, start, end, bit_unit)?,
+        ))
+    }
+
+    /**
+     * BITOP AND|OR|XOR|NOT destkey key [key ...]
+     */
+    pub(super) fn run_bitop(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let operation = match next_string(frames)?.to_uppercase().as_str() {
+            "AND" => BitOperation::And,
+            "OR" => BitOperation::Or,
+            "XOR" => BitOperation::Xor,
+            "NOT" => BitOperation::Not,
+            _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+        };
+        let destination = next_string(frames)?;
+        let keys = rest_strings(frames)?;
+        if keys.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+        if matches!(operation, BitOperation::Not) && keys.len() > 1 {
+            return Err(RunnerError::Other(
+                "ERR BITOP NOT must be called with a single source key.".to_string(),
+            ));
+        }
+
+        let len = self.db.bitop(operation, &destination, &keys)?;
+        Ok(Frame::Integer(len as i64))
+    }
+
+    /**
+     * BITFIELD key [GET type offset] [SET type offset value] [INCRBY type offset increment] [OVERFLOW WRAP|SAT|FAIL] ...
+     * BITFIELD_RO only accepts GET.
+     */
+    pub(super) fn run_bitfield(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        read_only: bool,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        let mut ops = vec![];
+        while !frames.is_empty() {
+            let subcommand = next_string(frames)?.to_uppercase();
+            if read_only && subcommand != "GET" {
+                return Err(RunnerError::Other(
+                    "ERR BITFIELD_RO only supports the GET subcommand".to_string(),
+                ));
+            }
+            let op = match subcommand.as_str() {
+                "GET" => {
+                    let ty = bitfield_type(frames)?;
+                    BitfieldOp::Get(ty, bitfield_offset(frames, ty)?)
+                }
+                "SET" => {
+                    let ty = bitfield_type(frames)?;
+                    let offset = bitfield_offset(frames, ty)?;
+                    BitfieldOp::Set(ty, offset, next_integer(frames)?)
+                }
+                "INCRBY" => {
+                    let ty = bitfield_type(frames)?;
+                    let offset = bitfield_offset(frames, ty)?;
+                    BitfieldOp::IncrBy(ty, offset, next_integer(frames)?)
+                }
+                "OVERFLOW" => match next_string(frames)?.to_uppercase().as_str() {
+                    "WRAP" => BitfieldOp::Overflow(Overflow::Wrap),
+                    "SAT" => BitfieldOp::Overflow(Overflow::Sat),
+                    "FAIL" => BitfieldOp::Overflow(Overflow::Fail),
+                    _ => {
+                        return Err(RunnerError::Other(
+                            "ERR Invalid OVERFLOW type specified".to_string(),
+                        ))
+                    }
+                },
+                _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            };
+            ops.push(op);
+        }
+
+        let replies = self.db.bitfield(&key, &ops)?;
+        Ok(Frame::Array(
+            replies
+                .into_iter()
+                .map(|reply| reply.map(Frame::Integer).unwrap_or_else(nil))
+                .collect(),
+        ))
+    }
+}
+
+fn bit_offset(frames: &mut VecDeque<Frame>) -> Result<u64, RunnerError> {
+    match next_integer(frames) {
+        Ok(offset) if offset >= 0 => Ok(offset as u64),
+        _ => Err(RunnerError::Other(
+            "ERR bit offset is not an integer or out of range".to_string(),
+        )),
+    }
+}
+
+// Parses the optional BYTE|BIT unit of BITCOUNT and BITPOS
+fn bit_unit(frames: &mut VecDeque<Frame>) -> Result<bool, RunnerError> {
+    if frames.is_empty() {
+        return Ok(false);
+    }
+    let unit = match next_string(frames)?.to_uppercase().as_str() {
+        "BYTE" => false,
+        "BIT" => true,
+        _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+    };
+    if !frames.is_empty() {
+        return Err(RunnerError::Other("ERR syntax error".to_string()));
+    }
+    Ok(unit)
+}
+
+// Parses a type such as i8 or u16
+fn bitfield_type(frames: &mut VecDeque<Frame>) -> Result<BitfieldType, RunnerError> {
+    let input = next_string(frames)?;
+    let (signed, bits) = match input.split_at_checked(1) {
+        Some(("i" | "I", bits)) => (true, bits),
+        Some(("u" | "U", bits)) => (false, bits),
+        _ => (false, ""),
+    };
+    match bits.parse::<u32>() {
+        Ok(bits) if bits >= 1 && (bits <= 63 || (signed && bits == 64)) => {
+            Ok(BitfieldType { signed, bits })
+        }
+        _ => Err(RunnerError::Other(
+            "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+                .to_string(),
+        )),
+    }
+}
+
+// Parses an offset in bits, or in multiples of the type width when prefixed with #
+fn bitfield_offset(frames: &mut VecDeque<Frame>, ty: BitfieldType) -> Result<u64, RunnerError> {
+    let input = next_string(frames)?;
+    let offset = match input.strip_prefix('#') {
+        Some(index) => index
+            .parse::<u64>()
+            .ok()
+            .and_then(|index| index.checked_mul(ty.bits as u64)),
+        None => input.parse::<u64>().ok(),
+    };
+    offset.ok_or_else(|| {
+        RunnerError::Other("ERR bit offset is not an integer or out of range".to_string())
+    })
+}
@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+
+use crate::frame::Frame;
+
+use super::{next_string, rest_strings, Runner, RunnerError};
+
+impl<'a> Runner<'a> {
+    /**
+     * CONFIG GET pattern [pattern ...] | SET parameter value [parameter value ...] | REWRITE | RESETSTAT
+     */
+    pub(super) fn run_config(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let subcommand = next_string(frames)?;
+        let config = &self.db.db.config;
+        match subcommand.to_uppercase().as_str() {
+            "GET" => {
+                let patterns = rest_strings(frames)?;
+                if patterns.is_empty() {
+                    return Err(RunnerError::Incomplete);
+                }
+                Ok(Frame::Array(
+                    config
+                        .get(&patterns)
+                        .into_iter()
+                        .flat_map(|(name, value)| {
+                            [
+                                Frame::SimpleString(name.to_string()),
+                                Frame::SimpleString(value),
+                            ]
+                        })
+                        .collect(),
+                ))
+            }
+            "SET" => {
+                let args = rest_strings(frames)?;
+                if args.is_empty() || args.len() % 2 != 0 {
+                    return Err(RunnerError::Other(
+                        "ERR wrong number of arguments for 'config|set' command".to_string(),
+                    ));
+                }
+                let pairs: Vec<(String, String)> = args
+                    .chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect();
+                let changed = config.set(&pairs)?;
+                self.db.apply_config(&changed);
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "REWRITE" => {
+                config.rewrite()?;
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "RESETSTAT" => {
+                self.db.db.stats.reset();
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            _ => Err(RunnerError::Other(format!(
+                "ERR unknown subcommand '{}'. Try CONFIG HELP.",
+                subcommand
+            ))),
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::VecDeque;
+
+use crate::{
+    db::{GeoOrigin, GeoSearch, GeoShape, ZAddFlags},
+    frame::Frame,
+};
+
+use super::{next_float, next_integer, next_string, nil, rest_strings, Runner, RunnerError};
+
+impl<'a> Runner<'a> {
+    /**
+     * GEOADD key [NX|XX] [CH] longitude latitude member [longitude latitude member ...]
+     */
+    pub(super) fn run_geoadd(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        let mut flags = ZAddFlags::default();
+        while let Some(Frame::SimpleString(flag)) = frames.front() {
+            match flag.to_uppercase().as_str() {
+                "NX" => flags.nx = true,
+                "XX" => flags.xx = true,
+                "CH" => flags.ch = true,
+                _ => break,
+            }
+            frames.pop_front();
+        }
+        if flags.nx && flags.xx {
+            return Err(RunnerError::Other(
+                "ERR XX and NX options at the same time are not compatible".to_string(),
+            ));
+        }
+
+        if frames.is_empty() || !frames.len().is_multiple_of(3) {
+            return Err(RunnerError::Other("ERR syntax error".to_string()));
+        }
+        let mut members = Vec::with_capacity(frames.len() / 3);
+        while !frames.is_empty() {
+            let longitude = next_float(frames)?;
+            let latitude = next_float(frames)?;
+            members.push((longitude, latitude, next_string(frames)?));
+        }
+
+        let reply = self.db.geoadd(&key, flags, members)?;
+        let changed = if flags.ch {
+            reply.added + reply.updated
+        } else {
+            reply.added
+        };
+        Ok(Frame::Integer(changed as i64))
+    }
+
+    /**
+     * GEODIST key member1 member2 [M|KM|FT|MI]
+     */
+    pub(super) fn run_geodist(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let first = next_string(frames)?;
+        let second = next_string(frames)?;
+        let unit = match frames.is_empty() {
+            true => 1.0,
+            false => unit(frames)?,
+        };
+        if !frames.is_empty() {
+            return Err(RunnerError::Other("ERR syntax error".to_string()));
+        }
+
+        Ok(self
+            .db
+            .geodist(&key, &first, &second)?
+            .map(|distance| self::distance(distance / unit))
+            .unwrap_or_else(nil))
+    }
+
+    pub(super) fn run_geopos(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let members = rest_strings(frames)?;
+
+        Ok(Frame::Array(
+            self.db
+                .geopos(&key, &members)?
+                .into_iter()
+                .map(|position| {
+                    position
+                        .map(|(longitude, latitude)| coordinates(longitude, latitude))
+                        .unwrap_or_else(nil)
+                })
+                .collect(),
+        ))
+    }
+
+    pub(super) fn run_geohash(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let members = rest_strings(frames)?;
+
+        Ok(Frame::Array(
+            self.db
+                .geohash(&key, &members)?
+                .into_iter()
+                .map(|hash| hash.map(Frame::SimpleString).unwrap_or_else(nil))
+                .collect(),
+        ))
+    }
+
+    /**
+     * GEOSEARCH key FROMMEMBER member|FROMLONLAT longitude latitude
+     *   BYRADIUS radius unit|BYBOX width height unit
+     *   [ASC|DESC] [COUNT count [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]
+     */
+    pub(super) fn run_geosearch(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let arguments = GeoSearchArguments::parse(frames, false)?;
+
+        let matches = self.db.geosearch(&key, &arguments.search)?;
+        let with_anything = arguments.with_dist || arguments.with_hash || arguments.with_coord;
+        Ok(Frame::Array(
+            matches
+                .into_iter()
+                .map(|found| {
+                    if !with_anything {
+                        return Frame::SimpleString(found.member);
+                    }
+                    let mut fields = VecDeque::from([Frame::SimpleString(found.member)]);
+                    if arguments.with_dist {
+                        fields.push_back(distance(found.distance / arguments.unit));
+                    }
+                    if arguments.with_hash {
+                        fields.push_back(Frame::Integer(found.hash as i64));
+                    }
+                    if arguments.with_coord {
+                        fields.push_back(coordinates(found.longitude, found.latitude));
+                    }
+                    Frame::Array(fields)
+                })
+                .collect(),
+        ))
+    }
+
+    /**
+     * GEOSEARCHSTORE destination source <GEOSEARCH arguments> [STOREDIST]
+     */
+    pub(super) fn run_geosearchstore(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let destination = next_string(frames)?;
+        let key = next_string(frames)?;
+        let arguments = GeoSearchArguments::parse(frames, true)?;
+        let store_dist = arguments.store_dist.then_some(arguments.unit);
+
+        let len = self
+            .db
+            .geosearch_store(&destination, &key, &arguments.search, store_dist)?;
+        Ok(Frame::Integer(len as i64))
+    }
+}
+
+struct GeoSearchArguments {
+    search: GeoSearch,
+    // Meters per unit of the shape, distances are replied in the same unit
+    unit: f64,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+    store_dist: bool,
+}
+
+impl GeoSearchArguments {
+    fn parse(frames: &mut VecDeque<Frame>, store: bool) -> Result<GeoSearchArguments, RunnerError> {
+        let mut origin = None;
+        let mut shape = None;
+        let mut unit = 1.0;
+        let mut count = None;
+        let mut any = false;
+        let mut sort = None;
+        let mut with_coord = false;
+        let mut with_dist = false;
+        let mut with_hash = false;
+        let mut store_dist = false;
+
+        while !frames.is_empty() {
+            let option = next_string(frames)?.to_uppercase();
+            match option.as_str() {
+                "FROMMEMBER" if origin.is_none() => {
+                    origin = Some(GeoOrigin::Member(next_string(frames)?))
+                }
+                "FROMLONLAT" if origin.is_none() => {
+                    let longitude = next_float(frames)?;
+                    let latitude = next_float(frames)?;
+                    origin = Some(GeoOrigin::Coordinates(longitude, latitude));
+                }
+                "FROMMEMBER" | "FROMLONLAT" => return Err(RunnerError::Other(
+                    "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"
+                        .to_string(),
+                )),
+                "BYRADIUS" if shape.is_none() => {
+                    let radius = non_negative(next_float(frames)?, "radius")?;
+                    unit = self::unit(frames)?;
+                    shape = Some(GeoShape::Radius(radius * unit));
+                }
+                "BYBOX" if shape.is_none() => {
+                    let width = non_negative(next_float(frames)?, "height or width")?;
+                    let height = non_negative(next_float(frames)?, "height or width")?;
+                    unit = self::unit(frames)?;
+                    shape = Some(GeoShape::Box {
+                        width: width * unit,
+                        height: height * unit,
+                    });
+                }
+                "BYRADIUS" | "BYBOX" => {
+                    return Err(RunnerError::Other(
+                        "ERR exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH"
+                            .to_string(),
+                    ))
+                }
+                "ASC" => sort = Some(false),
+                "DESC" => sort = Some(true),
+                "COUNT" => {
+                    count = match next_integer(frames)? {
+                        count if count > 0 => Some(count as usize),
+                        _ => return Err(RunnerError::Other("ERR COUNT must be > 0".to_string())),
+                    };
+                    if let Some(Frame::SimpleString(next)) = frames.front() {
+                        if next.eq_ignore_ascii_case("ANY") {
+                            frames.pop_front();
+                            any = true;
+                        }
+                    }
+                }
+                "WITHCOORD" if !store => with_coord = true,
+                "WITHDIST" if !store => with_dist = true,
+                "WITHHASH" if !store => with_hash = true,
+                "STOREDIST" if store => store_dist = true,
+                _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            }
+        }
+
+        let origin = origin.ok_or_else(|| {
+            RunnerError::Other(
+                "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"
+                    .to_string(),
+            )
+        })?;
+        let shape = shape.ok_or_else(|| {
+            RunnerError::Other(
+                "ERR exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH".to_string(),
+            )
+        })?;
+
+        Ok(GeoSearchArguments {
+            search: GeoSearch {
+                origin,
+                shape,
+                count,
+                any,
+                sort,
+            },
+            unit,
+            with_coord,
+            with_dist,
+            with_hash,
+            store_dist,
+        })
+    }
+}
+
+// Parses a distance unit into meters per unit
+fn unit(frames: &mut VecDeque<Frame>) -> Result<f64, RunnerError> {
+    match next_string(frames)?.to_lowercase().as_str() {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err(RunnerError::Other(
+            "ERR unsupported unit provided. please use M, KM, FT, MI".to_string(),
+        )),
+    }
+}
+
+fn non_negative(value: f64, name: &str) -> Result<f64, RunnerError> {
+    if value < 0.0 {
+        return Err(RunnerError::Other(format!(
+            "ERR {} cannot be negative",
+            name
+        )));
+    }
+    Ok(value)
+}
+
+fn distance(distance: f64) -> Frame {
+    Frame::SimpleString(format!("{:.4}", distance))
+}
+
+// A longitude latitude pair, printed like redis with up to 17 decimals
+fn coordinates(longitude: f64, latitude: f64) -> Frame {
+    let coordinate = |value: f64| {
+        let value = format!("{:.17}", value);
+        Frame::SimpleString(
+            value
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_string(),
+        )
+    };
+    Frame::Array(VecDeque::from([
+        coordinate(longitude),
+        coordinate(latitude),
+    ]))
+}
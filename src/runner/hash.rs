@@ -0,0 +1,361 @@
+use std::collections::VecDeque;
+
+use crate::{
+    db::{now_ms, ExpireCondition},
+    frame::Frame,
+};
+
+use super::{
+    bulk, next_float, next_integer, next_string, nil, rest_strings, Runner, RunnerError,
+    ScanArguments,
+};
+
+// Like redis, field expiry times are kept within 48 bits of milliseconds
+const MAX_FIELD_EXPIRE_TIME: u64 = (1 << 48) - 1;
+
+impl<'a> Runner<'a> {
+    pub(super) fn run_hset(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        if frames.is_empty() || !frames.len().is_multiple_of(2) {
+            return Err(RunnerError::Incomplete);
+        }
+
+        let mut fields = Vec::with_capacity(frames.len() / 2);
+        while !frames.is_empty() {
+            let field = next_string(frames)?;
+            let value = next_string(frames)?;
+            fields.push((field, value.into_bytes()));
+        }
+
+        let added = self.db.hset(&key, fields)?;
+        Ok(Frame::Integer(added as i64))
+    }
+
+    pub(super) fn run_hsetnx(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let field = next_string(frames)?;
+        let value = next_string(frames)?;
+
+        let added = self.db.hsetnx(&key, field, value.into_bytes())?;
+        Ok(Frame::Integer(added as i64))
+    }
+
+    pub(super) fn run_hget(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let field = next_string(frames)?;
+
+        match self.db.hget(&key, &field)? {
+            Some(value) => Ok(bulk(&value)),
+            None => Ok(nil()),
+        }
+    }
+
+    pub(super) fn run_hmget(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let fields = rest_strings(frames)?;
+        if fields.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+
+        let values = self.db.hmget(&key, &fields)?;
+        Ok(Frame::Array(
+            values
+                .iter()
+                .map(|value| match value {
+                    Some(value) => bulk(value),
+                    None => nil(),
+                })
+                .collect(),
+        ))
+    }
+
+    pub(super) fn run_hdel(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let fields = rest_strings(frames)?;
+        if fields.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+
+        let removed = self.db.hdel(&key, &fields)?;
+        Ok(Frame::Integer(removed as i64))
+    }
+
+    pub(super) fn run_hgetall(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        let fields = self.db.hgetall(&key)?;
+        Ok(Frame::Array(
+            fields
+                .iter()
+                .flat_map(|(field, value)| [bulk(field.as_bytes()), bulk(value)])
+                .collect(),
+        ))
+    }
+
+    pub(super) fn run_hkeys(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        let fields = self.db.hgetall(&key)?;
+        Ok(Frame::Array(
+            fields
+                .into_iter()
+                .map(|(field, _)| Frame::SimpleString(field))
+                .collect(),
+        ))
+    }
+
+    pub(super) fn run_hvals(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        let fields = self.db.hgetall(&key)?;
+        Ok(Frame::Array(
+            fields.iter().map(|(_, value)| bulk(value)).collect(),
+        ))
+    }
+
+    pub(super) fn run_hlen(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        Ok(Frame::Integer(self.db.hlen(&key)? as i64))
+    }
+
+    pub(super) fn run_hexists(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let field = next_string(frames)?;
+
+        Ok(Frame::Integer(self.db.hexists(&key, &field)? as i64))
+    }
+
+    pub(super) fn run_hstrlen(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let field = next_string(frames)?;
+
+        Ok(Frame::Integer(self.db.hstrlen(&key, &field)? as i64))
+    }
+
+    pub(super) fn run_hincrby(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let field = next_string(frames)?;
+        let increment = next_integer(frames)?;
+
+        Ok(Frame::Integer(self.db.hincrby(&key, &field, increment)?))
+    }
+
+    pub(super) fn run_hincrbyfloat(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let field = next_string(frames)?;
+        let increment = next_float(frames)?;
+
+        let value = self.db.hincrbyfloat(&key, &field, increment)?;
+        Ok(Frame::SimpleString(value.to_string()))
+    }
+
+    /**
+     * HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]
+     * Replies with the next cursor and a flat array of fields (and values).
+     */
+    pub(super) fn run_hscan(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let scan = ScanArguments::parse(frames)?;
+
+        let (cursor, fields) =
+            self.db
+                .hscan(&key, scan.cursor, scan.pattern.as_deref(), scan.count)?;
+
+        let mut items = VecDeque::new();
+        for (field, value) in fields {
+            items.push_back(Frame::SimpleString(field));
+            if !scan.novalues {
+                items.push_back(bulk(&value));
+            }
+        }
+        Ok(Frame::Array(VecDeque::from([
+            Frame::SimpleString(cursor.to_string()),
+            Frame::Array(items),
+        ])))
+    }
+
+    /**
+     * HRANDFIELD key [count [WITHVALUES]]
+     */
+    pub(super) fn run_hrandfield(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        if frames.is_empty() {
+            let fields = self.db.hrandfield(&key, 1)?;
+            return match fields.into_iter().next() {
+                Some((field, _)) => Ok(Frame::SimpleString(field)),
+                None => Ok(nil()),
+            };
+        }
+
+        let count = next_integer(frames)?;
+        let withvalues = match frames.pop_front() {
+            Some(Frame::SimpleString(option)) if option.to_uppercase() == "WITHVALUES" => true,
+            Some(_) => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            None => false,
+        };
+
+        let fields = self.db.hrandfield(&key, count)?;
+        let mut items = VecDeque::new();
+        for (field, value) in fields {
+            items.push_back(Frame::SimpleString(field));
+            if withvalues {
+                items.push_back(bulk(&value));
+            }
+        }
+        Ok(Frame::Array(items))
+    }
+
+    /**
+     * HEXPIRE key seconds [NX | XX | GT | LT] FIELDS numfields field [field ...]
+     * Also handles HPEXPIRE (millis) and HEXPIREAT/HPEXPIREAT (absolute unix times).
+     */
+    pub(super) fn run_hexpire(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        millis: bool,
+        absolute: bool,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let time = next_integer(frames)?;
+        // Times past the millisecond clock would otherwise wrap around to an expiry in the past
+        let time = u64::try_from(time).ok().and_then(|time| match millis {
+            true => Some(time),
+            false => time.checked_mul(1000),
+        });
+        let expires_at = match (time, absolute) {
+            (Some(time), true) => Some(time),
+            (Some(time), false) => now_ms().checked_add(time),
+            (None, _) => None,
+        };
+        let expires_at = match expires_at {
+            Some(expires_at) if expires_at <= MAX_FIELD_EXPIRE_TIME => expires_at,
+            _ => {
+                let command = match (millis, absolute) {
+                    (false, false) => "hexpire",
+                    (true, false) => "hpexpire",
+                    (false, true) => "hexpireat",
+                    (true, true) => "hpexpireat",
+                };
+                return Err(RunnerError::Other(format!(
+                    "ERR invalid expire time in '{}' command",
+                    command
+                )));
+            }
+        };
+
+        let condition = match frames.front() {
+            Some(Frame::SimpleString(option)) => match option.to_uppercase().as_str() {
+                "NX" => Some(ExpireCondition::Nx),
+                "XX" => Some(ExpireCondition::Xx),
+                "GT" => Some(ExpireCondition::Gt),
+                "LT" => Some(ExpireCondition::Lt),
+                _ => None,
+            },
+            _ => None,
+        };
+        if condition.is_some() {
+            frames.pop_front();
+        }
+        let fields = fields_argument(frames)?;
+
+        let replies = self.db.hexpire(&key, expires_at, condition, &fields)?;
+        Ok(Frame::Array(
+            replies.into_iter().map(Frame::Integer).collect(),
+        ))
+    }
+
+    /**
+     * HTTL key FIELDS numfields field [field ...]
+     * Also handles HPTTL (millis) and HEXPIRETIME/HPEXPIRETIME (absolute unix times).
+     */
+    pub(super) fn run_httl(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        millis: bool,
+        absolute: bool,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let fields = fields_argument(frames)?;
+
+        let now = now_ms() as i64;
+        let replies = self
+            .db
+            .hexpiretime(&key, &fields)?
+            .into_iter()
+            .map(|expires_at| {
+                if expires_at < 0 {
+                    return Frame::Integer(expires_at);
+                }
+                let time = if absolute {
+                    expires_at
+                } else {
+                    expires_at - now
+                };
+                if millis {
+                    Frame::Integer(time)
+                } else if absolute {
+                    Frame::Integer(time / 1000)
+                } else {
+                    Frame::Integer((time + 500) / 1000)
+                }
+            })
+            .collect();
+        Ok(Frame::Array(replies))
+    }
+
+    /**
+     * HPERSIST key FIELDS numfields field [field ...]
+     */
+    pub(super) fn run_hpersist(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let fields = fields_argument(frames)?;
+
+        let replies = self.db.hpersist(&key, &fields)?;
+        Ok(Frame::Array(
+            replies.into_iter().map(Frame::Integer).collect(),
+        ))
+    }
+}
+
+// Parses the FIELDS numfields field [field ...] part of the field expiry commands
+fn fields_argument(frames: &mut VecDeque<Frame>) -> Result<Vec<String>, RunnerError> {
+    if next_string(frames)?.to_uppercase() != "FIELDS" {
+        return Err(RunnerError::Other(
+            "ERR Mandatory argument FIELDS is missing or not at the right position".to_string(),
+        ));
+    }
+    let numfields = next_integer(frames)?;
+    let fields = rest_strings(frames)?;
+    if numfields < 1 || numfields as usize != fields.len() {
+        return Err(RunnerError::Other(
+            "ERR The `numfields` parameter must match the number of arguments".to_string(),
+        ));
+    }
+    Ok(fields)
+}
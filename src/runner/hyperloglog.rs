@@ -0,0 +1,43 @@
+use std::collections::VecDeque;
+
+use crate::frame::Frame;
+
+use super::{next_string, rest_strings, Runner, RunnerError};
+
+impl<'a> Runner<'a> {
+    /**
+     * PFADD key [element ...]
+     */
+    pub(super) fn run_pfadd(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let elements = rest_strings(frames)?;
+
+        Ok(Frame::Integer(self.db.pfadd(&key, &elements)? as i64))
+    }
+
+    pub(super) fn run_pfcount(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let keys = rest_strings(frames)?;
+        if keys.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+
+        Ok(Frame::Integer(self.db.pfcount(&keys)? as i64))
+    }
+
+    /**
+     * PFMERGE destkey [sourcekey ...]
+     */
+    pub(super) fn run_pfmerge(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let destination = next_string(frames)?;
+        let sources = rest_strings(frames)?;
+        self.db.pfmerge(&destination, &sources)?;
+
+        Ok(Frame::SimpleString("OK".to_string()))
+    }
+}
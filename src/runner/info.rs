@@ -0,0 +1,306 @@
+use std::collections::VecDeque;
+
+use crate::{
+    db::{now_ms, DB},
+    frame::Frame,
+    stats::Stats,
+};
+
+use super::{rest_strings, Runner, RunnerError};
+
+// The sections of INFO, and whether INFO without arguments includes them
+const SECTIONS: [(&str, bool); 7] = [
+    ("server", true),
+    ("clients", true),
+    ("memory", true),
+    ("persistence", true),
+    ("stats", true),
+    ("commandstats", false),
+    ("keyspace", true),
+];
+
+impl<'a> Runner<'a> {
+    /**
+     * INFO [section [section ...]]
+     * Sections are server, clients, memory, persistence, stats, commandstats and keyspace,
+     * default, all and everything select several of them.
+     * The reply is a bulk string whose lines end with \r\n.
+     */
+    pub(super) fn run_info(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let requested: Vec<String> = rest_strings(frames)?
+            .iter()
+            .map(|section| section.to_lowercase())
+            .collect();
+        let selected = |name: &str, default: bool| {
+            if requested.is_empty() {
+                return default;
+            }
+            requested.iter().any(|section| match section.as_str() {
+                "default" => default,
+                "all" | "everything" => true,
+                section => section == name,
+            })
+        };
+
+        let sections: Vec<String> = SECTIONS
+            .iter()
+            .filter(|(name, default)| selected(name, *default))
+            .map(|(name, _)| {
+                let title = format!("# {}{}", name[..1].to_uppercase(), &name[1..]);
+                let fields = section(self.db, name);
+                format!("{}\r\n{}\r\n", title, fields.join("\r\n"))
+            })
+            .collect();
+        Ok(Frame::BulkString(sections.join("\r\n").into_bytes()))
+    }
+}
+
+// The field:value lines of a section
+fn section(db: &DB, name: &str) -> Vec<String> {
+    let stats = &db.db.stats;
+    match name {
+        "server" => {
+            let settings = db.db.config.settings();
+            let uptime = stats.started_at.elapsed().as_secs();
+            vec![
+                format!("redis_version:{}", env!("CARGO_PKG_VERSION")),
+                "redis_mode:standalone".to_string(),
+                format!("os:{} {}", std::env::consts::OS, std::env::consts::ARCH),
+                format!("arch_bits:{}", usize::BITS),
+                format!("process_id:{}", std::process::id()),
+                format!(
+                    "tcp_port:{}",
+                    settings.addr.rsplit(':').next().unwrap_or_default()
+                ),
+                format!("uptime_in_seconds:{}", uptime),
+                format!("uptime_in_days:{}", uptime / 86400),
+                format!(
+                    "executable:{}",
+                    std::env::current_exe()
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_default()
+                ),
+                format!("config_file:{}", db.db.config.file().unwrap_or_default()),
+            ]
+        }
+        "clients" => vec![
+            format!(
+                "connected_clients:{}",
+                Stats::get(&stats.connected_clients)
+            ),
+            format!("blocked_clients:{}", Stats::get(&stats.blocked_clients)),
+        ],
+        "memory" => {
+            // The allocator isn't instrumented, the memory used is the resident set size of the process
+            let rss = resident_memory().unwrap_or(0);
+            vec![
+                format!("used_memory:{}", rss),
+                format!("used_memory_human:{}", human_bytes(rss)),
+                format!("used_memory_rss:{}", rss),
+                format!("used_memory_rss_human:{}", human_bytes(rss)),
+                "maxmemory:0".to_string(),
+                "maxmemory_policy:noeviction".to_string(),
+                "mem_allocator:libc".to_string(),
+            ]
+        }
+        "persistence" => {
+            let persistence = stats.persistence();
+            let dirty = db.db.lock().dirty;
+            vec![
+                "loading:0".to_string(),
+                format!("rdb_changes_since_last_save:{}", dirty),
+                "rdb_bgsave_in_progress:0".to_string(),
+                format!("rdb_saves:{}", persistence.saves),
+                format!("rdb_last_save_time:{}", persistence.last_save_time),
+                format!(
+                    "rdb_last_bgsave_status:{}",
+                    if persistence.last_save_ok { "ok" } else { "err" }
+                ),
+                format!(
+                    "rdb_last_bgsave_time_sec:{}",
+                    persistence
+                        .last_save_duration
+                        .map_or(-1, |duration| duration.as_secs() as i64)
+                ),
+            ]
+        }
+        "stats" => vec![
+            format!(
+                "total_connections_received:{}",
+                Stats::get(&stats.connections_received)
+            ),
+            format!(
+                "total_commands_processed:{}",
+                Stats::get(&stats.commands_processed)
+            ),
+            format!(
+                "rejected_connections:{}",
+                Stats::get(&stats.rejected_connections)
+            ),
+            format!("expired_keys:{}", Stats::get(&stats.expired_keys)),
+            format!("evicted_keys:{}", Stats::get(&stats.evicted_keys)),
+            format!("keyspace_hits:{}", Stats::get(&stats.keyspace_hits)),
+            format!("keyspace_misses:{}", Stats::get(&stats.keyspace_misses)),
+            format!("pubsub_channels:{}", db.db.pubsub.channels(None).len()),
+            format!("pubsub_patterns:{}", db.db.pubsub.numpat()),
+        ],
+        "commandstats" => stats
+            .commands()
+            .into_iter()
+            .map(|(name, command)| {
+                format!(
+                    "cmdstat_{}:calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}",
+                    name.to_lowercase(),
+                    command.calls,
+                    command.usec,
+                    match command.calls {
+                        0 => 0.0,
+                        calls => command.usec as f64 / calls as f64,
+                    },
+                    command.rejected_calls,
+                    command.failed_calls
+                )
+            })
+            .collect(),
+        "keyspace" => {
+            let store = db.db.lock();
+            if store.entries.is_empty() {
+                return vec![];
+            }
+            let now = now_ms();
+            let ttls: Vec<u64> = store
+                .ttls
+                .iter()
+                .map(|(expires_at, _)| (*expires_at as u64 * 1000).saturating_sub(now))
+                .collect();
+            let avg_ttl = match ttls.len() {
+                0 => 0,
+                len => ttls.iter().sum::<u64>() / len as u64,
+            };
+            vec![format!(
+                "db0:keys={},expires={},avg_ttl={}",
+                store.entries.len(),
+                ttls.len(),
+                avg_ttl
+            )]
+        }
+        _ => vec![],
+    }
+}
+
+// The resident set size of the process in bytes, from /proc on linux
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+// Bytes as INFO writes them for people, e.g 1.50M
+fn human_bytes(bytes: u64) -> String {
+    let units = ["B", "K", "M", "G", "T"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{}B", bytes),
+        unit => format!("{:.2}{}", value, units[unit]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        frame::{command, text},
+        stats::Outcome,
+    };
+    use std::time::Duration;
+
+    fn info(db: &mut DB, sections: &[&str]) -> String {
+        let frame = command(&[&["INFO"], sections].concat());
+        text(Runner::new(db).run(frame).unwrap())
+    }
+
+    #[tokio::test]
+    async fn info_default_sections() {
+        let mut db = DB::new();
+        let frame = Runner::new(&mut db).run(command(&["INFO"])).unwrap();
+        // The sections span lines, which only a bulk string can hold
+        assert!(matches!(frame, Frame::BulkString(_)));
+
+        let default = text(frame);
+        for section in [
+            "# Server\r\n",
+            "# Clients\r\n",
+            "# Memory\r\n",
+            "# Persistence\r\n",
+            "# Stats\r\n",
+        ] {
+            assert!(default.contains(section));
+        }
+        assert!(default.contains("\r\n\r\n# Clients\r\n"));
+        assert!(!default.contains("# Commandstats"));
+        assert!(!default.replace("\r\n", "").contains('\n'));
+    }
+
+    #[tokio::test]
+    async fn info_empty_keyspace() {
+        let mut db = DB::new();
+        let everything = info(&mut db, &["everything"]);
+        assert!(everything.contains("# Keyspace\r\n"));
+        assert!(!everything.contains("db0:"));
+    }
+
+    #[tokio::test]
+    async fn info_selected_sections() {
+        let mut db = DB::new();
+        db.set("b".to_string(), b"2".to_vec(), Some(100));
+        Runner::new(&mut db)
+            .run(command(&["SET", "a", "1"]))
+            .unwrap();
+        let stats = &db.db.stats;
+        stats.record_command("GET", Duration::from_micros(10), Outcome::Ok);
+        stats.record_command("GET", Duration::from_micros(20), Outcome::Failed);
+        stats.record_command("GET", Duration::ZERO, Outcome::Rejected);
+
+        let text = info(&mut db, &["keyspace", "COMMANDSTATS", "persistence"]);
+        assert!(text.contains("# Keyspace\r\ndb0:keys=2,expires=1,avg_ttl="));
+        assert!(text.contains(
+            "cmdstat_get:calls=2,usec=30,usec_per_call=15.00,rejected_calls=1,failed_calls=1"
+        ));
+        assert!(text.contains("rdb_changes_since_last_save:2"));
+        assert!(!text.contains("# Server"));
+    }
+
+    #[tokio::test]
+    async fn keyspace_hits_and_misses() {
+        let mut db = DB::new();
+        let mut runner = Runner::new(&mut db);
+        runner.run(command(&["SET", "a", "1"])).unwrap();
+        runner.run(command(&["GET", "a"])).unwrap();
+        runner.run(command(&["GET", "missing"])).unwrap();
+        let stats = &db.db.stats;
+        assert_eq!(Stats::get(&stats.keyspace_hits), 1);
+        assert_eq!(Stats::get(&stats.keyspace_misses), 1);
+        assert!(info(&mut db, &["stats"]).contains("keyspace_hits:1\r\n"));
+    }
+
+    #[tokio::test]
+    async fn reset_stats() {
+        let mut db = DB::new();
+        Runner::new(&mut db)
+            .run(command(&["GET", "missing"]))
+            .unwrap();
+        db.db
+            .stats
+            .record_command("GET", Duration::from_micros(10), Outcome::Ok);
+
+        db.db.stats.reset();
+        assert_eq!(Stats::get(&db.db.stats.keyspace_misses), 0);
+        assert!(db.db.stats.commands().is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+
+use crate::{
+    frame::Frame,
+    latency::LatencyEvent,
+    stats::{CommandStats, LATENCY_BUCKETS},
+};
+
+use super::{next_string, rest_strings, Runner, RunnerError};
+
+impl<'a> Runner<'a> {
+    /**
+     * LATENCY LATEST | HISTORY event | RESET [event ...] | HISTOGRAM [command ...] | DOCTOR
+     * LATEST replies the name, last spike time, last and highest latency of each event.
+     * HISTOGRAM replies the calls of each command by the microseconds they ran for, cumulatively.
+     */
+    pub(super) fn run_latency(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let subcommand = next_string(frames)?;
+        let monitor = &self.db.db.latency;
+        match subcommand.to_uppercase().as_str() {
+            "LATEST" if frames.is_empty() => Ok(Frame::Array(
+                monitor
+                    .events()
+                    .into_iter()
+                    .filter_map(|(name, event)| {
+                        let last = event.samples.back()?;
+                        Some(Frame::Array(VecDeque::from([
+                            Frame::SimpleString(name.to_string()),
+                            Frame::Integer(last.time as i64),
+                            Frame::Integer(last.latency as i64),
+                            Frame::Integer(event.max as i64),
+                        ])))
+                    })
+                    .collect(),
+            )),
+            "HISTORY" if frames.len() == 1 => {
+                let event = next_string(frames)?.to_lowercase();
+                Ok(Frame::Array(
+                    monitor
+                        .history(&event)
+                        .into_iter()
+                        .map(|sample| {
+                            Frame::Array(VecDeque::from([
+                                Frame::Integer(sample.time as i64),
+                                Frame::Integer(sample.latency as i64),
+                            ]))
+                        })
+                        .collect(),
+                ))
+            }
+            "RESET" => {
+                let events = rest_strings(frames)?;
+                Ok(Frame::Integer(monitor.reset(&events) as i64))
+            }
+            "HISTOGRAM" => {
+                let names = rest_strings(frames)?;
+                Ok(Frame::Array(
+                    self.db
+                        .db
+                        .stats
+                        .commands()
+                        .into_iter()
+                        .filter(|(name, stats)| {
+                            stats.calls > 0
+                                && (names.is_empty()
+                                    || names.iter().any(|wanted| wanted.eq_ignore_ascii_case(name)))
+                        })
+                        .flat_map(|(name, stats)| {
+                            [Frame::SimpleString(name.to_lowercase()), histogram(&stats)]
+                        })
+                        .collect(),
+                ))
+            }
+            "DOCTOR" if frames.is_empty() => Ok(Frame::BulkString(
+                doctor(monitor.threshold(), &monitor.events()).into_bytes(),
+            )),
+            "LATEST" | "HISTORY" | "DOCTOR" => Err(RunnerError::Other(format!(
+                "ERR wrong number of arguments for 'latency|{}' command",
+                subcommand.to_lowercase()
+            ))),
+            _ => Err(RunnerError::Other(format!(
+                "ERR unknown subcommand '{}'. Try LATENCY HELP.",
+                subcommand
+            ))),
+        }
+    }
+}
+
+// calls, then the cumulative calls at each bucket in microseconds that has any, +inf being the slower calls
+fn histogram(stats: &CommandStats) -> Frame {
+    let mut buckets = VecDeque::new();
+    let mut cumulative = 0;
+    for (bucket, count) in stats.latencies.iter().enumerate() {
+        if *count == 0 {
+            continue;
+        }
+        cumulative += count;
+        let bound = match LATENCY_BUCKETS.get(bucket) {
+            Some(seconds) => Frame::Integer((seconds * 1_000_000.0) as i64),
+            None => Frame::SimpleString("+inf".to_string()),
+        };
+        buckets.push_back(bound);
+        buckets.push_back(Frame::Integer(cumulative as i64));
+    }
+    Frame::Array(VecDeque::from([
+        Frame::SimpleString("calls".to_string()),
+        Frame::Integer(stats.calls as i64),
+        Frame::SimpleString("histogram_usec".to_string()),
+        Frame::Array(buckets),
+    ]))
+}
+
+// A report of the latency spikes with advice for each event, one per line
+fn doctor(threshold: u64, events: &[(&'static str, LatencyEvent)]) -> String {
+    if threshold == 0 && events.is_empty() {
+        return "Latency monitoring is disabled. Enable it with CONFIG SET latency-monitor-threshold <milliseconds>.".to_string();
+    }
+    if events.is_empty() {
+        return format!(
+            "No latency spike of {} milliseconds or more was observed since the server started or the last LATENCY RESET.",
+            threshold
+        );
+    }
+
+    let mut report = vec![format!(
+        "Latency spikes of {} milliseconds or more were observed for these events:",
+        threshold
+    )];
+    let mut advice = vec![];
+    for (index, (name, event)) in events.iter().enumerate() {
+        let latencies: Vec<u64> = event.samples.iter().map(|sample| sample.latency).collect();
+        let count = latencies.len() as u64;
+        let average = latencies.iter().sum::<u64>() / count.max(1);
+        let deviation = latencies
+            .iter()
+            .map(|latency| latency.abs_diff(average))
+            .sum::<u64>()
+            / count.max(1);
+        let period = match (event.samples.front(), event.samples.back()) {
+            (Some(first), Some(last)) if count > 1 => (last.time - first.time) / (count - 1),
+            _ => 0,
+        };
+        report.push(format!(
+            "{}. {}: {} latency spikes (average {}ms, mean deviation {}ms, period {} sec). Worst all time event {}ms.",
+            index + 1,
+            name,
+            count,
+            average,
+            deviation,
+            period,
+            event.max
+        ));
+        let hint = match *name {
+            "command" => "Slow commands are listed by SLOWLOG GET, avoid O(N) commands on big values, e.g with HSCAN instead of HGETALL.",
+            "fast-command" => "Commands that should be fast were slow, something else probably held the server, e.g a script, a transaction or a snapshot.",
+            "snapshot" => "The store is locked while the db is serialized for the RDB file, the bigger the db the longer clients wait. Consider raising flush-every.",
+            "snapshot-fsync" => "Writing the RDB file to disk is slow. Clients aren't blocked by it, but SHUTDOWN waits for it. Check the disk of dir.",
+            "expire-cycle" => "Many keys expired at once. Consider spreading the TTLs of keys set together.",
+            _ => continue,
+        };
+        advice.push(format!("- {}: {}", name, hint));
+    }
+    report.push(String::new());
+    report.push("Advice:".to_string());
+    report.extend(advice);
+    report.join("\n")
+}
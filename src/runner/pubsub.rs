@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+
+use crate::frame::Frame;
+
+use super::{next_string, rest_strings, Runner, RunnerError};
+
+impl<'a> Runner<'a> {
+    /**
+     * PUBLISH channel message
+     */
+    pub(super) fn run_publish(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let channel = next_string(frames)?;
+        let message = next_string(frames)?;
+
+        let receivers = self.db.db.pubsub.publish(&channel, &message);
+        Ok(Frame::Integer(receivers as i64))
+    }
+
+    /**
+     * SPUBLISH shardchannel message
+     */
+    pub(super) fn run_spublish(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let channel = next_string(frames)?;
+        let message = next_string(frames)?;
+
+        let receivers = self.db.db.pubsub.spublish(&channel, &message);
+        Ok(Frame::Integer(receivers as i64))
+    }
+
+    /**
+     * PUBSUB CHANNELS [pattern] | NUMSUB [channel ...] | NUMPAT
+     *   | SHARDCHANNELS [pattern] | SHARDNUMSUB [shardchannel ...]
+     */
+    pub(super) fn run_pubsub(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let hub = &self.db.db.pubsub;
+        let subcommand = next_string(frames)?;
+        match subcommand.to_uppercase().as_str() {
+            "CHANNELS" | "SHARDCHANNELS" => {
+                let pattern = match frames.is_empty() {
+                    true => None,
+                    false => Some(next_string(frames)?),
+                };
+                if !frames.is_empty() {
+                    return Err(RunnerError::Incomplete);
+                }
+                let channels = match subcommand.eq_ignore_ascii_case("CHANNELS") {
+                    true => hub.channels(pattern.as_deref()),
+                    false => hub.shard_channels(pattern.as_deref()),
+                };
+                Ok(Frame::Array(
+                    channels.into_iter().map(Frame::SimpleString).collect(),
+                ))
+            }
+            "NUMSUB" | "SHARDNUMSUB" => {
+                let channels = rest_strings(frames)?;
+                let counts = match subcommand.eq_ignore_ascii_case("NUMSUB") {
+                    true => hub.numsub(&channels),
+                    false => hub.shard_numsub(&channels),
+                };
+                let mut replies = VecDeque::with_capacity(channels.len() * 2);
+                for (channel, count) in channels.into_iter().zip(counts) {
+                    replies.push_back(Frame::SimpleString(channel));
+                    replies.push_back(Frame::Integer(count as i64));
+                }
+                Ok(Frame::Array(replies))
+            }
+            "NUMPAT" => Ok(Frame::Integer(hub.numpat() as i64)),
+            _ => Err(RunnerError::Other(format!(
+                "ERR unknown subcommand '{}'",
+                subcommand
+            ))),
+        }
+    }
+}
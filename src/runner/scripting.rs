@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+
+use crate::{
+    frame::Frame,
+    scripting::{Caller, RestorePolicy},
+};
+
+use super::{next_integer, next_string, nil, rest_strings, Runner, RunnerError};
+
+impl<'a> Runner<'a> {
+    // Scripts run their commands as the user of the runner, tracked for the same connection
+    fn caller(&self) -> Caller {
+        Caller {
+            user: self.user.clone(),
+            tracking: self.tracking,
+        }
+    }
+
+    /**
+     * EVAL script numkeys [key [key ...]] [arg [arg ...]]
+     */
+    pub(super) fn run_eval(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let source = next_string(frames)?;
+        let (keys, args) = script_arguments(frames)?;
+
+        let db = self.db.clone();
+        db.db.scripts.eval(&db, &self.caller(), &source, keys, args)
+    }
+
+    /**
+     * EVALSHA sha1 numkeys [key [key ...]] [arg [arg ...]]
+     */
+    pub(super) fn run_evalsha(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let sha = next_string(frames)?;
+        let (keys, args) = script_arguments(frames)?;
+
+        let db = self.db.clone();
+        let source = db.db.scripts.get(&sha).ok_or_else(|| {
+            RunnerError::Other("NOSCRIPT No matching script. Please use EVAL.".to_string())
+        })?;
+        db.db.scripts.eval(&db, &self.caller(), &source, keys, args)
+    }
+
+    /**
+     * SCRIPT LOAD script | EXISTS sha1 [sha1 ...] | FLUSH [ASYNC|SYNC] | KILL
+     */
+    pub(super) fn run_script(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let scripts = &self.db.db.scripts;
+        let subcommand = next_string(frames)?;
+        match subcommand.to_uppercase().as_str() {
+            "LOAD" => {
+                let source = next_string(frames)?;
+                Ok(Frame::SimpleString(scripts.load(&source)?))
+            }
+            "EXISTS" => {
+                let shas = rest_strings(frames)?;
+                if shas.is_empty() {
+                    return Err(RunnerError::Incomplete);
+                }
+                Ok(Frame::Array(
+                    scripts
+                        .exists(&shas)
+                        .into_iter()
+                        .map(|exists| Frame::Integer(exists as i64))
+                        .collect(),
+                ))
+            }
+            "FLUSH" => {
+                flush_mode(frames)?;
+                scripts.flush();
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "KILL" => {
+                scripts.kill()?;
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            _ => Err(RunnerError::Other(format!(
+                "ERR unknown subcommand '{}'",
+                subcommand
+            ))),
+        }
+    }
+
+    /**
+     * FCALL function numkeys [key [key ...]] [arg [arg ...]]
+     * FCALL_RO runs the same way but only for functions flagged no-writes.
+     */
+    pub(super) fn run_fcall(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        read_only: bool,
+    ) -> Result<Frame, RunnerError> {
+        let name = next_string(frames)?;
+        let (keys, args) = script_arguments(frames)?;
+
+        let db = self.db.clone();
+        db.db
+            .scripts
+            .fcall(&db, &self.caller(), &name, keys, args, read_only)
+    }
+
+    /**
+     * FUNCTION LOAD [REPLACE] code | LIST [LIBRARYNAME pattern] [WITHCODE] | DELETE library
+     *   | DUMP | RESTORE payload [FLUSH|APPEND|REPLACE] | FLUSH [ASYNC|SYNC] | KILL
+     */
+    pub(super) fn run_function(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let scripts = &self.db.db.scripts;
+        let subcommand = next_string(frames)?;
+        match subcommand.to_uppercase().as_str() {
+            "LOAD" => {
+                let mut replace = false;
+                let mut code = next_string(frames)?;
+                if code.eq_ignore_ascii_case("REPLACE") {
+                    replace = true;
+                    code = next_string(frames)?;
+                }
+                if !frames.is_empty() {
+                    return Err(RunnerError::Other("ERR syntax error".to_string()));
+                }
+                Ok(Frame::SimpleString(scripts.load_library(&code, replace)?))
+            }
+            "LIST" => {
+                let mut pattern = None;
+                let mut with_code = false;
+                while !frames.is_empty() {
+                    match next_string(frames)?.to_uppercase().as_str() {
+                        "LIBRARYNAME" if pattern.is_none() => pattern = Some(next_string(frames)?),
+                        "WITHCODE" => with_code = true,
+                        _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+                    }
+                }
+                Ok(Frame::Array(
+                    scripts
+                        .libraries(pattern.as_deref())
+                        .into_iter()
+                        .map(|library| {
+                            let functions = library
+                                .functions
+                                .into_iter()
+                                .map(|function| {
+                                    Frame::Array(VecDeque::from([
+                                        Frame::SimpleString("name".to_string()),
+                                        Frame::SimpleString(function.name),
+                                        Frame::SimpleString("description".to_string()),
+                                        function
+                                            .description
+                                            .map(Frame::SimpleString)
+                                            .unwrap_or_else(nil),
+                                        Frame::SimpleString("flags".to_string()),
+                                        Frame::Array(
+                                            function
+                                                .flags
+                                                .into_iter()
+                                                .map(Frame::SimpleString)
+                                                .collect(),
+                                        ),
+                                    ]))
+                                })
+                                .collect();
+                            let mut fields = VecDeque::from([
+                                Frame::SimpleString("library_name".to_string()),
+                                Frame::SimpleString(library.name),
+                                Frame::SimpleString("engine".to_string()),
+                                Frame::SimpleString("LUA".to_string()),
+                                Frame::SimpleString("functions".to_string()),
+                                Frame::Array(functions),
+                            ]);
+                            if with_code {
+                                fields.push_back(Frame::SimpleString("library_code".to_string()));
+                                fields.push_back(Frame::SimpleString(library.code));
+                            }
+                            Frame::Array(fields)
+                        })
+                        .collect(),
+                ))
+            }
+            "DELETE" => {
+                let name = next_string(frames)?;
+                scripts.delete_library(&name)?;
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "DUMP" => Ok(Frame::SimpleString(scripts.dump_libraries())),
+            "RESTORE" => {
+                let payload = next_string(frames)?;
+                let policy = match frames.is_empty() {
+                    true => RestorePolicy::Append,
+                    false => match next_string(frames)?.to_uppercase().as_str() {
+                        "APPEND" => RestorePolicy::Append,
+                        "REPLACE" => RestorePolicy::Replace,
+                        "FLUSH" => RestorePolicy::Flush,
+                        _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+                    },
+                };
+                scripts.restore_libraries(&payload, policy)?;
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "FLUSH" => {
+                flush_mode(frames)?;
+                scripts.flush_libraries();
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "KILL" => {
+                scripts.kill()?;
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            _ => Err(RunnerError::Other(format!(
+                "ERR unknown subcommand '{}'",
+                subcommand
+            ))),
+        }
+    }
+}
+
+// Splits the arguments of EVAL and EVALSHA into KEYS and ARGV
+fn script_arguments(
+    frames: &mut VecDeque<Frame>,
+) -> Result<(Vec<String>, Vec<String>), RunnerError> {
+    let numkeys = next_integer(frames)?;
+    if numkeys < 0 {
+        return Err(RunnerError::Other(
+            "ERR Number of keys can't be negative".to_string(),
+        ));
+    }
+    if numkeys as usize > frames.len() {
+        return Err(RunnerError::Other(
+            "ERR Number of keys can't be greater than number of args".to_string(),
+        ));
+    }
+    let mut args = rest_strings(frames)?;
+    let keys = args.drain(..numkeys as usize).collect();
+    Ok((keys, args))
+}
+
+// Flushing is cheap either way, ASYNC and SYNC only exist for compatibility
+fn flush_mode(frames: &mut VecDeque<Frame>) -> Result<(), RunnerError> {
+    match frames.pop_front() {
+        None => Ok(()),
+        Some(Frame::SimpleString(mode))
+            if mode.eq_ignore_ascii_case("ASYNC") || mode.eq_ignore_ascii_case("SYNC") =>
+        {
+            Ok(())
+        }
+        _ => Err(RunnerError::Other("ERR syntax error".to_string())),
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+
+use crate::{db::SetOperation, frame::Frame};
+
+use super::{next_integer, next_string, nil, rest_strings, Runner, RunnerError, ScanArguments};
+
+impl<'a> Runner<'a> {
+    pub(super) fn run_sadd(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let members = rest_strings(frames)?;
+        if members.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+
+        Ok(Frame::Integer(self.db.sadd(&key, members)? as i64))
+    }
+
+    pub(super) fn run_srem(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let members = rest_strings(frames)?;
+        if members.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+
+        Ok(Frame::Integer(self.db.srem(&key, &members)? as i64))
+    }
+
+    pub(super) fn run_smembers(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        Ok(strings(self.db.smembers(&key)?))
+    }
+
+    pub(super) fn run_sismember(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let member = next_string(frames)?;
+
+        let found = self.db.smismember(&key, &[member])?;
+        Ok(Frame::Integer(found[0] as i64))
+    }
+
+    pub(super) fn run_smismember(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let members = rest_strings(frames)?;
+        if members.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+
+        let found = self.db.smismember(&key, &members)?;
+        Ok(Frame::Array(
+            found
+                .into_iter()
+                .map(|found| Frame::Integer(found as i64))
+                .collect(),
+        ))
+    }
+
+    pub(super) fn run_scard(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        Ok(Frame::Integer(self.db.scard(&key)? as i64))
+    }
+
+    pub(super) fn run_smove(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let source = next_string(frames)?;
+        let destination = next_string(frames)?;
+        let member = next_string(frames)?;
+
+        let moved = self.db.smove(&source, &destination, member)?;
+        Ok(Frame::Integer(moved as i64))
+    }
+
+    /**
+     * SINTER|SUNION|SDIFF key [key ...]
+     */
+    pub(super) fn run_set_operation(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        operation: SetOperation,
+    ) -> Result<Frame, RunnerError> {
+        let keys = rest_strings(frames)?;
+        if keys.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+
+        Ok(strings(self.db.set_operation(&keys, operation)?))
+    }
+
+    /**
+     * SINTERSTORE|SUNIONSTORE|SDIFFSTORE destination key [key ...]
+     */
+    pub(super) fn run_set_operation_store(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        operation: SetOperation,
+    ) -> Result<Frame, RunnerError> {
+        let destination = next_string(frames)?;
+        let keys = rest_strings(frames)?;
+        if keys.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+
+        let len = self
+            .db
+            .set_operation_store(&destination, &keys, operation)?;
+        Ok(Frame::Integer(len as i64))
+    }
+
+    /**
+     * SINTERCARD numkeys key [key ...] [LIMIT limit]
+     */
+    pub(super) fn run_sintercard(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let numkeys = next_integer(frames)?;
+        if numkeys < 1 {
+            return Err(RunnerError::Other(
+                "ERR numkeys should be greater than 0".to_string(),
+            ));
+        }
+        if numkeys as usize > frames.len() {
+            return Err(RunnerError::Other(
+                "ERR Number of keys can't be greater than number of args".to_string(),
+            ));
+        }
+        let mut keys = Vec::with_capacity(numkeys as usize);
+        for _ in 0..numkeys {
+            keys.push(next_string(frames)?);
+        }
+
+        let mut limit = 0;
+        if !frames.is_empty() {
+            if next_string(frames)?.to_uppercase() != "LIMIT" {
+                return Err(RunnerError::Other("ERR syntax error".to_string()));
+            }
+            limit = next_integer(frames)?;
+            if limit < 0 {
+                return Err(RunnerError::Other(
+                    "ERR LIMIT can't be negative".to_string(),
+                ));
+            }
+        }
+
+        let count = self.db.sintercard(&keys, limit as usize)?;
+        Ok(Frame::Integer(count as i64))
+    }
+
+    /**
+     * SPOP key [count]
+     * Without a count a single member is returned instead of an array.
+     */
+    pub(super) fn run_spop(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        if frames.is_empty() {
+            let members = self.db.spop(&key, 1)?;
+            return Ok(members
+                .into_iter()
+                .next()
+                .map(Frame::SimpleString)
+                .unwrap_or_else(nil));
+        }
+
+        let count = next_integer(frames)?;
+        if count < 0 {
+            return Err(RunnerError::Other(
+                "ERR value is out of range, must be positive".to_string(),
+            ));
+        }
+        Ok(strings(self.db.spop(&key, count as usize)?))
+    }
+
+    /**
+     * SRANDMEMBER key [count]
+     */
+    pub(super) fn run_srandmember(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        if frames.is_empty() {
+            let members = self.db.srandmember(&key, 1)?;
+            return Ok(members
+                .into_iter()
+                .next()
+                .map(Frame::SimpleString)
+                .unwrap_or_else(nil));
+        }
+
+        let count = next_integer(frames)?;
+        Ok(strings(self.db.srandmember(&key, count)?))
+    }
+
+    /**
+     * SSCAN key cursor [MATCH pattern] [COUNT count]
+     */
+    pub(super) fn run_sscan(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let scan = ScanArguments::parse(frames)?;
+
+        let (cursor, members) =
+            self.db
+                .sscan(&key, scan.cursor, scan.pattern.as_deref(), scan.count)?;
+        Ok(Frame::Array(VecDeque::from([
+            Frame::SimpleString(cursor.to_string()),
+            strings(members),
+        ])))
+    }
+}
+
+fn strings(members: Vec<String>) -> Frame {
+    Frame::Array(members.into_iter().map(Frame::SimpleString).collect())
+}
@@ -0,0 +1,68 @@
+use std::collections::VecDeque;
+
+use crate::frame::Frame;
+
+use super::{next_integer, next_string, Runner, RunnerError};
+
+impl<'a> Runner<'a> {
+    /**
+     * SLOWLOG GET [count] | LEN | RESET
+     * GET replies the 10 most recent entries by default, a negative count replying all of them.
+     * Each entry is its id, unix timestamp, microseconds, arguments, client address and client name.
+     */
+    pub(super) fn run_slowlog(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let subcommand = next_string(frames)?;
+        let slowlog = &self.db.db.slowlog;
+        let max_args = match subcommand.eq_ignore_ascii_case("GET") {
+            true => 1,
+            false => 0,
+        };
+        if frames.len() > max_args {
+            return Err(RunnerError::Other(format!(
+                "ERR wrong number of arguments for 'slowlog|{}' command",
+                subcommand.to_lowercase()
+            )));
+        }
+        match subcommand.to_uppercase().as_str() {
+            "GET" => {
+                let count = match frames.is_empty() {
+                    true => Some(10),
+                    false => match next_integer(frames)? {
+                        count if count < 0 => None,
+                        count => Some(count as usize),
+                    },
+                };
+                Ok(Frame::Array(
+                    slowlog
+                        .get(count)
+                        .into_iter()
+                        .map(|entry| {
+                            Frame::Array(VecDeque::from([
+                                Frame::Integer(entry.id as i64),
+                                Frame::Integer(entry.timestamp as i64),
+                                Frame::Integer(entry.duration.as_micros() as i64),
+                                Frame::Array(
+                                    entry.args.into_iter().map(Frame::SimpleString).collect(),
+                                ),
+                                Frame::SimpleString(entry.client_addr),
+                                Frame::SimpleString(entry.client_name),
+                            ]))
+                        })
+                        .collect(),
+                ))
+            }
+            "LEN" => Ok(Frame::Integer(slowlog.len() as i64)),
+            "RESET" => {
+                slowlog.reset();
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            _ => Err(RunnerError::Other(format!(
+                "ERR unknown subcommand '{}'. Try SLOWLOG HELP.",
+                subcommand
+            ))),
+        }
+    }
+}
@@ -0,0 +1,710 @@
+use std::{collections::VecDeque, ops::Bound, time::Duration};
+
+use crate::{
+    db::{ClaimOptions, StreamEntry, StreamId, StreamTrim, TrimStrategy, XAddId},
+    frame::Frame,
+};
+
+use super::{bulk, next_integer, next_string, nil, rest_strings, Runner, RunnerError};
+
+impl<'a> Runner<'a> {
+    /**
+     * XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] *|id field value [field value ...]
+     */
+    pub(super) fn run_xadd(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        let mut nomkstream = false;
+        let mut trim = None;
+        let id = loop {
+            let argument = next_string(frames)?;
+            match argument.to_uppercase().as_str() {
+                "NOMKSTREAM" => nomkstream = true,
+                "MAXLEN" | "MINID" => trim = Some(parse_trim(&argument, frames)?),
+                _ => break xadd_id(&argument)?,
+            }
+        };
+
+        if frames.is_empty() || !frames.len().is_multiple_of(2) {
+            return Err(RunnerError::Incomplete);
+        }
+        let mut fields = Vec::with_capacity(frames.len() / 2);
+        while !frames.is_empty() {
+            let field = next_string(frames)?;
+            fields.push((field, next_string(frames)?.into_bytes()));
+        }
+
+        Ok(match self.db.xadd(&key, id, fields, nomkstream, trim)? {
+            Some(id) => Frame::SimpleString(id.to_string()),
+            None => nil(),
+        })
+    }
+
+    /**
+     * XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]
+     */
+    pub(super) fn run_xtrim(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let strategy = next_string(frames)?;
+        let trim = parse_trim(&strategy, frames)?;
+        if !frames.is_empty() {
+            return Err(RunnerError::Other("ERR syntax error".to_string()));
+        }
+
+        Ok(Frame::Integer(self.db.xtrim(&key, trim)? as i64))
+    }
+
+    pub(super) fn run_xlen(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        Ok(Frame::Integer(self.db.xlen(&key)? as i64))
+    }
+
+    /**
+     * XDEL key id [id ...]
+     */
+    pub(super) fn run_xdel(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let ids = stream_ids(frames)?;
+
+        Ok(Frame::Integer(self.db.xdel(&key, &ids)? as i64))
+    }
+
+    /**
+     * XRANGE key start end [COUNT count]
+     * XREVRANGE key end start [COUNT count]
+     */
+    pub(super) fn run_xrange(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        reverse: bool,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let (start, end) = if reverse {
+            let end = next_string(frames)?;
+            (next_string(frames)?, end)
+        } else {
+            (next_string(frames)?, next_string(frames)?)
+        };
+        let start = range_start(&start)?;
+        let end = range_end(&end)?;
+
+        let mut count = None;
+        if !frames.is_empty() {
+            if next_string(frames)?.to_uppercase() != "COUNT" {
+                return Err(RunnerError::Other("ERR syntax error".to_string()));
+            }
+            count = Some(next_integer(frames)?.max(0) as usize);
+        }
+
+        let entries = self.db.xrange(&key, start, end, reverse, count)?;
+        Ok(Frame::Array(entries.into_iter().map(entry).collect()))
+    }
+
+    /**
+     * XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+     * Blocking reads run again with $ replaced by the last id at the time of the first call.
+     */
+    pub(super) fn run_xread(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let mut count = None;
+        let mut block = None;
+        loop {
+            match next_string(frames)?.to_uppercase().as_str() {
+                "COUNT" => count = Some(next_integer(frames)?.max(0) as usize),
+                "BLOCK" => block = Some(block_timeout(frames)?),
+                "STREAMS" => break,
+                _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            }
+        }
+        let (keys, ids) = streams_argument(frames, "xread")?;
+
+        let mut streams = Vec::with_capacity(keys.len());
+        for (key, id) in keys.into_iter().zip(ids) {
+            let id = match id.as_str() {
+                "$" => self.db.xlast_id(&key)?,
+                id => StreamId::parse(id, 0).ok_or_else(invalid_id)?,
+            };
+            streams.push((key, id));
+        }
+
+        let result = self.db.xread(&streams, count)?;
+        if !result.is_empty() {
+            return Ok(Frame::Array(
+                result
+                    .into_iter()
+                    .map(|(key, entries)| {
+                        Frame::Array(VecDeque::from([
+                            Frame::SimpleString(key),
+                            Frame::Array(entries.into_iter().map(entry).collect()),
+                        ]))
+                    })
+                    .collect(),
+            ));
+        }
+
+        match block {
+            Some(timeout) => {
+                let mut retry = VecDeque::from([Frame::SimpleString("XREAD".to_string())]);
+                if let Some(count) = count {
+                    retry.push_back(Frame::SimpleString("COUNT".to_string()));
+                    retry.push_back(Frame::Integer(count as i64));
+                }
+                retry.push_back(Frame::SimpleString("BLOCK".to_string()));
+                retry.push_back(Frame::Integer(
+                    timeout.map_or(0, |timeout| timeout.as_millis() as i64),
+                ));
+                retry.push_back(Frame::SimpleString("STREAMS".to_string()));
+                retry.extend(
+                    streams
+                        .iter()
+                        .map(|(key, _)| Frame::SimpleString(key.clone())),
+                );
+                retry.extend(
+                    streams
+                        .iter()
+                        .map(|(_, id)| Frame::SimpleString(id.to_string())),
+                );
+                Err(RunnerError::Blocked {
+                    timeout,
+                    retry: Some(Frame::Array(retry)),
+                })
+            }
+            None => Ok(nil()),
+        }
+    }
+
+    /**
+     * XGROUP CREATE|SETID|DESTROY|CREATECONSUMER|DELCONSUMER key group ...
+     */
+    pub(super) fn run_xgroup(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let subcommand = next_string(frames)?.to_uppercase();
+        let key = next_string(frames)?;
+        let group = next_string(frames)?;
+
+        match subcommand.as_str() {
+            "CREATE" | "SETID" => {
+                let id = match next_string(frames)?.as_str() {
+                    "$" => None,
+                    id => Some(StreamId::parse(id, 0).ok_or_else(invalid_id)?),
+                };
+                let mut mkstream = false;
+                while !frames.is_empty() {
+                    match next_string(frames)?.to_uppercase().as_str() {
+                        "MKSTREAM" if subcommand == "CREATE" => mkstream = true,
+                        // Lag tracking is not supported, the counter is accepted and ignored
+                        "ENTRIESREAD" => {
+                            next_integer(frames)?;
+                        }
+                        _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+                    }
+                }
+
+                if subcommand == "CREATE" {
+                    self.db.xgroup_create(&key, &group, id, mkstream)?;
+                } else {
+                    self.db.xgroup_setid(&key, &group, id)?;
+                }
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "DESTROY" => Ok(Frame::Integer(self.db.xgroup_destroy(&key, &group)? as i64)),
+            "CREATECONSUMER" => {
+                let consumer = next_string(frames)?;
+                let created = self.db.xgroup_createconsumer(&key, &group, &consumer)?;
+                Ok(Frame::Integer(created as i64))
+            }
+            "DELCONSUMER" => {
+                let consumer = next_string(frames)?;
+                let pending = self.db.xgroup_delconsumer(&key, &group, &consumer)?;
+                Ok(Frame::Integer(pending as i64))
+            }
+            _ => Err(RunnerError::Other(format!(
+                "ERR unknown subcommand '{}'",
+                subcommand
+            ))),
+        }
+    }
+
+    /**
+     * XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS key [key ...] id [id ...]
+     */
+    pub(super) fn run_xreadgroup(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        if next_string(frames)?.to_uppercase() != "GROUP" {
+            return Err(RunnerError::Other("ERR syntax error".to_string()));
+        }
+        let group = next_string(frames)?;
+        let consumer = next_string(frames)?;
+
+        let mut count = None;
+        let mut block = None;
+        let mut noack = false;
+        loop {
+            match next_string(frames)?.to_uppercase().as_str() {
+                "COUNT" => count = Some(next_integer(frames)?.max(0) as usize),
+                "BLOCK" => block = Some(block_timeout(frames)?),
+                "NOACK" => noack = true,
+                "STREAMS" => break,
+                _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            }
+        }
+        let (keys, ids) = streams_argument(frames, "xreadgroup")?;
+
+        let mut streams = Vec::with_capacity(keys.len());
+        for (key, id) in keys.into_iter().zip(ids) {
+            let id = match id.as_str() {
+                ">" => None,
+                id => Some(StreamId::parse(id, 0).ok_or_else(invalid_id)?),
+            };
+            streams.push((key, id));
+        }
+
+        let result = self
+            .db
+            .xreadgroup(&group, &consumer, &streams, count, noack)?;
+        if result.is_empty() {
+            return match block {
+                Some(timeout) => Err(RunnerError::Blocked {
+                    timeout,
+                    retry: None,
+                }),
+                None => Ok(nil()),
+            };
+        }
+
+        Ok(Frame::Array(
+            result
+                .into_iter()
+                .map(|(key, entries)| {
+                    Frame::Array(VecDeque::from([
+                        Frame::SimpleString(key),
+                        Frame::Array(
+                            entries
+                                .into_iter()
+                                .map(|(id, fields)| match fields {
+                                    Some(fields) => entry((id, fields)),
+                                    None => Frame::Array(VecDeque::from([
+                                        Frame::SimpleString(id.to_string()),
+                                        nil(),
+                                    ])),
+                                })
+                                .collect(),
+                        ),
+                    ]))
+                })
+                .collect(),
+        ))
+    }
+
+    /**
+     * XACK key group id [id ...]
+     */
+    pub(super) fn run_xack(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let group = next_string(frames)?;
+        let ids = stream_ids(frames)?;
+
+        Ok(Frame::Integer(self.db.xack(&key, &group, &ids)? as i64))
+    }
+
+    /**
+     * XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+     */
+    pub(super) fn run_xpending(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let group = next_string(frames)?;
+
+        if frames.is_empty() {
+            let summary = self.db.xpending_summary(&key, &group)?;
+            let (first, last) = match summary.bounds {
+                Some((first, last)) => (
+                    Frame::SimpleString(first.to_string()),
+                    Frame::SimpleString(last.to_string()),
+                ),
+                None => (nil(), nil()),
+            };
+            let consumers = if summary.consumers.is_empty() {
+                nil()
+            } else {
+                Frame::Array(
+                    summary
+                        .consumers
+                        .into_iter()
+                        .map(|(consumer, count)| {
+                            Frame::Array(VecDeque::from([
+                                Frame::SimpleString(consumer),
+                                Frame::SimpleString(count.to_string()),
+                            ]))
+                        })
+                        .collect(),
+                )
+            };
+            return Ok(Frame::Array(VecDeque::from([
+                Frame::Integer(summary.count as i64),
+                first,
+                last,
+                consumers,
+            ])));
+        }
+
+        let mut min_idle = 0;
+        let mut start = next_string(frames)?;
+        if start.to_uppercase() == "IDLE" {
+            min_idle = next_integer(frames)?.max(0) as u64;
+            start = next_string(frames)?;
+        }
+        let start = range_start(&start)?;
+        let end = range_end(&next_string(frames)?)?;
+        let count = next_integer(frames)?.max(0) as usize;
+        let consumer = match frames.is_empty() {
+            true => None,
+            false => Some(next_string(frames)?),
+        };
+
+        let pending = self.db.xpending(
+            &key,
+            &group,
+            min_idle,
+            start,
+            end,
+            count,
+            consumer.as_deref(),
+        )?;
+        Ok(Frame::Array(
+            pending
+                .into_iter()
+                .map(|detail| {
+                    Frame::Array(VecDeque::from([
+                        Frame::SimpleString(detail.id.to_string()),
+                        Frame::SimpleString(detail.consumer),
+                        Frame::Integer(detail.idle as i64),
+                        Frame::Integer(detail.deliveries as i64),
+                    ]))
+                })
+                .collect(),
+        ))
+    }
+
+    /**
+     * XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-milliseconds]
+     * [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID lastid]
+     */
+    pub(super) fn run_xclaim(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let group = next_string(frames)?;
+        let consumer = next_string(frames)?;
+        let min_idle = next_integer(frames)?.max(0) as u64;
+
+        // Ids run until the first argument that isn't one
+        let mut ids = vec![];
+        let mut options = ClaimOptions::default();
+        let mut in_options = false;
+        while !frames.is_empty() {
+            let argument = next_string(frames)?;
+            match StreamId::parse(&argument, 0) {
+                Some(id) if !in_options => ids.push(id),
+                _ => {
+                    in_options = true;
+                    parse_claim_option(&argument, frames, &mut options)?
+                }
+            }
+        }
+        if ids.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+
+        let claimed = self
+            .db
+            .xclaim(&key, &group, &consumer, min_idle, &ids, options)?;
+        Ok(claimed_entries(claimed, options.just_id))
+    }
+
+    /**
+     * XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]
+     */
+    pub(super) fn run_xautoclaim(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let group = next_string(frames)?;
+        let consumer = next_string(frames)?;
+        let min_idle = next_integer(frames)?.max(0) as u64;
+        let start = match range_start(&next_string(frames)?)? {
+            Bound::Included(start) => start,
+            Bound::Excluded(start) => start.next().ok_or_else(invalid_id)?,
+            Bound::Unbounded => StreamId::MIN,
+        };
+
+        let mut count = 100;
+        let mut just_id = false;
+        while !frames.is_empty() {
+            match next_string(frames)?.to_uppercase().as_str() {
+                "COUNT" => {
+                    count = next_integer(frames)?;
+                    if count < 1 {
+                        return Err(RunnerError::Other("ERR COUNT must be > 0".to_string()));
+                    }
+                }
+                "JUSTID" => just_id = true,
+                _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            }
+        }
+
+        let (next, claimed, deleted) = self.db.xautoclaim(
+            &key,
+            &group,
+            &consumer,
+            min_idle,
+            start,
+            count as usize,
+            just_id,
+        )?;
+        Ok(Frame::Array(VecDeque::from([
+            Frame::SimpleString(next.to_string()),
+            claimed_entries(claimed, just_id),
+            Frame::Array(
+                deleted
+                    .into_iter()
+                    .map(|id| Frame::SimpleString(id.to_string()))
+                    .collect(),
+            ),
+        ])))
+    }
+
+    /**
+     * XINFO STREAM key | GROUPS key | CONSUMERS key group
+     */
+    pub(super) fn run_xinfo(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let subcommand = next_string(frames)?.to_uppercase();
+        let key = next_string(frames)?;
+        let no_key = || RunnerError::Other("ERR no such key".to_string());
+
+        match subcommand.as_str() {
+            "STREAM" => {
+                let info = self.db.xinfo_stream(&key)?.ok_or_else(no_key)?;
+                Ok(Frame::Array(VecDeque::from([
+                    Frame::SimpleString("length".to_string()),
+                    Frame::Integer(info.length as i64),
+                    Frame::SimpleString("last-generated-id".to_string()),
+                    Frame::SimpleString(info.last_id.to_string()),
+                    Frame::SimpleString("entries-added".to_string()),
+                    Frame::Integer(info.entries_added as i64),
+                    Frame::SimpleString("groups".to_string()),
+                    Frame::Integer(info.groups as i64),
+                    Frame::SimpleString("first-entry".to_string()),
+                    info.first.map(entry).unwrap_or_else(nil),
+                    Frame::SimpleString("last-entry".to_string()),
+                    info.last.map(entry).unwrap_or_else(nil),
+                ])))
+            }
+            "GROUPS" => {
+                let groups = self.db.xinfo_groups(&key)?.ok_or_else(no_key)?;
+                Ok(Frame::Array(
+                    groups
+                        .into_iter()
+                        .map(|group| {
+                            Frame::Array(VecDeque::from([
+                                Frame::SimpleString("name".to_string()),
+                                Frame::SimpleString(group.name),
+                                Frame::SimpleString("consumers".to_string()),
+                                Frame::Integer(group.consumers as i64),
+                                Frame::SimpleString("pending".to_string()),
+                                Frame::Integer(group.pending as i64),
+                                Frame::SimpleString("last-delivered-id".to_string()),
+                                Frame::SimpleString(group.last_delivered.to_string()),
+                            ]))
+                        })
+                        .collect(),
+                ))
+            }
+            "CONSUMERS" => {
+                let group = next_string(frames)?;
+                let consumers = self.db.xinfo_consumers(&key, &group)?;
+                Ok(Frame::Array(
+                    consumers
+                        .into_iter()
+                        .map(|consumer| {
+                            Frame::Array(VecDeque::from([
+                                Frame::SimpleString("name".to_string()),
+                                Frame::SimpleString(consumer.name),
+                                Frame::SimpleString("pending".to_string()),
+                                Frame::Integer(consumer.pending as i64),
+                                Frame::SimpleString("idle".to_string()),
+                                Frame::Integer(consumer.idle as i64),
+                            ]))
+                        })
+                        .collect(),
+                ))
+            }
+            _ => Err(RunnerError::Other(format!(
+                "ERR unknown subcommand '{}'",
+                subcommand
+            ))),
+        }
+    }
+}
+
+fn invalid_id() -> RunnerError {
+    RunnerError::Other("ERR Invalid stream ID specified as stream command argument".to_string())
+}
+
+// Parses the id given to XADD: *, ms-* or ms-seq
+fn xadd_id(input: &str) -> Result<XAddId, RunnerError> {
+    if input == "*" {
+        return Ok(XAddId::Auto);
+    }
+    if let Some(ms) = input.strip_suffix("-*") {
+        return ms.parse().map(XAddId::AutoSeq).map_err(|_| invalid_id());
+    }
+    StreamId::parse(input, 0)
+        .map(XAddId::Explicit)
+        .ok_or_else(invalid_id)
+}
+
+// Parses [=|~] threshold [LIMIT count] following MAXLEN or MINID
+fn parse_trim(strategy: &str, frames: &mut VecDeque<Frame>) -> Result<StreamTrim, RunnerError> {
+    let mut threshold = next_string(frames)?;
+    if threshold == "=" || threshold == "~" {
+        threshold = next_string(frames)?;
+    }
+    let strategy = match strategy.to_uppercase().as_str() {
+        "MAXLEN" => TrimStrategy::MaxLen(threshold.parse().map_err(|_| {
+            RunnerError::Other("ERR value is not an integer or out of range".to_string())
+        })?),
+        "MINID" => TrimStrategy::MinId(StreamId::parse(&threshold, 0).ok_or_else(invalid_id)?),
+        _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+    };
+
+    let mut limit = None;
+    if let Some(Frame::SimpleString(argument)) = frames.front() {
+        if argument.to_uppercase() == "LIMIT" {
+            frames.pop_front();
+            limit = Some(next_integer(frames)?.max(0) as usize);
+        }
+    }
+    Ok(StreamTrim { strategy, limit })
+}
+
+fn stream_ids(frames: &mut VecDeque<Frame>) -> Result<Vec<StreamId>, RunnerError> {
+    let ids = rest_strings(frames)?;
+    if ids.is_empty() {
+        return Err(RunnerError::Incomplete);
+    }
+    ids.iter()
+        .map(|id| StreamId::parse(id, 0).ok_or_else(invalid_id))
+        .collect()
+}
+
+// Parses the start of a range: -, (id or id where a missing sequence number means 0
+fn range_start(input: &str) -> Result<Bound<StreamId>, RunnerError> {
+    match input {
+        "-" => Ok(Bound::Unbounded),
+        _ => match input.strip_prefix('(') {
+            Some(id) => Ok(Bound::Excluded(
+                StreamId::parse(id, 0).ok_or_else(invalid_id)?,
+            )),
+            None => Ok(Bound::Included(
+                StreamId::parse(input, 0).ok_or_else(invalid_id)?,
+            )),
+        },
+    }
+}
+
+// Parses the end of a range: +, (id or id where a missing sequence number means the greatest one
+fn range_end(input: &str) -> Result<Bound<StreamId>, RunnerError> {
+    match input {
+        "+" => Ok(Bound::Unbounded),
+        _ => match input.strip_prefix('(') {
+            Some(id) => Ok(Bound::Excluded(
+                StreamId::parse(id, u64::MAX).ok_or_else(invalid_id)?,
+            )),
+            None => Ok(Bound::Included(
+                StreamId::parse(input, u64::MAX).ok_or_else(invalid_id)?,
+            )),
+        },
+    }
+}
+
+// Parses the BLOCK option, 0 blocks forever
+fn block_timeout(frames: &mut VecDeque<Frame>) -> Result<Option<Duration>, RunnerError> {
+    let timeout = next_integer(frames)?;
+    if timeout < 0 {
+        return Err(RunnerError::Other("ERR timeout is negative".to_string()));
+    }
+    Ok(match timeout {
+        0 => None,
+        timeout => Some(Duration::from_millis(timeout as u64)),
+    })
+}
+
+// Splits the arguments after STREAMS into keys and ids
+fn streams_argument(
+    frames: &mut VecDeque<Frame>,
+    command: &str,
+) -> Result<(Vec<String>, Vec<String>), RunnerError> {
+    let mut keys = rest_strings(frames)?;
+    if keys.is_empty() || !keys.len().is_multiple_of(2) {
+        return Err(RunnerError::Other(format!(
+            "ERR Unbalanced '{}' list of streams: for each stream key an ID or '$' must be specified.",
+            command
+        )));
+    }
+    let ids = keys.split_off(keys.len() / 2);
+    Ok((keys, ids))
+}
+
+fn parse_claim_option(
+    option: &str,
+    frames: &mut VecDeque<Frame>,
+    options: &mut ClaimOptions,
+) -> Result<(), RunnerError> {
+    match option.to_uppercase().as_str() {
+        "IDLE" => options.idle = Some(next_integer(frames)?.max(0) as u64),
+        "TIME" => options.time = Some(next_integer(frames)?.max(0) as u64),
+        "RETRYCOUNT" => options.retry_count = Some(next_integer(frames)?.max(0) as u64),
+        "FORCE" => options.force = true,
+        "JUSTID" => options.just_id = true,
+        "LASTID" => {
+            options.last_id =
+                Some(StreamId::parse(&next_string(frames)?, 0).ok_or_else(invalid_id)?)
+        }
+        _ => return Err(invalid_id()),
+    }
+    Ok(())
+}
+
+// An entry as [id, [field, value, ...]]
+fn entry((id, fields): StreamEntry) -> Frame {
+    let mut values = VecDeque::with_capacity(fields.len() * 2);
+    for (field, value) in fields {
+        values.push_back(Frame::SimpleString(field));
+        values.push_back(bulk(&value));
+    }
+    Frame::Array(VecDeque::from([
+        Frame::SimpleString(id.to_string()),
+        Frame::Array(values),
+    ]))
+}
+
+fn claimed_entries(claimed: Vec<StreamEntry>, just_id: bool) -> Frame {
+    Frame::Array(
+        claimed
+            .into_iter()
+            .map(|claimed| match just_id {
+                true => Frame::SimpleString(claimed.0.to_string()),
+                false => entry(claimed),
+            })
+            .collect(),
+    )
+}
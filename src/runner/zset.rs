@@ -0,0 +1,492 @@
+use std::{collections::VecDeque, time::Duration};
+
+use crate::{
+    db::{Aggregate, LexBound, ScoreBound, ZAddFlags, ZRange},
+    frame::Frame,
+};
+
+use super::{
+    next_float, next_integer, next_string, nil, rest_strings, Runner, RunnerError, ScanArguments,
+};
+
+/**
+ * What the start and stop arguments of a ZRANGE style command refer to.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum RangeBy {
+    Rank,
+    Score,
+    Lex,
+}
+
+impl<'a> Runner<'a> {
+    /**
+     * ZADD key [NX|XX] [GT|LT] [CH] [INCR] score member [score member ...]
+     */
+    pub(super) fn run_zadd(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        let mut flags = ZAddFlags::default();
+        while let Some(Frame::SimpleString(flag)) = frames.front() {
+            match flag.to_uppercase().as_str() {
+                "NX" => flags.nx = true,
+                "XX" => flags.xx = true,
+                "GT" => flags.gt = true,
+                "LT" => flags.lt = true,
+                "CH" => flags.ch = true,
+                "INCR" => flags.incr = true,
+                _ => break,
+            }
+            frames.pop_front();
+        }
+        if flags.nx && flags.xx {
+            return Err(RunnerError::Other(
+                "ERR XX and NX options at the same time are not compatible".to_string(),
+            ));
+        }
+        if (flags.gt && flags.lt) || (flags.nx && (flags.gt || flags.lt)) {
+            return Err(RunnerError::Other(
+                "ERR GT, LT, and/or NX options at the same time are not compatible".to_string(),
+            ));
+        }
+
+        if frames.is_empty() || !frames.len().is_multiple_of(2) {
+            return Err(RunnerError::Other("ERR syntax error".to_string()));
+        }
+        if flags.incr && frames.len() > 2 {
+            return Err(RunnerError::Other(
+                "ERR INCR option supports a single increment-element pair".to_string(),
+            ));
+        }
+        let mut members = Vec::with_capacity(frames.len() / 2);
+        while !frames.is_empty() {
+            let score = next_float(frames)?;
+            members.push((score, next_string(frames)?));
+        }
+
+        let reply = self.db.zadd(&key, flags, members)?;
+        if flags.incr {
+            return Ok(reply.score.map(score).unwrap_or_else(nil));
+        }
+        let changed = if flags.ch {
+            reply.added + reply.updated
+        } else {
+            reply.added
+        };
+        Ok(Frame::Integer(changed as i64))
+    }
+
+    /**
+     * ZINCRBY key increment member
+     */
+    pub(super) fn run_zincrby(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let increment = next_float(frames)?;
+        let member = next_string(frames)?;
+
+        let flags = ZAddFlags {
+            incr: true,
+            ..Default::default()
+        };
+        let reply = self.db.zadd(&key, flags, vec![(increment, member)])?;
+        Ok(reply.score.map(score).unwrap_or_else(nil))
+    }
+
+    pub(super) fn run_zrem(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let members = rest_strings(frames)?;
+        if members.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+
+        Ok(Frame::Integer(self.db.zrem(&key, &members)? as i64))
+    }
+
+    pub(super) fn run_zcard(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+
+        Ok(Frame::Integer(self.db.zcard(&key)? as i64))
+    }
+
+    pub(super) fn run_zscore(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let member = next_string(frames)?;
+
+        let scores = self.db.zmscore(&key, &[member])?;
+        Ok(scores[0].map(score).unwrap_or_else(nil))
+    }
+
+    pub(super) fn run_zmscore(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let members = rest_strings(frames)?;
+        if members.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+
+        let scores = self.db.zmscore(&key, &members)?;
+        Ok(Frame::Array(
+            scores
+                .into_iter()
+                .map(|found| found.map(score).unwrap_or_else(nil))
+                .collect(),
+        ))
+    }
+
+    /**
+     * ZRANK|ZREVRANK key member [WITHSCORE]
+     */
+    pub(super) fn run_zrank(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        reverse: bool,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let member = next_string(frames)?;
+        let withscore = match frames.pop_front() {
+            Some(Frame::SimpleString(option)) if option.to_uppercase() == "WITHSCORE" => true,
+            Some(_) => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            None => false,
+        };
+
+        Ok(match self.db.zrank(&key, &member, reverse)? {
+            Some((rank, member_score)) if withscore => Frame::Array(VecDeque::from([
+                Frame::Integer(rank as i64),
+                score(member_score),
+            ])),
+            Some((rank, _)) => Frame::Integer(rank as i64),
+            None => nil(),
+        })
+    }
+
+    /**
+     * ZCOUNT key min max
+     */
+    pub(super) fn run_zcount(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let min = score_bound(&next_string(frames)?)?;
+        let max = score_bound(&next_string(frames)?)?;
+
+        Ok(Frame::Integer(self.db.zcount(&key, min, max)? as i64))
+    }
+
+    /**
+     * ZRANGE key start stop [BYSCORE|BYLEX] [REV] [LIMIT offset count] [WITHSCORES]
+     * Also serves ZREVRANGE, ZRANGEBYSCORE, ZREVRANGEBYSCORE, ZRANGEBYLEX and ZREVRANGEBYLEX
+     * which fix how the range is selected and don't accept BYSCORE, BYLEX or REV.
+     */
+    pub(super) fn run_zrange(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        mut by: RangeBy,
+        mut reverse: bool,
+        legacy: bool,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let start = next_string(frames)?;
+        let stop = next_string(frames)?;
+
+        let mut limit = None;
+        let mut withscores = false;
+        while !frames.is_empty() {
+            match next_string(frames)?.to_uppercase().as_str() {
+                "BYSCORE" if !legacy => by = RangeBy::Score,
+                "BYLEX" if !legacy => by = RangeBy::Lex,
+                "REV" if !legacy => reverse = true,
+                "LIMIT" if !(legacy && by == RangeBy::Rank) => {
+                    limit = Some((next_integer(frames)?, next_integer(frames)?))
+                }
+                "WITHSCORES" if !(legacy && by == RangeBy::Lex) => withscores = true,
+                _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            }
+        }
+        if limit.is_some() && by == RangeBy::Rank {
+            return Err(RunnerError::Other(
+                "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                    .to_string(),
+            ));
+        }
+        if withscores && by == RangeBy::Lex {
+            return Err(RunnerError::Other(
+                "ERR syntax error, WITHSCORES not supported in combination with BYLEX".to_string(),
+            ));
+        }
+
+        // Reversed score and lex ranges are given as max then min
+        let (min, max) = if reverse && by != RangeBy::Rank {
+            (stop, start)
+        } else {
+            (start, stop)
+        };
+        let range = match by {
+            RangeBy::Rank => {
+                let parse = |index: String| {
+                    index.parse::<i64>().map_err(|_| {
+                        RunnerError::Other(
+                            "ERR value is not an integer or out of range".to_string(),
+                        )
+                    })
+                };
+                ZRange::Rank(parse(min)?, parse(max)?)
+            }
+            RangeBy::Score => ZRange::Score(score_bound(&min)?, score_bound(&max)?),
+            RangeBy::Lex => ZRange::Lex(lex_bound(min)?, lex_bound(max)?),
+        };
+
+        let (offset, count) = match limit {
+            Some((offset, _)) if offset < 0 => return Ok(Frame::Array(VecDeque::new())),
+            Some((offset, count)) => (offset as usize, usize::try_from(count).ok()),
+            None => (0, None),
+        };
+        let members = self.db.zrange(&key, &range, reverse, offset, count)?;
+        Ok(members_with_scores(members, withscores))
+    }
+
+    /**
+     * ZPOPMIN|ZPOPMAX key [count]
+     */
+    pub(super) fn run_zpop(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        max: bool,
+    ) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let count = if frames.is_empty() {
+            1
+        } else {
+            next_integer(frames)?
+        };
+        if count < 0 {
+            return Err(RunnerError::Other(
+                "ERR value is out of range, must be positive".to_string(),
+            ));
+        }
+
+        let members = self.db.zpop(&key, count as usize, max)?;
+        Ok(members_with_scores(members, true))
+    }
+
+    /**
+     * BZPOPMIN|BZPOPMAX key [key ...] timeout
+     * Blocks until one of the sorted sets has a member to pop, a timeout of 0 blocks forever.
+     */
+    pub(super) fn run_bzpop(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        max: bool,
+    ) -> Result<Frame, RunnerError> {
+        let mut keys = rest_strings(frames)?;
+        let timeout = keys.pop().ok_or(RunnerError::Incomplete)?;
+        if keys.is_empty() {
+            return Err(RunnerError::Incomplete);
+        }
+        let timeout = match timeout.parse::<f64>() {
+            Ok(timeout) if timeout.is_finite() => timeout,
+            _ => {
+                return Err(RunnerError::Other(
+                    "ERR timeout is not a float or out of range".to_string(),
+                ))
+            }
+        };
+        if timeout < 0.0 {
+            return Err(RunnerError::Other("ERR timeout is negative".to_string()));
+        }
+        // Finite timeouts may still be too long for a Duration, e.g 1e300
+        let timeout = match timeout == 0.0 {
+            true => None,
+            false => Some(
+                Duration::try_from_secs_f64(timeout)
+                    .map_err(|_| RunnerError::Other("ERR timeout is out of range".to_string()))?,
+            ),
+        };
+
+        match self.db.zpop_first(&keys, max)? {
+            Some((key, (member, member_score))) => Ok(Frame::Array(VecDeque::from([
+                Frame::SimpleString(key),
+                Frame::SimpleString(member),
+                score(member_score),
+            ]))),
+            None => Err(RunnerError::Blocked {
+                timeout,
+                retry: None,
+            }),
+        }
+    }
+
+    /**
+     * ZUNION|ZINTER numkeys key [key ...] [WEIGHTS weight ...] [AGGREGATE SUM|MIN|MAX] [WITHSCORES]
+     */
+    pub(super) fn run_zcombine(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        inter: bool,
+    ) -> Result<Frame, RunnerError> {
+        let arguments = CombineArguments::parse(frames, true)?;
+
+        let members = self.db.zcombine(
+            &arguments.keys,
+            &arguments.weights,
+            arguments.aggregate,
+            inter,
+        )?;
+        Ok(members_with_scores(members, arguments.withscores))
+    }
+
+    /**
+     * ZUNIONSTORE|ZINTERSTORE destination numkeys key [key ...] [WEIGHTS weight ...] [AGGREGATE SUM|MIN|MAX]
+     */
+    pub(super) fn run_zcombine_store(
+        &mut self,
+        frames: &mut VecDeque<Frame>,
+        inter: bool,
+    ) -> Result<Frame, RunnerError> {
+        let destination = next_string(frames)?;
+        let arguments = CombineArguments::parse(frames, false)?;
+
+        let len = self.db.zcombine_store(
+            &destination,
+            &arguments.keys,
+            &arguments.weights,
+            arguments.aggregate,
+            inter,
+        )?;
+        Ok(Frame::Integer(len as i64))
+    }
+
+    /**
+     * ZSCAN key cursor [MATCH pattern] [COUNT count]
+     */
+    pub(super) fn run_zscan(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let key = next_string(frames)?;
+        let scan = ScanArguments::parse(frames)?;
+
+        let (cursor, members) =
+            self.db
+                .zscan(&key, scan.cursor, scan.pattern.as_deref(), scan.count)?;
+        Ok(Frame::Array(VecDeque::from([
+            Frame::SimpleString(cursor.to_string()),
+            members_with_scores(members, true),
+        ])))
+    }
+}
+
+/**
+ * The arguments shared by ZUNION, ZINTER and their STORE variants.
+ */
+struct CombineArguments {
+    keys: Vec<String>,
+    weights: Vec<f64>,
+    aggregate: Aggregate,
+    withscores: bool,
+}
+
+impl CombineArguments {
+    fn parse(
+        frames: &mut VecDeque<Frame>,
+        allow_withscores: bool,
+    ) -> Result<CombineArguments, RunnerError> {
+        let numkeys = next_integer(frames)?;
+        if numkeys < 1 {
+            return Err(RunnerError::Other(
+                "ERR at least 1 input key is needed".to_string(),
+            ));
+        }
+        if numkeys as usize > frames.len() {
+            return Err(RunnerError::Other("ERR syntax error".to_string()));
+        }
+        let mut keys = Vec::with_capacity(numkeys as usize);
+        for _ in 0..numkeys {
+            keys.push(next_string(frames)?);
+        }
+
+        let mut arguments = CombineArguments {
+            keys,
+            weights: vec![],
+            aggregate: Aggregate::Sum,
+            withscores: false,
+        };
+        while !frames.is_empty() {
+            match next_string(frames)?.to_uppercase().as_str() {
+                "WEIGHTS" => {
+                    if frames.len() < arguments.keys.len() {
+                        return Err(RunnerError::Other("ERR syntax error".to_string()));
+                    }
+                    arguments.weights = (0..arguments.keys.len())
+                        .map(|_| {
+                            next_float(frames).map_err(|_| {
+                                RunnerError::Other("ERR weight value is not a float".to_string())
+                            })
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+                "AGGREGATE" => {
+                    arguments.aggregate = match next_string(frames)?.to_uppercase().as_str() {
+                        "SUM" => Aggregate::Sum,
+                        "MIN" => Aggregate::Min,
+                        "MAX" => Aggregate::Max,
+                        _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+                    }
+                }
+                "WITHSCORES" if allow_withscores => arguments.withscores = true,
+                _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            }
+        }
+        Ok(arguments)
+    }
+}
+
+// Parses a score range bound such as 1.5, (1.5 or -inf
+fn score_bound(input: &str) -> Result<ScoreBound, RunnerError> {
+    let (value, exclusive) = match input.strip_prefix('(') {
+        Some(value) => (value, true),
+        None => (input, false),
+    };
+    match value.parse::<f64>() {
+        Ok(value) if !value.is_nan() => Ok(ScoreBound { value, exclusive }),
+        _ => Err(RunnerError::Other(
+            "ERR min or max is not a float".to_string(),
+        )),
+    }
+}
+
+// Parses a lexicographical range bound: -, +, [member or (member
+fn lex_bound(input: String) -> Result<LexBound, RunnerError> {
+    match input.as_str() {
+        "-" => Ok(LexBound::Min),
+        "+" => Ok(LexBound::Max),
+        _ => match input.chars().next() {
+            Some('[') => Ok(LexBound::Inclusive(input[1..].to_string())),
+            Some('(') => Ok(LexBound::Exclusive(input[1..].to_string())),
+            _ => Err(RunnerError::Other(
+                "ERR min or max not valid string range item".to_string(),
+            )),
+        },
+    }
+}
+
+fn score(score: f64) -> Frame {
+    Frame::SimpleString(score.to_string())
+}
+
+// Members as a flat array, each followed by its score if asked for
+fn members_with_scores(members: Vec<(String, f64)>, withscores: bool) -> Frame {
+    let mut frames = VecDeque::with_capacity(members.len() * 2);
+    for (member, member_score) in members {
+        frames.push_back(Frame::SimpleString(member));
+        if withscores {
+            frames.push_back(score(member_score));
+        }
+    }
+    Frame::Array(frames)
+}
@@ -0,0 +1,652 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use mlua::{HookTriggers, Lua, LuaOptions, StdLib, Value, Variadic};
+use tokio::{runtime::RuntimeFlavor, sync::Notify};
+
+mod function;
+
+pub use function::{Function, Library, RestorePolicy};
+
+use crate::{
+    command::{arguments, spec, Category},
+    db::DB,
+    frame::Frame,
+    runner::{nil, Runner, RunnerError},
+};
+
+/**
+ * How long a script runs before other clients get BUSY replies instead of waiting for it.
+ * Like redis' lua-time-limit.
+ */
+pub const BUSY_THRESHOLD: Duration = Duration::from_secs(5);
+
+// How many Lua instructions run between checks for SCRIPT KILL
+const KILL_CHECK_INSTRUCTIONS: u32 = 10_000;
+
+/**
+ * The Lua scripts cache, keyed by the SHA1 of their source, the function libraries and the state of the running script.
+ * Scripts run one at a time holding the store for their whole run, like a transaction.
+ */
+pub struct Scripts {
+    cache: Mutex<HashMap<String, String>>,
+    // Libraries loaded with FUNCTION LOAD by name
+    libraries: Mutex<BTreeMap<String, Library>>,
+    // When the running script started, None while no script runs
+    running_since: Mutex<Option<Instant>>,
+    // Set by SCRIPT KILL, the running script checks it every few instructions
+    killed: Arc<AtomicBool>,
+    // Set once the running script calls a write command, it can't be killed from then on
+    written: AtomicBool,
+    // Wakes up clients waiting for the running script to finish
+    finished: Notify,
+}
+
+impl Default for Scripts {
+    fn default() -> Self {
+        Scripts {
+            cache: Mutex::new(HashMap::new()),
+            libraries: Mutex::new(BTreeMap::new()),
+            running_since: Mutex::new(None),
+            killed: Arc::new(AtomicBool::new(false)),
+            written: AtomicBool::new(false),
+            finished: Notify::new(),
+        }
+    }
+}
+
+/**
+ * Who runs a script: the ACL user its commands are checked against, None running them unchecked,
+ * and the tracking connection remembering the keys they read.
+ */
+#[derive(Clone, Default)]
+pub struct Caller {
+    pub user: Option<String>,
+    pub tracking: Option<u64>,
+}
+
+// An error reply of a command called by redis.call, replied as is when the script doesn't catch it
+#[derive(Debug)]
+struct CallError(String);
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CallError {}
+
+/**
+ * The lowercase hex SHA1 of a script, used to call it with EVALSHA.
+ */
+pub fn sha1(source: &str) -> String {
+    sha1_smol::Sha1::from(source).digest().to_string()
+}
+
+impl Scripts {
+    /**
+     * Compiles a script and caches it, returning its SHA1.
+     */
+    pub fn load(&self, source: &str) -> Result<String, RunnerError> {
+        let lua = sandbox()?;
+        lua.load(source)
+            .set_name("@user_script")
+            .into_function()
+            .map_err(|err| {
+                RunnerError::Other(format!(
+                    "ERR Error compiling script (new function): {}",
+                    message(&err)
+                ))
+            })?;
+
+        let sha = sha1(source);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(sha.clone(), source.to_string());
+        Ok(sha)
+    }
+
+    /**
+     * The source of a cached script.
+     */
+    pub fn get(&self, sha: &str) -> Option<String> {
+        self.cache.lock().unwrap().get(&sha.to_lowercase()).cloned()
+    }
+
+    pub fn exists(&self, shas: &[String]) -> Vec<bool> {
+        let cache = self.cache.lock().unwrap();
+        shas.iter()
+            .map(|sha| cache.contains_key(&sha.to_lowercase()))
+            .collect()
+    }
+
+    pub fn flush(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /**
+     * Stops the running script, it fails with an error once it gets to its next check.
+     * A script that already wrote to the store is left running so it doesn't leave half its writes behind.
+     */
+    pub fn kill(&self) -> Result<(), RunnerError> {
+        if self.running_since.lock().unwrap().is_none() {
+            return Err(RunnerError::Other(
+                "NOTBUSY No scripts in execution right now.".to_string(),
+            ));
+        }
+        if self.written.load(Ordering::Relaxed) {
+            return Err(RunnerError::Other(
+                "UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.".to_string(),
+            ));
+        }
+        self.killed.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /**
+     * Waits for the running script, if any, to finish.
+     * Returns false if it is still running past the BUSY_THRESHOLD.
+     */
+    pub async fn wait_idle(&self) -> bool {
+        self.wait(Some(BUSY_THRESHOLD)).await
+    }
+
+    /**
+     * Waits for the running script, if any, to finish however long it takes.
+     * Background jobs wait for it before locking the store so they don't hold up a thread of the runtime.
+     */
+    pub async fn finished(&self) {
+        self.wait(None).await;
+    }
+
+    async fn wait(&self, threshold: Option<Duration>) -> bool {
+        loop {
+            let finished = self.finished.notified();
+            tokio::pin!(finished);
+            finished.as_mut().enable();
+
+            let started = match *self.running_since.lock().unwrap() {
+                Some(started) => started,
+                None => return true,
+            };
+            match threshold {
+                Some(threshold) => {
+                    let deadline = tokio::time::Instant::from_std(started + threshold);
+                    tokio::select! {
+                        _ = finished => {}
+                        _ = tokio::time::sleep_until(deadline) => return false,
+                    }
+                }
+                None => finished.await,
+            }
+        }
+    }
+
+    /**
+     * Runs a script with the KEYS and ARGV tables, caching it first.
+     * The commands it calls run as the caller.
+     * No other connection touches the store until it returns.
+     */
+    pub fn eval(
+        &self,
+        db: &DB,
+        caller: &Caller,
+        source: &str,
+        keys: Vec<String>,
+        args: Vec<String>,
+    ) -> Result<Frame, RunnerError> {
+        let sha = self.load(source)?;
+
+        self.exclusive(db, || self.run(db, caller, &sha, source, keys, args))
+    }
+
+    // Runs a script or function holding the store, one at a time
+    fn exclusive<F>(&self, db: &DB, run: F) -> Result<Frame, RunnerError>
+    where
+        F: FnOnce() -> Result<Frame, RunnerError>,
+    {
+        let _transaction = db.db.lock_transaction();
+        {
+            let mut running_since = self.running_since.lock().unwrap();
+            // Scripts calling EVAL or FCALL through redis.call
+            if running_since.is_some() {
+                return Err(RunnerError::Other(
+                    "ERR This Redis command is not allowed from script".to_string(),
+                ));
+            }
+            *running_since = Some(Instant::now());
+        }
+        self.killed.store(false, Ordering::Relaxed);
+        self.written.store(false, Ordering::Relaxed);
+
+        // Other connections' tasks move to another thread while the script runs on this one,
+        // so a script looping forever can still be killed
+        let multi_thread = tokio::runtime::Handle::try_current()
+            .is_ok_and(|handle| handle.runtime_flavor() == RuntimeFlavor::MultiThread);
+        let reply = match multi_thread {
+            true => tokio::task::block_in_place(run),
+            false => run(),
+        };
+
+        *self.running_since.lock().unwrap() = None;
+        self.finished.notify_waiters();
+        reply
+    }
+
+    // A Lua state for running a script, with the redis library and the check for SCRIPT KILL
+    fn state(&self, db: &DB, caller: &Caller) -> Result<Lua, RunnerError> {
+        let lua = sandbox()?;
+        let killed = self.killed.clone();
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(KILL_CHECK_INSTRUCTIONS),
+            move |_, _| match killed.load(Ordering::Relaxed) {
+                true => Err(mlua::Error::external(CallError(
+                    "ERR Script killed by user with SCRIPT KILL...".to_string(),
+                ))),
+                false => Ok(()),
+            },
+        );
+        redis_library(&lua, db, caller).map_err(lua_error)?;
+        Ok(lua)
+    }
+
+    fn run(
+        &self,
+        db: &DB,
+        caller: &Caller,
+        sha: &str,
+        source: &str,
+        keys: Vec<String>,
+        args: Vec<String>,
+    ) -> Result<Frame, RunnerError> {
+        let lua = self.state(db, caller)?;
+        let globals = lua.globals();
+        globals.set("KEYS", keys).map_err(lua_error)?;
+        globals.set("ARGV", args).map_err(lua_error)?;
+
+        let reply = lua
+            .load(source)
+            .set_name("@user_script")
+            .call::<_, Value>(())
+            .map_err(|err| script_error(&err, &format!("f_{}", sha)))?;
+        Ok(into_frame(reply))
+    }
+}
+
+// The reply of a script that failed: the error of redis.call if that is what made it fail
+fn script_error(err: &mlua::Error, name: &str) -> RunnerError {
+    match call_error(err) {
+        Some(err) => RunnerError::Other(err),
+        None => RunnerError::Other(format!(
+            "ERR Error running script (call to {}): {}",
+            name,
+            message(err)
+        )),
+    }
+}
+
+// A Lua state with only the libraries redis exposes to scripts
+fn sandbox() -> Result<Lua, RunnerError> {
+    Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )
+    .map_err(lua_error)
+}
+
+fn lua_error(err: mlua::Error) -> RunnerError {
+    RunnerError::Other(format!("ERR {}", message(&err)))
+}
+
+// The message of a Lua error without its stack traceback, replies can't span lines
+fn message(err: &mlua::Error) -> String {
+    let message = match err {
+        mlua::Error::SyntaxError { message, .. } | mlua::Error::RuntimeError(message) => {
+            message.clone()
+        }
+        mlua::Error::CallbackError { cause, .. } => return message(cause),
+        err => err.to_string(),
+    };
+    message.lines().next().unwrap_or_default().to_string()
+}
+
+// The reply of a failed redis.call, if that is what made the script fail
+fn call_error(err: &mlua::Error) -> Option<String> {
+    match err {
+        mlua::Error::CallbackError { cause, .. } => call_error(cause),
+        err => err
+            .downcast_ref::<CallError>()
+            .map(|CallError(err)| err.clone()),
+    }
+}
+
+/**
+ * The redis table scripts use to run commands: redis.call, redis.pcall and their helpers.
+ */
+fn redis_library(lua: &Lua, db: &DB, caller: &Caller) -> mlua::Result<()> {
+    let redis = lua.create_table()?;
+
+    let call_db = db.clone();
+    let call_caller = caller.clone();
+    redis.set(
+        "call",
+        lua.create_function(move |lua, args: Variadic<Value>| {
+            let reply = call(&call_db, &call_caller, args)?;
+            if reply != nil() {
+                if let Frame::SimpleError(err) = reply {
+                    return Err(mlua::Error::external(CallError(err)));
+                }
+            }
+            from_frame(lua, reply)
+        })?,
+    )?;
+
+    let pcall_db = db.clone();
+    let pcall_caller = caller.clone();
+    redis.set(
+        "pcall",
+        lua.create_function(move |lua, args: Variadic<Value>| {
+            from_frame(lua, call(&pcall_db, &pcall_caller, args)?)
+        })?,
+    )?;
+
+    redis.set(
+        "sha1hex",
+        lua.create_function(|_, source: String| Ok(sha1(&source)))?,
+    )?;
+    redis.set(
+        "error_reply",
+        lua.create_function(|lua, err: String| {
+            let reply = lua.create_table()?;
+            reply.set("err", err)?;
+            Ok(reply)
+        })?,
+    )?;
+    redis.set(
+        "status_reply",
+        lua.create_function(|lua, status: String| {
+            let reply = lua.create_table()?;
+            reply.set("ok", status)?;
+            Ok(reply)
+        })?,
+    )?;
+    redis.set(
+        "log",
+        lua.create_function(|_, args: Variadic<Value>| {
+            let message: Vec<String> = args
+                .iter()
+                .skip(1)
+                .filter_map(|arg| arg.to_string().ok())
+                .collect();
+            tracing::info!("SCRIPT: {}", message.join(" "));
+            Ok(())
+        })?,
+    )?;
+    for (level, name) in ["LOG_DEBUG", "LOG_VERBOSE", "LOG_NOTICE", "LOG_WARNING"]
+        .iter()
+        .enumerate()
+    {
+        redis.set(*name, level)?;
+    }
+
+    lua.globals().set("redis", redis)
+}
+
+// Runs a command for redis.call and redis.pcall as the caller of the script, error replies included
+fn call(db: &DB, caller: &Caller, args: Variadic<Value>) -> mlua::Result<Frame> {
+    if args.is_empty() {
+        return Err(mlua::Error::external(CallError(
+            "ERR Please specify at least one argument for this redis lib call".to_string(),
+        )));
+    }
+    let frames = args
+        .iter()
+        .map(|arg| match arg {
+            Value::String(arg) => Ok(Frame::SimpleString(arg.to_string_lossy().into_owned())),
+            Value::Integer(arg) => Ok(Frame::SimpleString(arg.to_string())),
+            // Numbers are truncated to integers like redis does, e.g 3.7 is sent as 3
+            Value::Number(arg) => Ok(Frame::SimpleString((*arg as i64).to_string())),
+            _ => Err(mlua::Error::external(CallError(
+                "ERR Lua redis lib command arguments must be strings or integers".to_string(),
+            ))),
+        })
+        .collect::<mlua::Result<VecDeque<Frame>>>()?;
+
+    let frame = Frame::Array(frames);
+    if let Some(user) = &caller.user {
+        if let Err(err) = db.db.acl.check(user, &frame, "lua") {
+            return Ok(err.into_frame());
+        }
+    }
+    let write = arguments(&frame)
+        .and_then(|(command, _)| spec(&command))
+        .is_some_and(|spec| spec.has(Category::Write));
+    if write {
+        db.db.scripts.written.store(true, Ordering::Relaxed);
+    }
+
+    let mut db = db.clone();
+    let mut runner = Runner::new(&mut db).with_client("lua");
+    if let Some(id) = caller.tracking {
+        runner = runner.with_tracking(id);
+    }
+    Ok(runner.run(frame).unwrap_or_else(RunnerError::into_frame))
+}
+
+/**
+ * Converts a command reply for a script: integers to numbers, strings to strings, arrays to tables,
+ * nil to false and errors to a table with an err field.
+ */
+fn from_frame(lua: &Lua, frame: Frame) -> mlua::Result<Value<'_>> {
+    match frame {
+        Frame::Integer(value) => Ok(Value::Integer(value)),
+        Frame::SimpleString(value) => Ok(Value::String(lua.create_string(&value)?)),
+        Frame::BulkString(value) => Ok(Value::String(lua.create_string(&value)?)),
+        frame @ Frame::SimpleError(_) if frame == nil() => Ok(Value::Boolean(false)),
+        Frame::SimpleError(err) => {
+            let reply = lua.create_table()?;
+            reply.set("err", err)?;
+            Ok(Value::Table(reply))
+        }
+        Frame::Array(frames) => {
+            let reply = lua.create_table_with_capacity(frames.len(), 0)?;
+            for (index, frame) in frames.into_iter().enumerate() {
+                reply.set(index + 1, from_frame(lua, frame)?)?;
+            }
+            Ok(Value::Table(reply))
+        }
+    }
+}
+
+/**
+ * Converts what a script returns into a reply: numbers are truncated to integers, true is 1,
+ * false and nil are nil, tables with an ok or err field are status or error replies
+ * and other tables are arrays up to their first nil.
+ */
+fn into_frame(value: Value) -> Frame {
+    match value {
+        Value::Boolean(true) => Frame::Integer(1),
+        Value::Integer(value) => Frame::Integer(value),
+        Value::Number(value) => Frame::Integer(value as i64),
+        Value::String(value) => Frame::SimpleString(value.to_string_lossy().into_owned()),
+        Value::Table(table) => {
+            if let Ok(err) = table.raw_get::<_, String>("err") {
+                return Frame::SimpleError(err);
+            }
+            if let Ok(status) = table.raw_get::<_, String>("ok") {
+                return Frame::SimpleString(status);
+            }
+            Frame::Array(
+                table
+                    .sequence_values::<Value>()
+                    .map_while(Result::ok)
+                    .map(into_frame)
+                    .collect(),
+            )
+        }
+        _ => nil(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::WRONGTYPE, frame::command};
+
+    #[tokio::test]
+    async fn scripting_eval() {
+        let mut db = DB::new();
+        let script =
+            "redis.call('SET', KEYS[1], ARGV[1]) return {redis.call('GET', KEYS[1]), 7, false}";
+
+        let mut runner = Runner::new(&mut db);
+        let reply = runner
+            .run(command(&["EVAL", script, "1", "key", "value"]))
+            .unwrap();
+        assert_eq!(
+            reply,
+            Frame::Array(VecDeque::from([
+                Frame::SimpleString("value".to_string()),
+                Frame::Integer(7),
+                Frame::SimpleError("Nill".to_string()),
+            ]))
+        );
+
+        // EVAL caches the script for EVALSHA
+        let reply = runner
+            .run(command(&["EVALSHA", &sha1(script), "1", "other", "again"]))
+            .unwrap();
+        assert!(matches!(reply, Frame::Array(_)));
+        assert_eq!(db.get("other").unwrap().unwrap(), b"again".to_vec());
+
+        // Errors of redis.call fail the script, redis.pcall returns them
+        db.sadd("set", vec!["member".to_string()]).unwrap();
+        let mut runner = Runner::new(&mut db);
+        let reply = runner.run(command(&["EVAL", "return redis.call('GET', 'set')", "0"]));
+        assert!(matches!(reply, Err(RunnerError::Other(err)) if err == WRONGTYPE));
+        let reply = runner
+            .run(command(&[
+                "EVAL",
+                "return redis.pcall('GET', 'set')['err']",
+                "0",
+            ]))
+            .unwrap();
+        assert_eq!(reply, Frame::SimpleString(WRONGTYPE.to_string()));
+
+        assert!(runner
+            .run(command(&["EVALSHA", &sha1("return 1"), "0"]))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn lua_number_arguments() {
+        let mut db = DB::new();
+        let script = "redis.call('SET', KEYS[1], ARGV[1] + 0.7) \
+            redis.call('SET', KEYS[2], -2.5) \
+            return redis.call('GET', KEYS[1])";
+        let reply = Runner::new(&mut db)
+            .run(command(&["EVAL", script, "2", "positive", "negative", "3"]))
+            .unwrap();
+        assert_eq!(reply, Frame::SimpleString("3".to_string()));
+        assert_eq!(db.get("negative").unwrap().unwrap(), b"-2".to_vec());
+    }
+
+    #[tokio::test]
+    async fn script_kill() {
+        let db = DB::new();
+        let scripts = &db.db.scripts;
+        assert!(
+            matches!(scripts.kill(), Err(RunnerError::Other(err)) if err.starts_with("NOTBUSY"))
+        );
+
+        let run = |script: &'static str| {
+            let db = db.clone();
+            std::thread::spawn(move || {
+                db.db
+                    .scripts
+                    .eval(&db, &Caller::default(), script, vec![], vec![])
+            })
+        };
+        let looping = run("while true do end");
+        while scripts.running_since.lock().unwrap().is_none() {
+            std::thread::yield_now();
+        }
+        scripts.kill().unwrap();
+        let reply = looping.join().unwrap();
+        assert!(matches!(reply, Err(RunnerError::Other(err)) if err.contains("SCRIPT KILL")));
+
+        // Once it wrote, the script runs to its end
+        let writing = run("redis.call('SET', 'key', 'value') for i = 1, 100000000 do end return 1");
+        while !scripts.written.load(Ordering::Relaxed) {
+            std::thread::yield_now();
+        }
+        assert!(
+            matches!(scripts.kill(), Err(RunnerError::Other(err)) if err.starts_with("UNKILLABLE"))
+        );
+        assert_eq!(writing.join().unwrap().unwrap(), Frame::Integer(1));
+        assert_eq!(db.get("key").unwrap().unwrap(), b"value".to_vec());
+    }
+
+    #[tokio::test]
+    async fn function_libraries() {
+        let db = DB::new();
+        let scripts = &db.db.scripts;
+        let code = "#!lua name=counter\n\
+            redis.register_function('put', function(keys, args) return redis.call('SET', keys[1], args[1]) end)\n\
+            redis.register_function{function_name='fetch', callback=function(keys) return redis.call('GET', keys[1]) end, flags={'no-writes'}}";
+
+        assert_eq!(scripts.load_library(code, false).unwrap(), "counter");
+        assert!(scripts.load_library(code, false).is_err());
+        assert_eq!(scripts.libraries(None)[0].functions.len(), 2);
+
+        let put = scripts.fcall(
+            &db,
+            &Caller::default(),
+            "put",
+            vec!["key".to_string()],
+            vec!["value".to_string()],
+            false,
+        );
+        assert_eq!(put.unwrap(), Frame::SimpleString("OK".to_string()));
+        // Only functions flagged no-writes run with FCALL_RO
+        assert!(scripts
+            .fcall(
+                &db,
+                &Caller::default(),
+                "put",
+                vec!["key".to_string()],
+                vec![],
+                true
+            )
+            .is_err());
+        let fetch = scripts.fcall(
+            &db,
+            &Caller::default(),
+            "fetch",
+            vec!["key".to_string()],
+            vec![],
+            true,
+        );
+        assert_eq!(fetch.unwrap(), Frame::SimpleString("value".to_string()));
+
+        let payload = scripts.dump_libraries();
+        scripts.delete_library("counter").unwrap();
+        assert!(scripts.libraries(None).is_empty());
+        scripts
+            .restore_libraries(&payload, RestorePolicy::Append)
+            .unwrap();
+        assert!(scripts
+            .restore_libraries(&payload, RestorePolicy::Append)
+            .is_err());
+        assert_eq!(scripts.libraries(Some("count*"))[0].code, code);
+    }
+}
@@ -0,0 +1,417 @@
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use mlua::{Table, Value, Variadic};
+
+use crate::{db::DB, frame::Frame, glob, runner::RunnerError};
+
+use super::{into_frame, lua_error, message, sandbox, script_error, Caller, Scripts};
+
+/**
+ * A library of functions loaded with FUNCTION LOAD.
+ * Its code starts with a shebang naming it, e.g #!lua name=mylib
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Library {
+    pub name: String,
+    pub code: String,
+    pub functions: Vec<Function>,
+}
+
+/**
+ * A function registered by a library with redis.register_function.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+}
+
+/**
+ * What FUNCTION RESTORE does with libraries that already exist.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RestorePolicy {
+    // Fail if one of the libraries already exists
+    Append,
+    // Replace the libraries that already exist
+    Replace,
+    // Delete all libraries first
+    Flush,
+}
+
+const FLAGS: [&str; 5] = [
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+// Registry key of the callbacks registered while loading a library to run one of its functions
+const CALLBACKS: &str = "functions";
+
+impl Scripts {
+    /**
+     * Compiles a library and adds it, returning its name.
+     * An existing library with the same name is only replaced if replace is set.
+     */
+    pub fn load_library(&self, code: &str, replace: bool) -> Result<String, RunnerError> {
+        let library = compile(code)?;
+        let name = library.name.clone();
+
+        let mut libraries = self.libraries.lock().unwrap();
+        add(&mut libraries, library, replace)?;
+        Ok(name)
+    }
+
+    /**
+     * The libraries whose name matches a glob pattern, sorted by name.
+     */
+    pub fn libraries(&self, pattern: Option<&str>) -> Vec<Library> {
+        self.libraries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|library| {
+                pattern.is_none_or(|pattern| {
+                    glob::matches(pattern.as_bytes(), library.name.as_bytes())
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn delete_library(&self, name: &str) -> Result<(), RunnerError> {
+        match self.libraries.lock().unwrap().remove(name) {
+            Some(_) => Ok(()),
+            None => Err(RunnerError::Other("ERR Library not found".to_string())),
+        }
+    }
+
+    pub fn flush_libraries(&self) {
+        self.libraries.lock().unwrap().clear();
+    }
+
+    /**
+     * Serializes all libraries for FUNCTION RESTORE, as hex so the payload is a plain string.
+     * Each library is its code prefixed by its length in 4 big endian bytes.
+     */
+    pub fn dump_libraries(&self) -> String {
+        let mut payload = String::new();
+        for library in self.libraries.lock().unwrap().values() {
+            let length = (library.code.len() as u32).to_be_bytes();
+            for byte in length.iter().chain(library.code.as_bytes()) {
+                payload.push_str(&format!("{:02x}", byte));
+            }
+        }
+        payload
+    }
+
+    /**
+     * Restores libraries serialized by dump_libraries, nothing is restored if any of them fails.
+     */
+    pub fn restore_libraries(
+        &self,
+        payload: &str,
+        policy: RestorePolicy,
+    ) -> Result<(), RunnerError> {
+        let invalid =
+            || RunnerError::Other("ERR payload version or checksum are wrong".to_string());
+        if !payload.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+        let bytes = (0..payload.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(payload.get(index..index + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+
+        let mut codes = Vec::new();
+        let mut rest = bytes.as_slice();
+        while !rest.is_empty() {
+            let (length, tail) = rest.split_at_checked(4).ok_or_else(invalid)?;
+            let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+            let (code, tail) = tail.split_at_checked(length).ok_or_else(invalid)?;
+            codes.push(String::from_utf8(code.to_vec()).map_err(|_| invalid())?);
+            rest = tail;
+        }
+
+        let mut libraries = self.libraries.lock().unwrap();
+        let mut restored = match policy {
+            RestorePolicy::Flush => BTreeMap::new(),
+            _ => libraries.clone(),
+        };
+        for code in codes {
+            add(
+                &mut restored,
+                compile(&code)?,
+                policy == RestorePolicy::Replace,
+            )?;
+        }
+        *libraries = restored;
+        Ok(())
+    }
+
+    /**
+     * Runs a function with its keys and arguments.
+     * Read only calls (FCALL_RO) can only run functions flagged no-writes.
+     */
+    pub fn fcall(
+        &self,
+        db: &DB,
+        caller: &Caller,
+        name: &str,
+        keys: Vec<String>,
+        args: Vec<String>,
+        read_only: bool,
+    ) -> Result<Frame, RunnerError> {
+        let (code, function) = self
+            .libraries
+            .lock()
+            .unwrap()
+            .values()
+            .find_map(|library| {
+                library
+                    .functions
+                    .iter()
+                    .find(|function| function.name == name)
+                    .map(|function| (library.code.clone(), function.clone()))
+            })
+            .ok_or_else(|| RunnerError::Other("ERR Function not found".to_string()))?;
+        if read_only && !function.flags.iter().any(|flag| flag == "no-writes") {
+            return Err(RunnerError::Other(
+                "ERR Can not execute a script with write flag using *_ro command.".to_string(),
+            ));
+        }
+
+        self.exclusive(db, || {
+            let lua = self.state(db, caller)?;
+            let callbacks = lua.create_table().map_err(lua_error)?;
+            lua.set_named_registry_value(CALLBACKS, callbacks)
+                .map_err(lua_error)?;
+            let redis: Table = lua.globals().get("redis").map_err(lua_error)?;
+            let register = lua
+                .create_function(|lua, args: Variadic<Value>| {
+                    let (function, callback) = register(args)?;
+                    let callbacks: Table = lua.named_registry_value(CALLBACKS)?;
+                    callbacks.set(function.name, callback)
+                })
+                .map_err(lua_error)?;
+            redis
+                .set("register_function", register)
+                .map_err(lua_error)?;
+
+            lua.load(body(&code))
+                .set_name("@user_function")
+                .exec()
+                .map_err(|err| script_error(&err, name))?;
+            let callbacks: Table = lua.named_registry_value(CALLBACKS).map_err(lua_error)?;
+            let callback: mlua::Function = callbacks.get(name).map_err(lua_error)?;
+            let reply = callback
+                .call::<_, Value>((keys, args))
+                .map_err(|err| script_error(&err, name))?;
+            Ok(into_frame(reply))
+        })
+    }
+}
+
+// Adds a library unless it or one of its functions already exists
+fn add(
+    libraries: &mut BTreeMap<String, Library>,
+    library: Library,
+    replace: bool,
+) -> Result<(), RunnerError> {
+    if !replace && libraries.contains_key(&library.name) {
+        return Err(RunnerError::Other(format!(
+            "ERR Library '{}' already exists",
+            library.name
+        )));
+    }
+    for other in libraries
+        .values()
+        .filter(|other| other.name != library.name)
+    {
+        if let Some(function) = other.functions.iter().find(|function| {
+            library
+                .functions
+                .iter()
+                .any(|new| new.name == function.name)
+        }) {
+            return Err(RunnerError::Other(format!(
+                "ERR Function {} already exists",
+                function.name
+            )));
+        }
+    }
+    libraries.insert(library.name.clone(), library);
+    Ok(())
+}
+
+// Reads the shebang of a library and runs its code to find the functions it registers
+fn compile(code: &str) -> Result<Library, RunnerError> {
+    let shebang = code.lines().next().unwrap_or_default();
+    let metadata = shebang
+        .strip_prefix("#!")
+        .ok_or_else(|| RunnerError::Other("ERR Missing library metadata".to_string()))?;
+    let mut metadata = metadata.split_whitespace();
+    let engine = metadata.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(RunnerError::Other(format!(
+            "ERR Engine '{}' not found",
+            engine
+        )));
+    }
+    let mut name = None;
+    for value in metadata {
+        match value.split_once('=') {
+            Some(("name", value)) => name = Some(value.to_string()),
+            _ => {
+                return Err(RunnerError::Other(format!(
+                    "ERR Invalid metadata value given: {}",
+                    value
+                )))
+            }
+        }
+    }
+    let name =
+        name.ok_or_else(|| RunnerError::Other("ERR Library name was not given".to_string()))?;
+    if !is_valid_name(&name) {
+        return Err(RunnerError::Other(
+            "ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long"
+                .to_string(),
+        ));
+    }
+
+    let lua = sandbox()?;
+    let functions = Rc::new(RefCell::new(Vec::<Function>::new()));
+    let redis = lua.create_table().map_err(lua_error)?;
+    let registered = functions.clone();
+    let register = lua
+        .create_function(move |_, args: Variadic<Value>| {
+            let (function, _) = register(args)?;
+            let mut registered = registered.borrow_mut();
+            if registered.iter().any(|other| other.name == function.name) {
+                return Err(mlua::Error::RuntimeError(
+                    "Function already exists in the library".to_string(),
+                ));
+            }
+            registered.push(function);
+            Ok(())
+        })
+        .map_err(lua_error)?;
+    redis
+        .set("register_function", register)
+        .map_err(lua_error)?;
+    lua.globals().set("redis", redis).map_err(lua_error)?;
+
+    lua.load(body(code))
+        .set_name("@user_function")
+        .exec()
+        .map_err(|err| {
+            RunnerError::Other(format!(
+                "ERR Error registering functions: {}",
+                message(&err)
+            ))
+        })?;
+
+    let functions = functions.take();
+    if functions.is_empty() {
+        return Err(RunnerError::Other(
+            "ERR No functions registered".to_string(),
+        ));
+    }
+    Ok(Library {
+        name,
+        code: code.to_string(),
+        functions,
+    })
+}
+
+// The code of a library without its shebang, the line is kept empty so line numbers stay the same
+fn body(code: &str) -> &str {
+    code.find('\n').map_or("", |end| &code[end..])
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '_')
+}
+
+/**
+ * Parses the arguments of redis.register_function: either a name and a callback,
+ * or a table with function_name, callback and the optional flags and description.
+ */
+fn register(args: Variadic<Value>) -> mlua::Result<(Function, mlua::Function)> {
+    let wrong_arguments = || {
+        mlua::Error::RuntimeError(
+            "wrong number of arguments to redis.register_function".to_string(),
+        )
+    };
+    let (name, callback, description, flags) = match args.as_slice() {
+        [Value::String(name), Value::Function(callback)] => (
+            name.to_str()?.to_string(),
+            callback.clone(),
+            None,
+            Vec::new(),
+        ),
+        [Value::Table(options)] => {
+            let mut name = None;
+            let mut callback = None;
+            let mut description = None;
+            let mut flags = Vec::new();
+            for pair in options.clone().pairs::<String, Value>() {
+                let (key, value) = pair?;
+                match (key.as_str(), value) {
+                    ("function_name", Value::String(value)) => {
+                        name = Some(value.to_str()?.to_string())
+                    }
+                    ("callback", Value::Function(value)) => callback = Some(value),
+                    ("description", Value::String(value)) => {
+                        description = Some(value.to_str()?.to_string())
+                    }
+                    ("flags", Value::Table(value)) => {
+                        for flag in value.sequence_values::<String>() {
+                            let flag = flag?;
+                            if !FLAGS.contains(&flag.as_str()) {
+                                return Err(mlua::Error::RuntimeError(
+                                    "unknown flag given".to_string(),
+                                ));
+                            }
+                            flags.push(flag);
+                        }
+                    }
+                    _ => {
+                        return Err(mlua::Error::RuntimeError(
+                            "unknown argument given to redis.register_function".to_string(),
+                        ))
+                    }
+                }
+            }
+            (
+                name.ok_or_else(wrong_arguments)?,
+                callback.ok_or_else(wrong_arguments)?,
+                description,
+                flags,
+            )
+        }
+        _ => return Err(wrong_arguments()),
+    };
+
+    if !is_valid_name(&name) {
+        return Err(mlua::Error::RuntimeError(
+            "Function names can only contain letters, numbers, or underscores(_) and must be at least one character long"
+                .to_string(),
+        ));
+    }
+    Ok((
+        Function {
+            name,
+            description,
+            flags,
+        },
+        callback,
+    ))
+}
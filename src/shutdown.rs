@@ -0,0 +1,115 @@
+use crate::{command::arguments, frame::Frame, runner::RunnerError};
+
+/**
+ * The options of SHUTDOWN [NOSAVE|SAVE] [NOW] [FORCE] [ABORT].
+ * NOW has nothing to skip without replicas, it is accepted and ignored.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShutdownOptions {
+    // Flush the db into the RDB file before exiting, RDB persistence being always on it is only off with NOSAVE
+    pub save: bool,
+    // Exit even if the final save fails
+    pub force: bool,
+}
+
+impl Default for ShutdownOptions {
+    // The shutdown on SIGTERM and SIGINT
+    fn default() -> Self {
+        ShutdownOptions {
+            save: true,
+            force: false,
+        }
+    }
+}
+
+/**
+ * The options of a SHUTDOWN frame, None if the frame is another command.
+ */
+pub fn parse(frame: &Frame) -> Option<Result<ShutdownOptions, RunnerError>> {
+    let (command, args) = arguments(frame)?;
+    if !command.eq_ignore_ascii_case("SHUTDOWN") {
+        return None;
+    }
+
+    let mut options = ShutdownOptions::default();
+    let (mut save, mut nosave, mut abort) = (false, false, false);
+    for arg in &args {
+        match arg.to_uppercase().as_str() {
+            "SAVE" => save = true,
+            "NOSAVE" => nosave = true,
+            "NOW" => {}
+            "FORCE" => options.force = true,
+            "ABORT" => abort = true,
+            _ => return Some(Err(RunnerError::Other("ERR syntax error".to_string()))),
+        }
+    }
+    if (save && nosave) || (abort && args.len() > 1) {
+        return Some(Err(RunnerError::Other("ERR syntax error".to_string())));
+    }
+    // Shutdowns never wait for replicas here, so there is never one in progress to abort
+    if abort {
+        return Some(Err(RunnerError::Other(
+            "ERR No shutdown in progress.".to_string(),
+        )));
+    }
+    options.save = !nosave;
+    Some(Ok(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::DB, frame::command, rdb::RDB};
+
+    #[tokio::test]
+    async fn shutdown_save() {
+        assert!(parse(&command(&["GET", "key"])).is_none());
+        assert_eq!(
+            parse(&command(&["SHUTDOWN"])).unwrap().unwrap(),
+            ShutdownOptions::default()
+        );
+        assert_eq!(
+            parse(&command(&["shutdown", "nosave", "now", "force"]))
+                .unwrap()
+                .unwrap(),
+            ShutdownOptions {
+                save: false,
+                force: true
+            }
+        );
+        assert!(parse(&command(&["SHUTDOWN", "SAVE", "NOSAVE"]))
+            .unwrap()
+            .is_err());
+        assert!(parse(&command(&["SHUTDOWN", "ABORT"])).unwrap().is_err());
+
+        // The final save replaces the RDB file without leaving its temporary file behind
+        let dir = std::env::temp_dir().join(format!("mini-redis-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut db = DB::new();
+        db.db
+            .config
+            .set(&[("dir".to_string(), dir.to_str().unwrap().to_string())])
+            .unwrap();
+        db.set("key".to_string(), b"value".to_vec(), None);
+        let rdb = RDB::new(db.clone());
+        rdb.save().unwrap();
+        db.set("key".to_string(), b"updated".to_vec(), None);
+        rdb.save().unwrap();
+        let files: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(files, vec!["rdb.rdb".to_string()]);
+
+        let restored = DB::new();
+        restored
+            .db
+            .config
+            .set(&[("dir".to_string(), dir.to_str().unwrap().to_string())])
+            .unwrap();
+        RDB::new(restored.clone()).load().await.unwrap();
+        assert_eq!(restored.get("key").unwrap().unwrap(), b"updated");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(rdb.save().is_err());
+    }
+}
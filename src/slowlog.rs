@@ -0,0 +1,210 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{client::ClientInfo, command::arguments, db::now_ms, frame::Frame};
+
+// Arguments kept per entry, the last one telling how many were left out
+const MAX_ARGS: usize = 32;
+// Characters kept per argument
+const MAX_ARG_LEN: usize = 128;
+
+/**
+ * A command that ran for longer than slowlog-log-slower-than, as listed by SLOWLOG GET.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlowLogEntry {
+    pub id: u64,
+    // Unix time in seconds the command was processed at
+    pub timestamp: u64,
+    pub duration: Duration,
+    // The command and its arguments, truncated
+    pub args: Vec<String>,
+    pub client_addr: String,
+    pub client_name: String,
+}
+
+/**
+ * The most recent slow commands, dropping the oldest ones past slowlog-max-len.
+ */
+pub struct SlowLog {
+    entries: Mutex<VecDeque<SlowLogEntry>>,
+    // Ids keep increasing across SLOWLOG RESET
+    next_id: AtomicU64,
+    // Microseconds, negative disabling the slow log
+    threshold: AtomicI64,
+    max_len: AtomicU64,
+}
+
+impl Default for SlowLog {
+    fn default() -> Self {
+        SlowLog {
+            entries: Mutex::new(VecDeque::new()),
+            next_id: AtomicU64::new(0),
+            threshold: AtomicI64::new(10000),
+            max_len: AtomicU64::new(128),
+        }
+    }
+}
+
+impl SlowLog {
+    pub fn set_threshold(&self, micros: i64) {
+        self.threshold.store(micros, Ordering::Relaxed);
+    }
+
+    pub fn set_max_len(&self, len: u64) {
+        self.max_len.store(len, Ordering::Relaxed);
+        self.trim(&mut self.entries.lock().unwrap());
+    }
+
+    /**
+     * Adds a command sent by a client if it ran for longer than the threshold.
+     */
+    pub fn record(&self, frame: &Frame, duration: Duration, client: &ClientInfo) {
+        let threshold = self.threshold.load(Ordering::Relaxed);
+        if threshold < 0 || (duration.as_micros() as i64) < threshold {
+            return;
+        }
+        let Some((command, args)) = arguments(frame) else {
+            return;
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_front(SlowLogEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            timestamp: now_ms() / 1000,
+            duration,
+            args: truncate(command, args),
+            client_addr: client.addr.clone(),
+            client_name: client.state().name,
+        });
+        self.trim(&mut entries);
+    }
+
+    /**
+     * The count most recent entries, newest first, or every entry if count is None.
+     */
+    pub fn get(&self, count: Option<usize>) -> Vec<SlowLogEntry> {
+        let entries = self.entries.lock().unwrap();
+        let count = count.unwrap_or(entries.len());
+        entries.iter().take(count).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn reset(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    fn trim(&self, entries: &mut VecDeque<SlowLogEntry>) {
+        let max_len = self.max_len.load(Ordering::Relaxed) as usize;
+        entries.truncate(max_len);
+    }
+}
+
+// Keeps MAX_ARGS arguments of MAX_ARG_LEN characters, like redis does
+fn truncate(command: String, args: Vec<String>) -> Vec<String> {
+    let total = args.len() + 1;
+    let mut truncated: Vec<String> = std::iter::once(command)
+        .chain(args)
+        .take(match total > MAX_ARGS {
+            true => MAX_ARGS - 1,
+            false => MAX_ARGS,
+        })
+        .map(|arg| match arg.char_indices().nth(MAX_ARG_LEN) {
+            Some((end, _)) => format!("{}... ({} more bytes)", &arg[..end], arg.len() - end),
+            None => arg,
+        })
+        .collect();
+    if total > MAX_ARGS {
+        truncated.push(format!("... ({} more arguments)", total - MAX_ARGS + 1));
+    }
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::DB, frame::command, runner::Runner};
+
+    #[tokio::test]
+    async fn slowlog_entries() {
+        let mut db = DB::new();
+        let first = db.db.clients.register(1, "127.0.0.1:1", "127.0.0.1:6379");
+        let second = db.db.clients.register(2, "127.0.0.1:2", "127.0.0.1:6379");
+        second.update(|state| state.name = "worker".to_string());
+        let slowlog = &db.db.slowlog;
+        slowlog.record(&command(&["GET", "a"]), Duration::from_millis(5), &first);
+        assert!(slowlog.is_empty());
+
+        slowlog.set_threshold(1000);
+        slowlog.record(&command(&["GET", "a"]), Duration::from_millis(5), &first);
+        let long = "x".repeat(130);
+        let mut args = vec!["MSET", long.as_str()];
+        args.extend(["v"; 40]);
+        slowlog.record(&command(&args), Duration::from_millis(20), &second);
+        let entries = slowlog.get(None);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id, 1);
+        assert_eq!(entries[0].duration, Duration::from_millis(20));
+        assert_eq!(entries[0].client_addr, "127.0.0.1:2");
+        assert_eq!(entries[0].client_name, "worker");
+        assert_eq!(entries[0].args.len(), 32);
+        assert_eq!(
+            entries[0].args[1],
+            format!("{}... (2 more bytes)", "x".repeat(128))
+        );
+        assert_eq!(entries[0].args[31], "... (11 more arguments)");
+        assert_eq!(entries[1].args, vec!["GET", "a"]);
+
+        let mut runner = Runner::new(&mut db);
+        assert_eq!(
+            runner.run(command(&["SLOWLOG", "LEN"])).unwrap(),
+            Frame::Integer(2)
+        );
+        match runner.run(command(&["SLOWLOG", "GET", "1"])).unwrap() {
+            Frame::Array(entries) => match &entries[0] {
+                Frame::Array(entry) => {
+                    assert_eq!(entry[0], Frame::Integer(1));
+                    assert_eq!(entry[2], Frame::Integer(20000));
+                    assert_eq!(entry[4], Frame::SimpleString("127.0.0.1:2".to_string()));
+                    assert_eq!(entries.len(), 1);
+                }
+                frame => panic!("expected an entry, got {:?}", frame),
+            },
+            frame => panic!("expected the entries, got {:?}", frame),
+        }
+        runner
+            .run(command(&["CONFIG", "SET", "slowlog-max-len", "1"]))
+            .unwrap();
+        assert_eq!(
+            runner.run(command(&["SLOWLOG", "LEN"])).unwrap(),
+            Frame::Integer(1)
+        );
+        assert!(runner.run(command(&["SLOWLOG", "RESET", "now"])).is_err());
+        runner.run(command(&["SLOWLOG", "RESET"])).unwrap();
+        assert_eq!(
+            runner.run(command(&["SLOWLOG", "GET"])).unwrap(),
+            Frame::Array(VecDeque::new())
+        );
+
+        runner
+            .run(command(&["CONFIG", "SET", "slowlog-log-slower-than", "-1"]))
+            .unwrap();
+        db.db
+            .slowlog
+            .record(&command(&["GET", "a"]), Duration::from_secs(1), &first);
+        assert!(db.db.slowlog.is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::db::now_ms;
+
+/**
+ * How a command sent by a client ended.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Ok,
+    // It ran and replied an error
+    Failed,
+    // It was refused before running, e.g NOPERM, NOAUTH or wrong arguments
+    Rejected,
+}
+
+/**
+ * Upper bounds in seconds of the latency histogram buckets of the commands.
+ */
+pub const LATENCY_BUCKETS: [f64; 12] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0,
+];
+
+/**
+ * The calls of a command, as listed by INFO commandstats.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct CommandStats {
+    pub calls: u64,
+    // Microseconds spent running the command
+    pub usec: u64,
+    pub rejected_calls: u64,
+    pub failed_calls: u64,
+    // Calls by the first bucket of LATENCY_BUCKETS they fit in, the last one counting the slower calls
+    pub latencies: [u64; LATENCY_BUCKETS.len() + 1],
+}
+
+/**
+ * How the last flushes into the RDB file went, as listed by INFO persistence.
+ */
+#[derive(Clone, Copy)]
+pub struct Persistence {
+    pub saves: u64,
+    // Unix time in seconds of the last successful flush, the startup time until then
+    pub last_save_time: u64,
+    pub last_save_ok: bool,
+    pub last_save_duration: Option<Duration>,
+    pub failed_saves: u64,
+    // Time spent in every flush, successful or not
+    pub save_duration: Duration,
+}
+
+/**
+ * Counters of what the server did since it started, or since they were last reset by CONFIG RESETSTAT.
+ * Gauges like connected_clients are never reset.
+ */
+pub struct Stats {
+    pub started_at: Instant,
+    pub connected_clients: AtomicU64,
+    // Clients waiting on a blocking command, e.g BZPOPMIN
+    pub blocked_clients: AtomicU64,
+    pub connections_received: AtomicU64,
+    // Connections refused by protected mode
+    pub rejected_connections: AtomicU64,
+    pub commands_processed: AtomicU64,
+    pub expired_keys: AtomicU64,
+    // Keys evicted for maxmemory, which isn't supported so it stays at 0
+    pub evicted_keys: AtomicU64,
+    // Keys looked up by read commands that existed or not
+    pub keyspace_hits: AtomicU64,
+    pub keyspace_misses: AtomicU64,
+    commands: Mutex<HashMap<&'static str, CommandStats>>,
+    persistence: Mutex<Persistence>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            started_at: Instant::now(),
+            connected_clients: AtomicU64::new(0),
+            blocked_clients: AtomicU64::new(0),
+            connections_received: AtomicU64::new(0),
+            rejected_connections: AtomicU64::new(0),
+            commands_processed: AtomicU64::new(0),
+            expired_keys: AtomicU64::new(0),
+            evicted_keys: AtomicU64::new(0),
+            keyspace_hits: AtomicU64::new(0),
+            keyspace_misses: AtomicU64::new(0),
+            commands: Mutex::new(HashMap::new()),
+            persistence: Mutex::new(Persistence {
+                saves: 0,
+                last_save_time: now_ms() / 1000,
+                last_save_ok: true,
+                last_save_duration: None,
+                failed_saves: 0,
+                save_duration: Duration::ZERO,
+            }),
+        }
+    }
+}
+
+/**
+ * Counts something in progress, e.g a connected client, until it is dropped.
+ */
+pub struct Gauge<'a>(&'a AtomicU64);
+
+impl Drop for Gauge<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Stats {
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn enter(gauge: &AtomicU64) -> Gauge<'_> {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        Gauge(gauge)
+    }
+
+    pub fn get(counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Counts a call of a command sent by a client.
+     */
+    pub fn record_command(&self, name: &'static str, duration: Duration, outcome: Outcome) {
+        let mut commands = self.commands.lock().unwrap();
+        let stats = commands.entry(name).or_default();
+        match outcome {
+            Outcome::Rejected => stats.rejected_calls += 1,
+            outcome => {
+                stats.calls += 1;
+                stats.usec += duration.as_micros() as u64;
+                let seconds = duration.as_secs_f64();
+                let bucket = LATENCY_BUCKETS
+                    .iter()
+                    .position(|bound| seconds <= *bound)
+                    .unwrap_or(LATENCY_BUCKETS.len());
+                stats.latencies[bucket] += 1;
+                if outcome == Outcome::Failed {
+                    stats.failed_calls += 1;
+                }
+            }
+        }
+    }
+
+    /**
+     * The commands called since the last reset, sorted by name.
+     */
+    pub fn commands(&self) -> Vec<(&'static str, CommandStats)> {
+        let mut commands: Vec<(&'static str, CommandStats)> = self
+            .commands
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, stats)| (*name, *stats))
+            .collect();
+        commands.sort_by_key(|(name, _)| *name);
+        commands
+    }
+
+    pub fn record_save(&self, ok: bool, duration: Duration) {
+        let mut persistence = self.persistence.lock().unwrap();
+        persistence.last_save_ok = ok;
+        persistence.last_save_duration = Some(duration);
+        persistence.save_duration += duration;
+        if ok {
+            persistence.saves += 1;
+            persistence.last_save_time = now_ms() / 1000;
+        } else {
+            persistence.failed_saves += 1;
+        }
+    }
+
+    pub fn persistence(&self) -> Persistence {
+        *self.persistence.lock().unwrap()
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.connections_received,
+            &self.rejected_connections,
+            &self.commands_processed,
+            &self.expired_keys,
+            &self.evicted_keys,
+            &self.keyspace_hits,
+            &self.keyspace_misses,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.commands.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::VecDeque;
+
+use tokio::sync::mpsc;
+
+use crate::{
+    command::arguments,
+    db::DB,
+    frame::Frame,
+    runner::{next_string, rest_strings, RunnerError},
+};
+
+/**
+ * How a tracking connection decides which keys it is sent invalidations for.
+ */
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    // Every key the connection reads
+    Default,
+    // Every key matching the BCAST prefixes, whether it was read or not
+    Broadcast,
+    // Keys read by the command right after CLIENT CACHING YES
+    OptIn,
+    // Keys read by any command except the one right after CLIENT CACHING NO
+    OptOut,
+}
+
+/**
+ * The client side caching state of a connection, as set by CLIENT TRACKING.
+ * Invalidations are published on __redis__:invalidate to the connection given by REDIRECT,
+ * which has to be subscribed to it. Without REDIRECT they are written to the connection itself
+ * as messages of that channel, since only RESP2 is spoken.
+ */
+pub struct Tracking {
+    id: u64,
+    db: DB,
+    // The connection invalidations are sent to with the mode, 0 being this one, None while tracking is off
+    tracking: Option<(u64, Mode)>,
+    // Only set while tracking without REDIRECT
+    receiver: Option<mpsc::UnboundedReceiver<Frame>>,
+    prefixes: Vec<String>,
+    // Set by CLIENT CACHING for the next command
+    caching: Option<bool>,
+}
+
+impl Tracking {
+    pub fn new(db: DB, id: u64) -> Tracking {
+        Tracking {
+            id,
+            db,
+            tracking: None,
+            receiver: None,
+            prefixes: Vec::new(),
+            caching: None,
+        }
+    }
+
+    /**
+     * Handles the client side caching subcommands of CLIENT.
+     * Returns None if the subcommand isn't one of them.
+     */
+    pub fn run(
+        &mut self,
+        subcommand: &str,
+        frames: &mut VecDeque<Frame>,
+    ) -> Option<Result<Frame, RunnerError>> {
+        let reply = match subcommand.to_uppercase().as_str() {
+            "TRACKING" => self.run_tracking(frames),
+            "CACHING" => self.run_caching(frames),
+            "GETREDIR" => Ok(Frame::Integer(match self.tracking {
+                Some((redirect, _)) => redirect as i64,
+                None => -1,
+            })),
+            "TRACKINGINFO" => Ok(self.info()),
+            _ => return None,
+        };
+        Some(reply)
+    }
+
+    /**
+     * The connection invalidations are sent to, 0 being this one, None while tracking is off.
+     */
+    pub fn redirect(&self) -> Option<u64> {
+        self.tracking.map(|(redirect, _)| redirect)
+    }
+
+    /**
+     * Whether the keys read by a command are remembered, the runner then tracks them for the returned connection id.
+     * CLIENT CACHING applies to the next command, or to the whole transaction when that is MULTI.
+     */
+    pub fn reader(&mut self, frame: &Frame) -> Option<u64> {
+        let caching = match arguments(frame) {
+            Some((command, _)) if command.eq_ignore_ascii_case("MULTI") => self.caching,
+            _ => self.caching.take(),
+        };
+        let track = match self.tracking {
+            Some((_, Mode::Default)) => true,
+            Some((_, Mode::OptIn)) => caching == Some(true),
+            Some((_, Mode::OptOut)) => caching != Some(false),
+            Some((_, Mode::Broadcast)) | None => false,
+        };
+        track.then_some(self.id)
+    }
+
+    fn run_tracking(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let on = match next_string(frames)?.to_uppercase().as_str() {
+            "ON" => true,
+            "OFF" => false,
+            _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+        };
+        let mut args = rest_strings(frames)?.into_iter();
+        let mut redirect = None;
+        let mut prefixes = vec![];
+        let (mut bcast, mut optin, mut optout) = (false, false, false);
+        while let Some(arg) = args.next() {
+            match arg.to_uppercase().as_str() {
+                "REDIRECT" => {
+                    let id = args.next().ok_or(RunnerError::Incomplete)?;
+                    redirect = Some(id.parse::<u64>().map_err(|_| {
+                        RunnerError::Other(
+                            "ERR value is not an integer or out of range".to_string(),
+                        )
+                    })?);
+                }
+                "PREFIX" => prefixes.push(args.next().ok_or(RunnerError::Incomplete)?),
+                "BCAST" => bcast = true,
+                "OPTIN" => optin = true,
+                "OPTOUT" => optout = true,
+                _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+            }
+        }
+
+        if !on {
+            self.untrack();
+            return Ok(Frame::SimpleString("OK".to_string()));
+        }
+
+        if !bcast && !prefixes.is_empty() {
+            return Err(RunnerError::Other(
+                "ERR PREFIX option requires BCAST mode to be enabled".to_string(),
+            ));
+        }
+        let mode = match (bcast, optin, optout) {
+            (_, true, true) => {
+                return Err(RunnerError::Other(
+                    "ERR You can't use both OPTIN and OPTOUT".to_string(),
+                ))
+            }
+            (true, true, _) | (true, _, true) => {
+                return Err(RunnerError::Other(
+                    "ERR OPTIN and OPTOUT are not compatible with BCAST".to_string(),
+                ))
+            }
+            (true, _, _) => Mode::Broadcast,
+            (_, true, _) => Mode::OptIn,
+            (_, _, true) => Mode::OptOut,
+            _ => Mode::Default,
+        };
+        if let Some((_, current)) = self.tracking {
+            if current != mode {
+                return Err(RunnerError::Other(
+                    "ERR You can't switch the tracking mode before disabling tracking for this client, and then re-enabling it with a different mode.".to_string(),
+                ));
+            }
+        }
+
+        // REDIRECT is checked before anything changes, the redirect of a connection already tracking is replaced
+        let target = match redirect {
+            Some(redirect) if self.db.db.pubsub.is_subscriber(redirect) => redirect,
+            Some(_) => {
+                return Err(RunnerError::Other(
+                    "ERR The client ID you want redirect to does not exist".to_string(),
+                ))
+            }
+            None => self.id,
+        };
+        match redirect {
+            None if self.receiver.is_none() => {
+                self.receiver = Some(self.db.db.pubsub.track(self.id));
+            }
+            Some(_) if self.receiver.take().is_some() => self.db.db.pubsub.untrack(self.id),
+            _ => {}
+        }
+
+        let bcast = match mode {
+            Mode::Broadcast => Some(prefixes.as_slice()),
+            _ => None,
+        };
+        self.db.track(self.id, target, bcast);
+        self.tracking = Some((redirect.unwrap_or(0), mode));
+        self.prefixes.extend(prefixes);
+        self.prefixes.sort();
+        self.prefixes.dedup();
+        Ok(Frame::SimpleString("OK".to_string()))
+    }
+
+    fn run_caching(&mut self, frames: &mut VecDeque<Frame>) -> Result<Frame, RunnerError> {
+        let caching = match next_string(frames)?.to_uppercase().as_str() {
+            "YES" => true,
+            "NO" => false,
+            _ => return Err(RunnerError::Other("ERR syntax error".to_string())),
+        };
+        match (self.tracking, caching) {
+            (Some((_, Mode::OptIn)), true) | (Some((_, Mode::OptOut)), false) => {
+                self.caching = Some(caching);
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            (Some((_, Mode::OptOut)), true) => Err(RunnerError::Other(
+                "ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode."
+                    .to_string(),
+            )),
+            (Some((_, Mode::OptIn)), false) => Err(RunnerError::Other(
+                "ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode."
+                    .to_string(),
+            )),
+            _ => Err(RunnerError::Other(
+                "ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled".to_string(),
+            )),
+        }
+    }
+
+    // The reply of CLIENT TRACKINGINFO
+    fn info(&self) -> Frame {
+        let flags = match self.tracking {
+            None => vec!["off"],
+            Some((_, Mode::Default)) => vec!["on"],
+            Some((_, Mode::Broadcast)) => vec!["on", "bcast"],
+            Some((_, Mode::OptIn)) => vec!["on", "optin"],
+            Some((_, Mode::OptOut)) => vec!["on", "optout"],
+        };
+        let redirect = match self.tracking {
+            Some((redirect, _)) => redirect as i64,
+            None => -1,
+        };
+        Frame::Array(VecDeque::from([
+            Frame::SimpleString("flags".to_string()),
+            Frame::Array(
+                flags
+                    .into_iter()
+                    .map(|flag| Frame::SimpleString(flag.to_string()))
+                    .collect(),
+            ),
+            Frame::SimpleString("redirect".to_string()),
+            Frame::Integer(redirect),
+            Frame::SimpleString("prefixes".to_string()),
+            Frame::Array(
+                self.prefixes
+                    .iter()
+                    .map(|prefix| Frame::SimpleString(prefix.clone()))
+                    .collect(),
+            ),
+        ]))
+    }
+
+    /**
+     * Waits for the next invalidation sent to the connection itself, when it tracks keys without REDIRECT.
+     */
+    pub async fn receive(&mut self) -> Option<Frame> {
+        match &mut self.receiver {
+            Some(receiver) => receiver.recv().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /**
+     * An invalidation that was already sent to the connection itself, if any.
+     */
+    pub fn try_receive(&mut self) -> Option<Frame> {
+        self.receiver.as_mut()?.try_recv().ok()
+    }
+
+    fn untrack(&mut self) {
+        if self.tracking.take().is_some() {
+            self.db.untrack(self.id);
+        }
+        if self.receiver.take().is_some() {
+            self.db.db.pubsub.untrack(self.id);
+        }
+        self.prefixes.clear();
+        self.caching = None;
+    }
+}
+
+impl Drop for Tracking {
+    fn drop(&mut self) {
+        self.untrack();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::Client, frame::command, pubsub::Subscriber, runner::Runner,
+        transaction::Transaction,
+    };
+
+    #[tokio::test]
+    async fn client_tracking() {
+        let invalidation = |keys: &[&str]| {
+            Frame::Array(VecDeque::from([
+                Frame::SimpleString("message".to_string()),
+                Frame::SimpleString("__redis__:invalidate".to_string()),
+                command(keys),
+            ]))
+        };
+
+        let mut db = DB::new();
+        let mut invalidations = Subscriber::new(db.clone());
+        invalidations
+            .run(&command(&["SUBSCRIBE", "__redis__:invalidate"]))
+            .unwrap()
+            .unwrap();
+        let redirect = invalidations.id().to_string();
+
+        // Like a connection: the client tells the runner which connection the reads are tracked for
+        let run = |client: &mut Client, db: &mut DB, args: &[&str]| {
+            let frame = command(args);
+            assert!(client.run(&frame).is_none());
+            let mut runner = Runner::new(db);
+            if let Some(id) = client.reader(&frame) {
+                runner = runner.with_tracking(id);
+            }
+            runner.run(frame).unwrap()
+        };
+
+        let mut tracking = Client::new(db.clone(), 100, "127.0.0.1:100", "127.0.0.1:6379");
+        let on = command(&["CLIENT", "TRACKING", "ON", "REDIRECT", &redirect]);
+        assert!(tracking.run(&on).unwrap().is_ok());
+        run(&mut tracking, &mut db, &["GET", "key"]);
+        run(&mut tracking, &mut db, &["HGET", "hash", "field"]);
+
+        db.set("key".to_string(), b"value".to_vec(), None);
+        assert_eq!(invalidations.try_receive(), Some(invalidation(&["key"])));
+        // A key is invalidated once until it's read again
+        db.set("key".to_string(), b"other".to_vec(), None);
+        db.set("unread".to_string(), b"value".to_vec(), None);
+        assert_eq!(invalidations.try_receive(), None);
+
+        // So are the reads of scripts and of the commands run by EXEC
+        run(
+            &mut tracking,
+            &mut db,
+            &["EVAL", "return redis.call('GET', 'scripted')", "0"],
+        );
+        db.set("scripted".to_string(), b"value".to_vec(), None);
+        assert_eq!(
+            invalidations.try_receive(),
+            Some(invalidation(&["scripted"]))
+        );
+        let mut transaction = Transaction::new(db.clone());
+        let multi = command(&["MULTI"]);
+        let reader = tracking.reader(&multi);
+        transaction.run(&multi, "default", reader).unwrap().unwrap();
+        let queued = command(&["GET", "queued"]);
+        transaction.run(&queued, "default", None).unwrap().unwrap();
+        let exec = command(&["EXEC"]);
+        let reader = tracking.reader(&exec);
+        transaction.run(&exec, "default", reader).unwrap().unwrap();
+        db.set("queued".to_string(), b"value".to_vec(), None);
+        assert_eq!(invalidations.try_receive(), Some(invalidation(&["queued"])));
+
+        // BCAST connections are sent every key matching their prefixes
+        let mut bcast = Client::new(db.clone(), 101, "127.0.0.1:101", "127.0.0.1:6379");
+        let on = command(&[
+            "CLIENT", "TRACKING", "ON", "REDIRECT", &redirect, "BCAST", "PREFIX", "user:",
+        ]);
+        assert!(bcast.run(&on).unwrap().is_ok());
+        db.set("user:1".to_string(), b"value".to_vec(), None);
+        assert_eq!(invalidations.try_receive(), Some(invalidation(&["user:1"])));
+
+        drop(bcast);
+        assert!(tracking
+            .run(&command(&["CLIENT", "TRACKING", "OFF"]))
+            .unwrap()
+            .is_ok());
+        db.hset("hash", vec![("field".to_string(), b"1".to_vec())])
+            .unwrap();
+        db.set("user:2".to_string(), b"value".to_vec(), None);
+        assert_eq!(invalidations.try_receive(), None);
+    }
+
+    fn invalidation(keys: &[&str]) -> Frame {
+        Frame::Array(VecDeque::from([
+            Frame::SimpleString("message".to_string()),
+            Frame::SimpleString("__redis__:invalidate".to_string()),
+            command(keys),
+        ]))
+    }
+
+    // Runs a command like a connection would, tracking the keys it reads for the client
+    fn read(client: &mut Client, db: &mut DB, args: &[&str]) {
+        let frame = command(args);
+        let mut runner = Runner::new(db);
+        if let Some(id) = client.reader(&frame) {
+            runner = runner.with_tracking(id);
+        }
+        runner.run(frame).unwrap();
+    }
+
+    #[tokio::test]
+    async fn tracking_without_redirect() {
+        let mut db = DB::new();
+        let mut client = Client::new(db.clone(), 100, "127.0.0.1:100", "127.0.0.1:6379");
+        let on = command(&["CLIENT", "TRACKING", "ON"]);
+        assert!(client.run(&on).unwrap().is_ok());
+        // 0 stands for the connection itself
+        assert_eq!(
+            client
+                .run(&command(&["CLIENT", "GETREDIR"]))
+                .unwrap()
+                .unwrap(),
+            Frame::Integer(0)
+        );
+
+        read(&mut client, &mut db, &["GET", "key"]);
+        db.set("key".to_string(), b"value".to_vec(), None);
+        db.set("unread".to_string(), b"value".to_vec(), None);
+        assert_eq!(client.try_invalidated(), Some(invalidation(&["key"])));
+        assert_eq!(client.try_invalidated(), None);
+
+        let off = command(&["CLIENT", "TRACKING", "OFF"]);
+        assert!(client.run(&off).unwrap().is_ok());
+        read(&mut client, &mut db, &["GET", "key"]);
+        db.set("key".to_string(), b"other".to_vec(), None);
+        assert_eq!(client.try_invalidated(), None);
+        assert!(!db.db.pubsub.invalidate(100, &["key".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn tracking_modes_without_redirect() {
+        let mut db = DB::new();
+        let mut bcast = Client::new(db.clone(), 100, "127.0.0.1:100", "127.0.0.1:6379");
+        let on = command(&["CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "user:"]);
+        assert!(bcast.run(&on).unwrap().is_ok());
+        db.set("user:1".to_string(), b"value".to_vec(), None);
+        db.set("other".to_string(), b"value".to_vec(), None);
+        assert_eq!(bcast.try_invalidated(), Some(invalidation(&["user:1"])));
+        assert_eq!(bcast.try_invalidated(), None);
+
+        let mut optin = Client::new(db.clone(), 101, "127.0.0.1:101", "127.0.0.1:6379");
+        let on = command(&["CLIENT", "TRACKING", "ON", "OPTIN"]);
+        assert!(optin.run(&on).unwrap().is_ok());
+        read(&mut optin, &mut db, &["GET", "uncached"]);
+        let caching = command(&["CLIENT", "CACHING", "YES"]);
+        assert!(optin.run(&caching).unwrap().is_ok());
+        read(&mut optin, &mut db, &["GET", "cached"]);
+        db.set("uncached".to_string(), b"value".to_vec(), None);
+        db.set("cached".to_string(), b"value".to_vec(), None);
+        assert_eq!(optin.try_invalidated(), Some(invalidation(&["cached"])));
+        assert_eq!(optin.try_invalidated(), None);
+        // The connection waits for invalidations the same way as for published messages
+        assert!(optin.run(&caching).unwrap().is_ok());
+        read(&mut optin, &mut db, &["GET", "cached"]);
+        let mut other = db.clone();
+        tokio::spawn(async move { other.set("cached".to_string(), b"new".to_vec(), None) });
+        assert_eq!(
+            tokio::time::timeout(std::time::Duration::from_secs(1), optin.invalidated())
+                .await
+                .unwrap(),
+            Some(invalidation(&["cached"]))
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::VecDeque;
+
+use crate::{
+    command::{arguments, spec},
+    db::DB,
+    frame::Frame,
+    runner::{is_command, rest_strings, Runner, RunnerError},
+};
+
+// Commands handled by the connection instead of the runner, EXEC couldn't run them
+const CONNECTION_COMMANDS: [&str; 11] = [
+    "AUTH",
+    "ACL",
+    "CLIENT",
+    "MONITOR",
+    "SHUTDOWN",
+    "SUBSCRIBE",
+    "PSUBSCRIBE",
+    "SSUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "SUNSUBSCRIBE",
+];
+
+/**
+ * The MULTI/EXEC state of a connection.
+ * Frames sent after MULTI are queued and run together on EXEC, no other connection touches the store in between.
+ * Keys passed to WATCH make EXEC reply nil if another client modified them before it.
+ */
+pub struct Transaction {
+    db: DB,
+    // Frames queued since MULTI, None outside of a transaction
+    queued: Option<Vec<Frame>>,
+    // A queued command was rejected, EXEC discards the whole transaction
+    aborted: bool,
+    // Watched keys with their version at the time of WATCH
+    watched: Vec<(String, u64)>,
+    // Where queued frames come from as shown by MONITOR
+    client: Option<String>,
+}
+
+impl Transaction {
+    pub fn new(db: DB) -> Transaction {
+        Transaction {
+            db,
+            queued: None,
+            aborted: false,
+            watched: Vec::new(),
+            client: None,
+        }
+    }
+
+    /**
+     * Queues frames sent by a client, which MONITOR shows them as coming from.
+     */
+    pub fn with_client(mut self, client: &str) -> Self {
+        self.client = Some(client.to_string());
+        self
+    }
+
+    /**
+     * How many frames are queued since MULTI, None outside of a transaction.
+     */
+    pub fn queued(&self) -> Option<usize> {
+        self.queued.as_ref().map(|queued| queued.len())
+    }
+
+    /**
+     * Refuses the commands only the connection runs while in a transaction, which aborts it.
+     * The connection asks before running them itself, e.g AUTH before the session authenticates.
+     * Returns None if the frame isn't one of them or there is no transaction.
+     */
+    pub fn refuse(&mut self, frame: &Frame) -> Option<Result<Frame, RunnerError>> {
+        self.queued.as_ref()?;
+        let (command, _) = arguments(frame)?;
+        if !CONNECTION_COMMANDS.contains(&command.to_uppercase().as_str()) {
+            return None;
+        }
+        self.aborted = true;
+        Some(Err(RunnerError::Other(
+            "ERR Command not allowed inside a transaction".to_string(),
+        )))
+    }
+
+    /**
+     * Handles transaction commands and queues frames while in a transaction.
+     * Queuing a command with the wrong number of arguments, or one only the connection can run, aborts the transaction.
+     * Queued frames are run on behalf of user, the keys they read are tracked for the tracking connection if any.
+     * Returns None if the frame should be run right away.
+     */
+    pub fn run(
+        &mut self,
+        frame: &Frame,
+        user: &str,
+        tracking: Option<u64>,
+    ) -> Option<Result<Frame, RunnerError>> {
+        if let Some(refused) = self.refuse(frame) {
+            return Some(refused);
+        }
+        let mut frames = match frame {
+            Frame::Array(frames) => frames.clone(),
+            Frame::SimpleString(command) => VecDeque::from([Frame::SimpleString(command.clone())]),
+            _ => return None,
+        };
+        let command = match frames.pop_front() {
+            Some(Frame::SimpleString(command)) => command.to_uppercase(),
+            _ => return None,
+        };
+
+        let reply = match command.as_str() {
+            "MULTI" if self.queued.is_some() => Err(RunnerError::Other(
+                "ERR MULTI calls can not be nested".to_string(),
+            )),
+            "MULTI" => {
+                self.queued = Some(Vec::new());
+                self.aborted = false;
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "EXEC" => match self.queued.take() {
+                None => Err(RunnerError::Other("ERR EXEC without MULTI".to_string())),
+                Some(_) if self.aborted => {
+                    self.unwatch();
+                    Err(RunnerError::Other(
+                        "EXECABORT Transaction discarded because of previous errors.".to_string(),
+                    ))
+                }
+                Some(queued) => {
+                    let mut runner = Runner::new(&mut self.db).with_user(user);
+                    if let Some(client) = &self.client {
+                        runner = runner.with_client(client);
+                    }
+                    if let Some(id) = tracking {
+                        runner = runner.with_tracking(id);
+                    }
+                    let reply = runner.exec(queued, &self.watched);
+                    self.unwatch();
+                    Ok(reply)
+                }
+            },
+            "DISCARD" => match self.queued.take() {
+                None => Err(RunnerError::Other("ERR DISCARD without MULTI".to_string())),
+                Some(_) => {
+                    self.unwatch();
+                    Ok(Frame::SimpleString("OK".to_string()))
+                }
+            },
+            "WATCH" if self.queued.is_some() => Err(RunnerError::Other(
+                "ERR WATCH inside MULTI is not allowed".to_string(),
+            )),
+            "WATCH" => rest_strings(&mut frames).and_then(|keys| {
+                if keys.is_empty() {
+                    return Err(RunnerError::Incomplete);
+                }
+                let keys: Vec<String> = keys
+                    .into_iter()
+                    .filter(|key| !self.watched.iter().any(|(watched, _)| watched == key))
+                    .collect();
+                self.watched.extend(self.db.watch(&keys));
+                Ok(Frame::SimpleString("OK".to_string()))
+            }),
+            "UNWATCH" if self.queued.is_none() => {
+                self.unwatch();
+                Ok(Frame::SimpleString("OK".to_string()))
+            }
+            "QUIT" => return None,
+            _ => {
+                let queued = self.queued.as_mut()?;
+                if !is_command(&command) {
+                    self.aborted = true;
+                    return Some(Err(RunnerError::Unsupported));
+                }
+                if spec(&command).is_some_and(|spec| !spec.accepts(frames.len())) {
+                    self.aborted = true;
+                    return Some(Err(RunnerError::Other(format!(
+                        "ERR wrong number of arguments for '{}' command",
+                        command.to_lowercase()
+                    ))));
+                }
+                queued.push(frame.clone());
+                Ok(Frame::SimpleString("QUEUED".to_string()))
+            }
+        };
+        Some(reply)
+    }
+
+    fn unwatch(&mut self) {
+        let watched = std::mem::take(&mut self.watched);
+        self.db.unwatch(&watched);
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        self.unwatch();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::command;
+
+    #[tokio::test]
+    async fn transaction_watch() {
+        let ok = Frame::SimpleString("OK".to_string());
+        let queued = Frame::SimpleString("QUEUED".to_string());
+
+        let mut db = DB::new();
+        let mut transaction = Transaction::new(db.clone());
+        // Frames outside of a transaction are left to the runner
+        assert!(transaction
+            .run(&command(&["SET", "key", "1"]), "default", None)
+            .is_none());
+
+        assert_eq!(
+            transaction
+                .run(&command(&["WATCH", "key"]), "default", None)
+                .unwrap()
+                .unwrap(),
+            ok
+        );
+        assert_eq!(
+            transaction
+                .run(&command(&["MULTI"]), "default", None)
+                .unwrap()
+                .unwrap(),
+            ok
+        );
+        assert_eq!(
+            transaction
+                .run(&command(&["SET", "key", "2"]), "default", None)
+                .unwrap()
+                .unwrap(),
+            queued
+        );
+        assert_eq!(
+            transaction
+                .run(&command(&["GET", "key"]), "default", None)
+                .unwrap()
+                .unwrap(),
+            queued
+        );
+        let reply = transaction
+            .run(&command(&["EXEC"]), "default", None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            reply,
+            Frame::Array(VecDeque::from([
+                ok.clone(),
+                Frame::BulkString(b"2".to_vec())
+            ]))
+        );
+
+        // Another client modifying a watched key aborts EXEC
+        transaction.run(&command(&["WATCH", "key"]), "default", None);
+        transaction.run(&command(&["MULTI"]), "default", None);
+        transaction.run(&command(&["SET", "key", "3"]), "default", None);
+        db.set("key".to_string(), b"4".to_vec(), None);
+        let reply = transaction
+            .run(&command(&["EXEC"]), "default", None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply, Frame::SimpleError("Nill".to_string()));
+        assert_eq!(db.get("key").unwrap().unwrap(), b"4".to_vec());
+        assert!(db.db.state.lock().unwrap().watched.is_empty());
+
+        // Unknown commands discard the transaction
+        transaction.run(&command(&["MULTI"]), "default", None);
+        assert!(transaction
+            .run(&command(&["NOPE"]), "default", None)
+            .unwrap()
+            .is_err());
+        let reply = transaction
+            .run(&command(&["EXEC"]), "default", None)
+            .unwrap();
+        assert!(matches!(reply, Err(RunnerError::Other(err)) if err.starts_with("EXECABORT")));
+
+        // So do commands with the wrong number of arguments and the ones EXEC can't run
+        for frame in [
+            command(&["GET"]),
+            command(&["GET", "key", "extra"]),
+            command(&["MONITOR"]),
+            command(&["CLIENT", "SETNAME", "name"]),
+            command(&["AUTH", "password"]),
+            command(&["ACL", "WHOAMI"]),
+            command(&["SUBSCRIBE", "channel"]),
+        ] {
+            transaction.run(&command(&["MULTI"]), "default", None);
+            transaction.run(&command(&["SET", "key", "5"]), "default", None);
+            assert!(transaction.run(&frame, "default", None).unwrap().is_err());
+            if arguments(&frame).is_some_and(|(command, _)| command != "GET") {
+                assert!(matches!(
+                    transaction.refuse(&frame),
+                    Some(Err(RunnerError::Other(err))) if err == "ERR Command not allowed inside a transaction"
+                ));
+            }
+            let reply = transaction
+                .run(&command(&["EXEC"]), "default", None)
+                .unwrap();
+            assert!(matches!(reply, Err(RunnerError::Other(err)) if err.starts_with("EXECABORT")));
+        }
+        assert_eq!(db.get("key").unwrap().unwrap(), b"4".to_vec());
+        // Outside of a transaction they are left to the connection
+        assert!(transaction
+            .run(&command(&["MONITOR"]), "default", None)
+            .is_none());
+        assert!(transaction
+            .refuse(&command(&["AUTH", "password"]))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn shutdown_inside_multi() {
+        let db = DB::new();
+        let mut transaction = Transaction::new(db.clone());
+        transaction.run(&command(&["MULTI"]), "default", None);
+        transaction.run(&command(&["SET", "key", "1"]), "default", None);
+        // The server would exit right away if the connection ran it
+        let shutdown = command(&["SHUTDOWN", "NOSAVE"]);
+        assert!(matches!(
+            transaction.refuse(&shutdown),
+            Some(Err(RunnerError::Other(err))) if err == "ERR Command not allowed inside a transaction"
+        ));
+        let reply = transaction
+            .run(&command(&["EXEC"]), "default", None)
+            .unwrap();
+        assert!(matches!(reply, Err(RunnerError::Other(err)) if err.starts_with("EXECABORT")));
+        assert!(db.get("key").unwrap().is_none());
+        assert!(transaction.refuse(&shutdown).is_none());
+    }
+
+    #[tokio::test]
+    async fn watch_missing_key() {
+        let mut db = DB::new();
+        let mut transaction = Transaction::new(db.clone());
+        transaction.run(&command(&["WATCH", "key"]), "default", None);
+        // Deleting a key that doesn't exist doesn't modify it
+        assert!(db.delete("key").is_none());
+        transaction.run(&command(&["MULTI"]), "default", None);
+        transaction.run(&command(&["SET", "key", "1"]), "default", None);
+        let reply = transaction
+            .run(&command(&["EXEC"]), "default", None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            reply,
+            Frame::Array(VecDeque::from([Frame::SimpleString("OK".to_string())]))
+        );
+        assert_eq!(db.get("key").unwrap().unwrap(), b"1".to_vec());
+    }
+}